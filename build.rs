@@ -1,48 +1,57 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     println!("cargo:rerun-if-changed=cpp/impl");
     println!("cargo:rerun-if-changed=cpp/bridge");
     println!("cargo:rerun-if-changed=cpp/api");
     println!("cargo:rerun-if-changed=cpp/CMakeLists.txt");
+    println!("cargo:rerun-if-env-changed=POLAR_MQTT_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=POLAR_MQTT_SYSROOT");
+    println!("cargo:rerun-if-env-changed=CROSS_COMPILE");
+    println!("cargo:rerun-if-env-changed=ANDROID_NDK_HOME");
+    println!("cargo:rerun-if-env-changed=ANDROID_PLATFORM");
+    println!("cargo:rerun-if-env-changed=ANDROID_STL");
+    println!("cargo:rerun-if-env-changed=ENABLE_BITCODE");
 
-    let dst = cmake::Config::new("cpp")
-        .generator("Unix Makefiles")
-        .build_target("all")
-        .define("CMAKE_BUILD_TYPE", "Release")
-        .define("BUILD_SHARED_LIBS", "ON")
-        .define("CMAKE_POSITION_INDEPENDENT_CODE", "ON")
-        .define("CMAKE_INSTALL_RPATH_USE_LINK_PATH", "ON")
-        .define("CMAKE_MACOSX_RPATH", "ON")
-        .very_verbose(true)
-        .build();
-
-    let lib_path = dst.join("build");
-    println!("cargo:rustc-link-search=native={}", lib_path.display());
-    println!("cargo:rustc-link-lib=dylib=polar_mqtt_impl");
-    println!("cargo:rustc-link-lib=dylib=polar_mqtt_bridge");
-
-    if cfg!(target_os = "macos") {
-        println!("cargo:rustc-link-lib=dylib=c++");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_path.display());
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../lib");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../build");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@loader_path/../lib");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,@loader_path/../build");
-    } else if cfg!(target_os = "linux") {
-        println!("cargo:rustc-link-lib=dylib=stdc++");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN/../lib");
-        println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN/../build");
-    } else {
-        panic!("Unsupported OS");
+    // The Rust backends only need the bridge's types, not its libraries.
+    // `native_bridge` gates the code that calls the bridge directly.
+    println!("cargo:rustc-check-cfg=cfg(native_bridge)");
+    let wasm = env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch == "wasm32");
+    if env::var_os("CARGO_FEATURE_BACKEND_RUST").is_none() && !wasm {
+        println!("cargo:rustc-cfg=native_bridge");
+        // Prebuilt libraries, from POLAR_MQTT_LIB_DIR or with the `system`
+        // feature pkg-config, skip the cmake build. They must match the
+        // bridge header in this crate, which the bindings come from.
+        match env::var_os("POLAR_MQTT_LIB_DIR") {
+            Some(dir) => link_bridge(Some(Path::new(&dir)), true),
+            None => match system_bridge() {
+                Some(dir) => link_bridge(dir.as_deref(), true),
+                None => link_bridge(Some(&build_bridge()), false),
+            },
+        }
     }
 
+    // wasm32 has no C headers for clang to read, so it always uses the
+    // pregenerated bindings.
+    if !wasm {
+        generate_bindings();
+    }
+}
+
+// Without the `bindgen` feature src/bindings/pregenerated.rs is used.
+#[cfg(feature = "bindgen")]
+fn generate_bindings() {
+    // bindgen passes clang the target itself, but not where its headers are.
+    let sysroot = env::var("POLAR_MQTT_SYSROOT")
+        .map(|dir| vec![format!("--sysroot={}", dir)])
+        .unwrap_or_default();
     let bindings = bindgen::Builder::default()
         .header("cpp/bridge/include/mqtt_c.hpp")
         .clang_arg("-x")
         .clang_arg("c++")
         .clang_arg("-std=c++17")
+        .clang_args(sysroot)
         .allowlist_file("cpp/bridge/include/mqtt_c.hpp")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         .generate()
@@ -53,3 +62,285 @@ fn main() {
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
 }
+
+#[cfg(not(feature = "bindgen"))]
+fn generate_bindings() {}
+
+// The directory of the libraries found by pkg-config, None inside if they
+// are on the linker's default path.
+#[cfg(feature = "system")]
+fn system_bridge() -> Option<Option<PathBuf>> {
+    let library = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .probe("polar_mqtt")
+        .unwrap_or_else(|e| {
+            panic!(
+                "polar_mqtt not found with pkg-config ({}); install the bridge \
+                 libraries or set POLAR_MQTT_LIB_DIR",
+                e
+            )
+        });
+    Some(library.link_paths.into_iter().next())
+}
+
+#[cfg(not(feature = "system"))]
+fn system_bridge() -> Option<Option<PathBuf>> {
+    None
+}
+
+// The target's `target_os` and `target_env`; `cfg!` in a build script
+// describes the host.
+fn target() -> (String, String) {
+    let var = |name| env::var(name).unwrap_or_default();
+    (var("CARGO_CFG_TARGET_OS"), var("CARGO_CFG_TARGET_ENV"))
+}
+
+// Whether the C runtime is linked into the binary: the default on musl, or
+// asked for with `-C target-feature=+crt-static`.
+fn crt_static() -> bool {
+    env::var("CARGO_CFG_TARGET_FEATURE")
+        .is_ok_and(|features| features.split(',').any(|f| f == "crt-static"))
+}
+
+// Static bridge archives, with the `static` feature or when the binary is
+// fully static and so cannot load shared libraries. On Windows crt-static
+// only picks the C runtime. Mobile apps get them too: iOS apps cannot carry
+// loose dylibs, and an APK would otherwise need the bridge's .so files
+// packaged next to the app's.
+fn static_bridge() -> bool {
+    let os = target().0;
+    cfg!(feature = "static") || (crt_static() && os != "windows") || os == "android" || os == "ios"
+}
+
+// The NDK's C++ runtime, `c++_static` unless ANDROID_STL asks for
+// `c++_shared`, which an app loading several C++ libraries should use.
+fn android_stl() -> String {
+    env::var("ANDROID_STL").unwrap_or_else(|_| "c++_static".to_string())
+}
+
+fn build_bridge() -> PathBuf {
+    let shared = if static_bridge() { "OFF" } else { "ON" };
+    let mut config = cmake::Config::new("cpp");
+    config
+        .profile("Release")
+        .define("BUILD_SHARED_LIBS", shared)
+        .define("CMAKE_POSITION_INDEPENDENT_CODE", "ON")
+        .define("CMAKE_INSTALL_RPATH_USE_LINK_PATH", "ON")
+        .define("CMAKE_MACOSX_RPATH", "ON")
+        .very_verbose(true);
+
+    match target() {
+        (os, env) if os == "windows" && env == "msvc" => {
+            // Visual Studio, unless CMAKE_GENERATOR says otherwise, has no
+            // `all` target and builds into per-configuration directories, so
+            // the libraries are installed into OUT_DIR instead. The DLLs go
+            // to bin, which cargo puts on PATH for `cargo run` and `cargo
+            // test`.
+            let dst = config.static_crt(crt_static()).build();
+            println!(
+                "cargo:rustc-link-search=native={}",
+                dst.join("bin").display()
+            );
+            return dst.join("lib");
+        }
+        (os, _) if os == "windows" && cfg!(windows) => config.generator("MinGW Makefiles"),
+        (os, _) if os == "android" => android(config.generator("Unix Makefiles")),
+        (os, _) if os == "ios" => ios(config.generator("Unix Makefiles")),
+        _ => cross_compile(config.generator("Unix Makefiles")),
+    };
+    if crt_static() {
+        config.define("PahoMQTTC_USE_STATIC_LIBS", "ON");
+    }
+    config.build_target("all").build().join("build")
+}
+
+// cmake-rs already hands cmake the target's compilers from cc (CC_<target>,
+// CXX_<target>, or CROSS_COMPILE's prefix such as `aarch64-linux-gnu-`).
+// Unless a CMAKE_TOOLCHAIN_FILE does it, cmake must also be told it is
+// cross-compiling, and with POLAR_MQTT_SYSROOT to look for headers and
+// Paho there rather than on the host.
+fn cross_compile(config: &mut cmake::Config) -> &mut cmake::Config {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    let triple = var("TARGET");
+    let toolchain_file = [
+        "CMAKE_TOOLCHAIN_FILE".to_string(),
+        format!("CMAKE_TOOLCHAIN_FILE_{}", triple.replace('-', "_")),
+    ];
+    if triple == var("HOST")
+        || toolchain_file
+            .iter()
+            .any(|name| env::var_os(name).is_some())
+    {
+        return config;
+    }
+
+    if target().0 == "linux" {
+        config.define("CMAKE_SYSTEM_NAME", "Linux");
+    }
+    config.define("CMAKE_SYSTEM_PROCESSOR", var("CARGO_CFG_TARGET_ARCH"));
+    if let Some(sysroot) = env::var_os("POLAR_MQTT_SYSROOT") {
+        config
+            .define("CMAKE_SYSROOT", &sysroot)
+            .define("CMAKE_FIND_ROOT_PATH", &sysroot)
+            .define("CMAKE_FIND_ROOT_PATH_MODE_PROGRAM", "NEVER")
+            .define("CMAKE_FIND_ROOT_PATH_MODE_LIBRARY", "ONLY")
+            .define("CMAKE_FIND_ROOT_PATH_MODE_INCLUDE", "ONLY");
+    }
+    config
+}
+
+// The NDK's toolchain file sets up the compilers and sysroot for the ABI and
+// API level (ANDROID_PLATFORM, by default android-24). Paho, cross-built for
+// the same ABI, is looked for under POLAR_MQTT_SYSROOT.
+fn android(config: &mut cmake::Config) -> &mut cmake::Config {
+    let ndk = env::var_os("ANDROID_NDK_HOME")
+        .or_else(|| env::var_os("ANDROID_NDK_ROOT"))
+        .expect("set ANDROID_NDK_HOME to the NDK to build for Android");
+    let abi = match env::var("CARGO_CFG_TARGET_ARCH")
+        .unwrap_or_default()
+        .as_str()
+    {
+        "aarch64" => "arm64-v8a",
+        "arm" => "armeabi-v7a",
+        "x86_64" => "x86_64",
+        "x86" => "x86",
+        arch => panic!("Unsupported Android architecture: {}", arch),
+    };
+    config
+        .define(
+            "CMAKE_TOOLCHAIN_FILE",
+            Path::new(&ndk).join("build/cmake/android.toolchain.cmake"),
+        )
+        .define("ANDROID_ABI", abi)
+        .define(
+            "ANDROID_PLATFORM",
+            env::var("ANDROID_PLATFORM").unwrap_or_else(|_| "android-24".to_string()),
+        )
+        .define("ANDROID_STL", android_stl());
+    if let Some(sysroot) = env::var_os("POLAR_MQTT_SYSROOT") {
+        config.define("CMAKE_FIND_ROOT_PATH", sysroot);
+    }
+    config
+}
+
+// cmake-rs points cmake at the iOS SDK, architecture and deployment target
+// (IPHONEOS_DEPLOYMENT_TARGET) itself. Bitcode is only embedded when Xcode
+// asks for it, which it stopped doing in Xcode 14: ENABLE_BITCODE is set
+// for cargo runs from an Xcode build phase.
+fn ios(config: &mut cmake::Config) -> &mut cmake::Config {
+    if env::var("ENABLE_BITCODE").is_ok_and(|enabled| enabled == "YES") {
+        config.cflag("-fembed-bitcode").cxxflag("-fembed-bitcode");
+    }
+    if let Some(sysroot) = env::var_os("POLAR_MQTT_SYSROOT") {
+        config.define("CMAKE_FIND_ROOT_PATH", sysroot);
+    }
+    config
+}
+
+// `prebuilt` libraries live outside OUT_DIR, where cargo does not look for
+// them at run time, so binaries get an rpath to them. With the `static`
+// feature the bridge is linked into the binary instead, which then only
+// needs Paho and the C++ runtime at run time, and gets no rpath. Windows has
+// no rpath: the DLLs must be next to the binary or on PATH.
+fn link_bridge(lib_path: Option<&Path>, prebuilt: bool) {
+    let (os, env) = target();
+    let shared = !static_bridge();
+    if let Some(lib_path) = lib_path {
+        println!("cargo:rustc-link-search=native={}", lib_path.display());
+        if prebuilt && shared && os != "windows" {
+            println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_path.display());
+        }
+    }
+    if shared {
+        println!("cargo:rustc-link-lib=dylib=polar_mqtt_impl");
+        println!("cargo:rustc-link-lib=dylib=polar_mqtt_bridge");
+    } else {
+        // Archives are searched in order: the bridge calls the impl, which
+        // calls Paho.
+        println!("cargo:rustc-link-lib=static=polar_mqtt_bridge");
+        println!("cargo:rustc-link-lib=static=polar_mqtt_impl");
+        let build_dir = lib_path.filter(|_| !prebuilt);
+        link_dependency(build_dir, "PahoMQTTC_LIBRARY", "paho-mqtt3c");
+        // For PKCS#12 bundles.
+        let crypto = if os == "windows" {
+            "libcrypto"
+        } else {
+            "crypto"
+        };
+        link_dependency(build_dir, "OPENSSL_CRYPTO_LIBRARY", crypto);
+    }
+
+    match os.as_str() {
+        "android" if android_stl() == "c++_shared" => {
+            println!("cargo:rustc-link-lib=dylib=c++_shared")
+        }
+        "android" => {
+            println!("cargo:rustc-link-lib=dylib=c++_static");
+            println!("cargo:rustc-link-lib=dylib=c++abi");
+        }
+        "ios" => println!("cargo:rustc-link-lib=dylib=c++"),
+        "macos" => {
+            println!("cargo:rustc-link-lib=dylib=c++");
+            if !shared {
+                return;
+            }
+            if let (Some(lib_path), false) = (lib_path, prebuilt) {
+                println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_path.display());
+            }
+            println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../lib");
+            println!("cargo:rustc-link-arg=-Wl,-rpath,@executable_path/../build");
+            println!("cargo:rustc-link-arg=-Wl,-rpath,@loader_path/../lib");
+            println!("cargo:rustc-link-arg=-Wl,-rpath,@loader_path/../build");
+        }
+        // Fully static binaries are linked with -static, which makes the
+        // linker take libstdc++.a for this too.
+        "linux" => {
+            println!("cargo:rustc-link-lib=dylib=stdc++");
+            if !shared {
+                return;
+            }
+            println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN/../lib");
+            println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN/../build");
+        }
+        // MSVC links its C++ runtime itself, as chosen by crt-static.
+        "windows" if env == "msvc" => {}
+        "windows" => println!("cargo:rustc-link-lib=dylib=stdc++"),
+        os => panic!("Unsupported OS: {}", os),
+    }
+}
+
+// A static impl leaves linking Paho and libcrypto to the binary. After a
+// cmake build each is the library cmake found, whose path `cache_entry`
+// holds, static or shared (only static for a fully static binary); prebuilt
+// libraries get whichever `fallback` the linker's default path has.
+fn link_dependency(build_dir: Option<&Path>, cache_entry: &str, fallback: &str) {
+    let prefix = format!("{}:FILEPATH=", cache_entry);
+    let found = build_dir.and_then(|dir| {
+        let cache = std::fs::read_to_string(dir.join("CMakeCache.txt")).ok()?;
+        cache
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .map(PathBuf::from)
+    });
+    let Some(library) = found else {
+        println!("cargo:rustc-link-lib=dylib={}", fallback);
+        return;
+    };
+    if let Some(dir) = library.parent() {
+        println!("cargo:rustc-link-search=native={}", dir.display());
+    }
+    let file = library.file_name().unwrap_or_default().to_string_lossy();
+    let name = file.split('.').next().unwrap_or_default();
+    let extension = library.extension().and_then(|e| e.to_str());
+    let kind = match extension {
+        Some("a") => "static",
+        _ => "dylib",
+    };
+    // Only Unix adds the lib prefix itself: MSVC's libcrypto.lib is named
+    // in full.
+    let name = match extension {
+        Some("lib") => name,
+        _ => name.strip_prefix("lib").unwrap_or(name),
+    };
+    println!("cargo:rustc-link-lib={}={}", kind, name);
+}
@@ -8,10 +8,17 @@ find_path(PahoMQTTC_INCLUDE_DIR
     /usr/include
 )
 
+# Fully static binaries, such as the crate's musl builds, can only use the
+# static library
+if(PahoMQTTC_USE_STATIC_LIBS)
+    set(PahoMQTTC_NAMES libpaho-mqtt3c.a)
+else()
+    set(PahoMQTTC_NAMES paho-mqtt3c libpaho-mqtt3c)
+endif()
+
 find_library(PahoMQTTC_LIBRARY
     NAMES
-    paho-mqtt3c
-    libpaho-mqtt3c
+    ${PahoMQTTC_NAMES}
     PATHS
     /opt/homebrew/lib
     /usr/local/lib
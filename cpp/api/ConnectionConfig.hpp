@@ -1,6 +1,8 @@
 #ifndef MQTT_CONNECTION_CONFIG_HPP_
 #define MQTT_CONNECTION_CONFIG_HPP_
 
+#include <cstddef>
+#include <cstdint>
 #include <string>
 #include "dllexport.h"
 
@@ -18,7 +20,13 @@ namespace mqtt
             MAX_INFLIGHT = 3,
             MAX_QUEUED_MESSAGES = 4,
             RECONNECT_DELAY = 5,
-            TLS_ENABLED = 6
+            TLS_ENABLED = 6,
+            TCP_KEEPALIVE_IDLE = 7,
+            TCP_KEEPALIVE_INTERVAL = 8,
+            TCP_KEEPALIVE_COUNT = 9,
+            TCP_NODELAY = 10,
+            SEND_BUFFER_SIZE = 11,
+            RECEIVE_BUFFER_SIZE = 12
         };
 
         MQTT_DLLEXPORT ConnectionConfig &set(Parameter param, int32_t value);
@@ -26,6 +34,18 @@ namespace mqtt
         MQTT_DLLEXPORT ConnectionConfig &setBroker(const char *url, uint16_t port);
         MQTT_DLLEXPORT ConnectionConfig &setCredentials(const char *username, const char *password);
         MQTT_DLLEXPORT ConnectionConfig &setTlsCertificates(const char *caFile, const char *certFile, const char *keyFile);
+        // ALPN protocol list in wire format (length-prefixed names); an
+        // empty list disables ALPN.
+        MQTT_DLLEXPORT ConnectionConfig &setAlpnProtocols(const uint8_t *protos, size_t len);
+        // Client certificate, chain and key from a PKCS#12 bundle, used
+        // instead of setTlsCertificates' files; nullptr or "" clears it.
+        MQTT_DLLEXPORT ConnectionConfig &setTlsPkcs12(const char *file, const char *password);
+        // Connects over WebSocket, requesting `path`; nullptr selects plain
+        // TCP again.
+        MQTT_DLLEXPORT ConnectionConfig &setWebSocket(const char *path);
+        // Last will published by the broker if the connection drops; a null
+        // topic clears it.
+        MQTT_DLLEXPORT ConnectionConfig &setWill(const char *topic, const uint8_t *payload, size_t len, int qos, bool retained);
 
     private:
         friend class Session;
@@ -0,0 +1,49 @@
+#ifndef MQTT_LOG_HPP_
+#define MQTT_LOG_HPP_
+
+#include <cstdint>
+#include "dllexport.h"
+
+namespace mqtt
+{
+    enum class LogLevel : int32_t
+    {
+        ERROR = 0,
+        WARN = 1,
+        INFO = 2,
+        DEBUG = 3,
+        TRACE = 4
+    };
+
+    class LogHandler
+    {
+    public:
+        virtual ~LogHandler() {}
+        virtual void onLog(LogLevel level, const char *message) = 0;
+    };
+
+    class PacketHandler
+    {
+    public:
+        virtual ~PacketHandler() {}
+        // One line of Paho's protocol trace, describing a packet sent or
+        // received.
+        virtual void onPacket(const char *line) = 0;
+    };
+
+    class Log
+    {
+    public:
+        // Lines go to the handler when one is set, to the log file passed to
+        // APIFactory::initialize when open, and otherwise (warnings and
+        // errors only) to stderr. DEBUG and TRACE are dropped unless the
+        // factory was initialized with debug enabled.
+        MQTT_DLLEXPORT static void setHandler(LogHandler *handler);
+        MQTT_DLLEXPORT static void write(LogLevel level, const char *message);
+        // Packets are traced to the handler, when one is set, whether or not
+        // debug is enabled. Pass nullptr to stop.
+        MQTT_DLLEXPORT static void setPacketHandler(PacketHandler *handler);
+    };
+
+} // namespace mqtt
+#endif // MQTT_LOG_HPP_
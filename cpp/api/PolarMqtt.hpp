@@ -3,6 +3,7 @@
 
 #include "APIFactory.hpp"
 #include "ConnectionConfig.hpp"
+#include "Log.hpp"
 #include "Session.hpp"
 #include "Message.hpp"
 #include "MessageHandler.hpp"
@@ -31,6 +31,23 @@ namespace mqtt
                                        Message::QoS qos = Message::QoS::AT_MOST_ONCE,
                                        bool retain = false);
 
+        // Why the last subscribe, unsubscribe or publish that failed on the
+        // calling thread did; valid until the next failure on that thread.
+        MQTT_DLLEXPORT static const char *lastError();
+
+        // Milliseconds since the broker last sent a packet the session saw,
+        // or -1 while not connected. Paho does not report PINGRESPs, so
+        // keepalive traffic does not count.
+        MQTT_DLLEXPORT int64_t millisSinceInbound() const;
+
+        // Round trip to the broker in microseconds, -1 on failure, or
+        // PING_TIMEOUT when the broker has not answered within timeoutMs.
+        // The round trip is an UNSUBSCRIBE, not a PINGREQ, which Paho cannot
+        // send on demand. One that timed out is still awaited in the
+        // background, and pinging fails until it ends.
+        static constexpr int64_t PING_TIMEOUT = -3;
+        MQTT_DLLEXPORT int64_t ping(int32_t timeoutMs);
+
         // Handler registration
         MQTT_DLLEXPORT void setMessageHandler(MessageHandler *handler);
         MQTT_DLLEXPORT void setSessionHandler(SessionHandler *handler);
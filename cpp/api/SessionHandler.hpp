@@ -19,6 +19,7 @@ namespace mqtt
         virtual ~SessionHandler() {}
         virtual void onStateChange(SessionState newState) = 0;
         virtual void onError(int errorCode, const char *message) = 0;
+        virtual void onDeliveryComplete(int64_t messageId) {}
     };
 
 } // namespace mqtt
@@ -46,14 +46,37 @@ extern "C"
         MQTT_PARAM_MAX_INFLIGHT = 3,
         MQTT_PARAM_MAX_QUEUED_MESSAGES = 4,
         MQTT_PARAM_RECONNECT_DELAY = 5,
-        MQTT_PARAM_TLS_ENABLED = 6
+        MQTT_PARAM_TLS_ENABLED = 6,
+        MQTT_PARAM_TCP_KEEPALIVE_IDLE = 7,
+        MQTT_PARAM_TCP_KEEPALIVE_INTERVAL = 8,
+        MQTT_PARAM_TCP_KEEPALIVE_COUNT = 9,
+        MQTT_PARAM_TCP_NODELAY = 10,
+        MQTT_PARAM_SEND_BUFFER_SIZE = 11,
+        MQTT_PARAM_RECEIVE_BUFFER_SIZE = 12
     } mqtt_parameter_t;
 
+    typedef enum mqtt_log_level_t
+    {
+        MQTT_LOG_ERROR = 0,
+        MQTT_LOG_WARN = 1,
+        MQTT_LOG_INFO = 2,
+        MQTT_LOG_DEBUG = 3,
+        MQTT_LOG_TRACE = 4
+    } mqtt_log_level_t;
+
     // Callback function types
     // Note: Message data is only valid during callback execution
     typedef void (*mqtt_message_callback_t)(const mqtt_message_data_t *message, void *user_context);
     typedef void (*mqtt_state_callback_t)(mqtt_session_state_t new_state, void *user_context);
     typedef void (*mqtt_error_callback_t)(int error_code, const char *message, void *user_context);
+    typedef void (*mqtt_delivery_callback_t)(int64_t message_id, void *user_context);
+    typedef void (*mqtt_log_callback_t)(mqtt_log_level_t level, const char *message, void *user_context);
+    typedef void (*mqtt_packet_callback_t)(const char *line, void *user_context);
+
+    // Returned by a setter for an option the bridge cannot apply
+#define MQTT_ERR_UNSUPPORTED (-2)
+    // Returned by mqtt_session_ping when the broker does not answer in time
+#define MQTT_ERR_TIMEOUT (-3)
 
     // Session configuration functions
     int mqtt_set_int_parameter(mqtt_session_handle_t session, mqtt_parameter_t param, int32_t value);
@@ -62,21 +85,49 @@ extern "C"
     int mqtt_set_credentials(mqtt_session_handle_t session, const char *username, const char *password);
     int mqtt_set_tls_certificates(mqtt_session_handle_t session, const char *ca_file,
                                   const char *cert_file, const char *key_file);
+    // Wire-format ALPN list (length-prefixed names); NULL or 0 clears it
+    int mqtt_set_alpn_protocols(mqtt_session_handle_t session, const uint8_t *protos, size_t length);
+    // SNI name to send instead of the broker host; NULL or "" clears it.
+    // Not supported: any other name fails with MQTT_ERR_UNSUPPORTED
+    int mqtt_set_tls_server_name(mqtt_session_handle_t session, const char *server_name);
+    // Client identity from a .p12/.pfx bundle; NULL or "" clears it
+    int mqtt_set_tls_pkcs12(mqtt_session_handle_t session, const char *file, const char *password);
+    // Connect over WebSocket with the given request path; NULL selects TCP
+    int mqtt_set_websocket(mqtt_session_handle_t session, const char *path);
+
+    // Describes the last subscribe, unsubscribe or publish that failed on
+    // the calling thread; empty if none did
+    const char *mqtt_last_error(void);
+    // Pass a NULL topic to clear the will
+    int mqtt_set_will(mqtt_session_handle_t session, const char *topic,
+                      const uint8_t *payload, size_t length, mqtt_qos_t qos, int retain);
 
     // Session lifecycle functions
     int mqtt_initialize(const char *app_name, const char *app_version, int debug, const char *log_file);
     int mqtt_uninitialize(void);
+    // Process-wide; pass NULL to restore the default stderr/log file output
+    int mqtt_set_log_callback(mqtt_log_callback_t log_cb, void *user_context);
+    // Process-wide; receives Paho's protocol trace line for every packet
+    // sent or received, whether or not debug is enabled. Pass NULL to stop
+    int mqtt_set_packet_callback(mqtt_packet_callback_t packet_cb, void *user_context);
     mqtt_session_handle_t mqtt_create_session(const char *client_id,
                                               mqtt_message_callback_t message_cb,
                                               mqtt_state_callback_t state_cb,
                                               mqtt_error_callback_t error_cb,
                                               void *user_context);
     void mqtt_destroy_session(mqtt_session_handle_t session);
+    int mqtt_set_delivery_callback(mqtt_session_handle_t session, mqtt_delivery_callback_t delivery_cb);
 
     // Session control functions
     mqtt_session_state_t mqtt_session_get_state(mqtt_session_handle_t session);
     int mqtt_session_start(mqtt_session_handle_t session);
     int mqtt_session_stop(mqtt_session_handle_t session);
+    // Milliseconds since the broker last sent a packet, or -1 while not
+    // connected; keepalive PINGRESPs are not seen
+    int64_t mqtt_session_idle_ms(mqtt_session_handle_t session);
+    // Round trip to the broker in microseconds, -1 on failure, or
+    // MQTT_ERR_TIMEOUT after timeout_ms without an answer; blocks
+    int64_t mqtt_session_ping(mqtt_session_handle_t session, int32_t timeout_ms);
 
     // Subscription functions
     int64_t mqtt_subscribe(mqtt_session_handle_t session, const char *topic, mqtt_qos_t qos);
@@ -10,12 +10,18 @@ struct mqtt_session_t
     mqtt_message_callback_t message_cb;
     mqtt_state_callback_t state_cb;
     mqtt_error_callback_t error_cb;
+    mqtt_delivery_callback_t delivery_cb;
     void *user_context;
 };
 
 namespace
 {
+    class LogCallbackHandler;
+    class PacketCallbackHandler;
+
     mqtt::APIFactory *g_factory = nullptr;
+    std::unique_ptr<LogCallbackHandler> g_log_handler;
+    std::unique_ptr<PacketCallbackHandler> g_packet_handler;
     std::mutex g_mutex;
     std::unordered_map<mqtt_session_handle_t, std::unique_ptr<mqtt::MessageHandler>> g_message_handlers;
     std::unordered_map<mqtt_session_handle_t, std::unique_ptr<mqtt::SessionHandler>> g_session_handlers;
@@ -44,9 +50,55 @@ namespace
             }
         }
 
+        void onDeliveryComplete(int64_t messageId) override
+        {
+            if (delivery_cb_)
+            {
+                delivery_cb_(messageId, context_);
+            }
+        }
+
+        void setDeliveryCallback(mqtt_delivery_callback_t delivery_cb)
+        {
+            delivery_cb_ = delivery_cb;
+        }
+
     private:
         mqtt_state_callback_t state_cb_;
         mqtt_error_callback_t error_cb_;
+        mqtt_delivery_callback_t delivery_cb_{nullptr};
+        void *context_;
+    };
+
+    class LogCallbackHandler : public mqtt::LogHandler
+    {
+    public:
+        LogCallbackHandler(mqtt_log_callback_t cb, void *context)
+            : cb_(cb), context_(context) {}
+
+        void onLog(mqtt::LogLevel level, const char *message) override
+        {
+            cb_(static_cast<mqtt_log_level_t>(level), message, context_);
+        }
+
+    private:
+        mqtt_log_callback_t cb_;
+        void *context_;
+    };
+
+    class PacketCallbackHandler : public mqtt::PacketHandler
+    {
+    public:
+        PacketCallbackHandler(mqtt_packet_callback_t cb, void *context)
+            : cb_(cb), context_(context) {}
+
+        void onPacket(const char *line) override
+        {
+            cb_(line, context_);
+        }
+
+    private:
+        mqtt_packet_callback_t cb_;
         void *context_;
     };
 
@@ -80,10 +132,26 @@ namespace
 }
 
 // Session configuration functions
+// Paho does not expose its socket. The session finds it once connected, on
+// Linux and macOS only, and tunes it then; elsewhere these options fail.
+static bool socket_option(mqtt_parameter_t param)
+{
+#if defined(__linux__) || defined(__APPLE__)
+    (void)param;
+    return false;
+#else
+    return param == MQTT_PARAM_TCP_KEEPALIVE_IDLE || param == MQTT_PARAM_TCP_KEEPALIVE_INTERVAL ||
+           param == MQTT_PARAM_TCP_KEEPALIVE_COUNT || param == MQTT_PARAM_TCP_NODELAY ||
+           param == MQTT_PARAM_SEND_BUFFER_SIZE || param == MQTT_PARAM_RECEIVE_BUFFER_SIZE;
+#endif
+}
+
 int mqtt_set_int_parameter(mqtt_session_handle_t session, mqtt_parameter_t param, int32_t value)
 {
     if (!session || !session->session)
         return -1;
+    if (socket_option(param))
+        return MQTT_ERR_UNSUPPORTED;
     session->session->getConfig().set(
         static_cast<mqtt::ConnectionConfig::Parameter>(param), value);
     return 0;
@@ -93,6 +161,8 @@ int mqtt_set_bool_parameter(mqtt_session_handle_t session, mqtt_parameter_t para
 {
     if (!session || !session->session)
         return -1;
+    if (socket_option(param))
+        return MQTT_ERR_UNSUPPORTED;
     session->session->getConfig().set(
         static_cast<mqtt::ConnectionConfig::Parameter>(param), value != 0);
     return 0;
@@ -123,6 +193,47 @@ int mqtt_set_tls_certificates(mqtt_session_handle_t session, const char *ca_file
     return 0;
 }
 
+int mqtt_set_alpn_protocols(mqtt_session_handle_t session, const uint8_t *protos, size_t length)
+{
+    if (!session || !session->session)
+        return -1;
+    session->session->getConfig().setAlpnProtocols(protos, length);
+    return 0;
+}
+
+// Paho takes SNI from the server URI, and has no option to override it.
+int mqtt_set_tls_server_name(mqtt_session_handle_t session, const char *server_name)
+{
+    if (!session || !session->session)
+        return -1;
+    return server_name && *server_name ? MQTT_ERR_UNSUPPORTED : 0;
+}
+
+int mqtt_set_tls_pkcs12(mqtt_session_handle_t session, const char *file, const char *password)
+{
+    if (!session || !session->session)
+        return -1;
+    session->session->getConfig().setTlsPkcs12(file, password);
+    return 0;
+}
+
+int mqtt_set_websocket(mqtt_session_handle_t session, const char *path)
+{
+    if (!session || !session->session)
+        return -1;
+    session->session->getConfig().setWebSocket(path);
+    return 0;
+}
+
+int mqtt_set_will(mqtt_session_handle_t session, const char *topic,
+                  const uint8_t *payload, size_t length, mqtt_qos_t qos, int retain)
+{
+    if (!session || !session->session)
+        return -1;
+    session->session->getConfig().setWill(topic, payload, length, static_cast<int>(qos), retain != 0);
+    return 0;
+}
+
 // Session lifecycle functions
 int mqtt_initialize(const char *app_name, const char *app_version, int debug, const char *log_file)
 {
@@ -147,6 +258,24 @@ int mqtt_uninitialize(void)
     return 0;
 }
 
+int mqtt_set_log_callback(mqtt_log_callback_t log_cb, void *user_context)
+{
+    std::lock_guard<std::mutex> lock(g_mutex);
+    auto handler = log_cb ? std::make_unique<LogCallbackHandler>(log_cb, user_context) : nullptr;
+    mqtt::Log::setHandler(handler.get());
+    g_log_handler = std::move(handler);
+    return 0;
+}
+
+int mqtt_set_packet_callback(mqtt_packet_callback_t packet_cb, void *user_context)
+{
+    std::lock_guard<std::mutex> lock(g_mutex);
+    auto handler = packet_cb ? std::make_unique<PacketCallbackHandler>(packet_cb, user_context) : nullptr;
+    mqtt::Log::setPacketHandler(handler.get());
+    g_packet_handler = std::move(handler);
+    return 0;
+}
+
 mqtt_session_handle_t mqtt_create_session(const char *client_id,
                                           mqtt_message_callback_t message_cb,
                                           mqtt_state_callback_t state_cb,
@@ -165,6 +294,7 @@ mqtt_session_handle_t mqtt_create_session(const char *client_id,
         message_cb,
         state_cb,
         error_cb,
+        nullptr,
         user_context};
 
     if (message_cb)
@@ -200,6 +330,21 @@ void mqtt_destroy_session(mqtt_session_handle_t session)
     delete session;
 }
 
+int mqtt_set_delivery_callback(mqtt_session_handle_t session, mqtt_delivery_callback_t delivery_cb)
+{
+    if (!session || !session->session)
+        return -1;
+    std::lock_guard<std::mutex> lock(g_mutex);
+
+    auto it = g_session_handlers.find(session);
+    if (it == g_session_handlers.end())
+        return -1;
+
+    static_cast<SessionCallbackHandler *>(it->second.get())->setDeliveryCallback(delivery_cb);
+    session->delivery_cb = delivery_cb;
+    return 0;
+}
+
 // Session control functions
 mqtt_session_state_t mqtt_session_get_state(mqtt_session_handle_t session)
 {
@@ -224,6 +369,22 @@ int mqtt_session_stop(mqtt_session_handle_t session)
     return session->session->stop() ? 0 : -1;
 }
 
+int64_t mqtt_session_idle_ms(mqtt_session_handle_t session)
+{
+    if (!session || !session->session)
+        return -1;
+    return session->session->millisSinceInbound();
+}
+
+static_assert(mqtt::Session::PING_TIMEOUT == MQTT_ERR_TIMEOUT, "ping timeout codes differ");
+
+int64_t mqtt_session_ping(mqtt_session_handle_t session, int32_t timeout_ms)
+{
+    if (!session || !session->session)
+        return -1;
+    return session->session->ping(timeout_ms);
+}
+
 // Subscription functions
 int64_t mqtt_subscribe(mqtt_session_handle_t session, const char *topic, mqtt_qos_t qos)
 {
@@ -239,6 +400,11 @@ int mqtt_unsubscribe(mqtt_session_handle_t session, int64_t handle)
     return session->session->unsubscribe(handle) ? 0 : -1;
 }
 
+const char *mqtt_last_error(void)
+{
+    return mqtt::Session::lastError();
+}
+
 // Publishing functions
 int64_t mqtt_publish(mqtt_session_handle_t session, const char *topic,
                      const uint8_t *payload, size_t length,
@@ -1,18 +1,145 @@
 #include "PolarMqtt.hpp"
 #include <MQTTClient.h>
+#include <openssl/pem.h>
+#include <openssl/pkcs12.h>
+#include <algorithm>
+#include <atomic>
+#include <chrono>
+#include <cstdio>
+#include <filesystem>
+#include <fstream>
+#include <future>
 #include <map>
 #include <mutex>
+#include <set>
 #include <vector>
 #include <iostream>
 
+#ifndef _WIN32
+#include <stdlib.h>
+#endif
+
+#if defined(__linux__) || defined(__APPLE__)
+#include <netinet/in.h>
+#include <netinet/tcp.h>
+#include <sys/resource.h>
+#include <sys/socket.h>
+#define MQTT_HAVE_SOCKET_OPTIONS 1
+// The macro would clash with ConnectionConfig::Parameter::TCP_NODELAY.
+static constexpr int SOCKET_TCP_NODELAY = TCP_NODELAY;
+#undef TCP_NODELAY
+#endif
+
 namespace mqtt
 {
 
+    // Log Implementation
+    namespace
+    {
+        std::mutex g_logMutex;
+        LogHandler *g_logHandler = nullptr;
+        PacketHandler *g_packetHandler = nullptr;
+        std::ofstream g_logFile;
+        bool g_debug = false;
+
+        const char *levelName(LogLevel level)
+        {
+            switch (level)
+            {
+            case LogLevel::ERROR:
+                return "ERROR";
+            case LogLevel::WARN:
+                return "WARN";
+            case LogLevel::INFO:
+                return "INFO";
+            case LogLevel::DEBUG:
+                return "DEBUG";
+            default:
+                return "TRACE";
+            }
+        }
+
+        // Description of the last failed Session call on this thread.
+        thread_local std::string t_lastError;
+
+        void setLastError(int rc)
+        {
+            const char *reason = rc == MQTT_BAD_SUBSCRIBE ? "Rejected by the broker"
+                                                          : MQTTClient_strerror(rc);
+            t_lastError = std::string(reason) + " (" + std::to_string(rc) + ")";
+        }
+
+        void onPahoTrace(enum MQTTCLIENT_TRACE_LEVELS level, char *message)
+        {
+            {
+                std::lock_guard<std::mutex> lock(g_logMutex);
+                if (level == MQTTCLIENT_TRACE_PROTOCOL && g_packetHandler)
+                {
+                    g_packetHandler->onPacket(message);
+                }
+                // Traced for the packet handler only.
+                if (!g_debug)
+                {
+                    return;
+                }
+            }
+            LogLevel mapped = level >= MQTTCLIENT_TRACE_ERROR      ? LogLevel::ERROR
+                              : level == MQTTCLIENT_TRACE_PROTOCOL ? LogLevel::DEBUG
+                                                                   : LogLevel::TRACE;
+            Log::write(mapped, message);
+        }
+    }
+
+    void Log::setHandler(LogHandler *handler)
+    {
+        std::lock_guard<std::mutex> lock(g_logMutex);
+        g_logHandler = handler;
+    }
+
+    void Log::setPacketHandler(PacketHandler *handler)
+    {
+        bool trace;
+        {
+            std::lock_guard<std::mutex> lock(g_logMutex);
+            g_packetHandler = handler;
+            trace = handler || g_debug;
+        }
+        MQTTClient_setTraceCallback(trace ? onPahoTrace : nullptr);
+        if (trace)
+        {
+            MQTTClient_setTraceLevel(MQTTCLIENT_TRACE_PROTOCOL);
+        }
+    }
+
+    void Log::write(LogLevel level, const char *message)
+    {
+        std::lock_guard<std::mutex> lock(g_logMutex);
+        if (level > LogLevel::INFO && !g_debug)
+        {
+            return;
+        }
+        if (g_logFile.is_open())
+        {
+            g_logFile << "[" << levelName(level) << "] " << message << std::endl;
+        }
+        if (g_logHandler)
+        {
+            g_logHandler->onLog(level, message);
+        }
+        else if (!g_logFile.is_open() && level <= LogLevel::WARN)
+        {
+            std::cerr << message << std::endl;
+        }
+    }
+
     // Message Implementation
     struct Message::Impl
     {
         std::string topic;
-        std::vector<uint8_t> payload;
+        // Paho's buffer, valid while the message handler runs. Messages
+        // cannot outlive the callback, so it is never copied.
+        const uint8_t *payload{nullptr};
+        size_t payloadLength{0};
         Message::QoS qos{Message::QoS::AT_MOST_ONCE};
         bool retained{false};
         int64_t messageId{0};
@@ -22,8 +149,8 @@ namespace mqtt
     Message::~Message() { delete impl_; }
 
     const char *Message::getTopic() const { return impl_->topic.c_str(); }
-    const uint8_t *Message::getPayload() const { return impl_->payload.data(); }
-    size_t Message::getPayloadLength() const { return impl_->payload.size(); }
+    const uint8_t *Message::getPayload() const { return impl_->payload; }
+    size_t Message::getPayloadLength() const { return impl_->payloadLength; }
     Message::QoS Message::getQoS() const { return impl_->qos; }
     bool Message::isRetained() const { return impl_->retained; }
     int64_t Message::getMessageId() const { return impl_->messageId; }
@@ -41,10 +168,26 @@ namespace mqtt
         int32_t keepAliveInterval{60};
         bool cleanSession{true};
         int32_t connectionTimeout{30};
-        int32_t maxInflight{10};
+        int32_t maxInflight{10}; // unacknowledged QoS 1/2 publishes, 0 leaves Paho's default
         int32_t maxQueuedMessages{100};
         int32_t reconnectDelay{5};
         bool tlsEnabled{false};
+        std::string alpnProtos; // wire format
+        std::string pkcs12File;
+        std::string pkcs12Password;
+        bool webSocket{false};
+        std::string webSocketPath;
+        int32_t tcpKeepAliveIdle{0}; // seconds, 0 leaves the OS default
+        int32_t tcpKeepAliveInterval{0};
+        int32_t tcpKeepAliveCount{0};
+        int tcpNoDelay{-1}; // -1 leaves the OS default
+        int32_t sendBufferSize{0}; // bytes, 0 leaves the OS default
+        int32_t receiveBufferSize{0};
+        bool willEnabled{false};
+        std::string willTopic;
+        std::string willPayload;
+        int willQos{0};
+        bool willRetained{false};
     };
 
     ConnectionConfig::ConnectionConfig() : impl_(new Impl()) {}
@@ -69,6 +212,21 @@ namespace mqtt
         case Parameter::RECONNECT_DELAY:
             impl_->reconnectDelay = value;
             break;
+        case Parameter::TCP_KEEPALIVE_IDLE:
+            impl_->tcpKeepAliveIdle = value;
+            break;
+        case Parameter::TCP_KEEPALIVE_INTERVAL:
+            impl_->tcpKeepAliveInterval = value;
+            break;
+        case Parameter::TCP_KEEPALIVE_COUNT:
+            impl_->tcpKeepAliveCount = value;
+            break;
+        case Parameter::SEND_BUFFER_SIZE:
+            impl_->sendBufferSize = value;
+            break;
+        case Parameter::RECEIVE_BUFFER_SIZE:
+            impl_->receiveBufferSize = value;
+            break;
         default:
             break;
         }
@@ -85,6 +243,9 @@ namespace mqtt
         case Parameter::TLS_ENABLED:
             impl_->tlsEnabled = value;
             break;
+        case Parameter::TCP_NODELAY:
+            impl_->tcpNoDelay = value ? 1 : 0;
+            break;
         default:
             break;
         }
@@ -116,6 +277,256 @@ namespace mqtt
         return *this;
     }
 
+    ConnectionConfig &ConnectionConfig::setAlpnProtocols(const uint8_t *protos, size_t len)
+    {
+        impl_->alpnProtos.assign(reinterpret_cast<const char *>(protos), protos ? len : 0);
+        return *this;
+    }
+
+    ConnectionConfig &ConnectionConfig::setTlsPkcs12(const char *file, const char *password)
+    {
+        impl_->pkcs12File = file ? file : "";
+        impl_->pkcs12Password = password ? password : "";
+        if (!impl_->pkcs12File.empty())
+        {
+            impl_->tlsEnabled = true;
+        }
+        return *this;
+    }
+
+    ConnectionConfig &ConnectionConfig::setWebSocket(const char *path)
+    {
+        impl_->webSocket = path != nullptr;
+        impl_->webSocketPath = path ? path : "";
+        return *this;
+    }
+
+    ConnectionConfig &ConnectionConfig::setWill(const char *topic, const uint8_t *payload,
+                                                size_t len, int qos, bool retained)
+    {
+        impl_->willEnabled = topic != nullptr;
+        impl_->willTopic = topic ? topic : "";
+        impl_->willPayload.assign(reinterpret_cast<const char *>(payload), payload ? len : 0);
+        impl_->willQos = qos;
+        impl_->willRetained = retained;
+        return *this;
+    }
+
+#ifdef MQTT_HAVE_SOCKET_OPTIONS
+    namespace
+    {
+        // Paho does not expose its socket, so the session's connection is
+        // found among the stream sockets peered with the broker port, as the
+        // one that was not there before connecting.
+        std::set<int> brokerSockets(uint16_t port)
+        {
+            std::set<int> fds;
+            struct rlimit limit;
+            int maxFd = 1024;
+            if (getrlimit(RLIMIT_NOFILE, &limit) == 0 && limit.rlim_cur != RLIM_INFINITY)
+            {
+                maxFd = static_cast<int>(std::min<rlim_t>(limit.rlim_cur, 65536));
+            }
+            for (int fd = 0; fd < maxFd; ++fd)
+            {
+                int type = 0;
+                socklen_t len = sizeof(type);
+                if (getsockopt(fd, SOL_SOCKET, SO_TYPE, &type, &len) != 0 || type != SOCK_STREAM)
+                {
+                    continue;
+                }
+                struct sockaddr_storage peer;
+                socklen_t peerLen = sizeof(peer);
+                if (getpeername(fd, reinterpret_cast<struct sockaddr *>(&peer), &peerLen) != 0)
+                {
+                    continue;
+                }
+                uint16_t peerPort = 0;
+                if (peer.ss_family == AF_INET)
+                {
+                    peerPort = ntohs(reinterpret_cast<struct sockaddr_in *>(&peer)->sin_port);
+                }
+                else if (peer.ss_family == AF_INET6)
+                {
+                    peerPort = ntohs(reinterpret_cast<struct sockaddr_in6 *>(&peer)->sin6_port);
+                }
+                if (peerPort == port)
+                {
+                    fds.insert(fd);
+                }
+            }
+            return fds;
+        }
+
+        // The one socket in `after` but not in `before`, or -1 when another
+        // connection to the same port was opened meanwhile and it cannot be
+        // told apart.
+        int newSocket(const std::set<int> &before, const std::set<int> &after)
+        {
+            int found = -1;
+            for (int fd : after)
+            {
+                if (before.count(fd) != 0)
+                {
+                    continue;
+                }
+                if (found >= 0)
+                {
+                    return -1;
+                }
+                found = fd;
+            }
+            return found;
+        }
+
+        bool applyTcpKeepAlive(int fd, int idle, int interval, int count)
+        {
+            int on = 1;
+            if (setsockopt(fd, SOL_SOCKET, SO_KEEPALIVE, &on, sizeof(on)) != 0)
+            {
+                return false;
+            }
+#ifdef __APPLE__
+            bool ok = setsockopt(fd, IPPROTO_TCP, TCP_KEEPALIVE, &idle, sizeof(idle)) == 0;
+#else
+            bool ok = setsockopt(fd, IPPROTO_TCP, TCP_KEEPIDLE, &idle, sizeof(idle)) == 0;
+#endif
+            if (interval > 0)
+            {
+                ok = ok && setsockopt(fd, IPPROTO_TCP, TCP_KEEPINTVL, &interval, sizeof(interval)) == 0;
+            }
+            if (count > 0)
+            {
+                ok = ok && setsockopt(fd, IPPROTO_TCP, TCP_KEEPCNT, &count, sizeof(count)) == 0;
+            }
+            return ok;
+        }
+
+        // Set after connecting, a receive buffer cannot raise the TCP window
+        // scale agreed in the handshake, so it is only fully effective up to
+        // the OS's default size.
+        bool applySocketTuning(int fd, int noDelay, int sendBuffer, int receiveBuffer)
+        {
+            bool ok = true;
+            if (noDelay >= 0)
+            {
+                ok = setsockopt(fd, IPPROTO_TCP, SOCKET_TCP_NODELAY, &noDelay, sizeof(noDelay)) == 0;
+            }
+            if (sendBuffer > 0)
+            {
+                ok = ok && setsockopt(fd, SOL_SOCKET, SO_SNDBUF, &sendBuffer, sizeof(sendBuffer)) == 0;
+            }
+            if (receiveBuffer > 0)
+            {
+                ok = ok &&
+                     setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &receiveBuffer, sizeof(receiveBuffer)) == 0;
+            }
+            return ok;
+        }
+    }
+#endif
+
+    namespace
+    {
+        // A PKCS#12 bundle converted to the PEM files Paho reads, which are
+        // removed again once the connect has read them.
+        struct Pkcs12Files
+        {
+            std::string certFile;
+            std::string keyFile;
+
+            ~Pkcs12Files()
+            {
+                if (!certFile.empty())
+                {
+                    std::remove(certFile.c_str());
+                }
+                if (!keyFile.empty())
+                {
+                    std::remove(keyFile.c_str());
+                }
+            }
+        };
+
+        // A new file in the temp directory that only the user can read.
+        FILE *createTempFile(std::string &path)
+        {
+#ifdef _WIN32
+            // The temp directory is the user's own.
+            auto stamp = std::chrono::steady_clock::now().time_since_epoch().count();
+            path = (std::filesystem::temp_directory_path() /
+                    ("polar_mqtt_" + std::to_string(stamp)))
+                       .string();
+            return std::fopen(path.c_str(), "wb");
+#else
+            std::string pattern = (std::filesystem::temp_directory_path() / "polar_mqtt_XXXXXX").string();
+            std::vector<char> name(pattern.begin(), pattern.end());
+            name.push_back('\0');
+            int fd = mkstemp(name.data());
+            if (fd < 0)
+            {
+                return nullptr;
+            }
+            path = name.data();
+            return fdopen(fd, "w");
+#endif
+        }
+
+        // Writes the certificate, its chain and the key of a bundle. The key
+        // stays encrypted with the bundle's password, which Paho is given.
+        bool convertPkcs12(const std::string &file, const std::string &password, Pkcs12Files &out)
+        {
+            FILE *in = std::fopen(file.c_str(), "rb");
+            if (!in)
+            {
+                return false;
+            }
+            PKCS12 *bundle = d2i_PKCS12_fp(in, nullptr);
+            std::fclose(in);
+            if (!bundle)
+            {
+                return false;
+            }
+            EVP_PKEY *key = nullptr;
+            X509 *cert = nullptr;
+            STACK_OF(X509) *chain = nullptr;
+            bool ok = PKCS12_parse(bundle, password.c_str(), &key, &cert, &chain) == 1 && key && cert;
+            PKCS12_free(bundle);
+
+            if (ok)
+            {
+                FILE *certOut = createTempFile(out.certFile);
+                ok = certOut && PEM_write_X509(certOut, cert) == 1;
+                for (int i = 0; ok && i < sk_X509_num(chain); ++i)
+                {
+                    ok = PEM_write_X509(certOut, sk_X509_value(chain, i)) == 1;
+                }
+                if (certOut)
+                {
+                    ok = std::fclose(certOut) == 0 && ok;
+                }
+            }
+            if (ok)
+            {
+                FILE *keyOut = createTempFile(out.keyFile);
+                auto *pass = reinterpret_cast<unsigned char *>(const_cast<char *>(password.data()));
+                ok = keyOut && PEM_write_PrivateKey(keyOut, key,
+                                                    password.empty() ? nullptr : EVP_aes_256_cbc(),
+                                                    password.empty() ? nullptr : pass,
+                                                    static_cast<int>(password.size()),
+                                                    nullptr, nullptr) == 1;
+                if (keyOut)
+                {
+                    ok = std::fclose(keyOut) == 0 && ok;
+                }
+            }
+            EVP_PKEY_free(key);
+            X509_free(cert);
+            sk_X509_pop_free(chain, X509_free);
+            return ok;
+        }
+    }
+
     // Session Implementation
     struct Session::Impl
     {
@@ -129,18 +540,38 @@ namespace mqtt
         std::map<int64_t, std::string> subscriptions;
         int64_t nextSubHandle{1};
         int64_t nextMessageId{1};
+        std::mutex deliveryMutex;
+        std::map<MQTTClient_deliveryToken, int64_t> pendingDeliveries;
+        std::set<MQTTClient_deliveryToken> earlyDeliveries;
+        // Steady clock milliseconds of the last packet from the broker.
+        std::atomic<int64_t> lastInbound{0};
+        // The UNSUBSCRIBE of the last ping, which may outlive its wait.
+        std::mutex pingMutex;
+        std::shared_future<int> pingReply;
+
+        static int64_t nowMillis()
+        {
+            return std::chrono::duration_cast<std::chrono::milliseconds>(
+                       std::chrono::steady_clock::now().time_since_epoch())
+                .count();
+        }
+
+        void touch()
+        {
+            lastInbound.store(nowMillis(), std::memory_order_relaxed);
+        }
 
         static int onMessageCallback(void *context, char *topicName, int topicLen,
                                      MQTTClient_message *message)
         {
             auto *impl = static_cast<Session::Impl *>(context);
+            impl->touch();
             if (impl->msgHandler)
             {
                 Message msg;
                 msg.impl_->topic = topicName ? std::string(topicName) : std::string();
-                msg.impl_->payload.assign(
-                    static_cast<uint8_t *>(message->payload),
-                    static_cast<uint8_t *>(message->payload) + message->payloadlen);
+                msg.impl_->payload = static_cast<const uint8_t *>(message->payload);
+                msg.impl_->payloadLength = static_cast<size_t>(message->payloadlen);
                 msg.impl_->qos = static_cast<Message::QoS>(message->qos);
                 msg.impl_->retained = message->retained;
                 msg.impl_->messageId = message->msgid;
@@ -152,9 +583,35 @@ namespace mqtt
             return 1;
         }
 
+        static void onDeliveryComplete(void *context, MQTTClient_deliveryToken token)
+        {
+            auto *impl = static_cast<Session::Impl *>(context);
+            impl->touch();
+            int64_t messageId;
+            {
+                std::lock_guard<std::mutex> lock(impl->deliveryMutex);
+                auto it = impl->pendingDeliveries.find(token);
+                if (it == impl->pendingDeliveries.end())
+                {
+                    // The ack raced ahead of publish() registering the token
+                    impl->earlyDeliveries.insert(token);
+                    return;
+                }
+                messageId = it->second;
+                impl->pendingDeliveries.erase(it);
+            }
+            if (impl->sessionHandler)
+            {
+                impl->sessionHandler->onDeliveryComplete(messageId);
+            }
+        }
+
         static void onConnectionLost(void *context, char *cause)
         {
             auto *impl = static_cast<Session::Impl *>(context);
+            Log::write(LogLevel::WARN, ("Connection lost for " + impl->clientId + ": " +
+                                        (cause ? cause : "unknown cause"))
+                                           .c_str());
             {
                 std::lock_guard<std::mutex> lock(impl->stateMutex);
                 impl->currentState = SessionState::RECONNECTING;
@@ -196,7 +653,7 @@ namespace mqtt
     {
         if (!impl_ || !impl_->config.impl_)
         {
-            std::cerr << "Invalid implementation pointers" << std::endl;
+            Log::write(LogLevel::ERROR, "Invalid implementation pointers");
             return false;
         }
 
@@ -205,12 +662,31 @@ namespace mqtt
 
         if (cfg->broker.empty())
         {
-            std::cerr << "Broker URL not set" << std::endl;
+            Log::write(LogLevel::ERROR, "Broker URL not set");
+            return false;
+        }
+
+        // Must outlive MQTTClient_connect, like ssl_opts.
+        Pkcs12Files pkcs12;
+        if (cfg->tlsEnabled && !cfg->pkcs12File.empty() &&
+            !convertPkcs12(cfg->pkcs12File, cfg->pkcs12Password, pkcs12))
+        {
+            if (impl_->sessionHandler)
+            {
+                impl_->sessionHandler->onError(-1, "Failed to read PKCS#12 bundle");
+            }
             return false;
         }
 
-        std::string serverURI = (cfg->tlsEnabled ? "ssl://" : "tcp://") +
-                                cfg->broker + ":" + std::to_string(cfg->port);
+        const char *scheme = cfg->webSocket ? (cfg->tlsEnabled ? "wss://" : "ws://")
+                                            : (cfg->tlsEnabled ? "ssl://" : "tcp://");
+        // IPv6 literals need brackets to be told apart from the port.
+        std::string host = cfg->broker.find(':') != std::string::npos && cfg->broker[0] != '['
+                               ? "[" + cfg->broker + "]"
+                               : cfg->broker;
+        std::string serverURI = scheme + host + ":" + std::to_string(cfg->port) +
+                                (cfg->webSocket ? cfg->webSocketPath : "");
+        Log::write(LogLevel::INFO, ("Connecting " + impl_->clientId + " to " + serverURI).c_str());
 
         int rc = MQTTClient_create(&impl_->client, serverURI.c_str(),
                                    impl_->clientId.c_str(),
@@ -227,12 +703,16 @@ namespace mqtt
         MQTTClient_setCallbacks(impl_->client, impl_,
                                 Impl::onConnectionLost,
                                 Impl::onMessageCallback,
-                                nullptr);
+                                Impl::onDeliveryComplete);
 
         conn_opts.keepAliveInterval = cfg->keepAliveInterval;
         conn_opts.cleansession = cfg->cleanSession;
         conn_opts.retryInterval = cfg->reconnectDelay;
         conn_opts.reliable = 1;
+        if (cfg->maxInflight > 0)
+        {
+            conn_opts.maxInflightMessages = cfg->maxInflight;
+        }
 
         if (!cfg->username.empty())
         {
@@ -240,12 +720,36 @@ namespace mqtt
             conn_opts.password = cfg->password.c_str();
         }
 
+        MQTTClient_willOptions will_opts = MQTTClient_willOptions_initializer;
+        if (cfg->willEnabled)
+        {
+            will_opts.topicName = cfg->willTopic.c_str();
+            will_opts.payload.data = cfg->willPayload.data();
+            will_opts.payload.len = static_cast<int>(cfg->willPayload.size());
+            will_opts.qos = cfg->willQos;
+            will_opts.retained = cfg->willRetained ? 1 : 0;
+            conn_opts.will = &will_opts;
+        }
+
+        // Must outlive MQTTClient_connect, like will_opts.
+        MQTTClient_SSLOptions ssl_opts = MQTTClient_SSLOptions_initializer;
         if (cfg->tlsEnabled)
         {
-            MQTTClient_SSLOptions ssl_opts = MQTTClient_SSLOptions_initializer;
-            ssl_opts.trustStore = cfg->caFile.c_str();
-            ssl_opts.keyStore = cfg->certFile.c_str();
-            ssl_opts.privateKey = cfg->keyFile.c_str();
+            ssl_opts.trustStore = cfg->caFile.empty() ? nullptr : cfg->caFile.c_str();
+            ssl_opts.keyStore = cfg->certFile.empty() ? nullptr : cfg->certFile.c_str();
+            ssl_opts.privateKey = cfg->keyFile.empty() ? nullptr : cfg->keyFile.c_str();
+            if (!pkcs12.certFile.empty())
+            {
+                ssl_opts.keyStore = pkcs12.certFile.c_str();
+                ssl_opts.privateKey = pkcs12.keyFile.c_str();
+                ssl_opts.privateKeyPassword =
+                    cfg->pkcs12Password.empty() ? nullptr : cfg->pkcs12Password.c_str();
+            }
+            if (!cfg->alpnProtos.empty())
+            {
+                ssl_opts.protos = reinterpret_cast<const unsigned char *>(cfg->alpnProtos.data());
+                ssl_opts.protos_len = static_cast<unsigned int>(cfg->alpnProtos.size());
+            }
             conn_opts.ssl = &ssl_opts;
         }
 
@@ -254,6 +758,16 @@ namespace mqtt
             impl_->currentState = SessionState::CONNECTING;
         }
 
+        bool tuneSocket = cfg->tcpKeepAliveIdle > 0 || cfg->tcpNoDelay >= 0 ||
+                          cfg->sendBufferSize > 0 || cfg->receiveBufferSize > 0;
+#ifdef MQTT_HAVE_SOCKET_OPTIONS
+        std::set<int> socketsBefore;
+        if (tuneSocket)
+        {
+            socketsBefore = brokerSockets(cfg->port);
+        }
+#endif
+
         rc = MQTTClient_connect(impl_->client, &conn_opts);
         if (rc != MQTTCLIENT_SUCCESS)
         {
@@ -268,10 +782,39 @@ namespace mqtt
             return false;
         }
 
+        impl_->touch();
         {
             std::lock_guard<std::mutex> lock(impl_->stateMutex);
             impl_->currentState = SessionState::CONNECTED;
         }
+        Log::write(LogLevel::INFO, ("Connected " + impl_->clientId).c_str());
+
+        if (tuneSocket)
+        {
+#ifdef MQTT_HAVE_SOCKET_OPTIONS
+            int fd = newSocket(socketsBefore, brokerSockets(cfg->port));
+            if (fd < 0)
+            {
+                Log::write(LogLevel::WARN, "Connection socket not found, socket options not applied");
+            }
+            else
+            {
+                if (cfg->tcpKeepAliveIdle > 0 &&
+                    !applyTcpKeepAlive(fd, cfg->tcpKeepAliveIdle, cfg->tcpKeepAliveInterval,
+                                       cfg->tcpKeepAliveCount))
+                {
+                    Log::write(LogLevel::WARN, "Failed to apply TCP keepalive settings");
+                }
+                if (!applySocketTuning(fd, cfg->tcpNoDelay, cfg->sendBufferSize,
+                                       cfg->receiveBufferSize))
+                {
+                    Log::write(LogLevel::WARN, "Failed to apply socket options");
+                }
+            }
+#else
+            Log::write(LogLevel::WARN, "Socket options are not supported on this platform");
+#endif
+        }
 
         if (impl_->sessionHandler)
         {
@@ -285,8 +828,23 @@ namespace mqtt
     {
         if (impl_->client)
         {
+            Log::write(LogLevel::INFO, ("Disconnecting " + impl_->clientId).c_str());
             MQTTClient_disconnect(impl_->client, 10000);
+            std::shared_future<int> pingReply;
+            {
+                std::lock_guard<std::mutex> lock(impl_->pingMutex);
+                pingReply = impl_->pingReply;
+            }
+            if (pingReply.valid())
+            {
+                pingReply.wait();
+            }
             MQTTClient_destroy(&impl_->client);
+            {
+                std::lock_guard<std::mutex> lock(impl_->deliveryMutex);
+                impl_->pendingDeliveries.clear();
+                impl_->earlyDeliveries.clear();
+            }
             {
                 std::lock_guard<std::mutex> lock(impl_->stateMutex);
                 impl_->currentState = SessionState::DISCONNECTED;
@@ -304,12 +862,14 @@ namespace mqtt
         int rc = MQTTClient_subscribe(impl_->client, topic, static_cast<int>(qos));
         if (rc != MQTTCLIENT_SUCCESS)
         {
+            setLastError(rc);
             if (impl_->sessionHandler)
             {
                 impl_->sessionHandler->onError(rc, "Subscribe failed");
             }
             return -1;
         }
+        impl_->touch();
 
         int64_t handle = impl_->nextSubHandle++;
         impl_->subscriptions[handle] = topic;
@@ -321,18 +881,21 @@ namespace mqtt
         auto it = impl_->subscriptions.find(handle);
         if (it == impl_->subscriptions.end())
         {
+            t_lastError = "Unknown handle " + std::to_string(handle);
             return false;
         }
 
         int rc = MQTTClient_unsubscribe(impl_->client, it->second.c_str());
         if (rc != MQTTCLIENT_SUCCESS)
         {
+            setLastError(rc);
             if (impl_->sessionHandler)
             {
                 impl_->sessionHandler->onError(rc, "Unsubscribe failed");
             }
             return false;
         }
+        impl_->touch();
 
         impl_->subscriptions.erase(it);
         return true;
@@ -350,9 +913,11 @@ namespace mqtt
         int64_t messageId = impl_->nextMessageId++;
         pubmsg.msgid = static_cast<int>(messageId);
 
-        int rc = MQTTClient_publishMessage(impl_->client, topic, &pubmsg, nullptr);
+        MQTTClient_deliveryToken token = 0;
+        int rc = MQTTClient_publishMessage(impl_->client, topic, &pubmsg, &token);
         if (rc != MQTTCLIENT_SUCCESS)
         {
+            setLastError(rc);
             if (impl_->sessionHandler)
             {
                 impl_->sessionHandler->onError(rc, "Publish failed");
@@ -360,9 +925,90 @@ namespace mqtt
             return -1;
         }
 
+        if (qos != Message::QoS::AT_MOST_ONCE)
+        {
+            bool delivered = false;
+            {
+                std::lock_guard<std::mutex> lock(impl_->deliveryMutex);
+                if (impl_->earlyDeliveries.erase(token) > 0)
+                {
+                    delivered = true;
+                }
+                else
+                {
+                    impl_->pendingDeliveries[token] = messageId;
+                }
+            }
+            if (delivered && impl_->sessionHandler)
+            {
+                impl_->sessionHandler->onDeliveryComplete(messageId);
+            }
+        }
+
         return messageId;
     }
 
+    const char *Session::lastError()
+    {
+        return t_lastError.c_str();
+    }
+
+    // Paho offers no way to send a PINGREQ on demand, so the round trip is
+    // an UNSUBSCRIBE of a filter this session never subscribes to, which the
+    // broker answers with an UNSUBACK as promptly as it would a PINGRESP.
+    // It runs on a thread of its own, as Paho's waits only for its command
+    // timeout; `stop` waits for it before destroying the client.
+    int64_t Session::ping(int32_t timeoutMs)
+    {
+        if (getState() != SessionState::CONNECTED)
+        {
+            t_lastError = "Not connected";
+            return -1;
+        }
+        auto started = std::chrono::steady_clock::now();
+        std::shared_future<int> reply;
+        {
+            std::lock_guard<std::mutex> lock(impl_->pingMutex);
+            if (impl_->pingReply.valid() &&
+                impl_->pingReply.wait_for(std::chrono::seconds(0)) != std::future_status::ready)
+            {
+                t_lastError = "An earlier ping is still waiting for the broker";
+                return -1;
+            }
+            MQTTClient client = impl_->client;
+            impl_->pingReply =
+                std::async(std::launch::async, [client]
+                           { return MQTTClient_unsubscribe(client, "polar_mqtt/ping"); })
+                    .share();
+            reply = impl_->pingReply;
+        }
+        if (reply.wait_for(std::chrono::milliseconds(std::max(timeoutMs, 0))) !=
+            std::future_status::ready)
+        {
+            t_lastError = "Ping timed out";
+            return PING_TIMEOUT;
+        }
+        int rc = reply.get();
+        if (rc != MQTTCLIENT_SUCCESS)
+        {
+            setLastError(rc);
+            return -1;
+        }
+        impl_->touch();
+        return std::chrono::duration_cast<std::chrono::microseconds>(
+                   std::chrono::steady_clock::now() - started)
+            .count();
+    }
+
+    int64_t Session::millisSinceInbound() const
+    {
+        if (getState() != SessionState::CONNECTED)
+        {
+            return -1;
+        }
+        return Impl::nowMillis() - impl_->lastInbound.load(std::memory_order_relaxed);
+    }
+
     void Session::setMessageHandler(MessageHandler *handler)
     {
         impl_->msgHandler = handler;
@@ -393,11 +1039,49 @@ namespace mqtt
     int APIFactory::initialize(const char *appName, const char *appVersion,
                                bool debug, const char *logFile)
     {
+        {
+            std::lock_guard<std::mutex> lock(g_logMutex);
+            g_debug = debug;
+            if (logFile && *logFile)
+            {
+                g_logFile.open(logFile, std::ios::app);
+                if (!g_logFile.is_open())
+                {
+                    std::cerr << "Failed to open log file " << logFile << std::endl;
+                    return -1;
+                }
+            }
+        }
+
+        bool packets;
+        {
+            std::lock_guard<std::mutex> lock(g_logMutex);
+            packets = g_packetHandler != nullptr;
+        }
+        if (debug || packets)
+        {
+            MQTTClient_setTraceCallback(onPahoTrace);
+            MQTTClient_setTraceLevel(MQTTCLIENT_TRACE_PROTOCOL);
+        }
+
+        std::string banner = std::string("Initialized ") + (appName ? appName : "") +
+                             " " + (appVersion ? appVersion : "");
+        Log::write(LogLevel::INFO, banner.c_str());
         return 0;
     }
 
     int APIFactory::uninitialize()
     {
+        MQTTClient_setTraceCallback(nullptr);
+        {
+            std::lock_guard<std::mutex> lock(g_logMutex);
+            if (g_logFile.is_open())
+            {
+                g_logFile.close();
+            }
+            g_debug = false;
+        }
+
         --refCount_;
         if (refCount_ == 0 && instance_)
         {
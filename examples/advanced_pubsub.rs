@@ -51,7 +51,7 @@ fn main() -> Result<(), AppError> {
     let stats_clone = Arc::clone(&stats);
     let error_tx = Arc::new(Mutex::new(error_tx));
 
-    let mut client = Client::new(
+    let client = Client::new(
         &client_id,
         {
             let stats = Arc::clone(&stats);
@@ -122,7 +122,7 @@ fn main() -> Result<(), AppError> {
 
     let sub_handles: Vec<_> = subscriptions
         .iter()
-        .filter_map(|(topic, qos)| match client.subscribe(topic, *qos) {
+        .filter_map(|(topic, qos)| match client.subscribe(*topic, *qos) {
             Ok(handle) => {
                 println!("Subscribed to {} with handle {}", topic, handle);
                 Some(handle)
@@ -137,7 +137,7 @@ fn main() -> Result<(), AppError> {
     let publisher_thread = thread::spawn({
         let client_id = format!("rust-publisher-{}", Uuid::new_v4());
         move || -> Result<(), AppError> {
-            let mut publisher = Client::new(
+            let publisher = Client::new(
                 &client_id,
                 |_| {},
                 |state| println!("Publisher state: {:?}", state),
@@ -152,11 +152,11 @@ fn main() -> Result<(), AppError> {
                     Message::new(
                         "test/sensors/temperature",
                         format!("{{\"value\": {}, \"unit\": \"C\"}}", 20 + (i % 5)),
-                    ),
+                    )?,
                     Message::new(
                         "test/control/pump",
                         format!("{{\"state\": {}}}", i % 2 == 0),
-                    ),
+                    )?,
                     Message::new(
                         "test/status/system",
                         format!(
@@ -164,7 +164,7 @@ fn main() -> Result<(), AppError> {
                             i * 60,
                             i as f32 * 0.1
                         ),
-                    ),
+                    )?,
                 ];
 
                 for msg in messages {
@@ -1,9 +1,14 @@
-use polar_mqtt::{Client, Message, QoS};
+use polar_mqtt::{Client, InitOptions, Message, QoS};
 use std::sync::mpsc;
 use std::time::Duration;
 
 fn main() -> polar_mqtt::Result<()> {
     println!("Initializing API...");
+    polar_mqtt::init(InitOptions {
+        app_name: "basic_pubsub".into(),
+        app_version: env!("CARGO_PKG_VERSION").into(),
+        ..Default::default()
+    })?;
 
     // Create channel for message communication
     let (tx, rx) = mpsc::channel();
@@ -16,8 +21,8 @@ fn main() -> polar_mqtt::Result<()> {
     let test_topic_clone = test_topic.clone();
 
     // Create client with callbacks
-    let mut client = Client::new(
-        &format!("TestClient_{}", uuid::Uuid::new_v4()),
+    let client = Client::new(
+        format!("TestClient_{}", uuid::Uuid::new_v4()),
         move |msg| {
             println!("\nReceived message in callback:");
             println!("  Topic: {}", msg.topic());
@@ -31,9 +36,7 @@ fn main() -> polar_mqtt::Result<()> {
             println!("  Retained: {}", msg.is_retained());
 
             if msg.topic() == test_topic_clone {
-                let message = Message::new(msg.topic(), msg.payload().to_vec())
-                    .with_qos(msg.qos())
-                    .with_retain(msg.is_retained());
+                let message = msg.to_owned();
                 if let Err(e) = tx.send(message) {
                     println!("Error sending message through channel: {}", e);
                 }
@@ -101,7 +104,7 @@ fn main() -> polar_mqtt::Result<()> {
     println!("Payload: {}", test_payload);
     println!("Payload bytes: {:02X?}", test_payload.as_bytes());
 
-    let msg = Message::new(&test_topic, test_payload.clone())
+    let msg = Message::new(&test_topic, test_payload.clone())?
         .with_qos(QoS::AtLeastOnce)
         .with_retain(false);
 
@@ -150,7 +153,10 @@ fn main() -> polar_mqtt::Result<()> {
     println!("\nCleaning up...");
     client.unsubscribe(sub_handle)?;
     println!("Unsubscribed successfully");
-    println!("Final connection state: {:?}", client.state());
+
+    let report = client.shutdown(Duration::from_secs(2));
+    println!("Shutdown report: {:?}", report);
+    println!("Final connection state: {:?}", report.final_state);
 
     Ok(())
 }
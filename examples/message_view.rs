@@ -42,7 +42,7 @@ fn main() -> polar_mqtt::Result<()> {
     let client_id = format!("rust-client-{}", Uuid::new_v4());
     println!("Starting MQTT client with ID: {}", client_id);
 
-    let mut client = Client::new(
+    let client = Client::new(
         &client_id,
         {
             let tx = tx.clone();
@@ -0,0 +1,188 @@
+// Embedding the client in a mobile app: a C ABI for Swift, and JNI entry
+// points for Kotlin/Java on Android. Built as a library rather than run:
+//
+//   cargo build --release --example mobile --target aarch64-apple-ios
+//   cargo build --release --example mobile --target aarch64-linux-android
+//
+// (see "Android and iOS" in the README for the toolchain variables). The
+// static library (`libmobile.a`) goes into the Xcode project, with the three
+// `polar_telemetry_*` declarations below in its bridging header:
+//
+//   let client = polar_telemetry_connect("sensor-42", "mqtts://broker.example.com:8883")
+//   polar_telemetry_publish(client, "devices/sensor-42/battery", bytes, bytes.count)
+//   polar_telemetry_disconnect(client)
+//
+// On Android `libmobile.so` goes into `jniLibs/arm64-v8a`, and is called
+// through:
+//
+//   package com.example.telemetry
+//
+//   object Telemetry {
+//       init { System.loadLibrary("mobile") }
+//       external fun connect(clientId: String, uri: String): Long
+//       external fun publish(handle: Long, topic: String, payload: ByteArray): Long
+//       external fun disconnect(handle: Long)
+//   }
+//
+// Native log lines go to logcat on Android, tagged `polar_mqtt`.
+
+use polar_mqtt::{Client, Message, QoS};
+use std::ffi::{c_char, CStr};
+
+fn connect(client_id: &str, uri: &str) -> polar_mqtt::Result<Client> {
+    // Telemetry only publishes; the app watches `Client::state` if it
+    // needs to know about the connection.
+    let client = Client::new(client_id.to_string(), |_| {}, |_| {}, |_, _| {})?;
+    client.connect_uri(uri)?;
+    Ok(client)
+}
+
+fn publish(client: &Client, topic: &str, payload: &[u8]) -> polar_mqtt::Result<i64> {
+    client.publish(&Message::new(topic, payload.to_vec())?.with_qos(QoS::AtLeastOnce))
+}
+
+/// Connects a client, or returns null if it could not.
+///
+/// # Safety
+///
+/// `client_id` and `uri` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn polar_telemetry_connect(
+    client_id: *const c_char,
+    uri: *const c_char,
+) -> *mut Client {
+    if client_id.is_null() || uri.is_null() {
+        return std::ptr::null_mut();
+    }
+    let client_id = CStr::from_ptr(client_id).to_string_lossy();
+    let uri = CStr::from_ptr(uri).to_string_lossy();
+    match connect(&client_id, &uri) {
+        Ok(client) => Box::into_raw(Box::new(client)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Publishes at QoS 1 and returns the message id, or -1 on failure.
+///
+/// # Safety
+///
+/// `client` must come from `polar_telemetry_connect`, `topic` must be a
+/// NUL-terminated string and `payload` must point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn polar_telemetry_publish(
+    client: *const Client,
+    topic: *const c_char,
+    payload: *const u8,
+    length: usize,
+) -> i64 {
+    if client.is_null() || topic.is_null() || (payload.is_null() && length > 0) {
+        return -1;
+    }
+    let topic = CStr::from_ptr(topic).to_string_lossy();
+    let payload = match length {
+        0 => &[][..],
+        _ => std::slice::from_raw_parts(payload, length),
+    };
+    publish(&*client, &topic, payload).unwrap_or(-1)
+}
+
+/// Disconnects and frees the client.
+///
+/// # Safety
+///
+/// `client` must come from `polar_telemetry_connect`, and is not valid
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn polar_telemetry_disconnect(client: *mut Client) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+#[cfg(target_os = "android")]
+mod android {
+    use super::{connect, publish};
+    use polar_mqtt::Client;
+    use std::ffi::{c_char, c_void, CStr};
+
+    type JObject = *mut c_void;
+
+    // The JNIEnv functions used here, at their indices in the JNI function
+    // table. The `jni` crate covers the rest.
+    #[repr(C)]
+    struct Functions {
+        _before: [*const c_void; 169],
+        get_string_utf_chars:
+            unsafe extern "system" fn(*mut Env, JObject, *mut u8) -> *const c_char,
+        release_string_utf_chars: unsafe extern "system" fn(*mut Env, JObject, *const c_char),
+        get_array_length: unsafe extern "system" fn(*mut Env, JObject) -> i32,
+        _between: [*const c_void; 28],
+        get_byte_array_region: unsafe extern "system" fn(*mut Env, JObject, i32, i32, *mut i8),
+    }
+
+    type Env = *const Functions;
+
+    unsafe fn string(env: *mut Env, string: JObject) -> Option<String> {
+        if string.is_null() {
+            return None;
+        }
+        let chars = ((**env).get_string_utf_chars)(env, string, std::ptr::null_mut());
+        if chars.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(chars).to_string_lossy().into_owned();
+        ((**env).release_string_utf_chars)(env, string, chars);
+        Some(owned)
+    }
+
+    unsafe fn bytes(env: *mut Env, array: JObject) -> Vec<u8> {
+        if array.is_null() {
+            return Vec::new();
+        }
+        let length = ((**env).get_array_length)(env, array);
+        let mut bytes = vec![0i8; length.max(0) as usize];
+        ((**env).get_byte_array_region)(env, array, 0, length, bytes.as_mut_ptr());
+        bytes.into_iter().map(|b| b as u8).collect()
+    }
+
+    // Returns 0 if the client could not connect.
+    #[no_mangle]
+    pub unsafe extern "system" fn Java_com_example_telemetry_Telemetry_connect(
+        env: *mut Env,
+        _this: JObject,
+        client_id: JObject,
+        uri: JObject,
+    ) -> i64 {
+        let (Some(client_id), Some(uri)) = (string(env, client_id), string(env, uri)) else {
+            return 0;
+        };
+        match connect(&client_id, &uri) {
+            Ok(client) => Box::into_raw(Box::new(client)) as i64,
+            Err(_) => 0,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "system" fn Java_com_example_telemetry_Telemetry_publish(
+        env: *mut Env,
+        _this: JObject,
+        handle: i64,
+        topic: JObject,
+        payload: JObject,
+    ) -> i64 {
+        let client = handle as *const Client;
+        match (client.is_null(), string(env, topic)) {
+            (false, Some(topic)) => publish(&*client, &topic, &bytes(env, payload)).unwrap_or(-1),
+            _ => -1,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "system" fn Java_com_example_telemetry_Telemetry_disconnect(
+        _env: *mut Env,
+        _this: JObject,
+        handle: i64,
+    ) {
+        super::polar_telemetry_disconnect(handle as *mut Client);
+    }
+}
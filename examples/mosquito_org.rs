@@ -1,21 +1,19 @@
-use polar_mqtt::{Client, QoS};
+use polar_mqtt::{Client, Profile, QoS};
 use std::sync::mpsc;
 use std::time::Duration;
-use uuid::Uuid;
 
 fn main() -> polar_mqtt::Result<()> {
     println!("Initializing MQTT monitor...");
 
     let (stop_tx, stop_rx) = mpsc::channel();
 
-    let client_id = format!("RustMonitor_{}", Uuid::new_v4());
-    println!("Client ID: {}", client_id);
+    let profile = Profile::mosquitto_test();
 
     let (state_tx, state_rx) = mpsc::channel();
     let (error_tx, error_rx) = mpsc::channel();
 
-    let mut client = Client::new(
-        &client_id,
+    let client = Client::new(
+        profile.client_id("RustMonitor"),
         move |msg| {
             let payload = msg.payload();
             let preview =
@@ -58,8 +56,9 @@ fn main() -> polar_mqtt::Result<()> {
         }
     });
 
-    println!("Connecting to test.mosquitto.org...");
-    client.connect("test.mosquitto.org", 1883)?;
+    println!("Client ID: {}", client.client_id());
+    println!("Connecting to {}...", profile.uri());
+    profile.connect(&client)?;
 
     match state_rx.recv_timeout(Duration::from_secs(5)) {
         Ok(state) => println!("Connection state: {:?}", state),
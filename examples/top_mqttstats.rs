@@ -1,9 +1,8 @@
-use polar_mqtt::{Client, QoS};
-use std::collections::HashMap;
+use polar_mqtt::{Client, QoS, Ranking, TopicStats};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use uuid::Uuid;
 
 fn main() -> polar_mqtt::Result<()> {
@@ -14,22 +13,13 @@ fn main() -> polar_mqtt::Result<()> {
 
     let (state_tx, state_rx) = mpsc::channel();
 
-    // Topic statistics: message count and data in bytes
-    let topic_stats = Arc::new(Mutex::new(HashMap::new()));
-    let topic_stats_clone = Arc::clone(&topic_stats);
+    // Topic statistics: message and data rates over the last 10 seconds
+    let topic_stats = Arc::new(TopicStats::new(Duration::from_secs(10)));
     let shutdown_flag = Arc::new(AtomicBool::new(false));
 
-    let mut client = Client::new(
+    let client = Client::new(
         &client_id,
-        move |msg| {
-            let topic = msg.topic().to_string();
-            let payload_size = msg.payload().len();
-
-            let mut stats = topic_stats_clone.lock().unwrap();
-            let entry = stats.entry(topic).or_insert((0, 0));
-            entry.0 += 1; // Increment message count
-            entry.1 += payload_size; // Add payload size
-        },
+        |_| {},
         move |state| {
             let _ = state_tx.send(state);
         },
@@ -37,6 +27,7 @@ fn main() -> polar_mqtt::Result<()> {
             println!("Error occurred: {} ({})", code, msg);
         },
     )?;
+    client.set_topic_stats(Some(Arc::clone(&topic_stats)));
 
     println!("Connecting to test.mosquitto.org...");
     client.connect("test.mosquitto.org", 1883)?;
@@ -59,51 +50,26 @@ fn main() -> polar_mqtt::Result<()> {
     let display_thread = thread::spawn({
         let topic_stats = Arc::clone(&topic_stats);
         let shutdown_flag = Arc::clone(&shutdown_flag);
-        move || {
-            let mut last_check = Instant::now();
-            loop {
-                if shutdown_flag.load(Ordering::SeqCst) {
-                    break;
-                }
-
-                thread::sleep(Duration::from_secs(2));
-                let now = Instant::now();
-                let elapsed_secs = now.duration_since(last_check).as_secs_f64();
-                last_check = now;
-
-                let mut stats = topic_stats.lock().unwrap();
-
-                // Calculate message rate and data rate for each topic
-                let mut rate_stats: Vec<_> = stats
-                    .iter()
-                    .map(|(topic, &(msg_count, data_size))| {
-                        (
-                            topic.clone(),
-                            msg_count as f64 / elapsed_secs,
-                            (data_size as f64 / 1024.0) / elapsed_secs, // Data rate in KiB/s
-                        )
-                    })
-                    .collect();
-
-                // Sort by message rate first, then by data rate
-                rate_stats.sort_by(|a, b| {
-                    b.1.partial_cmp(&a.1)
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                        .then_with(|| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal))
-                });
-
-                // Print top 10 topics
-                println!("Top 10 Topics by Message Rate and Data Rate:");
-                println!("{:<50} {:>10} {:>10}", "Topic", "Msg/s", "KiB/s");
-                println!("──────────────────────────────────────────────────────────────────────");
-                for (topic, msg_rate, data_rate) in rate_stats.iter().take(10) {
-                    println!("{:<50} {:>10.2} {:>10.2}", topic, msg_rate, data_rate);
-                }
-                println!();
-
-                // Reset stats for the next interval
-                stats.clear();
+        move || loop {
+            if shutdown_flag.load(Ordering::SeqCst) {
+                break;
+            }
+
+            thread::sleep(Duration::from_secs(2));
+
+            // Print top 10 topics by message rate
+            println!("Top 10 Topics by Message Rate and Data Rate:");
+            println!("{:<50} {:>10} {:>10}", "Topic", "Msg/s", "KiB/s");
+            println!("──────────────────────────────────────────────────────────────────────");
+            for rate in topic_stats.top_n(10, Ranking::MessageRate) {
+                println!(
+                    "{:<50} {:>10.2} {:>10.2}",
+                    rate.topic,
+                    rate.messages_per_sec,
+                    rate.bytes_per_sec / 1024.0
+                );
             }
+            println!();
         }
     });
 
@@ -12,7 +12,7 @@ fn main() {
     let running_pub = Arc::clone(&running);
     threads.push(thread::spawn(move || {
         println!("Starting publisher");
-        let mut client = Client::new(
+        let client = Client::new(
             "debug-pub",
             |_| {},
             |state| println!("Publisher state: {:?}", state),
@@ -24,8 +24,10 @@ fn main() {
         client.connect("test.mosquitto.org", 1883).unwrap();
         thread::sleep(Duration::from_secs(1));
 
+        let msg = Message::new("test/debug", "test")
+            .unwrap()
+            .with_qos(QoS::AtLeastOnce);
         while running_pub.load(Ordering::Relaxed) {
-            let msg = Message::new("test/debug", "test").with_qos(QoS::AtLeastOnce);
             println!("Publishing message");
             let _ = client.publish(&msg);
             thread::sleep(Duration::from_millis(100));
@@ -36,7 +38,7 @@ fn main() {
     let running_sub = Arc::clone(&running);
     threads.push(thread::spawn(move || {
         println!("Starting subscriber");
-        let mut client = Client::new(
+        let client = Client::new(
             "debug-sub",
             |msg| println!("Received: {:?}", msg.topic()),
             |state| println!("Subscriber state: {:?}", state),
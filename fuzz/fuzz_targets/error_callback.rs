@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use polar_mqtt::fuzzing::CallbackShim;
+
+fuzz_target!(|data: &[u8]| {
+    let shim = CallbackShim::new();
+    shim.error(data);
+    assert_eq!(shim.panics(), 0);
+});
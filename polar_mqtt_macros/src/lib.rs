@@ -0,0 +1,120 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, FnArg, ItemFn, LitStr, ReturnType, Type};
+
+/// Turns a function into a handler that can be registered with
+/// `polar_mqtt::Router::handler`.
+///
+/// The attribute takes a topic filter or template, e.g.
+/// `#[mqtt_handler("devices/{id}/status")]`. Arguments are filled in by type:
+/// `&MessageView` receives the message, `&Params` the template parameters,
+/// and at most one other argument is decoded from the payload through
+/// `polar_mqtt::FromPayload`. The function returns either `()` or
+/// `polar_mqtt::Result<()>`. Like actix-web's route macros, the function is
+/// replaced by a unit struct of the same name.
+#[proc_macro_attribute]
+pub fn mqtt_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let filter = parse_macro_input!(attr as LitStr);
+    let func = parse_macro_input!(item as ItemFn);
+    expand(filter, func)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Arg<'a> {
+    Message,
+    Params,
+    Payload(&'a Type),
+}
+
+fn classify(arg: &FnArg) -> syn::Result<Arg<'_>> {
+    let FnArg::Typed(arg) = arg else {
+        return Err(Error::new(arg.span(), "handlers cannot take `self`"));
+    };
+    if let Type::Reference(reference) = &*arg.ty {
+        if let Type::Path(path) = &*reference.elem {
+            match path.path.segments.last().map(|s| s.ident.to_string()) {
+                Some(ident) if ident == "MessageView" => return Ok(Arg::Message),
+                Some(ident) if ident == "Params" => return Ok(Arg::Params),
+                _ => {}
+            }
+        }
+        return Err(Error::new(
+            arg.ty.span(),
+            "payload arguments must be owned types implementing `FromPayload`",
+        ));
+    }
+    Ok(Arg::Payload(&arg.ty))
+}
+
+fn expand(filter: LitStr, func: ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &func.sig;
+    if sig.asyncness.is_some() {
+        return Err(Error::new(sig.span(), "handlers cannot be async"));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new(
+            sig.generics.span(),
+            "handlers cannot be generic",
+        ));
+    }
+
+    let mut decode = None;
+    let mut call_args = Vec::new();
+    for arg in &sig.inputs {
+        match classify(arg)? {
+            Arg::Message => call_args.push(quote!(msg)),
+            Arg::Params => call_args.push(quote!(params)),
+            Arg::Payload(ty) => {
+                if decode.is_some() {
+                    return Err(Error::new(
+                        arg.span(),
+                        "handlers take at most one payload argument",
+                    ));
+                }
+                let payload = format_ident!("payload");
+                decode = Some(quote! {
+                    let #payload = <#ty as ::polar_mqtt::FromPayload>::from_payload(msg.payload())?;
+                });
+                call_args.push(quote!(#payload));
+            }
+        }
+    }
+
+    let name = &sig.ident;
+    let call = match sig.output {
+        ReturnType::Default => quote! {
+            #name(#(#call_args),*);
+            Ok(())
+        },
+        ReturnType::Type(..) => quote!(#name(#(#call_args),*)),
+    };
+    let vis = &func.vis;
+    let attrs = &func.attrs;
+
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(non_camel_case_types)]
+        #vis struct #name;
+
+        impl ::polar_mqtt::Handler for #name {
+            fn filter(&self) -> &'static str {
+                #filter
+            }
+
+            fn call(
+                &self,
+                msg: &::polar_mqtt::MessageView,
+                params: &::polar_mqtt::Params,
+            ) -> ::polar_mqtt::Result<()> {
+                #func
+
+                let _ = params;
+                #decode
+                #call
+            }
+        }
+    })
+}
@@ -0,0 +1,169 @@
+use crate::error::{Error, Result};
+use crate::topic;
+
+// What a rule of a `TopicAcl` applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Publish,
+    Subscribe,
+    Both,
+}
+
+impl Access {
+    fn includes(self, access: Access) -> bool {
+        self == Access::Both || self == access
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    access: Access,
+    pattern: String,
+}
+
+// Topics a client may publish to and filters it may subscribe to, checked
+// locally before anything is sent, for sandboxing code that is handed a
+// client, such as third-party plugins. Patterns are MQTT filters. Deny
+// rules win over allow rules; what no rule names gets the default.
+//
+// A publish is allowed when its topic matches an allow pattern. A
+// subscription must stay within one: `sensors/+/temp` is allowed by
+// `sensors/#`, `#` is not. It is denied as soon as it could receive a
+// message on a denied topic: with `secret/#` denied, `#` is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicAcl {
+    rules: Vec<Rule>,
+    default_allow: bool,
+}
+
+impl TopicAcl {
+    pub fn allow_all() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: true,
+        }
+    }
+
+    pub fn deny_all() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: false,
+        }
+    }
+
+    pub fn allow(mut self, access: Access, pattern: impl Into<String>) -> Self {
+        self.rules.push(Rule {
+            allow: true,
+            access,
+            pattern: pattern.into(),
+        });
+        self
+    }
+
+    pub fn deny(mut self, access: Access, pattern: impl Into<String>) -> Self {
+        self.rules.push(Rule {
+            allow: false,
+            access,
+            pattern: pattern.into(),
+        });
+        self
+    }
+
+    pub fn check_publish(&self, topic: &str) -> Result<()> {
+        self.check(Access::Publish, topic, topic::matches, topic::matches)
+    }
+
+    pub fn check_subscribe(&self, filter: &str) -> Result<()> {
+        self.check(Access::Subscribe, filter, overlaps, covers)
+    }
+
+    fn check(
+        &self,
+        access: Access,
+        name: &str,
+        denied_by: fn(&str, &str) -> bool,
+        allowed_by: fn(&str, &str) -> bool,
+    ) -> Result<()> {
+        let rules = || {
+            self.rules
+                .iter()
+                .filter(move |rule| rule.access.includes(access))
+        };
+        let denied = rules().any(|rule| !rule.allow && denied_by(&rule.pattern, name));
+        let allowed =
+            self.default_allow || rules().any(|rule| rule.allow && allowed_by(&rule.pattern, name));
+        match !denied && allowed {
+            true => Ok(()),
+            false => Err(Error::AccessDenied(name.to_string())),
+        }
+    }
+}
+
+// Whether every topic `filter` matches is matched by `pattern`.
+fn covers(pattern: &str, filter: &str) -> bool {
+    if filter.starts_with('$') && pattern.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut patterns = pattern.split('/');
+    let mut filters = filter.split('/');
+    loop {
+        match (patterns.next(), filters.next()) {
+            (Some("#"), _) => return true,
+            (Some(_), Some("#")) => return false,
+            (Some("+"), Some(_)) => {}
+            (Some(p), Some(f)) if p == f && f != "+" => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Whether some topic is matched by both filters.
+fn overlaps(a: &str, b: &str) -> bool {
+    if (a.starts_with('$') && b.starts_with(['+', '#']))
+        || (b.starts_with('$') && a.starts_with(['+', '#']))
+    {
+        return false;
+    }
+    let mut a = a.split('/');
+    let mut b = b.split('/');
+    loop {
+        match (a.next(), b.next()) {
+            (Some("#"), _) | (_, Some("#")) => return true,
+            (Some("+"), Some(_)) | (Some(_), Some("+")) => {}
+            (Some(x), Some(y)) if x == y => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny_rules_win_and_subscriptions_must_stay_inside() {
+        let acl = TopicAcl::deny_all()
+            .allow(Access::Both, "plugins/weather/#")
+            .allow(Access::Subscribe, "sensors/#")
+            .deny(Access::Both, "sensors/secret/#");
+
+        assert!(acl.check_publish("plugins/weather/today").is_ok());
+        assert!(acl.check_publish("sensors/1/temp").is_err());
+        assert!(acl.check_subscribe("sensors/1/+").is_ok());
+        assert!(acl.check_subscribe("sensors/secret/key").is_err());
+        // Could receive sensors/secret/temp.
+        assert!(acl.check_subscribe("sensors/+/temp").is_err());
+        assert!(acl.check_subscribe("#").is_err());
+
+        let acl = TopicAcl::allow_all().deny(Access::Publish, "$SYS/#");
+        assert!(acl.check_publish("$SYS/broker/uptime").is_err());
+        assert!(acl.check_subscribe("$SYS/#").is_ok());
+        assert!(matches!(
+            acl.check_publish("$SYS/x"),
+            Err(Error::AccessDenied(topic)) if topic == "$SYS/x"
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+use crate::hierarchy::write_json_str;
+use crate::observer::Observer;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::types::{ConnectionState, QoS};
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+// A record of what a client did, for deployments that must be able to
+// reconstruct who sent what when. Added with `Client::add_audit_log`, it
+// writes one JSON object per line for every connect, state change,
+// subscribe, unsubscribe and publish:
+//
+//   {"time_us":1760000000000000,"client":"meter-7","event":"publish",
+//    "topic":"meters/7","bytes":12,"qos":1,"outcome":"sent","message_id":3}
+//
+// `outcome` is `sent`, `acked`, `failed` (with an `error`), `accepted` or
+// `refused`. Publish acknowledgements are logged by message id. Topics are
+// as sent, namespace included; payloads are never logged. Lines are written
+// on the thread of the event, and a line that cannot be written is lost.
+pub struct AuditLog {
+    client_id: String,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    // Appends to the file at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::to_writer(LineWriter::new(file)))
+    }
+
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            client_id: String::new(),
+            out: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub(crate) fn for_client(mut self, client_id: &str) -> Self {
+        self.client_id = client_id.to_string();
+        self
+    }
+
+    // Writes an event with `fields`, a run of `,"key":value` pairs.
+    fn write(&self, event: &str, fields: impl FnOnce(&mut String)) {
+        let time_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let mut line = format!("{{\"time_us\":{},\"client\":", time_us);
+        write_json_str(&mut line, &self.client_id);
+        line.push_str(",\"event\":");
+        write_json_str(&mut line, event);
+        fields(&mut line);
+        line.push_str("}\n");
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = out.write_all(line.as_bytes());
+    }
+}
+
+fn field(line: &mut String, key: &str, value: &str) {
+    let _ = write!(line, ",\"{}\":", key);
+    write_json_str(line, value);
+}
+
+fn publish_fields(line: &mut String, topic: &str, qos: QoS, bytes: usize, outcome: &str) {
+    field(line, "topic", topic);
+    let _ = write!(line, ",\"bytes\":{},\"qos\":{}", bytes, qos as u8);
+    field(line, "outcome", outcome);
+}
+
+impl Observer for AuditLog {
+    fn on_connect(&self, host: &str, port: u16, error: Option<&Error>) {
+        self.write("connect", |line| {
+            field(line, "host", host);
+            let _ = write!(line, ",\"port\":{}", port);
+            match error {
+                Some(error) => {
+                    field(line, "outcome", "failed");
+                    field(line, "error", &error.to_string());
+                }
+                None => field(line, "outcome", "accepted"),
+            }
+        });
+    }
+
+    fn on_state_change(&self, state: ConnectionState) {
+        self.write("state", |line| {
+            field(line, "state", &format!("{:?}", state).to_lowercase())
+        });
+    }
+
+    fn on_publish_sent(&self, topic: &str, message_id: i64, qos: QoS, bytes: usize) {
+        self.write("publish", |line| {
+            publish_fields(line, topic, qos, bytes, "sent");
+            let _ = write!(line, ",\"message_id\":{}", message_id);
+        });
+    }
+
+    fn on_publish_failed(&self, topic: &str, qos: QoS, bytes: usize, error: &Error) {
+        self.write("publish", |line| {
+            publish_fields(line, topic, qos, bytes, "failed");
+            field(line, "error", &error.to_string());
+        });
+    }
+
+    fn on_puback(&self, message_id: i64, _latency: Option<Duration>) {
+        self.write("publish", |line| {
+            field(line, "outcome", "acked");
+            let _ = write!(line, ",\"message_id\":{}", message_id);
+        });
+    }
+
+    fn on_suback(&self, filter: &str, qos: QoS, accepted: bool) {
+        self.write("subscribe", |line| {
+            field(line, "filter", filter);
+            let _ = write!(line, ",\"qos\":{}", qos as u8);
+            field(
+                line,
+                "outcome",
+                match accepted {
+                    true => "accepted",
+                    false => "refused",
+                },
+            );
+        });
+    }
+
+    fn on_unsuback(&self, filter: &str) {
+        self.write("unsubscribe", |line| {
+            field(line, "filter", filter);
+            field(line, "outcome", "accepted");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acl::{Access, TopicAcl};
+    use crate::backend::fake::Fake;
+    use crate::client::Client;
+    use crate::message::Message;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_records_operations_as_json_lines() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("meter-7", fake, |_| {}, |_| {}, |_, _| {}).unwrap();
+        let out = Shared::default();
+        client.add_audit_log(AuditLog::to_writer(out.clone()));
+
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("cmd/#", QoS::AtLeastOnce).unwrap();
+        let message = Message::new("meters/\"7\"", "12.5").unwrap();
+        client.publish(&message).unwrap();
+        client.set_acl(Some(
+            TopicAcl::allow_all().deny(Access::Publish, "meters/#"),
+        ));
+        assert!(client.publish(&message).is_err());
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.iter().all(|line| line.starts_with("{\"time_us\":")
+            && line.contains(",\"client\":\"meter-7\",")
+            && line.ends_with('}')));
+        assert!(text.contains(
+            "\"event\":\"connect\",\"host\":\"broker\",\"port\":1883,\"outcome\":\"accepted\""
+        ));
+        assert!(text.contains(
+            "\"event\":\"subscribe\",\"filter\":\"cmd/#\",\"qos\":1,\"outcome\":\"accepted\""
+        ));
+        assert!(text.contains(
+            "\"event\":\"publish\",\"topic\":\"meters/\\\"7\\\"\",\"bytes\":4,\"qos\":0,\"outcome\":\"sent\""
+        ));
+        assert!(lines
+            .last()
+            .unwrap()
+            .contains("\"outcome\":\"failed\",\"error\":\"Access denied to meters/\\\"7\\\"\""));
+    }
+}
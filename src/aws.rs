@@ -0,0 +1,126 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::options::{ConnectOptions, TlsOptions};
+use std::time::Duration;
+
+// AWS IoT Core accepts MQTT over TLS on port 443 when the client offers this
+// ALPN protocol, which gets through firewalls that block 8883.
+pub const ALPN_PROTOCOL: &str = "x-amzn-mqtt-ca";
+pub const PORT: u16 = 443;
+
+const MAX_CLIENT_ID_LEN: usize = 128;
+const MIN_KEEP_ALIVE: Duration = Duration::from_secs(30);
+const MAX_KEEP_ALIVE: Duration = Duration::from_secs(1200);
+
+// Connection profile for an AWS IoT Core endpoint, authenticated with a
+// device certificate (mutual TLS).
+#[derive(Debug, Clone)]
+pub struct AwsIot {
+    endpoint: String,
+    tls: TlsOptions,
+}
+
+impl AwsIot {
+    // `endpoint` is the account's data endpoint, e.g.
+    // `xxxxxxxx-ats.iot.eu-west-1.amazonaws.com`; `ca_file` the Amazon root CA.
+    pub fn new(
+        endpoint: impl Into<String>,
+        ca_file: impl Into<String>,
+        cert_file: impl Into<String>,
+        key_file: impl Into<String>,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            tls: TlsOptions::new()
+                .with_ca_file(ca_file)
+                .with_client_cert(cert_file, key_file)
+                .with_alpn(ALPN_PROTOCOL),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    // `options` with this profile's TLS settings, and the keep-alive clamped
+    // to the 30-1200 s range AWS IoT accepts.
+    pub fn options(&self, options: &ConnectOptions) -> ConnectOptions {
+        let keep_alive = options
+            .keep_alive()
+            .unwrap_or(Duration::from_secs(60))
+            .clamp(MIN_KEEP_ALIVE, MAX_KEEP_ALIVE);
+        options
+            .clone()
+            .with_keep_alive(keep_alive)
+            .with_tls(self.tls.clone())
+    }
+
+    // Checks the client id, connects and translates refusals into what they
+    // mean for AWS IoT.
+    pub fn connect(&self, client: &Client, options: &ConnectOptions) -> Result<()> {
+        validate_client_id(client.client_id())?;
+        client
+            .connect_with(&self.endpoint, PORT, &self.options(options))
+            .map_err(refusal)
+    }
+}
+
+// AWS IoT client ids are 1-128 bytes. Restricting them to the characters
+// allowed in thing names keeps them usable in `iot:Connect` policies.
+pub fn validate_client_id(client_id: &str) -> Result<()> {
+    let reason = if client_id.is_empty() {
+        "empty"
+    } else if client_id.len() > MAX_CLIENT_ID_LEN {
+        "longer than 128 bytes"
+    } else if !client_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '-'))
+    {
+        "only letters, digits, ':', '_' and '-' are allowed"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidClientId(format!("{}: {}", client_id, reason)))
+}
+
+// AWS IoT refuses connections it cannot authorize with the generic MQTT
+// codes. It also closes a connection without reason when another client
+// connects with the same id, which surfaces as a lost connection instead.
+fn refusal(error: Error) -> Error {
+    let Error::ConnectionRefused { code, reason } = error else {
+        return error;
+    };
+    let reason = match code {
+        2 => "client id rejected",
+        4 | 5 => {
+            "not authorized: check that the certificate is active and attached \
+             to a policy allowing iot:Connect for this client id"
+        }
+        3 => "service unavailable or connection rate limit exceeded",
+        _ => return Error::ConnectionRefused { code, reason },
+    };
+    Error::ConnectionRefused {
+        code,
+        reason: format!("AWS IoT: {}", reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        assert!(validate_client_id("sensor-42:eu_west").is_ok());
+        for bad in ["", "has space", "a/b", &"x".repeat(129)] {
+            assert!(validate_client_id(bad).is_err(), "{:?}", bad);
+        }
+
+        let profile = AwsIot::new("example-ats.iot.eu-west-1.amazonaws.com", "ca", "c", "k");
+        let options =
+            profile.options(&ConnectOptions::new().with_keep_alive(Duration::from_secs(5)));
+        assert_eq!(options.keep_alive(), Some(MIN_KEEP_ALIVE));
+        let tls = options.tls().unwrap();
+        assert_eq!(tls.alpn_wire().unwrap(), b"\x0ex-amzn-mqtt-ca");
+    }
+}
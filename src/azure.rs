@@ -0,0 +1,284 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::options::{ConnectOptions, TlsOptions};
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::types::{QoS, ShutdownReport};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const API_VERSION: &str = "2021-04-12";
+pub const PORT: u16 = 8883;
+
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(3600);
+
+// A device (or module) connection string from IoT Hub:
+// `HostName=<hub>;DeviceId=<id>;SharedAccessKey=<base64 key>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionString {
+    pub host_name: String,
+    pub device_id: String,
+    pub module_id: Option<String>,
+    pub shared_access_key_name: Option<String>,
+    pub shared_access_key: String,
+}
+
+impl FromStr for ConnectionString {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mut host_name, mut device_id, mut module_id, mut key_name, mut key) =
+            (None, None, None, None, None);
+        for part in s.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=').ok_or(Error::InvalidCredentials)?;
+            let value = Some(value.to_string());
+            match name {
+                "HostName" => host_name = value,
+                "DeviceId" => device_id = value,
+                "ModuleId" => module_id = value,
+                "SharedAccessKeyName" => key_name = value,
+                "SharedAccessKey" => key = value,
+                _ => {}
+            }
+        }
+        Ok(Self {
+            host_name: host_name.ok_or(Error::InvalidCredentials)?,
+            device_id: device_id.ok_or(Error::InvalidCredentials)?,
+            module_id,
+            shared_access_key_name: key_name,
+            shared_access_key: key.ok_or(Error::InvalidCredentials)?,
+        })
+    }
+}
+
+impl ConnectionString {
+    // IoT Hub requires the MQTT client id to be `<device>` or
+    // `<device>/<module>`.
+    pub fn client_id(&self) -> String {
+        match &self.module_id {
+            Some(module_id) => format!("{}/{}", self.device_id, module_id),
+            None => self.device_id.clone(),
+        }
+    }
+
+    pub fn username(&self) -> String {
+        format!(
+            "{}/{}/?api-version={}",
+            self.host_name,
+            self.client_id(),
+            API_VERSION
+        )
+    }
+
+    // Topic for device-to-cloud messages.
+    pub fn events_topic(&self) -> String {
+        match &self.module_id {
+            Some(module_id) => format!(
+                "devices/{}/modules/{}/messages/events/",
+                self.device_id, module_id
+            ),
+            None => format!("devices/{}/messages/events/", self.device_id),
+        }
+    }
+
+    // Filter for cloud-to-device messages.
+    pub fn c2d_filter(&self) -> String {
+        format!("devices/{}/messages/devicebound/#", self.device_id)
+    }
+
+    // A SAS token valid until `expiry`, used as the MQTT password.
+    pub fn sas_token(&self, expiry: SystemTime) -> Result<String> {
+        let mut resource = format!("{}/devices/{}", self.host_name, self.device_id);
+        if let Some(module_id) = &self.module_id {
+            resource = format!("{}/modules/{}", resource, module_id);
+        }
+        let resource = url_encode(&resource);
+        let expiry = expiry
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InvalidCredentials)?
+            .as_secs();
+
+        let key = BASE64
+            .decode(&self.shared_access_key)
+            .map_err(|_| Error::InvalidCredentials)?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&key).map_err(|_| Error::InvalidCredentials)?;
+        mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+        let signature = BASE64.encode(mac.finalize().into_bytes());
+
+        let mut token = format!(
+            "SharedAccessSignature sr={}&sig={}&se={}",
+            resource,
+            url_encode(&signature),
+            expiry
+        );
+        if let Some(key_name) = &self.shared_access_key_name {
+            token.push_str("&skn=");
+            token.push_str(&url_encode(key_name));
+        }
+        Ok(token)
+    }
+}
+
+fn url_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+type C2dHandler = dyn Fn(&MessageView) + Send + Sync;
+
+// An IoT Hub device over an existing `Client`, authenticated with SAS
+// tokens. Tokens expire, and IoT Hub then drops the connection, so the
+// device reconnects with a fresh one once 80% of the TTL has passed. The
+// check runs on every `publish_event`; devices that stay quiet longer than
+// the TTL should call `refresh_if_due` periodically.
+pub struct AzureDevice {
+    client: Client,
+    connection: ConnectionString,
+    token_ttl: Duration,
+    refresh_at: Option<SystemTime>,
+    options: ConnectOptions,
+    c2d_handler: Option<Arc<C2dHandler>>,
+    c2d_handle: Option<i64>,
+}
+
+impl AzureDevice {
+    // The client must have been created with `connection.client_id()`.
+    pub fn new(client: Client, connection: ConnectionString) -> Result<Self> {
+        if client.client_id() != connection.client_id() {
+            return Err(Error::InvalidClientId(format!(
+                "{}: IoT Hub expects {}",
+                client.client_id(),
+                connection.client_id()
+            )));
+        }
+        Ok(Self {
+            client,
+            connection,
+            token_ttl: DEFAULT_TOKEN_TTL,
+            refresh_at: None,
+            options: ConnectOptions::default(),
+            c2d_handler: None,
+            c2d_handle: None,
+        })
+    }
+
+    pub fn with_token_ttl(mut self, ttl: Duration) -> Self {
+        self.token_ttl = ttl;
+        self
+    }
+
+    // Receives cloud-to-device messages, across reconnects.
+    pub fn with_c2d_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.c2d_handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn connection(&self) -> &ConnectionString {
+        &self.connection
+    }
+
+    // Connects over TLS with a new token. TLS settings in `options` are kept,
+    // credentials are replaced.
+    pub fn connect(&mut self, options: &ConnectOptions) -> Result<()> {
+        self.options = options.clone();
+        self.reconnect()
+    }
+
+    // Reconnects with a fresh token if the current one is close to expiry.
+    // Returns whether it did.
+    pub fn refresh_if_due(&mut self) -> Result<bool> {
+        match self.refresh_at {
+            Some(refresh_at) if SystemTime::now() >= refresh_at => {
+                self.client.disconnect()?;
+                self.reconnect()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn publish_event(&mut self, payload: impl Into<Vec<u8>>, qos: QoS) -> Result<i64> {
+        self.refresh_if_due()?;
+        let message = Message::new(self.connection.events_topic(), payload)?.with_qos(qos);
+        self.client.publish(&message)
+    }
+
+    pub fn shutdown(self, flush_timeout: Duration) -> ShutdownReport {
+        self.client.shutdown(flush_timeout)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let now = SystemTime::now();
+        let token = self.connection.sas_token(now + self.token_ttl)?;
+        let options = self
+            .options
+            .clone()
+            .with_tls(self.options.tls().cloned().unwrap_or_else(TlsOptions::new))
+            .with_credentials(self.connection.username(), token);
+        self.client
+            .connect_with(&self.connection.host_name, PORT, &options)?;
+        self.refresh_at = Some(now + self.token_ttl.mul_f64(0.8));
+
+        if let Some(handle) = self.c2d_handle.take() {
+            let _ = self.client.unsubscribe(handle);
+        }
+        if let Some(handler) = &self.c2d_handler {
+            let handler = handler.clone();
+            self.c2d_handle = Some(self.client.subscribe_with(
+                self.connection.c2d_filter().as_str(),
+                QoS::AtLeastOnce,
+                move |msg: &MessageView| handler(msg),
+            )?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sas_token() {
+        let connection: ConnectionString = "HostName=myhub.azure-devices.net;DeviceId=dev1;\
+            SharedAccessKey=MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY="
+            .parse()
+            .unwrap();
+        assert_eq!(
+            connection.username(),
+            "myhub.azure-devices.net/dev1/?api-version=2021-04-12"
+        );
+        assert_eq!(connection.events_topic(), "devices/dev1/messages/events/");
+
+        let token = connection
+            .sas_token(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .unwrap();
+        assert_eq!(
+            token,
+            "SharedAccessSignature sr=myhub.azure-devices.net%2Fdevices%2Fdev1\
+             &sig=wosmA3uJK9T0mdB5CIYn%2BsAVA2ODflnucuvNO5S6mbM%3D&se=1700000000"
+        );
+
+        assert!("HostName=h;DeviceId=d".parse::<ConnectionString>().is_err());
+    }
+}
@@ -0,0 +1,435 @@
+use super::{Backend, Callbacks, Session, TIMEOUT, UNSUPPORTED};
+use crate::bindings;
+use crate::message::Message;
+use crate::time::Instant;
+use crate::topic;
+use crate::types::{ConnectionState, QoS};
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// A broker and bridge in memory, for testing clients without the C++
+// libraries: any broker accepts a connect, a publish reaches every
+// connected session subscribed to its topic, and QoS 1 and 2 publishes are
+// acknowledged at once. Callbacks run on the calling thread, as Paho's
+// synchronous client would run some of them, with no lock held. `cut_off`
+// stands in for a wedged bridge or a half-open connection.
+#[derive(Default)]
+pub(crate) struct Fake {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    sessions: HashMap<usize, FakeSession>,
+    next_session: usize,
+    next_handle: i64,
+    next_message_id: i64,
+    published: Vec<Message>,
+    last_error: String,
+    int_parameters: HashMap<bindings::mqtt_parameter_t, i32>,
+}
+
+struct FakeSession {
+    callbacks: Callbacks,
+    // The callbacks' context, as an address so the fake is Send.
+    context: usize,
+    broker: Option<(String, u16)>,
+    state: ConnectionState,
+    subscriptions: HashMap<i64, (String, QoS)>,
+    // Nothing from the broker reaches it, and no callback runs, until it
+    // connects again.
+    cut_off: bool,
+    // When the broker last sent it anything.
+    heard: Instant,
+}
+
+// A callback to run once the lock is released.
+enum Event {
+    State(Callbacks, usize, ConnectionState),
+    Message(Callbacks, usize, Message),
+    Delivered(Callbacks, usize, i64),
+}
+
+impl Fake {
+    // Everything published through the fake, in order.
+    pub(crate) fn published(&self) -> Vec<Message> {
+        self.lock().published.clone()
+    }
+
+    // The filters subscribed by all sessions, sorted.
+    pub(crate) fn filters(&self) -> Vec<String> {
+        let mut filters: Vec<String> = self
+            .lock()
+            .sessions
+            .values()
+            .flat_map(|session| session.subscriptions.values())
+            .map(|(filter, _)| filter.clone())
+            .collect();
+        filters.sort();
+        filters
+    }
+
+    // The value a session was last given for `param`.
+    pub(crate) fn int_parameter(&self, param: bindings::mqtt_parameter_t) -> Option<i32> {
+        self.lock().int_parameters.get(&param).copied()
+    }
+
+    // Sends a message from elsewhere to the subscribed sessions.
+    pub(crate) fn inject(&self, message: Message) {
+        let events = self.route(&mut self.lock(), message);
+        run(events);
+    }
+
+    // Stops calling back the connected sessions, messages and
+    // acknowledgements included, while their publishes still succeed.
+    pub(crate) fn cut_off(&self) {
+        for session in self.lock().sessions.values_mut() {
+            session.cut_off = session.state == ConnectionState::Connected;
+        }
+    }
+
+    // Loses every connection, as a broker going away would.
+    pub(crate) fn drop_connections(&self) {
+        let events = self
+            .lock()
+            .sessions
+            .values_mut()
+            .filter(|session| session.state == ConnectionState::Connected)
+            .map(|session| {
+                session.state = ConnectionState::Reconnecting;
+                Event::State(
+                    session.callbacks,
+                    session.context,
+                    ConnectionState::Reconnecting,
+                )
+            })
+            .collect();
+        run(events);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Runs `f` on the session, or fails with -1 if there is none.
+    fn with_session<T: From<i8>>(
+        &self,
+        session: Session,
+        f: impl FnOnce(&mut FakeSession) -> T,
+    ) -> T {
+        match self.lock().sessions.get_mut(&(session as usize)) {
+            Some(session) => f(session),
+            None => T::from(-1),
+        }
+    }
+
+    fn route(&self, inner: &mut Inner, message: Message) -> Vec<Event> {
+        let events = inner
+            .sessions
+            .values_mut()
+            .filter(|session| session.state == ConnectionState::Connected && !session.cut_off)
+            .filter(|session| {
+                session
+                    .subscriptions
+                    .values()
+                    .any(|(filter, _)| topic::matches(filter, &message.topic))
+            })
+            .map(|session| {
+                session.heard = Instant::now();
+                Event::Message(session.callbacks, session.context, message.clone())
+            })
+            .collect();
+        inner.published.push(message);
+        events
+    }
+
+    fn failed<T: From<i8>>(&self, inner: &mut Inner, reason: &str) -> T {
+        inner.last_error = reason.to_string();
+        T::from(-1)
+    }
+}
+
+fn run(events: Vec<Event>) {
+    for event in events {
+        let context = |address: usize| address as *mut c_void;
+        unsafe {
+            match event {
+                Event::State(callbacks, address, state) => {
+                    let state = match state {
+                        ConnectionState::Disconnected => {
+                            bindings::mqtt_session_state_t_MQTT_STATE_DISCONNECTED
+                        }
+                        ConnectionState::Connecting => {
+                            bindings::mqtt_session_state_t_MQTT_STATE_CONNECTING
+                        }
+                        ConnectionState::Connected => {
+                            bindings::mqtt_session_state_t_MQTT_STATE_CONNECTED
+                        }
+                        ConnectionState::Reconnecting => {
+                            bindings::mqtt_session_state_t_MQTT_STATE_RECONNECTING
+                        }
+                    };
+                    (callbacks.state)(state, context(address))
+                }
+                Event::Message(callbacks, address, message) => {
+                    let Ok(topic) = CString::new(message.topic.as_str()) else {
+                        continue;
+                    };
+                    let data = bindings::mqtt_message_data_t {
+                        topic: topic.as_ptr(),
+                        payload: message.payload.as_ptr(),
+                        payload_length: message.payload.len(),
+                        qos: message.qos as i32,
+                        retained: message.retained as i32,
+                        message_id: 0,
+                    };
+                    (callbacks.message)(&data, context(address))
+                }
+                Event::Delivered(callbacks, address, message_id) => {
+                    (callbacks.delivery)(message_id, context(address))
+                }
+            }
+        }
+    }
+}
+
+impl Backend for Fake {
+    fn create_session(
+        &self,
+        _client_id: &CStr,
+        callbacks: Callbacks,
+        context: *mut c_void,
+    ) -> Session {
+        let mut inner = self.lock();
+        inner.next_session += 1;
+        let id = inner.next_session;
+        inner.sessions.insert(
+            id,
+            FakeSession {
+                callbacks,
+                context: context as usize,
+                broker: None,
+                state: ConnectionState::Disconnected,
+                subscriptions: HashMap::new(),
+                cut_off: false,
+                heard: Instant::now(),
+            },
+        );
+        // Never dereferenced, only used as a key.
+        std::ptr::null_mut::<u8>().wrapping_add(id).cast()
+    }
+
+    fn destroy_session(&self, session: Session) {
+        self.lock().sessions.remove(&(session as usize));
+    }
+
+    fn set_will(&self, session: Session, _will: Option<(&CStr, &[u8], QoS, bool)>) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_websocket(&self, session: Session, _path: Option<&CStr>) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_credentials(&self, session: Session, _username: &CStr, _password: &CStr) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_tls_certificates(&self, session: Session, _ca: &CStr, _cert: &CStr, _key: &CStr) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_alpn_protocols(&self, session: Session, _protocols: &[u8]) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    // As with every real backend, only the broker host can be sent.
+    fn set_tls_server_name(&self, session: Session, name: &CStr) -> i32 {
+        self.with_session(session, |_| match name.is_empty() {
+            true => 0,
+            false => UNSUPPORTED,
+        })
+    }
+
+    fn set_tls_pkcs12(&self, session: Session, _file: &CStr, _password: &CStr) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_int_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: i32,
+    ) -> i32 {
+        let mut inner = self.lock();
+        if !inner.sessions.contains_key(&(session as usize)) {
+            return -1;
+        }
+        inner.int_parameters.insert(param, value);
+        0
+    }
+
+    fn set_bool_parameter(
+        &self,
+        session: Session,
+        _param: bindings::mqtt_parameter_t,
+        _value: bool,
+    ) -> i32 {
+        self.with_session(session, |_| 0)
+    }
+
+    fn set_broker(&self, session: Session, host: &CStr, port: u16) -> i32 {
+        self.with_session(session, |session| {
+            session.broker = Some((host.to_string_lossy().into_owned(), port));
+            0
+        })
+    }
+
+    fn start(&self, session: Session) -> i32 {
+        let event = {
+            let mut inner = self.lock();
+            let Some(session) = inner.sessions.get_mut(&(session as usize)) else {
+                return -1;
+            };
+            if session.broker.is_none() {
+                return -1;
+            }
+            session.state = ConnectionState::Connected;
+            session.cut_off = false;
+            session.heard = Instant::now();
+            Event::State(
+                session.callbacks,
+                session.context,
+                ConnectionState::Connected,
+            )
+        };
+        run(vec![event]);
+        0
+    }
+
+    fn stop(&self, session: Session) -> i32 {
+        let event = {
+            let mut inner = self.lock();
+            let Some(session) = inner.sessions.get_mut(&(session as usize)) else {
+                return -1;
+            };
+            if session.state == ConnectionState::Disconnected {
+                return 0;
+            }
+            // Paho forgets the subscriptions of a clean session.
+            session.subscriptions.clear();
+            session.state = ConnectionState::Disconnected;
+            if session.cut_off {
+                return 0;
+            }
+            Event::State(
+                session.callbacks,
+                session.context,
+                ConnectionState::Disconnected,
+            )
+        };
+        run(vec![event]);
+        0
+    }
+
+    fn state(&self, session: Session) -> ConnectionState {
+        self.lock()
+            .sessions
+            .get(&(session as usize))
+            .map_or(ConnectionState::Disconnected, |session| session.state)
+    }
+
+    fn idle_ms(&self, session: Session) -> i64 {
+        self.with_session(session, |session| match session.state {
+            ConnectionState::Connected => session.heard.elapsed().as_millis() as i64,
+            _ => -1,
+        })
+    }
+
+    // A session cut off never hears the answer.
+    fn ping(&self, session: Session, _timeout_ms: i32) -> i64 {
+        self.with_session(session, |session| match session.state {
+            ConnectionState::Connected if session.cut_off => TIMEOUT,
+            ConnectionState::Connected => 1,
+            _ => -1,
+        })
+    }
+
+    fn subscribe(&self, session: Session, filter: &CStr, qos: QoS) -> i64 {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let Some(session) = inner.sessions.get_mut(&(session as usize)) else {
+            return self.failed(inner, "no such session");
+        };
+        if session.state != ConnectionState::Connected {
+            return self.failed(inner, "not connected");
+        }
+        inner.next_handle += 1;
+        session.subscriptions.insert(
+            inner.next_handle,
+            (filter.to_string_lossy().into_owned(), qos),
+        );
+        inner.next_handle
+    }
+
+    fn unsubscribe(&self, session: Session, handle: i64) -> i32 {
+        let mut inner = self.lock();
+        let removed = inner
+            .sessions
+            .get_mut(&(session as usize))
+            .and_then(|session| session.subscriptions.remove(&handle));
+        match removed {
+            Some(_) => 0,
+            None => self.failed(&mut inner, "unknown subscription"),
+        }
+    }
+
+    fn publish(
+        &self,
+        session: Session,
+        topic: &CStr,
+        payload: &[u8],
+        qos: QoS,
+        retained: bool,
+    ) -> i64 {
+        let (message_id, events) = {
+            let mut inner = self.lock();
+            let Some(sender) = inner.sessions.get_mut(&(session as usize)) else {
+                return self.failed(&mut inner, "no such session");
+            };
+            if sender.state != ConnectionState::Connected {
+                return self.failed(&mut inner, "not connected");
+            }
+            let (callbacks, context, cut_off) = (sender.callbacks, sender.context, sender.cut_off);
+            if !cut_off && qos != QoS::AtMostOnce {
+                sender.heard = Instant::now();
+            }
+            let message = Message {
+                topic: topic.to_string_lossy().into_owned(),
+                payload: payload.to_vec(),
+                qos,
+                retained,
+                expires_at: None,
+            };
+            let mut events = self.route(&mut inner, message);
+            let message_id = match qos {
+                QoS::AtMostOnce => 0,
+                _ => {
+                    inner.next_message_id += 1;
+                    if !cut_off {
+                        events.push(Event::Delivered(callbacks, context, inner.next_message_id));
+                    }
+                    inner.next_message_id
+                }
+            };
+            (message_id, events)
+        };
+        run(events);
+        message_id
+    }
+
+    fn last_error(&self) -> String {
+        self.lock().last_error.clone()
+    }
+}
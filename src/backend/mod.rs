@@ -0,0 +1,282 @@
+use crate::bindings;
+use crate::types::{ConnectionState, QoS};
+use std::ffi::{c_void, CStr};
+use std::sync::Arc;
+
+#[cfg(test)]
+pub(crate) mod fake;
+// Tested on the host too, where the web backend is not built.
+#[cfg(any(test, target_arch = "wasm32"))]
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+mod packet;
+#[cfg(all(feature = "backend-rust", not(target_arch = "wasm32")))]
+mod rumqtt;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+pub(crate) type Session = *mut bindings::mqtt_session_t;
+
+// The bridge's callbacks into a client, registered with each session.
+#[derive(Clone, Copy)]
+pub(crate) struct Callbacks {
+    pub(crate) message: unsafe extern "C" fn(*const bindings::mqtt_message_data_t, *mut c_void),
+    pub(crate) state: unsafe extern "C" fn(bindings::mqtt_session_state_t, *mut c_void),
+    pub(crate) error: unsafe extern "C" fn(i32, *const std::os::raw::c_char, *mut c_void),
+    pub(crate) delivery: unsafe extern "C" fn(i64, *mut c_void),
+}
+
+// Returned by a setter for an option the backend cannot apply, as
+// `MQTT_ERR_UNSUPPORTED` by the bridge.
+pub(crate) const UNSUPPORTED: i32 = -2;
+
+// Returned by `ping` when the broker does not answer in time, as
+// `MQTT_ERR_TIMEOUT` by the bridge.
+pub(crate) const TIMEOUT: i64 = -3;
+
+// The calls a client makes on a session, so that its bookkeeping can be
+// tested, and run under Miri, against `fake::Fake` instead of the C++
+// bridge. Return values are the bridge's: 0 or a non-negative id on
+// success, `UNSUPPORTED` from a setter the backend cannot honour. The
+// process-wide calls (initialization, log and packet callbacks) are not per
+// client and stay with `bindings`.
+//
+// A session is only passed back to the backend that created it, and not
+// after `destroy_session`.
+pub(crate) trait Backend: Send + Sync {
+    // Null if the session could not be created. `context` is handed to
+    // every callback.
+    fn create_session(
+        &self,
+        client_id: &CStr,
+        callbacks: Callbacks,
+        context: *mut c_void,
+    ) -> Session;
+    fn destroy_session(&self, session: Session);
+
+    // None clears the will.
+    fn set_will(&self, session: Session, will: Option<(&CStr, &[u8], QoS, bool)>) -> i32;
+    // None selects TCP.
+    fn set_websocket(&self, session: Session, path: Option<&CStr>) -> i32;
+    fn set_credentials(&self, session: Session, username: &CStr, password: &CStr) -> i32;
+    fn set_tls_certificates(&self, session: Session, ca: &CStr, cert: &CStr, key: &CStr) -> i32;
+    // Wire format; empty clears it.
+    fn set_alpn_protocols(&self, session: Session, protocols: &[u8]) -> i32;
+    // Empty sends the broker host.
+    fn set_tls_server_name(&self, session: Session, name: &CStr) -> i32;
+    // An empty file clears it.
+    fn set_tls_pkcs12(&self, session: Session, file: &CStr, password: &CStr) -> i32;
+    fn set_int_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: i32,
+    ) -> i32;
+    fn set_bool_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: bool,
+    ) -> i32;
+    fn set_broker(&self, session: Session, host: &CStr, port: u16) -> i32;
+
+    fn start(&self, session: Session) -> i32;
+    fn stop(&self, session: Session) -> i32;
+    fn state(&self, session: Session) -> ConnectionState;
+    fn idle_ms(&self, session: Session) -> i64;
+    fn ping(&self, session: Session, timeout_ms: i32) -> i64;
+
+    fn subscribe(&self, session: Session, filter: &CStr, qos: QoS) -> i64;
+    fn unsubscribe(&self, session: Session, handle: i64) -> i32;
+    fn publish(
+        &self,
+        session: Session,
+        topic: &CStr,
+        payload: &[u8],
+        qos: QoS,
+        retained: bool,
+    ) -> i64;
+
+    // Why the last call on this thread failed; empty if none did.
+    fn last_error(&self) -> String;
+}
+
+// The backend clients use: the C++ bridge, with `backend-rust` the rumqttc
+// one, which needs no native libraries, and on wasm32 the browser's
+// WebSocket.
+#[cfg(native_bridge)]
+pub(crate) fn selected() -> Arc<dyn Backend> {
+    Arc::new(Ffi)
+}
+
+#[cfg(all(feature = "backend-rust", not(target_arch = "wasm32")))]
+pub(crate) fn selected() -> Arc<dyn Backend> {
+    Arc::new(rumqtt::Rumqtt)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn selected() -> Arc<dyn Backend> {
+    Arc::new(web::Web)
+}
+
+// The C++ bridge.
+#[cfg(native_bridge)]
+pub(crate) struct Ffi;
+
+#[cfg(native_bridge)]
+impl Backend for Ffi {
+    fn create_session(
+        &self,
+        client_id: &CStr,
+        callbacks: Callbacks,
+        context: *mut c_void,
+    ) -> Session {
+        unsafe {
+            let session = bindings::mqtt_create_session(
+                client_id.as_ptr(),
+                Some(callbacks.message),
+                Some(callbacks.state),
+                Some(callbacks.error),
+                context,
+            );
+            if !session.is_null() {
+                bindings::mqtt_set_delivery_callback(session, Some(callbacks.delivery));
+            }
+            session
+        }
+    }
+
+    fn destroy_session(&self, session: Session) {
+        unsafe { bindings::mqtt_destroy_session(session) }
+    }
+
+    fn set_will(&self, session: Session, will: Option<(&CStr, &[u8], QoS, bool)>) -> i32 {
+        unsafe {
+            match will {
+                Some((topic, payload, qos, retained)) => bindings::mqtt_set_will(
+                    session,
+                    topic.as_ptr(),
+                    payload.as_ptr(),
+                    payload.len(),
+                    qos.into(),
+                    retained as i32,
+                ),
+                None => bindings::mqtt_set_will(
+                    session,
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    0,
+                    QoS::AtMostOnce.into(),
+                    0,
+                ),
+            }
+        }
+    }
+
+    fn set_websocket(&self, session: Session, path: Option<&CStr>) -> i32 {
+        unsafe {
+            bindings::mqtt_set_websocket(session, path.map_or(std::ptr::null(), CStr::as_ptr))
+        }
+    }
+
+    fn set_credentials(&self, session: Session, username: &CStr, password: &CStr) -> i32 {
+        unsafe { bindings::mqtt_set_credentials(session, username.as_ptr(), password.as_ptr()) }
+    }
+
+    fn set_tls_certificates(&self, session: Session, ca: &CStr, cert: &CStr, key: &CStr) -> i32 {
+        unsafe {
+            bindings::mqtt_set_tls_certificates(session, ca.as_ptr(), cert.as_ptr(), key.as_ptr())
+        }
+    }
+
+    fn set_alpn_protocols(&self, session: Session, protocols: &[u8]) -> i32 {
+        unsafe { bindings::mqtt_set_alpn_protocols(session, protocols.as_ptr(), protocols.len()) }
+    }
+
+    fn set_tls_server_name(&self, session: Session, name: &CStr) -> i32 {
+        unsafe { bindings::mqtt_set_tls_server_name(session, name.as_ptr()) }
+    }
+
+    fn set_tls_pkcs12(&self, session: Session, file: &CStr, password: &CStr) -> i32 {
+        unsafe { bindings::mqtt_set_tls_pkcs12(session, file.as_ptr(), password.as_ptr()) }
+    }
+
+    fn set_int_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: i32,
+    ) -> i32 {
+        unsafe { bindings::mqtt_set_int_parameter(session, param, value) }
+    }
+
+    fn set_bool_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: bool,
+    ) -> i32 {
+        unsafe { bindings::mqtt_set_bool_parameter(session, param, value as i32) }
+    }
+
+    fn set_broker(&self, session: Session, host: &CStr, port: u16) -> i32 {
+        unsafe { bindings::mqtt_set_broker(session, host.as_ptr(), port) }
+    }
+
+    fn start(&self, session: Session) -> i32 {
+        unsafe { bindings::mqtt_session_start(session) }
+    }
+
+    fn stop(&self, session: Session) -> i32 {
+        unsafe { bindings::mqtt_session_stop(session) }
+    }
+
+    fn state(&self, session: Session) -> ConnectionState {
+        unsafe { bindings::mqtt_session_get_state(session) }.into()
+    }
+
+    fn idle_ms(&self, session: Session) -> i64 {
+        unsafe { bindings::mqtt_session_idle_ms(session) }
+    }
+
+    fn ping(&self, session: Session, timeout_ms: i32) -> i64 {
+        unsafe { bindings::mqtt_session_ping(session, timeout_ms) }
+    }
+
+    fn subscribe(&self, session: Session, filter: &CStr, qos: QoS) -> i64 {
+        unsafe { bindings::mqtt_subscribe(session, filter.as_ptr(), qos.into()) }
+    }
+
+    fn unsubscribe(&self, session: Session, handle: i64) -> i32 {
+        unsafe { bindings::mqtt_unsubscribe(session, handle) }
+    }
+
+    fn publish(
+        &self,
+        session: Session,
+        topic: &CStr,
+        payload: &[u8],
+        qos: QoS,
+        retained: bool,
+    ) -> i64 {
+        unsafe {
+            bindings::mqtt_publish(
+                session,
+                topic.as_ptr(),
+                payload.as_ptr(),
+                payload.len(),
+                qos.into(),
+                retained as i32,
+            )
+        }
+    }
+
+    fn last_error(&self) -> String {
+        let message = unsafe { bindings::mqtt_last_error() };
+        match message.is_null() {
+            true => String::new(),
+            false => unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+// MQTT 3.1.1 packets, for the backends that speak the protocol themselves
+// rather than through a client library: just the packets a client sends,
+// and those it receives.
+
+pub(crate) const PINGREQ: [u8; 2] = [0xc0, 0];
+pub(crate) const DISCONNECT: [u8; 2] = [0xe0, 0];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Packet {
+    // The CONNACK return code, 0 if accepted.
+    ConnAck(u8),
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+        retained: bool,
+        // 0 at QoS 0.
+        pkid: u16,
+    },
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    // False if the broker refused any of the filters.
+    SubAck {
+        pkid: u16,
+        accepted: bool,
+    },
+    UnsubAck(u16),
+    PingResp,
+}
+
+// The will: topic, payload, QoS and retained.
+pub(crate) type Will<'a> = (&'a str, &'a [u8], u8, bool);
+
+pub(crate) fn connect(
+    client_id: &str,
+    keep_alive: u16,
+    clean_session: bool,
+    credentials: Option<(&str, &str)>,
+    will: Option<Will<'_>>,
+) -> Vec<u8> {
+    let mut flags = (clean_session as u8) << 1;
+    let mut body = Vec::new();
+    string(&mut body, "MQTT");
+    body.push(4);
+    body.push(0);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    string(&mut body, client_id);
+    if let Some((topic, payload, qos, retained)) = will {
+        flags |= 0x04 | (qos << 3) | ((retained as u8) << 5);
+        string(&mut body, topic);
+        bytes(&mut body, payload);
+    }
+    if let Some((username, password)) = credentials {
+        flags |= 0x80 | 0x40;
+        string(&mut body, username);
+        string(&mut body, password);
+    }
+    body[7] = flags;
+    frame(0x10, &body)
+}
+
+pub(crate) fn publish(topic: &str, payload: &[u8], qos: u8, retained: bool, pkid: u16) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    string(&mut body, topic);
+    if qos > 0 {
+        body.extend_from_slice(&pkid.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    frame(0x30 | (qos << 1) | retained as u8, &body)
+}
+
+pub(crate) fn puback(pkid: u16) -> Vec<u8> {
+    frame(0x40, &pkid.to_be_bytes())
+}
+
+pub(crate) fn pubrec(pkid: u16) -> Vec<u8> {
+    frame(0x50, &pkid.to_be_bytes())
+}
+
+pub(crate) fn pubrel(pkid: u16) -> Vec<u8> {
+    frame(0x62, &pkid.to_be_bytes())
+}
+
+pub(crate) fn pubcomp(pkid: u16) -> Vec<u8> {
+    frame(0x70, &pkid.to_be_bytes())
+}
+
+pub(crate) fn subscribe(pkid: u16, filter: &str, qos: u8) -> Vec<u8> {
+    let mut body = pkid.to_be_bytes().to_vec();
+    string(&mut body, filter);
+    body.push(qos);
+    frame(0x82, &body)
+}
+
+pub(crate) fn unsubscribe(pkid: u16, filter: &str) -> Vec<u8> {
+    let mut body = pkid.to_be_bytes().to_vec();
+    string(&mut body, filter);
+    frame(0xa2, &body)
+}
+
+fn frame(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn string(buffer: &mut Vec<u8>, value: &str) {
+    bytes(buffer, value.as_bytes());
+}
+
+fn bytes(buffer: &mut Vec<u8>, value: &[u8]) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value);
+}
+
+// Splits the bytes received into packets. A packet may arrive across
+// several reads, and a read may hold several packets.
+#[derive(Default)]
+pub(crate) struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub(crate) fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // The next complete packet, if any. An error means the stream is
+    // corrupt and the connection should be dropped.
+    pub(crate) fn next_packet(&mut self) -> Result<Option<Packet>, &'static str> {
+        let Some(&header) = self.buffer.first() else {
+            return Ok(None);
+        };
+        let mut length = 0usize;
+        let mut offset = 1;
+        loop {
+            let Some(&byte) = self.buffer.get(offset) else {
+                return Ok(None);
+            };
+            length += ((byte & 0x7f) as usize) << (7 * (offset - 1));
+            offset += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if offset > 4 {
+                return Err("malformed remaining length");
+            }
+        }
+        if self.buffer.len() < offset + length {
+            return Ok(None);
+        }
+        let packet = decode(header, &self.buffer[offset..offset + length]);
+        self.buffer.drain(..offset + length);
+        packet.map(Some)
+    }
+}
+
+fn decode(header: u8, body: &[u8]) -> Result<Packet, &'static str> {
+    let pkid = |at: usize| {
+        body.get(at..at + 2)
+            .map(|id| u16::from_be_bytes([id[0], id[1]]))
+            .ok_or("truncated packet")
+    };
+    Ok(match header >> 4 {
+        2 => Packet::ConnAck(*body.get(1).ok_or("truncated CONNACK")?),
+        3 => {
+            let qos = (header >> 1) & 0x03;
+            let length = pkid(0)? as usize;
+            let topic = body.get(2..2 + length).ok_or("truncated PUBLISH")?;
+            let topic = String::from_utf8(topic.to_vec()).map_err(|_| "topic is not UTF-8")?;
+            let (pkid, payload) = match qos {
+                0 => (0, 2 + length),
+                _ => (pkid(2 + length)?, 4 + length),
+            };
+            Packet::Publish {
+                topic,
+                payload: body.get(payload..).ok_or("truncated PUBLISH")?.to_vec(),
+                qos,
+                retained: header & 0x01 != 0,
+                pkid,
+            }
+        }
+        4 => Packet::PubAck(pkid(0)?),
+        5 => Packet::PubRec(pkid(0)?),
+        6 => Packet::PubRel(pkid(0)?),
+        7 => Packet::PubComp(pkid(0)?),
+        9 => Packet::SubAck {
+            pkid: pkid(0)?,
+            accepted: body
+                .get(2..)
+                .unwrap_or_default()
+                .iter()
+                .all(|&code| code != 0x80),
+        },
+        11 => Packet::UnsubAck(pkid(0)?),
+        13 => Packet::PingResp,
+        _ => return Err("unexpected packet type"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets_split_across_reads_are_reassembled() {
+        let payload = vec![7u8; 300];
+        let mut stream = publish("sensors/1", &payload, 1, true, 42);
+        stream.extend_from_slice(&[0x90, 3, 0, 9, 0x80]);
+        stream.extend_from_slice(&[0xd0, 0]);
+
+        let mut decoder = Decoder::default();
+        decoder.feed(&stream[..2]);
+        assert_eq!(decoder.next_packet(), Ok(None));
+        decoder.feed(&stream[2..]);
+        assert_eq!(
+            decoder.next_packet(),
+            Ok(Some(Packet::Publish {
+                topic: "sensors/1".to_string(),
+                payload,
+                qos: 1,
+                retained: true,
+                pkid: 42,
+            }))
+        );
+        assert_eq!(
+            decoder.next_packet(),
+            Ok(Some(Packet::SubAck {
+                pkid: 9,
+                accepted: false
+            }))
+        );
+        assert_eq!(decoder.next_packet(), Ok(Some(Packet::PingResp)));
+        assert_eq!(decoder.next_packet(), Ok(None));
+
+        let packet = connect("id", 30, true, Some(("user", "pass")), None);
+        assert_eq!(&packet[..2], &[0x10, 26]);
+        assert_eq!(packet[9], 0xc2);
+    }
+}
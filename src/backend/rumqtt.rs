@@ -0,0 +1,651 @@
+use super::{Backend, Callbacks, Session, UNSUPPORTED};
+use crate::bindings;
+use crate::types::{ConnectionState, QoS};
+use rumqttc::{
+    ConnectionError, Event, LastWill, MqttOptions, NetworkOptions, Outgoing, Packet,
+    SubscribeReasonCode, TlsConfiguration, Transport,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{c_void, CStr, CString};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How long `start` waits for the CONNACK and `subscribe` for the SUBACK,
+// as the bridge's command timeout does.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// The bridge's contract on top of rumqttc, for builds without the C++
+// libraries. Each session runs rumqttc's synchronous client on a thread of
+// its own, which makes the callbacks; `subscribe` waits for the SUBACK, as
+// Paho's synchronous client does. A lost connection is reported as
+// reconnecting and left to failover, as with the bridge.
+//
+// Not supported: `Client::ping`, PKCS#12 bundles, TCP_NODELAY, TCP
+// keepalive settings and the TLS server name, which fail. ALPN is only sent with a CA file: without one the
+// platform's roots are used with rumqttc's default TLS settings.
+pub(crate) struct Rumqtt;
+
+struct RumqttSession {
+    callbacks: Callbacks,
+    // The callbacks' context, as an address so the session is Send.
+    context: usize,
+    config: Mutex<Config>,
+    link: Mutex<Option<Link>>,
+    // Held while queueing a request and sending it, so the ids queued
+    // match the order rumqttc assigns packet ids in.
+    sending: Mutex<()>,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Config {
+    client_id: String,
+    broker: Option<(String, u16)>,
+    credentials: Option<(String, String)>,
+    will: Option<LastWill>,
+    websocket: Option<String>,
+    // CA, certificate and key files, empty when not given.
+    tls: Option<(String, String, String)>,
+    alpn: Vec<Vec<u8>>,
+    keep_alive: Option<Duration>,
+    max_inflight: Option<u16>,
+    send_buffer_size: Option<u32>,
+    receive_buffer_size: Option<u32>,
+}
+
+struct Link {
+    client: rumqttc::Client,
+    thread: JoinHandle<()>,
+}
+
+struct State {
+    connection: ConnectionState,
+    // Set by the network thread once the broker answers the CONNECT:
+    // Ok, or the CONNACK code (-1 if it never came).
+    connack: Option<Result<(), i32>>,
+    stopping: bool,
+    last_packet: Option<Instant>,
+    next_id: i64,
+    // Our ids of publishes and subscribes not yet given a packet id, in
+    // the order they were sent.
+    unsent_publishes: VecDeque<(i64, QoS)>,
+    unsent_subscribes: VecDeque<i64>,
+    publishes: HashMap<u16, i64>,
+    subscribes: HashMap<u16, i64>,
+    // SUBACKs received, by subscription id.
+    subacks: HashMap<i64, bool>,
+    filters: HashMap<i64, String>,
+}
+
+impl RumqttSession {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn config(&self) -> MutexGuard<'_, Config> {
+        self.config.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn context(&self) -> *mut c_void {
+        self.context as *mut c_void
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.lock().connection = state;
+        let state = match state {
+            ConnectionState::Disconnected => bindings::mqtt_session_state_t_MQTT_STATE_DISCONNECTED,
+            ConnectionState::Connecting => bindings::mqtt_session_state_t_MQTT_STATE_CONNECTING,
+            ConnectionState::Connected => bindings::mqtt_session_state_t_MQTT_STATE_CONNECTED,
+            ConnectionState::Reconnecting => bindings::mqtt_session_state_t_MQTT_STATE_RECONNECTING,
+        };
+        unsafe { (self.callbacks.state)(state, self.context()) }
+    }
+
+    fn error(&self, code: i32, message: &str) {
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+        unsafe { (self.callbacks.error)(code, message.as_ptr(), self.context()) }
+    }
+
+    fn options(&self) -> Option<MqttOptions> {
+        let config = self.config();
+        let (host, port) = config.broker.clone()?;
+        let tls = match &config.tls {
+            Some((ca, cert, key)) if !ca.is_empty() => Some(TlsConfiguration::Simple {
+                ca: std::fs::read(ca).ok()?,
+                alpn: (!config.alpn.is_empty()).then(|| config.alpn.clone()),
+                client_auth: match (cert.is_empty(), key.is_empty()) {
+                    (false, false) => Some((std::fs::read(cert).ok()?, std::fs::read(key).ok()?)),
+                    _ => None,
+                },
+            }),
+            Some(_) => Some(TlsConfiguration::default()),
+            None => None,
+        };
+        let (url, transport) = match (&config.websocket, tls) {
+            (Some(path), Some(tls)) => (
+                format!("wss://{}:{}{}", host, port, path),
+                Transport::Wss(tls),
+            ),
+            (Some(path), None) => (format!("ws://{}:{}{}", host, port, path), Transport::Ws),
+            (None, Some(tls)) => (host, Transport::Tls(tls)),
+            (None, None) => (host, Transport::Tcp),
+        };
+
+        let mut options = MqttOptions::new(config.client_id.clone(), url, port);
+        options.set_transport(transport);
+        if let Some((username, password)) = &config.credentials {
+            options.set_credentials(username.clone(), password.clone());
+        }
+        if let Some(will) = &config.will {
+            options.set_last_will(will.clone());
+        }
+        if let Some(keep_alive) = config.keep_alive {
+            options.set_keep_alive(keep_alive);
+        }
+        if let Some(max_inflight) = config.max_inflight {
+            options.set_inflight(max_inflight.max(1));
+        }
+        Some(options)
+    }
+
+    fn network_options(&self) -> NetworkOptions {
+        let config = self.config();
+        let mut options = NetworkOptions::new();
+        if let Some(bytes) = config.send_buffer_size {
+            options.set_tcp_send_buffer_size(bytes);
+        }
+        if let Some(bytes) = config.receive_buffer_size {
+            options.set_tcp_recv_buffer_size(bytes);
+        }
+        options
+    }
+
+    // The network thread: makes the callbacks until the connection ends.
+    fn run(&self, mut connection: rumqttc::Connection) {
+        for event in connection.iter() {
+            if self.lock().stopping {
+                return;
+            }
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    self.connection_failed(error);
+                    return;
+                }
+            };
+            match event {
+                Event::Incoming(packet) => {
+                    self.lock().last_packet = Some(Instant::now());
+                    self.incoming(packet);
+                }
+                Event::Outgoing(Outgoing::Publish(pkid)) => {
+                    let mut state = self.lock();
+                    if let Some((id, qos)) = state.unsent_publishes.pop_front() {
+                        if qos != QoS::AtMostOnce {
+                            state.publishes.insert(pkid, id);
+                        }
+                    }
+                }
+                Event::Outgoing(Outgoing::Subscribe(pkid)) => {
+                    let mut state = self.lock();
+                    if let Some(id) = state.unsent_subscribes.pop_front() {
+                        state.subscribes.insert(pkid, id);
+                    }
+                }
+                Event::Outgoing(_) => {}
+            }
+        }
+    }
+
+    fn incoming(&self, packet: Packet) {
+        match packet {
+            Packet::ConnAck(_) => {
+                // Connected before `start` returns, as with the bridge.
+                self.set_state(ConnectionState::Connected);
+                self.lock().connack = Some(Ok(()));
+                self.changed.notify_all();
+            }
+            Packet::Publish(publish) => {
+                let Ok(topic) = CString::new(publish.topic) else {
+                    return;
+                };
+                let message = bindings::mqtt_message_data_t {
+                    topic: topic.as_ptr(),
+                    payload: publish.payload.as_ptr(),
+                    payload_length: publish.payload.len(),
+                    qos: publish.qos as i32,
+                    retained: publish.retain as i32,
+                    message_id: publish.pkid as i64,
+                };
+                unsafe { (self.callbacks.message)(&message, self.context()) }
+            }
+            Packet::PubAck(ack) => self.delivered(ack.pkid),
+            Packet::PubComp(ack) => self.delivered(ack.pkid),
+            Packet::SubAck(ack) => {
+                let mut state = self.lock();
+                if let Some(id) = state.subscribes.remove(&ack.pkid) {
+                    let accepted = ack
+                        .return_codes
+                        .iter()
+                        .all(|code| !matches!(code, SubscribeReasonCode::Failure));
+                    state.subacks.insert(id, accepted);
+                    self.changed.notify_all();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn delivered(&self, pkid: u16) {
+        let id = self.lock().publishes.remove(&pkid);
+        if let Some(id) = id {
+            unsafe { (self.callbacks.delivery)(id, self.context()) }
+        }
+    }
+
+    fn connection_failed(&self, error: ConnectionError) {
+        let (connected, stopping) = {
+            let state = self.lock();
+            (state.connack.is_some(), state.stopping)
+        };
+        if connected {
+            if !stopping {
+                self.set_state(ConnectionState::Reconnecting);
+            }
+            return;
+        }
+        // Reported before `start` is woken, which reads it back.
+        let code = match &error {
+            ConnectionError::ConnectionRefused(code) => {
+                self.error(*code as i32, &error.to_string());
+                *code as i32
+            }
+            _ => -1,
+        };
+        self.lock().connack = Some(Err(code));
+        self.changed.notify_all();
+    }
+
+    // Waits for `done` to return Some, or for the timeout.
+    fn wait<T>(&self, mut done: impl FnMut(&mut State) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = done(&mut state) {
+                return Some(result);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return None;
+            }
+            state = self
+                .changed
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+fn get<'a>(session: Session) -> &'a RumqttSession {
+    unsafe { &*(session as *const RumqttSession) }
+}
+
+fn failed<T: From<i8>>(reason: &str) -> T {
+    LAST_ERROR.with(|error| *error.borrow_mut() = reason.to_string());
+    T::from(-1)
+}
+
+fn qos(qos: QoS) -> rumqttc::QoS {
+    match qos {
+        QoS::AtMostOnce => rumqttc::QoS::AtMostOnce,
+        QoS::AtLeastOnce => rumqttc::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => rumqttc::QoS::ExactlyOnce,
+    }
+}
+
+impl Backend for Rumqtt {
+    fn create_session(
+        &self,
+        client_id: &CStr,
+        callbacks: Callbacks,
+        context: *mut c_void,
+    ) -> Session {
+        let session = Box::new(RumqttSession {
+            callbacks,
+            context: context as usize,
+            config: Mutex::new(Config {
+                client_id: client_id.to_string_lossy().into_owned(),
+                ..Config::default()
+            }),
+            link: Mutex::new(None),
+            sending: Mutex::new(()),
+            state: Mutex::new(State {
+                connection: ConnectionState::Disconnected,
+                connack: None,
+                stopping: false,
+                last_packet: None,
+                next_id: 1,
+                unsent_publishes: VecDeque::new(),
+                unsent_subscribes: VecDeque::new(),
+                publishes: HashMap::new(),
+                subscribes: HashMap::new(),
+                subacks: HashMap::new(),
+                filters: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+        });
+        Box::into_raw(session).cast()
+    }
+
+    fn destroy_session(&self, session: Session) {
+        self.stop(session);
+        drop(unsafe { Box::from_raw(session as *mut RumqttSession) });
+    }
+
+    fn set_will(&self, session: Session, will: Option<(&CStr, &[u8], QoS, bool)>) -> i32 {
+        get(session).config().will = will.map(|(topic, payload, qos_, retained)| {
+            LastWill::new(
+                topic.to_string_lossy(),
+                payload.to_vec(),
+                qos(qos_),
+                retained,
+            )
+        });
+        0
+    }
+
+    fn set_websocket(&self, session: Session, path: Option<&CStr>) -> i32 {
+        get(session).config().websocket = path.map(|path| path.to_string_lossy().into_owned());
+        0
+    }
+
+    fn set_credentials(&self, session: Session, username: &CStr, password: &CStr) -> i32 {
+        let username = username.to_string_lossy().into_owned();
+        get(session).config().credentials = match username.is_empty() {
+            true => None,
+            false => Some((username, password.to_string_lossy().into_owned())),
+        };
+        0
+    }
+
+    fn set_tls_certificates(&self, session: Session, ca: &CStr, cert: &CStr, key: &CStr) -> i32 {
+        let path = |path: &CStr| path.to_string_lossy().into_owned();
+        get(session).config().tls = Some((path(ca), path(cert), path(key)));
+        0
+    }
+
+    fn set_alpn_protocols(&self, session: Session, mut protocols: &[u8]) -> i32 {
+        let mut alpn = Vec::new();
+        while let Some((&len, rest)) = protocols.split_first() {
+            let Some(name) = rest.get(..len as usize) else {
+                return -1;
+            };
+            alpn.push(name.to_vec());
+            protocols = &rest[len as usize..];
+        }
+        get(session).config().alpn = alpn;
+        0
+    }
+
+    // rumqttc always sends the broker host.
+    fn set_tls_server_name(&self, _session: Session, name: &CStr) -> i32 {
+        match name.is_empty() {
+            true => 0,
+            false => UNSUPPORTED,
+        }
+    }
+
+    fn set_tls_pkcs12(&self, _session: Session, file: &CStr, _password: &CStr) -> i32 {
+        match file.is_empty() {
+            true => 0,
+            false => failed("PKCS#12 bundles need the C++ bridge"),
+        }
+    }
+
+    fn set_int_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: i32,
+    ) -> i32 {
+        let mut config = get(session).config();
+        match param {
+            bindings::mqtt_parameter_t_MQTT_PARAM_KEEP_ALIVE_INTERVAL => {
+                config.keep_alive = Some(Duration::from_secs(value.max(0) as u64))
+            }
+            bindings::mqtt_parameter_t_MQTT_PARAM_MAX_INFLIGHT => {
+                config.max_inflight = Some(value.clamp(0, u16::MAX as i32) as u16)
+            }
+            bindings::mqtt_parameter_t_MQTT_PARAM_SEND_BUFFER_SIZE => {
+                config.send_buffer_size = (value > 0).then_some(value as u32)
+            }
+            bindings::mqtt_parameter_t_MQTT_PARAM_RECEIVE_BUFFER_SIZE => {
+                config.receive_buffer_size = (value > 0).then_some(value as u32)
+            }
+            bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_IDLE
+            | bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_INTERVAL
+            | bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_COUNT => return UNSUPPORTED,
+            _ => {}
+        }
+        0
+    }
+
+    fn set_bool_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: bool,
+    ) -> i32 {
+        if param == bindings::mqtt_parameter_t_MQTT_PARAM_TCP_NODELAY {
+            return UNSUPPORTED;
+        }
+        let mut config = get(session).config();
+        if param == bindings::mqtt_parameter_t_MQTT_PARAM_TLS_ENABLED {
+            config.tls = match (value, config.tls.take()) {
+                (true, tls) => Some(tls.unwrap_or_default()),
+                (false, _) => None,
+            };
+        }
+        0
+    }
+
+    fn set_broker(&self, session: Session, host: &CStr, port: u16) -> i32 {
+        get(session).config().broker = Some((host.to_string_lossy().into_owned(), port));
+        0
+    }
+
+    fn start(&self, handle: Session) -> i32 {
+        let session = get(handle);
+        let Some(options) = session.options() else {
+            return failed("no broker, or unreadable TLS files");
+        };
+        {
+            let mut state = session.lock();
+            state.connack = None;
+            state.stopping = false;
+            state.connection = ConnectionState::Connecting;
+        }
+        let (client, mut connection) = rumqttc::Client::new(options, 64);
+        connection
+            .eventloop
+            .set_network_options(session.network_options());
+        let address = handle as usize;
+        let thread = thread::spawn(move || {
+            // The session outlives the thread: `stop` joins it.
+            let session = unsafe { &*(address as *const RumqttSession) };
+            session.run(connection)
+        });
+        *session.link.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(Link { client, thread });
+
+        match session.wait(|state| state.connack) {
+            Some(Ok(())) => 0,
+            _ => {
+                self.stop(handle);
+                failed("connect failed")
+            }
+        }
+    }
+
+    fn stop(&self, handle: Session) -> i32 {
+        let session = get(handle);
+        let link = session
+            .link
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let Some(link) = link else {
+            return 0;
+        };
+        let was = {
+            let mut state = session.lock();
+            state.stopping = true;
+            state.unsent_publishes.clear();
+            state.unsent_subscribes.clear();
+            state.publishes.clear();
+            state.subscribes.clear();
+            state.filters.clear();
+            state.connection
+        };
+        // The network thread ends on the next event, the DISCONNECT at the
+        // latest.
+        let _ = link.client.disconnect();
+        if link.thread.thread().id() != thread::current().id() {
+            let _ = link.thread.join();
+        }
+        if was != ConnectionState::Disconnected && was != ConnectionState::Connecting {
+            session.set_state(ConnectionState::Disconnected);
+        } else {
+            session.lock().connection = ConnectionState::Disconnected;
+        }
+        0
+    }
+
+    fn state(&self, session: Session) -> ConnectionState {
+        get(session).lock().connection
+    }
+
+    fn idle_ms(&self, session: Session) -> i64 {
+        let state = get(session).lock();
+        match (state.connection, state.last_packet) {
+            (ConnectionState::Connected, Some(at)) => at.elapsed().as_millis() as i64,
+            _ => -1,
+        }
+    }
+
+    fn ping(&self, _session: Session, _timeout_ms: i32) -> i64 {
+        failed("ping is not supported by the Rust backend")
+    }
+
+    fn subscribe(&self, session: Session, filter: &CStr, qos_: QoS) -> i64 {
+        let session = get(session);
+        let id = {
+            let _sending = session
+                .sending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let link = session.link.lock().unwrap_or_else(PoisonError::into_inner);
+            let (Some(link), ConnectionState::Connected) = (&*link, session.lock().connection)
+            else {
+                return failed("not connected");
+            };
+            let id = {
+                let mut state = session.lock();
+                let id = state.next_id;
+                state.next_id += 1;
+                state.unsent_subscribes.push_back(id);
+                id
+            };
+            let filter = filter.to_string_lossy().into_owned();
+            if let Err(error) = link.client.try_subscribe(filter.clone(), qos(qos_)) {
+                session.lock().unsent_subscribes.pop_back();
+                return failed(&error.to_string());
+            }
+            session.lock().filters.insert(id, filter);
+            id
+        };
+        match session.wait(|state| state.subacks.remove(&id)) {
+            Some(true) => id,
+            Some(false) => {
+                session.lock().filters.remove(&id);
+                failed("subscription refused by the broker")
+            }
+            None => {
+                session.lock().filters.remove(&id);
+                failed("timed out waiting for SUBACK")
+            }
+        }
+    }
+
+    fn unsubscribe(&self, session: Session, handle: i64) -> i32 {
+        let session = get(session);
+        let Some(filter) = session.lock().filters.remove(&handle) else {
+            return failed("unknown subscription");
+        };
+        let link = session.link.lock().unwrap_or_else(PoisonError::into_inner);
+        match link
+            .as_ref()
+            .map(|link| link.client.try_unsubscribe(filter))
+        {
+            Some(Ok(())) => 0,
+            Some(Err(error)) => failed(&error.to_string()),
+            None => failed("not connected"),
+        }
+    }
+
+    fn publish(
+        &self,
+        session: Session,
+        topic: &CStr,
+        payload: &[u8],
+        qos_: QoS,
+        retained: bool,
+    ) -> i64 {
+        let session = get(session);
+        let _sending = session
+            .sending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let link = session.link.lock().unwrap_or_else(PoisonError::into_inner);
+        let (Some(link), ConnectionState::Connected) = (&*link, session.lock().connection) else {
+            return failed("not connected");
+        };
+        let id = {
+            let mut state = session.lock();
+            let id = match qos_ {
+                QoS::AtMostOnce => 0,
+                _ => {
+                    state.next_id += 1;
+                    state.next_id
+                }
+            };
+            state.unsent_publishes.push_back((id, qos_));
+            id
+        };
+        let topic = topic.to_string_lossy().into_owned();
+        match link
+            .client
+            .try_publish(topic, qos(qos_), retained, payload.to_vec())
+        {
+            Ok(()) => id,
+            Err(error) => {
+                session.lock().unsent_publishes.pop_back();
+                failed(&error.to_string())
+            }
+        }
+    }
+
+    fn last_error(&self) -> String {
+        LAST_ERROR.with(|error| error.borrow().clone())
+    }
+}
@@ -0,0 +1,613 @@
+use super::packet::{self, Decoder, Packet};
+use super::{Backend, Callbacks, Session, UNSUPPORTED};
+use crate::bindings;
+use crate::types::{ConnectionState, QoS};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_void, CStr, CString};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// The bridge's contract on top of the browser's WebSocket, for wasm32,
+// speaking MQTT 3.1.1 itself. The browser runs everything on one thread
+// and never blocks, so unlike the bridge `start` returns once the socket is
+// opening and the session reports Connected when the CONNACK arrives;
+// `subscribe` returns before the SUBACK, and a refused subscription is
+// reported to the error callback. Publishes and subscribes made before the
+// CONNACK are sent after it. A lost connection is reported as reconnecting,
+// as with the bridge.
+//
+// Not supported: `Client::ping`, the server name, TCP_NODELAY and socket
+// buffer sizes, which fail, the connection timeout and in-flight limits,
+// and TLS files, PKCS#12 bundles and ALPN, which are the browser's
+// business. Keep-alive PINGREQs need a
+// window's timers; elsewhere, as in a worker, the CONNECT asks for no
+// keep-alive. What needs a thread of its own fails, or is left out, above
+// the backend (see `runtime::needs_threads`).
+pub(crate) struct Web;
+
+struct WebSession {
+    callbacks: Callbacks,
+    context: *mut c_void,
+    config: RefCell<Config>,
+    state: RefCell<State>,
+    socket: RefCell<Option<WebSocket>>,
+    // Set once the session is boxed, as they hold its address, and kept
+    // until it is destroyed so that no socket calls a dropped closure.
+    handlers: Option<Handlers>,
+}
+
+struct Handlers {
+    open: Closure<dyn FnMut()>,
+    message: Closure<dyn FnMut(MessageEvent)>,
+    close: Closure<dyn FnMut(CloseEvent)>,
+    keep_alive: Closure<dyn FnMut()>,
+}
+
+struct Config {
+    client_id: String,
+    broker: Option<(String, u16)>,
+    credentials: Option<(String, String)>,
+    will: Option<(String, Vec<u8>, u8, bool)>,
+    websocket: Option<String>,
+    tls: bool,
+    keep_alive: u16,
+    clean_session: bool,
+}
+
+struct State {
+    connection: ConnectionState,
+    // Set once the broker accepts the CONNECT.
+    connected: bool,
+    // `Date.now()` of the last packet received.
+    last_packet: Option<f64>,
+    interval: Option<i32>,
+    decoder: Decoder,
+    // Packets sent before the CONNACK, in order.
+    queued: Vec<Vec<u8>>,
+    next_id: i64,
+    next_pkid: u16,
+    publishes: HashMap<u16, i64>,
+    subscribes: HashMap<u16, i64>,
+    filters: HashMap<i64, String>,
+    // QoS 2 publishes received and not yet released, so that a resent one
+    // is not delivered twice.
+    received: HashSet<u16>,
+}
+
+impl State {
+    // Ids carry on across connections, as the client keys on them.
+    fn new(connection: ConnectionState, next_id: i64, filters: HashMap<i64, String>) -> State {
+        State {
+            connection,
+            connected: false,
+            last_packet: None,
+            interval: None,
+            decoder: Decoder::default(),
+            queued: Vec::new(),
+            next_id,
+            next_pkid: 0,
+            publishes: HashMap::new(),
+            subscribes: HashMap::new(),
+            filters,
+            received: HashSet::new(),
+        }
+    }
+}
+
+impl Handlers {
+    fn new(address: usize) -> Handlers {
+        // The session outlives its handlers: they are dropped with it.
+        let session = move || unsafe { &*(address as *const WebSession) };
+        Handlers {
+            open: Closure::new(move || session().opened()),
+            message: Closure::new(move |event: MessageEvent| session().received(event)),
+            close: Closure::new(move |event: CloseEvent| session().closed(event)),
+            keep_alive: Closure::new(move || {
+                let _ = session().send(packet::PINGREQ.to_vec());
+            }),
+        }
+    }
+}
+
+impl WebSession {
+    fn handlers(&self) -> &Handlers {
+        self.handlers
+            .as_ref()
+            .expect("handlers are set on creation")
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.state.borrow_mut().connection = state;
+        let state = match state {
+            ConnectionState::Disconnected => bindings::mqtt_session_state_t_MQTT_STATE_DISCONNECTED,
+            ConnectionState::Connecting => bindings::mqtt_session_state_t_MQTT_STATE_CONNECTING,
+            ConnectionState::Connected => bindings::mqtt_session_state_t_MQTT_STATE_CONNECTED,
+            ConnectionState::Reconnecting => bindings::mqtt_session_state_t_MQTT_STATE_RECONNECTING,
+        };
+        unsafe { (self.callbacks.state)(state, self.context) }
+    }
+
+    fn error(&self, code: i32, message: &str) {
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+        unsafe { (self.callbacks.error)(code, message.as_ptr(), self.context) }
+    }
+
+    fn url(&self) -> Option<String> {
+        let config = self.config.borrow();
+        let (host, port) = config.broker.as_ref()?;
+        let scheme = if config.tls { "wss" } else { "ws" };
+        let path = config.websocket.as_deref().unwrap_or("/mqtt");
+        Some(format!("{}://{}:{}{}", scheme, host, port, path))
+    }
+
+    // Sends `packet`, or queues it until the CONNACK.
+    fn send(&self, packet: Vec<u8>) -> Result<(), String> {
+        let socket = self.socket.borrow();
+        let Some(socket) = &*socket else {
+            return Err("not connected".to_string());
+        };
+        let mut state = self.state.borrow_mut();
+        if !state.connected {
+            state.queued.push(packet);
+            return Ok(());
+        }
+        drop(state);
+        socket
+            .send_with_u8_array(&packet)
+            .map_err(|error| format!("{:?}", error))
+    }
+
+    // A packet id not in use.
+    fn pkid(state: &mut State) -> u16 {
+        loop {
+            state.next_pkid = state.next_pkid.wrapping_add(1).max(1);
+            let pkid = state.next_pkid;
+            if !state.publishes.contains_key(&pkid) && !state.subscribes.contains_key(&pkid) {
+                return pkid;
+            }
+        }
+    }
+
+    fn opened(&self) {
+        let connect = {
+            let config = self.config.borrow();
+            let keep_alive = match web_sys::window() {
+                Some(_) => config.keep_alive,
+                None => 0,
+            };
+            packet::connect(
+                &config.client_id,
+                keep_alive,
+                config.clean_session,
+                config
+                    .credentials
+                    .as_ref()
+                    .map(|(username, password)| (username.as_str(), password.as_str())),
+                config.will.as_ref().map(|(topic, payload, qos, retained)| {
+                    (topic.as_str(), payload.as_slice(), *qos, *retained)
+                }),
+            )
+        };
+        if let Some(socket) = &*self.socket.borrow() {
+            let _ = socket.send_with_u8_array(&connect);
+        }
+    }
+
+    fn received(&self, event: MessageEvent) {
+        let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() else {
+            return;
+        };
+        let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+        {
+            let mut state = self.state.borrow_mut();
+            state.decoder.feed(&bytes);
+            state.last_packet = Some(js_sys::Date::now());
+        }
+        loop {
+            // Bound on its own so the borrow ends before the packet is handled.
+            let next = self.state.borrow_mut().decoder.next_packet();
+            match next {
+                Ok(Some(packet)) => self.incoming(packet),
+                Ok(None) => return,
+                Err(reason) => {
+                    self.error(-1, reason);
+                    if let Some(socket) = &*self.socket.borrow() {
+                        let _ = socket.close();
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn incoming(&self, packet: Packet) {
+        match packet {
+            Packet::ConnAck(0) => {
+                let queued = {
+                    let mut state = self.state.borrow_mut();
+                    state.connected = true;
+                    std::mem::take(&mut state.queued)
+                };
+                self.start_keep_alive();
+                if let Some(socket) = &*self.socket.borrow() {
+                    for packet in queued {
+                        let _ = socket.send_with_u8_array(&packet);
+                    }
+                }
+                self.set_state(ConnectionState::Connected);
+            }
+            Packet::ConnAck(code) => {
+                self.error(code as i32, "connection refused by the broker");
+                self.close();
+                self.set_state(ConnectionState::Disconnected);
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                qos,
+                retained,
+                pkid,
+            } => {
+                let deliver = match qos {
+                    0 => true,
+                    1 => {
+                        let _ = self.send(packet::puback(pkid));
+                        true
+                    }
+                    _ => {
+                        let first = self.state.borrow_mut().received.insert(pkid);
+                        let _ = self.send(packet::pubrec(pkid));
+                        first
+                    }
+                };
+                let Ok(topic) = CString::new(topic) else {
+                    return;
+                };
+                if deliver {
+                    let message = bindings::mqtt_message_data_t {
+                        topic: topic.as_ptr(),
+                        payload: payload.as_ptr(),
+                        payload_length: payload.len(),
+                        qos: qos as i32,
+                        retained: retained as i32,
+                        message_id: pkid as i64,
+                    };
+                    unsafe { (self.callbacks.message)(&message, self.context) }
+                }
+            }
+            Packet::PubAck(pkid) | Packet::PubComp(pkid) => {
+                let id = self.state.borrow_mut().publishes.remove(&pkid);
+                if let Some(id) = id {
+                    unsafe { (self.callbacks.delivery)(id, self.context) }
+                }
+            }
+            Packet::PubRec(pkid) => {
+                let _ = self.send(packet::pubrel(pkid));
+            }
+            Packet::PubRel(pkid) => {
+                self.state.borrow_mut().received.remove(&pkid);
+                let _ = self.send(packet::pubcomp(pkid));
+            }
+            Packet::SubAck { pkid, accepted } => {
+                let refused = {
+                    let mut state = self.state.borrow_mut();
+                    match state.subscribes.remove(&pkid) {
+                        Some(id) if !accepted => state.filters.remove(&id),
+                        _ => None,
+                    }
+                };
+                if let Some(filter) = refused {
+                    self.error(
+                        -1,
+                        &format!("subscription to {} refused by the broker", filter),
+                    );
+                }
+            }
+            Packet::UnsubAck(_) | Packet::PingResp => {}
+        }
+    }
+
+    fn closed(&self, event: CloseEvent) {
+        let connected = self.state.borrow().connected;
+        self.close();
+        if connected {
+            self.set_state(ConnectionState::Reconnecting);
+        } else {
+            self.error(-1, &format!("connect failed: {}", event.reason()));
+            self.set_state(ConnectionState::Disconnected);
+        }
+    }
+
+    fn start_keep_alive(&self) {
+        let keep_alive = self.config.borrow().keep_alive;
+        let Some(window) = web_sys::window().filter(|_| keep_alive > 0) else {
+            return;
+        };
+        let interval = window
+            .set_interval_with_callback_and_timeout_and_arguments_0(
+                self.handlers().keep_alive.as_ref().unchecked_ref(),
+                keep_alive as i32 * 1000,
+            )
+            .ok();
+        self.state.borrow_mut().interval = interval;
+    }
+
+    // Drops the socket, without calling back. Returns the state it was in.
+    fn close(&self) -> ConnectionState {
+        let socket = self.socket.borrow_mut().take();
+        let (was, interval) = {
+            let mut state = self.state.borrow_mut();
+            let interval = state.interval.take();
+            let filters = std::mem::take(&mut state.filters);
+            *state = State::new(state.connection, state.next_id, filters);
+            (state.connection, interval)
+        };
+        if let (Some(interval), Some(window)) = (interval, web_sys::window()) {
+            window.clear_interval_with_handle(interval);
+        }
+        if let Some(socket) = socket {
+            // A later socket's session must not see this one's events.
+            socket.set_onopen(None);
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+        was
+    }
+}
+
+fn get<'a>(session: Session) -> &'a WebSession {
+    unsafe { &*(session as *const WebSession) }
+}
+
+fn failed<T: From<i8>>(reason: &str) -> T {
+    LAST_ERROR.with(|error| *error.borrow_mut() = reason.to_string());
+    T::from(-1)
+}
+
+impl Backend for Web {
+    fn create_session(
+        &self,
+        client_id: &CStr,
+        callbacks: Callbacks,
+        context: *mut c_void,
+    ) -> Session {
+        let session = Box::into_raw(Box::new(WebSession {
+            callbacks,
+            context,
+            config: RefCell::new(Config {
+                client_id: client_id.to_string_lossy().into_owned(),
+                broker: None,
+                credentials: None,
+                will: None,
+                websocket: None,
+                tls: false,
+                keep_alive: 60,
+                clean_session: true,
+            }),
+            state: RefCell::new(State::new(ConnectionState::Disconnected, 1, HashMap::new())),
+            socket: RefCell::new(None),
+            handlers: None,
+        }));
+        unsafe { (*session).handlers = Some(Handlers::new(session as usize)) };
+        session.cast()
+    }
+
+    fn destroy_session(&self, session: Session) {
+        self.stop(session);
+        drop(unsafe { Box::from_raw(session as *mut WebSession) });
+    }
+
+    fn set_will(&self, session: Session, will: Option<(&CStr, &[u8], QoS, bool)>) -> i32 {
+        get(session).config.borrow_mut().will = will.map(|(topic, payload, qos, retained)| {
+            (
+                topic.to_string_lossy().into_owned(),
+                payload.to_vec(),
+                qos as u8,
+                retained,
+            )
+        });
+        0
+    }
+
+    fn set_websocket(&self, session: Session, path: Option<&CStr>) -> i32 {
+        get(session).config.borrow_mut().websocket =
+            path.map(|path| path.to_string_lossy().into_owned());
+        0
+    }
+
+    fn set_credentials(&self, session: Session, username: &CStr, password: &CStr) -> i32 {
+        let username = username.to_string_lossy().into_owned();
+        get(session).config.borrow_mut().credentials = match username.is_empty() {
+            true => None,
+            false => Some((username, password.to_string_lossy().into_owned())),
+        };
+        0
+    }
+
+    fn set_tls_certificates(&self, session: Session, _ca: &CStr, _cert: &CStr, _key: &CStr) -> i32 {
+        get(session).config.borrow_mut().tls = true;
+        0
+    }
+
+    fn set_alpn_protocols(&self, _session: Session, _protocols: &[u8]) -> i32 {
+        0
+    }
+
+    // The browser sends the host of the URL.
+    fn set_tls_server_name(&self, _session: Session, name: &CStr) -> i32 {
+        match name.is_empty() {
+            true => 0,
+            false => UNSUPPORTED,
+        }
+    }
+
+    fn set_tls_pkcs12(&self, _session: Session, _file: &CStr, _password: &CStr) -> i32 {
+        0
+    }
+
+    fn set_int_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: i32,
+    ) -> i32 {
+        match param {
+            bindings::mqtt_parameter_t_MQTT_PARAM_KEEP_ALIVE_INTERVAL => {
+                get(session).config.borrow_mut().keep_alive = value.clamp(0, u16::MAX as i32) as u16
+            }
+            // The browser owns the socket.
+            bindings::mqtt_parameter_t_MQTT_PARAM_SEND_BUFFER_SIZE
+            | bindings::mqtt_parameter_t_MQTT_PARAM_RECEIVE_BUFFER_SIZE
+            | bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_IDLE
+            | bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_INTERVAL
+            | bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_COUNT => return UNSUPPORTED,
+            _ => {}
+        }
+        0
+    }
+
+    fn set_bool_parameter(
+        &self,
+        session: Session,
+        param: bindings::mqtt_parameter_t,
+        value: bool,
+    ) -> i32 {
+        let mut config = get(session).config.borrow_mut();
+        match param {
+            bindings::mqtt_parameter_t_MQTT_PARAM_TLS_ENABLED => config.tls = value,
+            bindings::mqtt_parameter_t_MQTT_PARAM_CLEAN_SESSION => config.clean_session = value,
+            bindings::mqtt_parameter_t_MQTT_PARAM_TCP_NODELAY => return UNSUPPORTED,
+            _ => {}
+        }
+        0
+    }
+
+    fn set_broker(&self, session: Session, host: &CStr, port: u16) -> i32 {
+        get(session).config.borrow_mut().broker = Some((host.to_string_lossy().into_owned(), port));
+        0
+    }
+
+    fn start(&self, handle: Session) -> i32 {
+        let session = get(handle);
+        let Some(url) = session.url() else {
+            return failed("no broker");
+        };
+        session.close();
+        let socket = match WebSocket::new_with_str(&url, "mqtt") {
+            Ok(socket) => socket,
+            Err(error) => return failed(&format!("{:?}", error)),
+        };
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let handlers = session.handlers();
+        socket.set_onopen(Some(handlers.open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(handlers.message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(handlers.close.as_ref().unchecked_ref()));
+        *session.socket.borrow_mut() = Some(socket);
+        session.state.borrow_mut().connection = ConnectionState::Connecting;
+        0
+    }
+
+    fn stop(&self, handle: Session) -> i32 {
+        let session = get(handle);
+        if session.state.borrow().connected {
+            let _ = session.send(packet::DISCONNECT.to_vec());
+        }
+        session.state.borrow_mut().filters.clear();
+        match session.close() {
+            ConnectionState::Connected | ConnectionState::Reconnecting => {
+                session.set_state(ConnectionState::Disconnected)
+            }
+            _ => session.state.borrow_mut().connection = ConnectionState::Disconnected,
+        }
+        0
+    }
+
+    fn state(&self, session: Session) -> ConnectionState {
+        get(session).state.borrow().connection
+    }
+
+    fn idle_ms(&self, session: Session) -> i64 {
+        let state = get(session).state.borrow();
+        match (state.connection, state.last_packet) {
+            (ConnectionState::Connected, Some(at)) => (js_sys::Date::now() - at) as i64,
+            _ => -1,
+        }
+    }
+
+    fn ping(&self, _session: Session, _timeout_ms: i32) -> i64 {
+        failed("ping is not supported in the browser")
+    }
+
+    fn subscribe(&self, session: Session, filter: &CStr, qos: QoS) -> i64 {
+        let session = get(session);
+        let filter = filter.to_string_lossy().into_owned();
+        let (id, pkid) = {
+            let mut state = session.state.borrow_mut();
+            let id = state.next_id;
+            state.next_id += 1;
+            let pkid = WebSession::pkid(&mut state);
+            (id, pkid)
+        };
+        if let Err(error) = session.send(packet::subscribe(pkid, &filter, qos as u8)) {
+            return failed(&error);
+        }
+        let mut state = session.state.borrow_mut();
+        state.subscribes.insert(pkid, id);
+        state.filters.insert(id, filter);
+        id
+    }
+
+    fn unsubscribe(&self, session: Session, handle: i64) -> i32 {
+        let session = get(session);
+        let Some(filter) = session.state.borrow_mut().filters.remove(&handle) else {
+            return failed("unknown subscription");
+        };
+        let pkid = WebSession::pkid(&mut session.state.borrow_mut());
+        match session.send(packet::unsubscribe(pkid, &filter)) {
+            Ok(()) => 0,
+            Err(error) => failed(&error),
+        }
+    }
+
+    fn publish(
+        &self,
+        session: Session,
+        topic: &CStr,
+        payload: &[u8],
+        qos: QoS,
+        retained: bool,
+    ) -> i64 {
+        let session = get(session);
+        let (id, pkid) = match qos {
+            QoS::AtMostOnce => (0, 0),
+            _ => {
+                let mut state = session.state.borrow_mut();
+                state.next_id += 1;
+                (state.next_id, WebSession::pkid(&mut state))
+            }
+        };
+        let topic = topic.to_string_lossy();
+        if let Err(error) =
+            session.send(packet::publish(&topic, payload, qos as u8, retained, pkid))
+        {
+            return failed(&error);
+        }
+        if id != 0 {
+            session.state.borrow_mut().publishes.insert(pkid, id);
+        }
+        id
+    }
+
+    fn last_error(&self) -> String {
+        LAST_ERROR.with(|error| error.borrow().clone())
+    }
+}
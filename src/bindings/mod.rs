@@ -4,4 +4,15 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 
+#[cfg(all(feature = "bindgen", not(target_arch = "wasm32")))]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+// Without the `bindgen` feature, and so without libclang, and on wasm32,
+// the bindings are the committed ones. Regenerate them whenever the bridge
+// header changes:
+//
+//   bindgen cpp/bridge/include/mqtt_c.hpp \
+//       --allowlist-file cpp/bridge/include/mqtt_c.hpp --no-layout-tests \
+//       -o src/bindings/pregenerated.rs -- -x c++ -std=c++17
+#[cfg(any(not(feature = "bindgen"), target_arch = "wasm32"))]
+include!("pregenerated.rs");
@@ -0,0 +1,233 @@
+/* automatically generated by rust-bindgen 0.70.1 */
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mqtt_session_t {
+    _unused: [u8; 0],
+}
+pub type mqtt_session_handle_t = *mut mqtt_session_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mqtt_message_data_t {
+    pub topic: *const ::std::os::raw::c_char,
+    pub payload: *const u8,
+    pub payload_length: usize,
+    pub qos: i32,
+    pub retained: i32,
+    pub message_id: i64,
+}
+pub const mqtt_qos_t_MQTT_QOS_AT_MOST_ONCE: mqtt_qos_t = 0;
+pub const mqtt_qos_t_MQTT_QOS_AT_LEAST_ONCE: mqtt_qos_t = 1;
+pub const mqtt_qos_t_MQTT_QOS_EXACTLY_ONCE: mqtt_qos_t = 2;
+pub type mqtt_qos_t = ::std::os::raw::c_uint;
+pub const mqtt_session_state_t_MQTT_STATE_DISCONNECTED: mqtt_session_state_t = 0;
+pub const mqtt_session_state_t_MQTT_STATE_CONNECTING: mqtt_session_state_t = 1;
+pub const mqtt_session_state_t_MQTT_STATE_CONNECTED: mqtt_session_state_t = 2;
+pub const mqtt_session_state_t_MQTT_STATE_RECONNECTING: mqtt_session_state_t = 3;
+pub type mqtt_session_state_t = ::std::os::raw::c_uint;
+pub const mqtt_parameter_t_MQTT_PARAM_KEEP_ALIVE_INTERVAL: mqtt_parameter_t = 0;
+pub const mqtt_parameter_t_MQTT_PARAM_CLEAN_SESSION: mqtt_parameter_t = 1;
+pub const mqtt_parameter_t_MQTT_PARAM_CONNECTION_TIMEOUT: mqtt_parameter_t = 2;
+pub const mqtt_parameter_t_MQTT_PARAM_MAX_INFLIGHT: mqtt_parameter_t = 3;
+pub const mqtt_parameter_t_MQTT_PARAM_MAX_QUEUED_MESSAGES: mqtt_parameter_t = 4;
+pub const mqtt_parameter_t_MQTT_PARAM_RECONNECT_DELAY: mqtt_parameter_t = 5;
+pub const mqtt_parameter_t_MQTT_PARAM_TLS_ENABLED: mqtt_parameter_t = 6;
+pub const mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_IDLE: mqtt_parameter_t = 7;
+pub const mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_INTERVAL: mqtt_parameter_t = 8;
+pub const mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_COUNT: mqtt_parameter_t = 9;
+pub const mqtt_parameter_t_MQTT_PARAM_TCP_NODELAY: mqtt_parameter_t = 10;
+pub const mqtt_parameter_t_MQTT_PARAM_SEND_BUFFER_SIZE: mqtt_parameter_t = 11;
+pub const mqtt_parameter_t_MQTT_PARAM_RECEIVE_BUFFER_SIZE: mqtt_parameter_t = 12;
+pub type mqtt_parameter_t = ::std::os::raw::c_uint;
+pub const mqtt_log_level_t_MQTT_LOG_ERROR: mqtt_log_level_t = 0;
+pub const mqtt_log_level_t_MQTT_LOG_WARN: mqtt_log_level_t = 1;
+pub const mqtt_log_level_t_MQTT_LOG_INFO: mqtt_log_level_t = 2;
+pub const mqtt_log_level_t_MQTT_LOG_DEBUG: mqtt_log_level_t = 3;
+pub const mqtt_log_level_t_MQTT_LOG_TRACE: mqtt_log_level_t = 4;
+pub type mqtt_log_level_t = ::std::os::raw::c_uint;
+pub type mqtt_message_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(
+        message: *const mqtt_message_data_t,
+        user_context: *mut ::std::os::raw::c_void,
+    ),
+>;
+pub type mqtt_state_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(
+        new_state: mqtt_session_state_t,
+        user_context: *mut ::std::os::raw::c_void,
+    ),
+>;
+pub type mqtt_error_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(
+        error_code: ::std::os::raw::c_int,
+        message: *const ::std::os::raw::c_char,
+        user_context: *mut ::std::os::raw::c_void,
+    ),
+>;
+pub type mqtt_delivery_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(message_id: i64, user_context: *mut ::std::os::raw::c_void),
+>;
+pub type mqtt_log_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(
+        level: mqtt_log_level_t,
+        message: *const ::std::os::raw::c_char,
+        user_context: *mut ::std::os::raw::c_void,
+    ),
+>;
+pub type mqtt_packet_callback_t = ::std::option::Option<
+    unsafe extern "C" fn(
+        line: *const ::std::os::raw::c_char,
+        user_context: *mut ::std::os::raw::c_void,
+    ),
+>;
+extern "C" {
+    pub fn mqtt_set_int_parameter(
+        session: mqtt_session_handle_t,
+        param: mqtt_parameter_t,
+        value: i32,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_bool_parameter(
+        session: mqtt_session_handle_t,
+        param: mqtt_parameter_t,
+        value: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_broker(
+        session: mqtt_session_handle_t,
+        url: *const ::std::os::raw::c_char,
+        port: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_credentials(
+        session: mqtt_session_handle_t,
+        username: *const ::std::os::raw::c_char,
+        password: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_tls_certificates(
+        session: mqtt_session_handle_t,
+        ca_file: *const ::std::os::raw::c_char,
+        cert_file: *const ::std::os::raw::c_char,
+        key_file: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_alpn_protocols(
+        session: mqtt_session_handle_t,
+        protos: *const u8,
+        length: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_tls_server_name(
+        session: mqtt_session_handle_t,
+        server_name: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_tls_pkcs12(
+        session: mqtt_session_handle_t,
+        file: *const ::std::os::raw::c_char,
+        password: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_websocket(
+        session: mqtt_session_handle_t,
+        path: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_last_error() -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mqtt_set_will(
+        session: mqtt_session_handle_t,
+        topic: *const ::std::os::raw::c_char,
+        payload: *const u8,
+        length: usize,
+        qos: mqtt_qos_t,
+        retain: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_initialize(
+        app_name: *const ::std::os::raw::c_char,
+        app_version: *const ::std::os::raw::c_char,
+        debug: ::std::os::raw::c_int,
+        log_file: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_uninitialize() -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_log_callback(
+        log_cb: mqtt_log_callback_t,
+        user_context: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_set_packet_callback(
+        packet_cb: mqtt_packet_callback_t,
+        user_context: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_create_session(
+        client_id: *const ::std::os::raw::c_char,
+        message_cb: mqtt_message_callback_t,
+        state_cb: mqtt_state_callback_t,
+        error_cb: mqtt_error_callback_t,
+        user_context: *mut ::std::os::raw::c_void,
+    ) -> mqtt_session_handle_t;
+}
+extern "C" {
+    pub fn mqtt_destroy_session(session: mqtt_session_handle_t);
+}
+extern "C" {
+    pub fn mqtt_set_delivery_callback(
+        session: mqtt_session_handle_t,
+        delivery_cb: mqtt_delivery_callback_t,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_session_get_state(session: mqtt_session_handle_t) -> mqtt_session_state_t;
+}
+extern "C" {
+    pub fn mqtt_session_start(session: mqtt_session_handle_t) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_session_stop(session: mqtt_session_handle_t) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_session_idle_ms(session: mqtt_session_handle_t) -> i64;
+}
+extern "C" {
+    pub fn mqtt_session_ping(session: mqtt_session_handle_t, timeout_ms: i32) -> i64;
+}
+extern "C" {
+    pub fn mqtt_subscribe(
+        session: mqtt_session_handle_t,
+        topic: *const ::std::os::raw::c_char,
+        qos: mqtt_qos_t,
+    ) -> i64;
+}
+extern "C" {
+    pub fn mqtt_unsubscribe(session: mqtt_session_handle_t, handle: i64) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mqtt_publish(
+        session: mqtt_session_handle_t,
+        topic: *const ::std::os::raw::c_char,
+        payload: *const u8,
+        length: usize,
+        qos: mqtt_qos_t,
+        retain: ::std::os::raw::c_int,
+    ) -> i64;
+}
@@ -0,0 +1,365 @@
+use crate::client::Client;
+use crate::envelope::{Envelope, CONTENT_TYPE};
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::time::Instant;
+use crate::types::{QoS, TopicFilter};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::Duration;
+
+// The envelope property naming the bridge that forwarded a message, with
+// `LoopProtection::Marker`.
+pub const MARKER: &str = "bridge-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // From the local broker to the remote one.
+    Out,
+    // From the remote broker to the local one.
+    In,
+    Both,
+}
+
+// How a bridge keeps from forwarding back what it forwarded, which the
+// subscriptions of a two-way route would otherwise receive again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopProtection {
+    // Drops a message arriving on a side the bridge published the same
+    // topic and payload to within the window. Payloads are left untouched.
+    Echo(Duration),
+    // Wraps forwarded payloads in an envelope (see `Envelope`) whose
+    // `MARKER` property holds this id, and drops messages carrying it.
+    // Consumers then have to unwrap the envelope.
+    Marker(String),
+}
+
+impl Default for LoopProtection {
+    fn default() -> Self {
+        Self::Echo(Duration::from_secs(10))
+    }
+}
+
+// A filter to mirror between the brokers, in the local broker's terms. The
+// prefix rewrite maps local topics to remote ones: messages going out are
+// rewritten from `from` to `to`, messages coming in from `to` to `from`,
+// and incoming routes subscribe to the rewritten filter.
+#[derive(Debug, Clone)]
+pub struct Route {
+    filter: TopicFilter,
+    direction: Direction,
+    qos: QoS,
+    forward_qos: Option<QoS>,
+    prefix: Option<(String, String)>,
+}
+
+impl Route {
+    pub fn new<T>(filter: T, direction: Direction) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        Ok(Self {
+            filter: filter.try_into()?,
+            direction,
+            qos: QoS::AtLeastOnce,
+            forward_qos: None,
+            prefix: None,
+        })
+    }
+
+    // The QoS the route subscribes with.
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    // Republishes at `qos` rather than at the QoS the message arrived with.
+    pub fn with_forward_qos(mut self, qos: QoS) -> Self {
+        self.forward_qos = Some(qos);
+        self
+    }
+
+    pub fn with_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.prefix = Some((from.into(), to.into()));
+        self
+    }
+
+    fn filter_for(&self, outgoing: bool) -> Result<TopicFilter> {
+        match &self.prefix {
+            Some((from, to)) if !outgoing => {
+                let filter = self.filter.as_str();
+                match filter.strip_prefix(from.as_str()) {
+                    Some(rest) => format!("{}{}", to, rest).try_into(),
+                    None => Ok(self.filter.clone()),
+                }
+            }
+            _ => Ok(self.filter.clone()),
+        }
+    }
+
+    fn rewrite(&self, topic: &str, outgoing: bool) -> String {
+        let Some((from, to)) = &self.prefix else {
+            return topic.to_string();
+        };
+        let (from, to) = if outgoing { (from, to) } else { (to, from) };
+        match topic.strip_prefix(from.as_str()) {
+            Some(rest) => format!("{}{}", to, rest),
+            None => topic.to_string(),
+        }
+    }
+}
+
+// Mirrors routes between two brokers, through a client connected to each.
+// The bridge keeps the clients alive; their subscriptions are removed when
+// it is dropped.
+pub struct Bridge {
+    local: Arc<Client>,
+    remote: Arc<Client>,
+    routes: Vec<Route>,
+    loop_protection: LoopProtection,
+    // (forwarded to the local broker, handle)
+    handles: Vec<(bool, i64)>,
+}
+
+impl Bridge {
+    pub fn new(local: Arc<Client>, remote: Arc<Client>) -> Self {
+        Self {
+            local,
+            remote,
+            routes: Vec::new(),
+            loop_protection: LoopProtection::default(),
+            handles: Vec::new(),
+        }
+    }
+
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    pub fn with_loop_protection(mut self, loop_protection: LoopProtection) -> Self {
+        self.loop_protection = loop_protection;
+        self
+    }
+
+    pub fn local(&self) -> &Arc<Client> {
+        &self.local
+    }
+
+    pub fn remote(&self) -> &Arc<Client> {
+        &self.remote
+    }
+
+    // Subscribes every route on the side it forwards from.
+    pub fn start(mut self) -> Result<Self> {
+        let echoes = [Arc::new(Echoes::default()), Arc::new(Echoes::default())];
+        let routes = std::mem::take(&mut self.routes);
+        for route in &routes {
+            let outgoing = match route.direction {
+                Direction::Out => &[true][..],
+                Direction::In => &[false][..],
+                Direction::Both => &[true, false][..],
+            };
+            for &outgoing in outgoing {
+                if let Err(e) = self.subscribe(route, outgoing, &echoes) {
+                    self.unsubscribe();
+                    return Err(e);
+                }
+            }
+        }
+        self.routes = routes;
+        Ok(self)
+    }
+
+    fn subscribe(
+        &mut self,
+        route: &Route,
+        outgoing: bool,
+        echoes: &[Arc<Echoes>; 2],
+    ) -> Result<()> {
+        let (source, target) = if outgoing {
+            (&self.local, &self.remote)
+        } else {
+            (&self.remote, &self.local)
+        };
+        let forwarder = Forwarder {
+            route: route.clone(),
+            outgoing,
+            loop_protection: self.loop_protection.clone(),
+            // What arrives on the source may be an echo of what was sent to
+            // it, and what is sent to the target is remembered for its side.
+            arrived: echoes[usize::from(!outgoing)].clone(),
+            sent: echoes[usize::from(outgoing)].clone(),
+        };
+        // Weak, as the source client owns the handler.
+        let target: Weak<Client> = Arc::downgrade(target);
+        let handle = source.subscribe_with(route.filter_for(outgoing)?, route.qos, move |msg| {
+            let (Some(target), Some(message)) = (target.upgrade(), forwarder.forward(msg)) else {
+                return;
+            };
+            if target.publish(&message).is_err() {
+                forwarder.sent.forget(&message);
+            }
+        })?;
+        self.handles.push((!outgoing, handle));
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self) {
+        for (to_local, handle) in self.handles.drain(..) {
+            // Subscribed on the side forwarded from.
+            let source = if to_local { &self.remote } else { &self.local };
+            let _ = source.unsubscribe(handle);
+        }
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+// Recently forwarded messages, by hash of topic and payload.
+#[derive(Default)]
+struct Echoes {
+    sent: Mutex<HashMap<u64, Instant>>,
+}
+
+impl Echoes {
+    fn key(message: &Message) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        message.topic.hash(&mut hasher);
+        message.payload.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn remember(&self, message: &Message, window: Duration, now: Instant) {
+        let mut sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        sent.retain(|_, at| now.duration_since(*at) < window);
+        sent.insert(Self::key(message), now);
+    }
+
+    fn forget(&self, message: &Message) {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&Self::key(message));
+    }
+
+    // Consumes the entry, so a later identical message goes through.
+    fn is_echo(&self, message: &Message, window: Duration, now: Instant) -> bool {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&Self::key(message))
+            .is_some_and(|at| now.duration_since(at) < window)
+    }
+}
+
+// One direction of a route.
+struct Forwarder {
+    route: Route,
+    outgoing: bool,
+    loop_protection: LoopProtection,
+    arrived: Arc<Echoes>,
+    sent: Arc<Echoes>,
+}
+
+impl Forwarder {
+    fn forward(&self, msg: &MessageView) -> Option<Message> {
+        self.forward_at(msg, Instant::now())
+    }
+
+    fn forward_at(&self, msg: &MessageView, now: Instant) -> Option<Message> {
+        let received = msg.to_owned();
+        let payload = match &self.loop_protection {
+            LoopProtection::Echo(window) => {
+                if self.arrived.is_echo(&received, *window, now) {
+                    return None;
+                }
+                received.payload
+            }
+            LoopProtection::Marker(id) => {
+                let envelope = Envelope::decode(&received.payload)
+                    .unwrap_or_else(|_| Envelope::new(CONTENT_TYPE, received.payload));
+                if envelope.property(MARKER) == Some(id.as_str()) {
+                    return None;
+                }
+                envelope.with_property(MARKER, id.as_str()).encode().ok()?
+            }
+        };
+        let topic = self.route.rewrite(&received.topic, self.outgoing);
+        let message = Message::new(topic, payload)
+            .ok()?
+            .with_qos(self.route.forward_qos.unwrap_or(received.qos))
+            .with_retain(received.retained);
+        if let LoopProtection::Echo(window) = &self.loop_protection {
+            self.sent.remember(&message, *window, now);
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarders(loop_protection: LoopProtection) -> (Forwarder, Forwarder) {
+        let route = Route::new("site/#", Direction::Both)
+            .unwrap()
+            .with_prefix("site/", "edge1/site/")
+            .with_forward_qos(QoS::AtLeastOnce);
+        let echoes = [Arc::new(Echoes::default()), Arc::new(Echoes::default())];
+        let forwarder = |outgoing: bool| Forwarder {
+            route: route.clone(),
+            outgoing,
+            loop_protection: loop_protection.clone(),
+            arrived: echoes[usize::from(!outgoing)].clone(),
+            sent: echoes[usize::from(outgoing)].clone(),
+        };
+        (forwarder(true), forwarder(false))
+    }
+
+    #[test]
+    fn test_rewrites_and_suppresses_echoes() {
+        let (out, back) = forwarders(LoopProtection::Echo(Duration::from_secs(10)));
+        assert_eq!(
+            out.route.filter_for(false).unwrap().as_str(),
+            "edge1/site/#"
+        );
+        let now = Instant::now();
+        let msg = Message::new("site/temp", "21").unwrap().with_retain(true);
+        let forwarded = out.forward_at(&msg.view(), now).unwrap();
+        assert_eq!(forwarded.topic(), "edge1/site/temp");
+        assert_eq!(forwarded.payload(), b"21");
+        assert_eq!(forwarded.qos(), QoS::AtLeastOnce);
+        assert!(forwarded.is_retained());
+
+        // The remote subscription sees it again and must not send it back,
+        // but a later identical message from the remote side goes through.
+        assert!(back.forward_at(&forwarded.view(), now).is_none());
+        let reply = back.forward_at(&forwarded.view(), now).unwrap();
+        assert_eq!(reply.topic(), "site/temp");
+        assert!(out.forward_at(&reply.view(), now).is_none());
+
+        // Past the window it is no longer taken for an echo.
+        out.forward_at(&msg.view(), now).unwrap();
+        let later = now + Duration::from_secs(11);
+        assert!(back.forward_at(&forwarded.view(), later).is_some());
+    }
+
+    #[test]
+    fn test_marker_drops_own_messages() {
+        let (out, back) = forwarders(LoopProtection::Marker("edge1".to_string()));
+        let msg = Message::new("site/temp", "21").unwrap();
+        let forwarded = out.forward_at(&msg.view(), Instant::now()).unwrap();
+        let envelope = Envelope::decode(forwarded.payload()).unwrap();
+        assert_eq!(envelope.property(MARKER), Some("edge1"));
+        assert_eq!(envelope.payload, b"21");
+        assert!(back.forward_at(&forwarded.view(), Instant::now()).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::error::{Error, Result};
+use crate::types::QoS;
+
+// The largest packet MQTT can frame: a one-byte header and a remaining
+// length of at most 268,435,455 bytes in four bytes.
+pub const PROTOCOL_MAX_PACKET_SIZE: u32 = 268_435_460;
+
+// What the broker is known to accept, set with
+// `ConnectOptions::with_broker_limits`. Every backend connects with MQTT
+// 3.1.1, whose CONNACK advertises none of these, so they are not
+// discovered: they come from the broker's configuration, and default to
+// what the protocol itself allows. Topic aliases are a v5 feature the
+// client never uses, so there is no limit for them. Shared subscriptions are not part of 3.1.1 but most
+// brokers accept `$share/` filters anyway, so they are assumed.
+//
+// The client checks publishes and subscriptions against them and fails
+// without involving the broker, which would otherwise drop the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokerLimits {
+    pub maximum_qos: QoS,
+    pub retain_available: bool,
+    pub maximum_packet_size: u32,
+    pub wildcard_subscriptions: bool,
+    pub shared_subscriptions: bool,
+}
+
+impl Default for BrokerLimits {
+    fn default() -> Self {
+        Self {
+            maximum_qos: QoS::ExactlyOnce,
+            retain_available: true,
+            maximum_packet_size: PROTOCOL_MAX_PACKET_SIZE,
+            wildcard_subscriptions: true,
+            shared_subscriptions: true,
+        }
+    }
+}
+
+fn level(qos: QoS) -> u8 {
+    match qos {
+        QoS::AtMostOnce => 0,
+        QoS::AtLeastOnce => 1,
+        QoS::ExactlyOnce => 2,
+    }
+}
+
+// Size of the PUBLISH packet carrying `payload_len` bytes to `topic`.
+pub(crate) fn publish_packet_size(topic: &str, payload_len: usize, qos: QoS) -> usize {
+    let packet_id = if qos == QoS::AtMostOnce { 0 } else { 2 };
+    let remaining = 2 + topic.len() + packet_id + payload_len;
+    let length_bytes = match remaining {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+    1 + length_bytes + remaining
+}
+
+impl BrokerLimits {
+    // `limit` is the client's own maximum packet size.
+    pub(crate) fn check_publish(
+        &self,
+        topic: &str,
+        payload_len: usize,
+        qos: QoS,
+        retain: bool,
+        limit: Option<u32>,
+    ) -> Result<()> {
+        if level(qos) > level(self.maximum_qos) {
+            return Err(Error::PublicationError(format!(
+                "{:?} is above the broker's maximum QoS, {:?}",
+                qos, self.maximum_qos
+            )));
+        }
+        if retain && !self.retain_available {
+            return Err(Error::PublicationError(
+                "The broker does not retain messages".to_string(),
+            ));
+        }
+        let max = limit.map_or(self.maximum_packet_size, |limit| {
+            limit.min(self.maximum_packet_size)
+        }) as usize;
+        let size = publish_packet_size(topic, payload_len, qos);
+        if size > max {
+            return Err(Error::PacketTooLarge { size, max });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_subscribe(&self, filter: &str) -> Result<()> {
+        if filter.starts_with("$share/") {
+            if !self.shared_subscriptions {
+                return Err(Error::SubscriptionError(
+                    "The broker does not support shared subscriptions".to_string(),
+                ));
+            }
+        } else if !self.wildcard_subscriptions && filter.contains(['+', '#']) {
+            return Err(Error::SubscriptionError(
+                "The broker does not support wildcard subscriptions".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::client::Client;
+    use crate::message::Message;
+    use crate::options::ConnectOptions;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_checks_requests_against_limits() {
+        assert_eq!(publish_packet_size("a/b", 10, QoS::AtMostOnce), 17);
+        assert_eq!(publish_packet_size("a/b", 200, QoS::AtLeastOnce), 210);
+
+        let open = BrokerLimits::default();
+        assert!(open
+            .check_publish("a/b", 1 << 20, QoS::ExactlyOnce, true, None)
+            .is_ok());
+        assert!(matches!(
+            open.check_publish("a/b", 1 << 20, QoS::AtMostOnce, false, Some(1024)),
+            Err(Error::PacketTooLarge { max: 1024, .. })
+        ));
+        assert!(open.check_subscribe("$share/g/a/#").is_ok());
+
+        let limited = BrokerLimits {
+            maximum_qos: QoS::AtLeastOnce,
+            retain_available: false,
+            maximum_packet_size: 64,
+            wildcard_subscriptions: false,
+            shared_subscriptions: false,
+        };
+        assert!(limited
+            .check_publish("a/b", 10, QoS::ExactlyOnce, false, None)
+            .is_err());
+        assert!(limited
+            .check_publish("a/b", 10, QoS::AtLeastOnce, true, None)
+            .is_err());
+        assert!(limited
+            .check_publish("a/b", 57, QoS::AtMostOnce, false, Some(100))
+            .is_ok());
+        assert!(matches!(
+            limited.check_publish("a/b", 58, QoS::AtMostOnce, false, Some(100)),
+            Err(Error::PacketTooLarge { size: 65, max: 64 })
+        ));
+        assert!(limited.check_subscribe("a/+").is_err());
+        assert!(limited.check_subscribe("$share/g/a").is_err());
+        assert!(limited.check_subscribe("a/b").is_ok());
+    }
+
+    #[test]
+    fn test_requests_beyond_the_limits_are_not_sent() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("limits", fake.clone(), |_| {}, |_| {}, |_, _| {});
+        let client = client.unwrap();
+        let limits = BrokerLimits {
+            maximum_qos: QoS::AtLeastOnce,
+            wildcard_subscriptions: false,
+            ..BrokerLimits::default()
+        };
+        let options = ConnectOptions::new().with_broker_limits(limits.clone());
+        client.connect_with("broker", 1883, &options).unwrap();
+        assert_eq!(client.broker_limits(), limits);
+
+        let message = Message::new("a/b", "1").unwrap();
+        assert!(client
+            .publish(&message.clone().with_qos(QoS::ExactlyOnce))
+            .is_err());
+        assert!(client.subscribe("a/#", QoS::AtMostOnce).is_err());
+        assert!(fake.published().is_empty());
+        assert!(fake.filters().is_empty());
+        client.publish(&message.with_qos(QoS::AtLeastOnce)).unwrap();
+        client.subscribe("a/b", QoS::AtMostOnce).unwrap();
+        assert_eq!(fake.published().len(), 1);
+        assert_eq!(fake.filters(), ["a/b"]);
+
+        // A connect without them goes back to what the protocol allows.
+        client.connect("broker", 1883).unwrap();
+        assert_eq!(client.broker_limits(), BrokerLimits::default());
+    }
+}
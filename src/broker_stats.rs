@@ -0,0 +1,229 @@
+use crate::client::Client;
+use crate::error::Result;
+use crate::message::MessageView;
+use crate::time::Instant;
+use crate::types::QoS;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+
+const SYS_FILTER: &str = "$SYS/#";
+
+// Broker counters read from `$SYS` topics. Fields are None until the broker
+// has published them; brokers publish their `$SYS` tree periodically
+// (Mosquitto every `sys_interval`, 10 seconds by default), and not every
+// broker publishes every counter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrokerStats {
+    pub version: Option<String>,
+    pub uptime: Option<Duration>,
+    pub clients_connected: Option<u64>,
+    pub clients_total: Option<u64>,
+    pub subscriptions: Option<u64>,
+    pub retained_messages: Option<u64>,
+    // Totals since the broker started.
+    pub messages_received: Option<u64>,
+    pub messages_sent: Option<u64>,
+    pub bytes_received: Option<u64>,
+    pub bytes_sent: Option<u64>,
+    // Averaged by the broker over its last minute.
+    pub messages_received_per_sec: Option<f64>,
+    pub messages_sent_per_sec: Option<f64>,
+    // Every `$SYS` topic seen, with its last payload, for counters not
+    // parsed above.
+    pub raw: BTreeMap<String, String>,
+    pub updated: Option<Instant>,
+}
+
+impl BrokerStats {
+    // Takes in one `$SYS` message, Mosquitto's `$SYS/broker/...` or EMQX's
+    // `$SYS/brokers/<node>/...`; anything else is only kept in `raw`.
+    pub fn update(&mut self, topic: &str, payload: &str) {
+        self.update_at(topic, payload, Instant::now());
+    }
+
+    fn update_at(&mut self, topic: &str, payload: &str, now: Instant) {
+        let value = payload.trim();
+        self.raw.insert(topic.to_string(), value.to_string());
+        self.updated = Some(now);
+
+        let (key, emqx) = match topic.strip_prefix("$SYS/broker/") {
+            Some(key) => (key, false),
+            None => match topic
+                .strip_prefix("$SYS/brokers/")
+                .and_then(|rest| rest.split_once('/'))
+            {
+                Some((_node, key)) => (key, true),
+                None => return,
+            },
+        };
+        let count = || value.parse::<u64>().ok();
+        match key {
+            "version" => self.version = Some(value.to_string()),
+            "uptime" => self.uptime = parse_uptime(value, emqx),
+            "clients/connected" | "stats/connections/count" => self.clients_connected = count(),
+            "clients/total" => self.clients_total = count(),
+            "subscriptions/count" | "stats/subscriptions/count" => self.subscriptions = count(),
+            "retained messages/count" | "stats/retained/count" => self.retained_messages = count(),
+            "messages/received" | "metrics/messages/received" => self.messages_received = count(),
+            "messages/sent" | "metrics/messages/sent" => self.messages_sent = count(),
+            "bytes/received" | "metrics/bytes/received" => self.bytes_received = count(),
+            "bytes/sent" | "metrics/bytes/sent" => self.bytes_sent = count(),
+            "load/messages/received/1min" => {
+                self.messages_received_per_sec = value.parse::<f64>().ok().map(|v| v / 60.0)
+            }
+            "load/messages/sent/1min" => {
+                self.messages_sent_per_sec = value.parse::<f64>().ok().map(|v| v / 60.0)
+            }
+            _ => {}
+        }
+    }
+}
+
+// Mosquitto says "12345 seconds", EMQX 4 "1 days, 2 hours, 3 minutes, 4
+// seconds" and EMQX 5 a bare number of milliseconds.
+fn parse_uptime(value: &str, emqx: bool) -> Option<Duration> {
+    if let Ok(number) = value.parse::<u64>() {
+        return Some(if emqx {
+            Duration::from_millis(number)
+        } else {
+            Duration::from_secs(number)
+        });
+    }
+    let mut total = 0;
+    for part in value.split(',') {
+        let (number, unit) = part.trim().split_once(' ')?;
+        let number: u64 = number.parse().ok()?;
+        total += number
+            * match unit.trim_end_matches('s') {
+                "second" => 1,
+                "minute" => 60,
+                "hour" => 3600,
+                "day" => 86_400,
+                _ => return None,
+            };
+    }
+    Some(Duration::from_secs(total))
+}
+
+pub type BrokerStatsCallback = dyn Fn(&BrokerStats) + Send + Sync;
+
+// Keeps `BrokerStats` current from the broker's `$SYS` topics: poll it with
+// `stats`, or pass a callback to `attach_with`, called after every update.
+// The subscription is removed when the monitor is dropped.
+pub struct BrokerStatsMonitor {
+    stats: Arc<Mutex<BrokerStats>>,
+    client: Weak<Client>,
+    handle: i64,
+}
+
+impl BrokerStatsMonitor {
+    pub fn attach(client: &Arc<Client>) -> Result<Self> {
+        Self::subscribe(client, None)
+    }
+
+    // `on_update` runs on the message callback thread and must not block.
+    pub fn attach_with<F>(client: &Arc<Client>, on_update: F) -> Result<Self>
+    where
+        F: Fn(&BrokerStats) + Send + Sync + 'static,
+    {
+        Self::subscribe(client, Some(Box::new(on_update)))
+    }
+
+    fn subscribe(
+        client: &Arc<Client>,
+        on_update: Option<Box<BrokerStatsCallback>>,
+    ) -> Result<Self> {
+        let stats = Arc::new(Mutex::new(BrokerStats::default()));
+        let handle = {
+            let stats = stats.clone();
+            client.subscribe_with(SYS_FILTER, QoS::AtMostOnce, move |msg: &MessageView| {
+                let payload = String::from_utf8_lossy(msg.payload());
+                let mut stats = stats.lock().unwrap_or_else(PoisonError::into_inner);
+                stats.update(msg.topic(), &payload);
+                if let Some(on_update) = &on_update {
+                    on_update(&stats);
+                }
+            })?
+        };
+        Ok(Self {
+            stats,
+            client: Arc::downgrade(client),
+            handle,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BrokerStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn stats(&self) -> BrokerStats {
+        self.lock().clone()
+    }
+}
+
+impl Drop for BrokerStatsMonitor {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            let _ = client.unsubscribe(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_mosquitto_and_emqx() {
+        let now = Instant::now();
+        let mut mosquitto = BrokerStats::default();
+        for (topic, payload) in [
+            ("$SYS/broker/version", "mosquitto version 2.0.18"),
+            ("$SYS/broker/uptime", "3600 seconds"),
+            ("$SYS/broker/clients/connected", "42"),
+            ("$SYS/broker/subscriptions/count", "7"),
+            ("$SYS/broker/retained messages/count", "3"),
+            ("$SYS/broker/messages/received", "1000"),
+            ("$SYS/broker/bytes/sent", "65536"),
+            ("$SYS/broker/load/messages/received/1min", "120.00"),
+            ("$SYS/broker/heap/current", "1024"),
+        ] {
+            mosquitto.update_at(topic, payload, now);
+        }
+        assert_eq!(
+            mosquitto.version.as_deref(),
+            Some("mosquitto version 2.0.18")
+        );
+        assert_eq!(mosquitto.uptime, Some(Duration::from_secs(3600)));
+        assert_eq!(mosquitto.clients_connected, Some(42));
+        assert_eq!(mosquitto.subscriptions, Some(7));
+        assert_eq!(mosquitto.retained_messages, Some(3));
+        assert_eq!(mosquitto.messages_received, Some(1000));
+        assert_eq!(mosquitto.bytes_sent, Some(65536));
+        assert_eq!(mosquitto.messages_received_per_sec, Some(2.0));
+        assert_eq!(mosquitto.messages_sent, None);
+        assert_eq!(mosquitto.raw["$SYS/broker/heap/current"], "1024");
+        assert_eq!(mosquitto.updated, Some(now));
+
+        let mut emqx = BrokerStats::default();
+        for (topic, payload) in [
+            (
+                "$SYS/brokers/emqx@127.0.0.1/uptime",
+                "1 days, 2 hours, 3 minutes, 4 seconds",
+            ),
+            ("$SYS/brokers/emqx@127.0.0.1/stats/connections/count", "5"),
+            ("$SYS/brokers/emqx@127.0.0.1/metrics/messages/sent", "99"),
+        ] {
+            emqx.update_at(topic, payload, now);
+        }
+        assert_eq!(emqx.uptime, Some(Duration::from_secs(93_784)));
+        assert_eq!(emqx.clients_connected, Some(5));
+        assert_eq!(emqx.messages_sent, Some(99));
+        assert_eq!(
+            parse_uptime("93784000", true),
+            Some(Duration::from_secs(93_784))
+        );
+        assert_eq!(parse_uptime("soon", false), None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+type Waker = Box<dyn Fn() + Send + Sync>;
+
+// Interrupts blocking calls from another thread: `Client::shutdown_with_cancel`,
+// RPC calls (`RpcClient::with_cancellation`) and publishes waiting for the
+// rate limit (`RateLimit::with_cancellation`) return as soon as it is
+// cancelled, failing with `Error::Cancelled` where they can fail. Clones
+// share the same state. Connecting blocks inside the bridge and cannot be
+// interrupted.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Wakers>,
+}
+
+#[derive(Default)]
+struct Wakers {
+    next_id: u64,
+    registered: HashMap<u64, Waker>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Cancels every operation using the token, now and later.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let wakers = std::mem::take(&mut self.lock().registered);
+        for wake in wakers.into_values() {
+            wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Wakers> {
+        self.inner
+            .wakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Runs `wake` on cancellation, or right away if already cancelled, until
+    // the registration is dropped. Waits use it to notify their condition
+    // variable; `wake` must take that variable's mutex before notifying so
+    // that a waiter checking `is_cancelled` under it cannot miss the wakeup.
+    pub(crate) fn on_cancel<F>(&self, wake: F) -> Registration
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let mut wakers = self.lock();
+        if self.is_cancelled() {
+            drop(wakers);
+            wake();
+            return Registration {
+                token: self.clone(),
+                id: None,
+            };
+        }
+        let id = wakers.next_id;
+        wakers.next_id += 1;
+        wakers.registered.insert(id, Box::new(wake));
+        Registration {
+            token: self.clone(),
+            id: Some(id),
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+// Tokens are equal when they are clones of each other.
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+pub(crate) struct Registration {
+    token: CancellationToken,
+    id: Option<u64>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token.lock().registered.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_cancel_wakes_registered_once() {
+        let token = CancellationToken::new();
+        let woken = Arc::new(AtomicUsize::new(0));
+        let wake = {
+            let woken = woken.clone();
+            move || {
+                woken.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+
+        drop(token.on_cancel(wake.clone()));
+        let _registration = token.on_cancel(wake.clone());
+        let clone = token.clone();
+        assert_eq!(clone, token);
+        assert_ne!(CancellationToken::new(), token);
+
+        clone.cancel();
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(woken.load(Ordering::SeqCst), 1);
+
+        // Registering after cancellation wakes right away.
+        let _late = token.on_cancel(wake);
+        assert_eq!(woken.load(Ordering::SeqCst), 2);
+    }
+}
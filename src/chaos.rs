@@ -0,0 +1,375 @@
+use crate::client::Client;
+use crate::message::{Message, MessageView};
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::types::ConnectionState;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Faults injected into a client, for testing how an application copes with
+// a lossy, jittery network and a flapping connection. Attached with
+// `start`, it acts on inbound messages as they leave the bridge, before the
+// client counts them: each message is, with the configured probabilities,
+// dropped, delayed by up to `max_delay`, held back until after the next
+// one, or delivered twice. Delayed and held messages reach the callbacks
+// from the injector's own thread.
+//
+// With `with_disconnect_every`, the connection is also dropped and made
+// again on that schedule, as the watchdog does with a stale one, so state
+// callbacks and resubscription run. A fixed `with_seed` makes the choices
+// repeatable.
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    drop: f64,
+    delay: f64,
+    max_delay: Duration,
+    reorder: f64,
+    duplicate: f64,
+    disconnect_every: Option<Duration>,
+    seed: Option<u64>,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            delay: 0.0,
+            max_delay: Duration::from_millis(500),
+            reorder: 0.0,
+            duplicate: 0.0,
+            disconnect_every: None,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub dropped: u64,
+    pub delayed: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+    pub disconnects: u64,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Probabilities are clamped to 0..=1.
+    pub fn with_drop(mut self, probability: f64) -> Self {
+        self.drop = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_delay(mut self, probability: f64, max_delay: Duration) -> Self {
+        self.delay = probability.clamp(0.0, 1.0);
+        self.max_delay = max_delay;
+        self
+    }
+
+    // A held message is released after `max_delay` if no other arrives.
+    pub fn with_reorder(mut self, probability: f64) -> Self {
+        self.reorder = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_duplicate(mut self, probability: f64) -> Self {
+        self.duplicate = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_disconnect_every(mut self, interval: Duration) -> Self {
+        self.disconnect_every = Some(interval);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Injects faults into `client` until the handle is dropped, when
+    // messages still delayed or held are delivered.
+    pub fn start(self, client: &Arc<Client>) -> FaultInjectorHandle {
+        let chaos = Arc::new(Chaos::new(self));
+        client.set_chaos(Some(chaos.clone()));
+        let thread = {
+            let (client, chaos) = (Arc::downgrade(client), chaos.clone());
+            thread::spawn(move || run(&client, &chaos))
+        };
+        FaultInjectorHandle {
+            client: Arc::downgrade(client),
+            chaos,
+            thread: Some(thread),
+        }
+    }
+}
+
+pub struct FaultInjectorHandle {
+    client: Weak<Client>,
+    chaos: Arc<Chaos>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FaultInjectorHandle {
+    pub fn stats(&self) -> FaultStats {
+        self.chaos.stats()
+    }
+}
+
+impl Drop for FaultInjectorHandle {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            client.set_chaos(None);
+        }
+        self.chaos.lock().stopped = true;
+        self.chaos.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Delivers delayed and held messages when due, and disconnects on schedule.
+fn run(client: &Weak<Client>, chaos: &Chaos) {
+    let mut next_disconnect = chaos
+        .config
+        .disconnect_every
+        .map(|every| Instant::now() + every);
+    loop {
+        let now = Instant::now();
+        let (due, stopped) = {
+            let mut state = chaos.lock();
+            let stopped = state.stopped;
+            (chaos.release(&mut state, now, stopped), stopped)
+        };
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        for message in &due {
+            client.deliver_injected(message);
+        }
+        if stopped {
+            return;
+        }
+        if let (Some(at), Some(every)) = (next_disconnect, chaos.config.disconnect_every) {
+            if at <= now {
+                if client.state() == ConnectionState::Connected && client.reconnect().is_ok() {
+                    chaos.disconnects.fetch_add(1, Ordering::Relaxed);
+                }
+                next_disconnect = Some(Instant::now() + every);
+            }
+        }
+        drop(client);
+
+        let state = chaos.lock();
+        if state.stopped {
+            continue;
+        }
+        let wake_at = state
+            .delayed
+            .iter()
+            .map(|(at, _)| *at)
+            .chain(state.held.as_ref().map(|(at, _)| *at))
+            .chain(next_disconnect)
+            .min();
+        let timeout = match wake_at {
+            Some(at) => at.saturating_duration_since(Instant::now()),
+            None => Duration::from_secs(1),
+        };
+        let _ = chaos
+            .wake
+            .wait_timeout(state, timeout)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+// The injector's state, shared with the client's message callback.
+pub(crate) struct Chaos {
+    config: FaultInjector,
+    state: Mutex<State>,
+    wake: Condvar,
+    dropped: AtomicU64,
+    delayed: AtomicU64,
+    reordered: AtomicU64,
+    duplicated: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+struct State {
+    rng: u64,
+    delayed: Vec<(Instant, Message)>,
+    held: Option<(Instant, Message)>,
+    stopped: bool,
+}
+
+impl Chaos {
+    fn new(config: FaultInjector) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        Self {
+            config,
+            state: Mutex::new(State {
+                // Xorshift needs a non-zero state.
+                rng: seed | 1,
+                delayed: Vec::new(),
+                held: None,
+                stopped: false,
+            }),
+            wake: Condvar::new(),
+            dropped: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+            reordered: AtomicU64::new(0),
+            duplicated: AtomicU64::new(0),
+            disconnects: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Passes `msg` on to `deliver` zero, one or two times, possibly along
+    // with a message held back earlier.
+    pub(crate) fn intercept(&self, msg: &MessageView, deliver: impl Fn(&MessageView)) {
+        let (duplicate, held) = {
+            let mut state = self.lock();
+            if state.roll(self.config.drop) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            if state.roll(self.config.delay) {
+                let delay = self.config.max_delay.mul_f64(state.next_f64());
+                state.delayed.push((Instant::now() + delay, msg.to_owned()));
+                self.delayed.fetch_add(1, Ordering::Relaxed);
+                self.wake.notify_all();
+                return;
+            }
+            if state.held.is_none() && state.roll(self.config.reorder) {
+                state.held = Some((Instant::now() + self.config.max_delay, msg.to_owned()));
+                self.reordered.fetch_add(1, Ordering::Relaxed);
+                self.wake.notify_all();
+                return;
+            }
+            (
+                state.roll(self.config.duplicate),
+                state.held.take().map(|(_, held)| held),
+            )
+        };
+
+        deliver(msg);
+        if duplicate {
+            self.duplicated.fetch_add(1, Ordering::Relaxed);
+            deliver(msg);
+        }
+        if let Some(held) = held {
+            deliver(&held.view());
+        }
+    }
+
+    // Takes the messages due at `now`, or all of them.
+    fn release(&self, state: &mut State, now: Instant, all: bool) -> Vec<Message> {
+        let mut due = Vec::new();
+        if state.held.as_ref().is_some_and(|(at, _)| all || *at <= now) {
+            due.extend(state.held.take().map(|(_, held)| held));
+        }
+        state.delayed.sort_by_key(|(at, _)| *at);
+        let ready = match all {
+            true => state.delayed.len(),
+            false => state.delayed.partition_point(|(at, _)| *at <= now),
+        };
+        due.extend(state.delayed.drain(..ready).map(|(_, message)| message));
+        due
+    }
+
+    fn stats(&self) -> FaultStats {
+        FaultStats {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            delayed: self.delayed.load(Ordering::Relaxed),
+            reordered: self.reordered.load(Ordering::Relaxed),
+            duplicated: self.duplicated.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl State {
+    // Xorshift64*: plenty for picking faults.
+    fn next_u64(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_through(chaos: &Chaos, payloads: &[&str]) -> Vec<String> {
+        let seen = Mutex::new(Vec::new());
+        for payload in payloads {
+            let message = Message::new("a/b", *payload).unwrap();
+            chaos.intercept(&message.view(), |msg| {
+                seen.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(msg.payload()).into_owned())
+            });
+        }
+        seen.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_injects_each_fault() {
+        let dropping = Chaos::new(FaultInjector::new().with_drop(1.0));
+        assert!(run_through(&dropping, &["1", "2"]).is_empty());
+        assert_eq!(dropping.stats().dropped, 2);
+
+        let duplicating = Chaos::new(FaultInjector::new().with_duplicate(1.0));
+        assert_eq!(run_through(&duplicating, &["1"]), ["1", "1"]);
+
+        // Each held message comes out after the next one.
+        let reordering = Chaos::new(FaultInjector::new().with_reorder(1.0));
+        assert_eq!(run_through(&reordering, &["1", "2", "3"]), ["2", "1"]);
+        let mut state = reordering.lock();
+        let rest = reordering.release(&mut state, Instant::now(), true);
+        assert_eq!(rest[0].payload(), b"3");
+
+        let delaying = Chaos::new(
+            FaultInjector::new()
+                .with_delay(1.0, Duration::from_millis(100))
+                .with_seed(7),
+        );
+        assert!(run_through(&delaying, &["1", "2"]).is_empty());
+        let mut state = delaying.lock();
+        let start = Instant::now();
+        assert!(delaying
+            .release(&mut state, start - Duration::from_secs(1), false)
+            .is_empty());
+        let due = delaying.release(&mut state, start + Duration::from_millis(100), false);
+        assert_eq!(due.len(), 2);
+        assert_eq!(delaying.stats().delayed, 2);
+
+        // The same seed makes the same choices.
+        let choices = |seed| {
+            let chaos = Chaos::new(FaultInjector::new().with_drop(0.5).with_seed(seed));
+            run_through(&chaos, &["1", "2", "3", "4", "5", "6", "7", "8"])
+        };
+        assert_eq!(choices(42), choices(42));
+    }
+}
@@ -1,30 +1,221 @@
+use crate::acl::TopicAcl;
+use crate::audit::AuditLog;
+use crate::backend::{self, Backend, Callbacks, Session};
 use crate::bindings;
+use crate::broker_limits::BrokerLimits;
+use crate::cancel::CancellationToken;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::client_id::{hostname, ClientId, ResolvedClientId};
+#[cfg(feature = "cloudevents")]
+use crate::cloudevents::{Event, Mode as EventMode};
+use crate::codec::Codec;
+#[cfg(feature = "prost")]
+use crate::codec::ProstCodec;
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+use crate::compression::{self, Compression};
+use crate::dead_letter::{DeadLetterReason, DeadLetters};
+use crate::dedup::{InboundDedup, PublishDedup};
+#[cfg(feature = "encryption")]
+use crate::encryption::Encryption;
 use crate::error::{Error, Result};
+use crate::failover::Failover;
+use crate::inbound::{Inbound, InboundQueue};
+use crate::inflight::Inflight;
+#[cfg(feature = "tracing")]
+use crate::instrument::Instrumentation;
+use crate::latency::Latency;
+use crate::lease::{Lease, Leases};
 use crate::message::{Message, MessageView};
-use crate::types::{ConnectionState, QoS};
+#[cfg(feature = "metrics")]
+use crate::metrics::ClientMetrics;
+use crate::middleware::{self, Action, Middleware};
+use crate::observer::{DropReason, Observer, Observers};
+use crate::options::{duration_secs, AddressFamily, ConnectOptions, Resolver, TlsOptions};
+use crate::oversize::{OversizePolicy, OversizedMessage, PayloadLimit};
+use crate::provenance::Provenance;
+use crate::ratelimit::{Admission, RateLimit, RateLimiter};
+use crate::router::Router;
+use crate::runtime::{self, RuntimeGuard};
+use crate::sampling::Sampler;
+use crate::scope::Scope;
+use crate::stats::{LatencyStats, TopicStats};
+use crate::stop::Stop;
+use crate::time::Instant;
+use crate::types::{ConnectionState, QoS, ShutdownReport, Topic, TopicFilter};
+use crate::uri::BrokerUri;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::sync::Once;
-
-static INIT: Once = Once::new();
+use std::io::IoSlice;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(any(feature = "otel", feature = "uuid"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 pub type MessageCallback = dyn Fn(&MessageView) + Send + Sync;
 pub type StateCallback = dyn Fn(ConnectionState) + Send + Sync;
 pub type ErrorCallback = dyn Fn(i32, &str) + Send + Sync;
+pub type PanicHook = dyn Fn(&str, &str) + Send + Sync;
+
+// Passed to the error callback for a panic caught in a callback, when no
+// panic hook is set.
+pub const PANIC_ERROR_CODE: i32 = -100;
+
+// Topics kept converted by `publish_raw`; past this, new ones are converted
+// on every call.
+const MAX_INTERNED_TOPICS: usize = 1024;
+
+// `fetch_retained` takes the retained messages to be over after this long
+// without one.
+const RETAINED_QUIET: Duration = Duration::from_millis(250);
+
+// Staging buffers for `publish_vectored` keep up to this much capacity
+// between calls.
+const MAX_STAGING_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static STAGING: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Concatenates `slices` into this thread's staging buffer and passes the
+// result to `f`, so repeated calls allocate only to grow the buffer.
+fn gather<R>(slices: &[IoSlice<'_>], f: impl FnOnce(&[u8]) -> R) -> R {
+    STAGING.with(|staging| {
+        let Ok(mut buffer) = staging.try_borrow_mut() else {
+            // Reentered from `f`; use a buffer of its own.
+            return f(&slices
+                .iter()
+                .flat_map(|s| s.iter().copied())
+                .collect::<Vec<u8>>());
+        };
+        buffer.clear();
+        for slice in slices {
+            buffer.extend_from_slice(slice);
+        }
+        let result = f(&buffer);
+        buffer.clear();
+        buffer.shrink_to(MAX_STAGING_CAPACITY);
+        result
+    })
+}
+
+// A callback bound to one subscription, called for the messages its filter
+// matches in addition to the client-wide message callback.
+struct SubscriptionHandler {
+    handle: i64,
+    filter: TopicFilter,
+    callback: Arc<MessageCallback>,
+}
+
+// What is needed to restore a subscription on a re-created session. The
+// handle given to the caller stays the same, the bridge's changes.
+struct Subscription {
+    filter: String,
+    qos: QoS,
+    bridge_handle: i64,
+}
 
-struct CallbackContext {
+pub(crate) struct CallbackContext {
+    backend: Arc<dyn Backend>,
+    // Replaced by `recreate_session`.
+    session: RwLock<Session>,
+    // Held while stopping and starting the session, so that failover and
+    // `recreate_session` do not interleave.
+    connecting: Mutex<()>,
+    // The broker of the last successful connect.
+    broker: Mutex<Option<(String, u16)>>,
+    // The address connected to, when known.
+    address: Mutex<Option<SocketAddr>>,
+    // From `ConnectOptions::with_broker_limits`.
+    broker_limits: RwLock<BrokerLimits>,
+    // From `ConnectOptions::with_max_packet_size`; 0 when unset.
+    max_packet_size: AtomicU32,
+    // From `ConnectOptions::with_namespace`, ending in `/`.
+    namespace: RwLock<Option<String>>,
+    // From `ConnectOptions::with_address_family` and `with_resolver`.
+    address_family: RwLock<Option<AddressFamily>>,
+    resolver: RwLock<Option<Resolver>>,
+    failover: RwLock<Option<Arc<Failover>>>,
+    presence: Option<Message>,
     message_callback: Box<MessageCallback>,
+    handlers: RwLock<Vec<SubscriptionHandler>>,
+    subscriptions: Mutex<HashMap<i64, Subscription>>,
+    // Last message per topic, when enabled.
+    last_values: RwLock<Option<HashMap<String, Message>>>,
     state_callback: Box<StateCallback>,
     error_callback: Box<ErrorCallback>,
+    inflight: Arc<Inflight>,
+    // Code of the last error reported by the bridge, to explain a failed
+    // connect.
+    last_error: AtomicI32,
+    // Milliseconds after `created` of the last call from the bridge.
+    created: Instant,
+    last_activity: AtomicU64,
+    // Watchdog probes, dropped before dispatch.
+    probe_topic: OnceLock<String>,
+    // Latency probes, consumed before dispatch.
+    latency: RwLock<Option<Arc<Latency>>>,
+    sampler: RwLock<Sampler>,
+    topic_stats: RwLock<Option<Arc<TopicStats>>>,
+    payload_limit: RwLock<Option<PayloadLimit>>,
+    inbound: RwLock<Option<Arc<Inbound>>>,
+    messages_dropped: AtomicU64,
+    messages_expired: AtomicU64,
+    inbound_dedup: RwLock<Option<InboundDedup>>,
+    duplicates_dropped: AtomicU64,
+    panic_hook: RwLock<Option<Box<PanicHook>>>,
+    callback_panics: AtomicU64,
+    // Replaced whole when a layer is added, so callbacks clone it cheaply.
+    middleware: RwLock<Arc<[Arc<dyn Middleware>]>>,
+    observers: Observers,
+    dead_letters: RwLock<Option<Arc<DeadLetters>>>,
+    #[cfg(feature = "encryption")]
+    encryption: RwLock<Option<Encryption>>,
+    #[cfg(feature = "chaos")]
+    chaos: RwLock<Option<Arc<Chaos>>>,
+    #[cfg(feature = "metrics")]
+    metrics: ClientMetrics,
+    #[cfg(feature = "tracing")]
+    instrumentation: Instrumentation,
 }
 
 pub struct Client {
-    session: *mut bindings::mqtt_session_t,
-    _context: Box<CallbackContext>, // Keep the context alive.
+    context: Box<CallbackContext>, // Keep the context alive.
+    client_id: ResolvedClientId,
+    // Options of the last connect, for `recreate_session`.
+    options: Mutex<Option<ConnectOptions>>,
+    next_handle: AtomicI64,
+    // Negative, so never equal to a real subscription handle.
+    next_placeholder: AtomicI64,
+    leases: Arc<Leases>,
+    dedup: Mutex<Option<PublishDedup>>,
+    reaper: Mutex<Option<JoinHandle<()>>>,
+    failover_thread: Mutex<Option<JoinHandle<()>>>,
+    rate_limiter: RwLock<Option<Arc<RateLimiter>>>,
+    rate_limit_thread: Mutex<Option<JoinHandle<()>>>,
+    dispatch_threads: Mutex<Vec<JoinHandle<()>>>,
+    provenance: RwLock<Option<Provenance>>,
+    acl: RwLock<Option<TopicAcl>>,
+    #[cfg(any(feature = "zstd", feature = "gzip"))]
+    compression: RwLock<Option<Compression>>,
+    #[cfg(feature = "otel")]
+    trace_propagation: AtomicBool,
+    #[cfg(feature = "uuid")]
+    idempotency_keys: AtomicBool,
+    interned_topics: RwLock<HashMap<String, CString>>,
+    // Dropped last, after the session is destroyed. None on a test backend.
+    _runtime: Option<RuntimeGuard>,
 }
 
 impl Client {
     pub fn new<F1, F2, F3>(
-        client_id: &str,
+        client_id: impl Into<ClientId>,
         on_message: F1,
         on_state_change: F2,
         on_error: F3,
@@ -34,211 +225,2950 @@ impl Client {
         F2: Fn(ConnectionState) + Send + Sync + 'static,
         F3: Fn(i32, &str) + Send + Sync + 'static,
     {
-        // Initialize API once
-        INIT.call_once(|| {
-            let app_name = CString::new("RustMQTTClient").expect("Invalid app name");
-            let app_version = CString::new("1.0").expect("Invalid version string");
-            let debug = 0;
-            let log_file = std::ptr::null();
-            unsafe {
-                bindings::mqtt_initialize(app_name.as_ptr(), app_version.as_ptr(), debug, log_file);
-            }
-        });
-
-        let client_id = CString::new(client_id)?;
+        // Initialize the API if this is the first live client
+        let runtime = RuntimeGuard::acquire()?;
+        Self::build(
+            client_id.into(),
+            backend::selected(),
+            Some(runtime),
+            Box::new(on_message),
+            Box::new(on_state_change),
+            Box::new(on_error),
+        )
+    }
 
-        // Create callback context
-        let context = Box::new(CallbackContext {
-            message_callback: Box::new(on_message),
-            state_callback: Box::new(on_state_change),
-            error_callback: Box::new(on_error),
-        });
+    // A client on `backend`, such as the in-memory `Fake`, without
+    // initializing the native library.
+    #[cfg(test)]
+    pub(crate) fn with_backend<F1, F2, F3>(
+        client_id: impl Into<ClientId>,
+        backend: Arc<dyn Backend>,
+        on_message: F1,
+        on_state_change: F2,
+        on_error: F3,
+    ) -> Result<Self>
+    where
+        F1: Fn(&MessageView) + Send + Sync + 'static,
+        F2: Fn(ConnectionState) + Send + Sync + 'static,
+        F3: Fn(i32, &str) + Send + Sync + 'static,
+    {
+        Self::build(
+            client_id.into(),
+            backend,
+            None,
+            Box::new(on_message),
+            Box::new(on_state_change),
+            Box::new(on_error),
+        )
+    }
 
-        // Convert the Box into a raw pointer for passing to C
-        let context_ptr = Box::into_raw(context) as *mut std::ffi::c_void;
-
-        // Create the MQTT session
-        let session = unsafe {
-            bindings::mqtt_create_session(
-                client_id.as_ptr(),
-                Some(Self::message_callback),
-                Some(Self::state_callback),
-                Some(Self::error_callback),
-                context_ptr,
-            )
+    fn build(
+        client_id: ClientId,
+        backend: Arc<dyn Backend>,
+        runtime: Option<RuntimeGuard>,
+        on_message: Box<MessageCallback>,
+        on_state_change: Box<StateCallback>,
+        on_error: Box<ErrorCallback>,
+    ) -> Result<Self> {
+        let resolved = client_id.resolve();
+        let client_id = resolved.id.as_str();
+        let presence = match &resolved.presence_topic {
+            Some(topic) => Some(
+                Message::new(topic, resolved.presence_payload())?
+                    .with_qos(QoS::AtLeastOnce)
+                    .with_retain(true),
+            ),
+            None => None,
         };
 
-        if session.is_null() {
-            unsafe {
-                drop(Box::from_raw(context_ptr as *mut CallbackContext));
+        let context = CallbackContext::new(
+            client_id,
+            backend,
+            presence,
+            on_message,
+            on_state_change,
+            on_error,
+        );
+
+        // The bridge keeps a pointer to the boxed context, which does not
+        // move when the Box does.
+        let session = Self::create_session(client_id, &context)?;
+        *context
+            .session
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = session;
+
+        Ok(Self {
+            context, // Keep the context alive
+            client_id: resolved,
+            options: Mutex::new(None),
+            next_handle: AtomicI64::new(1),
+            next_placeholder: AtomicI64::new(-1),
+            leases: Arc::new(Leases::default()),
+            dedup: Mutex::new(None),
+            reaper: Mutex::new(None),
+            failover_thread: Mutex::new(None),
+            rate_limiter: RwLock::new(None),
+            rate_limit_thread: Mutex::new(None),
+            dispatch_threads: Mutex::new(Vec::new()),
+            provenance: RwLock::new(None),
+            acl: RwLock::new(None),
+            #[cfg(any(feature = "zstd", feature = "gzip"))]
+            compression: RwLock::new(None),
+            #[cfg(feature = "otel")]
+            trace_propagation: AtomicBool::new(false),
+            #[cfg(feature = "uuid")]
+            idempotency_keys: AtomicBool::new(false),
+            interned_topics: RwLock::new(HashMap::new()),
+            _runtime: runtime,
+        })
+    }
+
+    // Like `new`, with incoming messages dispatched through `router`.
+    pub fn with_router<F2, F3>(
+        client_id: impl Into<ClientId>,
+        router: Router,
+        on_state_change: F2,
+        on_error: F3,
+    ) -> Result<Self>
+    where
+        F2: Fn(ConnectionState) + Send + Sync + 'static,
+        F3: Fn(i32, &str) + Send + Sync + 'static,
+    {
+        Self::new(
+            client_id,
+            move |msg| {
+                router.dispatch(msg);
+            },
+            on_state_change,
+            on_error,
+        )
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> Result<()> {
+        self.connect_with(host, port, &ConnectOptions::default())
+    }
+
+    pub fn connect_with(&self, host: &str, port: u16, options: &ConnectOptions) -> Result<()> {
+        self.stop_failover();
+        self.configure(options)?;
+        let _connecting = self.context.connecting();
+        self.context.start(host, port)
+    }
+
+    // Connects to a broker given as a URI, such as `ssl://broker:8883` or
+    // `ws://broker:8080/mqtt`. The scheme picks the transport: ssl:// and
+    // wss:// turn TLS on, with the system trust store unless `options` has
+    // TLS settings, while tcp:// and ws:// turn it off.
+    pub fn connect_uri(&self, uri: &str) -> Result<()> {
+        self.connect_uri_with(uri, &ConnectOptions::default())
+    }
+
+    pub fn connect_uri_with(&self, uri: &str, options: &ConnectOptions) -> Result<()> {
+        let uri: BrokerUri = uri.parse()?;
+        self.connect_with(&uri.host, uri.port, &uri.options(options))
+    }
+
+    // Connects to the first of `brokers` that accepts, in order, waiting
+    // out the backoff from `options` between attempts. Afterwards a lost
+    // connection fails over to the next broker, round-robin, and restores
+    // the subscriptions, until `disconnect` or shutdown.
+    pub fn connect_brokers<H>(
+        &self,
+        brokers: impl IntoIterator<Item = (H, u16)>,
+        options: &ConnectOptions,
+    ) -> Result<()>
+    where
+        H: Into<String>,
+    {
+        runtime::needs_threads("connect_brokers")?;
+        self.stop_failover();
+        let brokers: Vec<(String, u16)> = brokers
+            .into_iter()
+            .map(|(host, port)| (host.into(), port))
+            .collect();
+        if brokers.is_empty() {
+            return Err(Error::InvalidBrokerUrl("no brokers given".to_string()));
+        }
+        self.configure(options)?;
+
+        let failover = Arc::new(Failover::new(
+            brokers,
+            options.backoff().unwrap_or_default(),
+        ));
+        let mut result = Err(Error::ConnectionError);
+        for failures in 0..failover.len() as u32 {
+            if failures > 0 {
+                failover.back_off(failures);
+            }
+            let (host, port) = failover.next_broker();
+            let _connecting = self.context.connecting();
+            if failures > 0 {
+                // Paho keeps the client of a failed connect.
+                self.context.stop();
+            }
+            result = self.context.start(host, port);
+            if result.is_ok() {
+                break;
             }
-            return Err(Error::InitializationError);
         }
+        result?;
+
+        *self
+            .context
+            .failover
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(failover.clone());
+        let context = ContextRef(&*self.context);
+        *self
+            .failover_thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(thread::spawn(move || {
+            let context = unsafe { context.get() };
+            while failover.wait_lost() {
+                let mut failures = 0;
+                loop {
+                    let (host, port) = failover.next_broker();
+                    if context.restart(host, port).is_ok() {
+                        break;
+                    }
+                    failures += 1;
+                    if !failover.back_off(failures) {
+                        return;
+                    }
+                }
+            }
+        }));
+        Ok(())
+    }
 
-        let context = unsafe { Box::from_raw(context_ptr as *mut CallbackContext) };
+    // What the broker is assumed to accept, see `BrokerLimits`.
+    pub fn broker_limits(&self) -> BrokerLimits {
+        self.context.broker_limits().clone()
+    }
 
-        Ok(Self {
+    // The broker the client is connected to, which changes on failover.
+    pub fn current_broker(&self) -> Option<(String, u16)> {
+        if self.state() != ConnectionState::Connected {
+            return None;
+        }
+        self.context
+            .broker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // The broker address the client is connected to: the one chosen with
+    // `ConnectOptions::with_address_family`, or the broker host if that is
+    // an address. None when the backend resolved the host.
+    pub fn peer_address(&self) -> Option<SocketAddr> {
+        if self.state() != ConnectionState::Connected {
+            return None;
+        }
+        *self
+            .context
+            .address
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn configure(&self, options: &ConnectOptions) -> Result<()> {
+        *self.options.lock().unwrap_or_else(PoisonError::into_inner) = Some(options.clone());
+        self.apply_options(options)
+    }
+
+    fn stop_failover(&self) {
+        let failover = self
+            .context
+            .failover
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(failover) = failover {
+            failover.stop();
+        }
+        let thread = self
+            .failover_thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+
+    /// Returns the underlying bridge session for use with [`crate::sys`].
+    ///
+    /// # Safety
+    ///
+    /// The pointer is only valid while this client is alive and until
+    /// [`Client::recreate_session`] replaces it, and must be used according
+    /// to the rules in the [`crate::sys`] module documentation.
+    #[cfg(feature = "raw")]
+    pub unsafe fn raw_session(&self) -> *mut bindings::mqtt_session_t {
+        *self.session()
+    }
+
+    // Replaces the bridge session with a new one, for when the native layer
+    // has wedged: the old session is destroyed, the new one connects with the
+    // settings of the last connect and every subscription is restored under
+    // its existing handle. Returns how many were restored. Publishes in
+    // flight on the old session are forgotten.
+    pub fn recreate_session(&self) -> Result<usize> {
+        let _connecting = self.context.connecting();
+        let options = self
+            .options
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(Error::ConnectionError)?;
+        let (host, port) = self
+            .context
+            .broker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(Error::ConnectionError)?;
+
+        let session = Self::create_session(&self.client_id.id, &self.context)?;
+        let old = std::mem::replace(
+            &mut *self
+                .context
+                .session
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
             session,
-            _context: context, // Keep the context alive
-        })
+        );
+        self.context.backend.stop(old);
+        self.context.backend.destroy_session(old);
+        self.context.inflight.clear();
+
+        self.apply_options(&options)?;
+        self.context.start(&host, port)?;
+        Ok(self.context.resubscribe())
+    }
+
+    // Swaps in new TLS settings, such as a rotated client certificate, and
+    // reconnects to the current broker with them, keeping the subscriptions.
+    // Later connects, failover's included, use them too. The bridge reads
+    // the files on every connect, so certificates replaced in place only
+    // need the same options passed again. Returns how many subscriptions
+    // were restored.
+    pub fn reload_tls(&self, tls: TlsOptions) -> Result<usize> {
+        let (host, port) = self
+            .context
+            .broker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(Error::ConnectionError)?;
+        {
+            let _connecting = self.context.connecting();
+            self.apply_tls(Some(&tls))?;
+            if let Some(options) = &mut *self.options.lock().unwrap_or_else(PoisonError::into_inner)
+            {
+                options.tls = Some(tls);
+            }
+        }
+        self.context.restart(&host, port)?;
+        Ok(self.subscription_count())
     }
 
-    pub fn connect(&mut self, host: &str, port: u16) -> Result<()> {
-        let broker_host = CString::new(host)?;
+    // Reconnects to the current broker, keeping the subscriptions.
+    pub(crate) fn reconnect(&self) -> Result<usize> {
+        let (host, port) = self
+            .context
+            .broker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .ok_or(Error::ConnectionError)?;
+        self.context.restart(&host, port)?;
+        Ok(self.subscription_count())
+    }
 
-        let result = unsafe { bindings::mqtt_set_broker(self.session, broker_host.as_ptr(), port) };
+    #[cfg_attr(not(feature = "management"), allow(dead_code))]
+    pub(crate) fn resubscribe(&self) -> usize {
+        let _connecting = self.context.connecting();
+        self.context.resubscribe()
+    }
 
-        if result != 0 {
-            return Err(Error::InvalidBrokerUrl);
+    #[cfg_attr(not(feature = "management"), allow(dead_code))]
+    pub(crate) fn subscription_count(&self) -> usize {
+        self.context
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    // How long since the bridge last called back, for any reason.
+    pub(crate) fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.context.last_activity.load(Ordering::Relaxed));
+        self.context.created.elapsed().saturating_sub(last)
+    }
+
+    // How long since the broker last sent a packet the bridge saw, None
+    // while not connected. Keepalive responses are not seen.
+    pub(crate) fn inbound_idle_for(&self) -> Option<Duration> {
+        let session = *self.session();
+        let millis = self.context.backend.idle_ms(session);
+        u64::try_from(millis).ok().map(Duration::from_millis)
+    }
+
+    pub(crate) fn set_probe_topic(&self, topic: String) {
+        let _ = self.context.probe_topic.set(topic);
+    }
+
+    pub(crate) fn set_latency(&self, latency: Option<Arc<Latency>>) {
+        *self
+            .context
+            .latency
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = latency;
+    }
+
+    #[cfg(feature = "chaos")]
+    pub(crate) fn set_chaos(&self, chaos: Option<Arc<Chaos>>) {
+        *self
+            .context
+            .chaos
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = chaos;
+    }
+
+    // Hands a message held back by a `FaultInjector` to the callbacks.
+    #[cfg(feature = "chaos")]
+    pub(crate) fn deliver_injected(&self, message: &Message) {
+        self.context
+            .guard("message", || self.context.accept(&message.view()));
+    }
+
+    // Round-trip times through the broker, while a `LatencyProbe` runs.
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        self.context
+            .latency
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(|latency| latency.stats())
+    }
+
+    fn create_session(client_id: &str, context: &CallbackContext) -> Result<Session> {
+        let client_id = CString::new(client_id)?;
+        let context_ptr = context as *const CallbackContext as *mut std::ffi::c_void;
+
+        let callbacks = Callbacks {
+            message: Self::message_callback,
+            state: Self::state_callback,
+            error: Self::error_callback,
+            delivery: Self::delivery_callback,
+        };
+        let session = context
+            .backend
+            .create_session(&client_id, callbacks, context_ptr);
+
+        if session.is_null() {
+            return Err(Error::InitializationError);
         }
+        Ok(session)
+    }
+
+    fn session(&self) -> RwLockReadGuard<'_, Session> {
+        self.context.session()
+    }
+
+    // The id the session was created with, including any suffixes.
+    pub fn client_id(&self) -> &str {
+        &self.client_id.id
+    }
 
-        let result = unsafe { bindings::mqtt_session_start(self.session) };
+    // The configured id, before suffixes were appended.
+    pub fn base_client_id(&self) -> &str {
+        &self.client_id.base
+    }
 
+    fn apply_options(&self, options: &ConnectOptions) -> Result<()> {
+        self.context
+            .max_packet_size
+            .store(options.max_packet_size.unwrap_or(0), Ordering::Relaxed);
+        *self
+            .context
+            .broker_limits
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) =
+            options.broker_limits.clone().unwrap_or_default();
+        let namespace = match &options.namespace {
+            Some(namespace) => {
+                let namespace = format!("{}/", namespace.trim_end_matches('/'));
+                crate::topic::validate_topic_name(&namespace)?;
+                Some(namespace)
+            }
+            None => None,
+        };
+        let mut current = self
+            .context
+            .namespace
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *current != namespace {
+            *current = namespace;
+            // They are kept as sent, namespace included.
+            self.interned_topics
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+        drop(current);
+        *self
+            .context
+            .address_family
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = options.address_family;
+        *self
+            .context
+            .resolver
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = options.resolver.clone();
+        // Always set, so a will from an earlier connect is cleared.
+        let session = *self.session();
+        let backend = &self.context.backend;
+        let result = match &options.will {
+            Some(will) => {
+                let topic = CString::new(&*self.context.namespaced(&will.topic))?;
+                backend.set_will(
+                    session,
+                    Some((&topic, &will.payload, will.qos, will.retained)),
+                )
+            }
+            None => backend.set_will(session, None),
+        };
+        if result != 0 {
+            return Err(Error::ConnectionError);
+        }
+        self.apply_tls(options.tls.as_ref())?;
+        let path = options.websocket.as_deref().map(CString::new).transpose()?;
+        let result = backend.set_websocket(session, path.as_deref());
         if result != 0 {
             return Err(Error::ConnectionError);
         }
+        // Empty credentials clear those of an earlier connect.
+        let (username, password) = match &options.credentials {
+            Some((username, password)) => (CString::new(&**username)?, CString::new(&**password)?),
+            None => (CString::default(), CString::default()),
+        };
+        let result = backend.set_credentials(session, &username, &password);
+        if result != 0 {
+            return Err(Error::InvalidCredentials);
+        }
+        if let Some(keep_alive) = options.keep_alive {
+            self.set_int_parameter(
+                bindings::mqtt_parameter_t_MQTT_PARAM_KEEP_ALIVE_INTERVAL,
+                duration_secs(keep_alive),
+            )?;
+        }
+        if let Some(max_inflight) = options.max_inflight {
+            self.set_int_parameter(
+                bindings::mqtt_parameter_t_MQTT_PARAM_MAX_INFLIGHT,
+                i32::from(max_inflight),
+            )?;
+        }
+
+        if let Some(tcp) = options.tcp_keepalive {
+            let params = [
+                (
+                    bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_IDLE,
+                    duration_secs(tcp.idle).max(1),
+                ),
+                (
+                    bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_INTERVAL,
+                    tcp.interval.map_or(0, duration_secs),
+                ),
+                (
+                    bindings::mqtt_parameter_t_MQTT_PARAM_TCP_KEEPALIVE_COUNT,
+                    tcp.retries.map_or(0, |r| r.min(i32::MAX as u32) as i32),
+                ),
+            ];
+            for (param, value) in params {
+                let result = backend.set_int_parameter(session, param, value);
+                option_result(result, "TCP keepalive")?;
+            }
+        }
+        if let Some(nodelay) = options.tcp_nodelay {
+            let result = backend.set_bool_parameter(
+                session,
+                bindings::mqtt_parameter_t_MQTT_PARAM_TCP_NODELAY,
+                nodelay,
+            );
+            option_result(result, "TCP_NODELAY")?;
+        }
+        if let Some(bytes) = options.send_buffer_size {
+            let result = backend.set_int_parameter(
+                session,
+                bindings::mqtt_parameter_t_MQTT_PARAM_SEND_BUFFER_SIZE,
+                bytes.min(i32::MAX as u32) as i32,
+            );
+            option_result(result, "send buffer size")?;
+        }
+        if let Some(bytes) = options.receive_buffer_size {
+            let result = backend.set_int_parameter(
+                session,
+                bindings::mqtt_parameter_t_MQTT_PARAM_RECEIVE_BUFFER_SIZE,
+                bytes.min(i32::MAX as u32) as i32,
+            );
+            option_result(result, "receive buffer size")?;
+        }
 
         Ok(())
     }
 
-    pub fn subscribe(&self, topic: &str, qos: QoS) -> Result<i64> {
-        let topic = CString::new(topic)?;
-
-        let handle = unsafe { bindings::mqtt_subscribe(self.session, topic.as_ptr(), qos.into()) };
-
-        if handle < 0 {
-            Err(Error::SubscriptionError)
+    fn apply_tls(&self, tls: Option<&TlsOptions>) -> Result<()> {
+        // The backend would be given the address, and verify the broker's
+        // certificate against it.
+        #[cfg(not(target_arch = "wasm32"))]
+        if tls.is_some() {
+            let context = &self.context;
+            if context
+                .address_family
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_some()
+                || context
+                    .resolver
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .is_some()
+            {
+                return Err(Error::InvalidOptions(
+                    "an address family or resolver cannot be used with TLS".to_string(),
+                ));
+            }
+        }
+        let session = *self.session();
+        let backend = &self.context.backend;
+        let server_name =
+            CString::new(tls.and_then(|tls| tls.server_name.as_deref()).unwrap_or(""))?;
+        option_result(
+            backend.set_tls_server_name(session, &server_name),
+            "TLS server name",
+        )?;
+        let results = match tls {
+            Some(tls) => {
+                let path = |path: &Option<String>| CString::new(path.as_deref().unwrap_or(""));
+                let (ca_file, cert_file, key_file) = (
+                    path(&tls.ca_file)?,
+                    path(&tls.cert_file)?,
+                    path(&tls.key_file)?,
+                );
+                let (pkcs12_file, pkcs12_password) =
+                    (path(&tls.pkcs12_file)?, path(&tls.pkcs12_password)?);
+                let alpn = tls.alpn_wire()?;
+                [
+                    backend.set_tls_certificates(session, &ca_file, &cert_file, &key_file),
+                    backend.set_tls_pkcs12(session, &pkcs12_file, &pkcs12_password),
+                    backend.set_alpn_protocols(session, &alpn),
+                ]
+            }
+            None => {
+                let empty = CString::default();
+                [
+                    backend.set_bool_parameter(
+                        session,
+                        bindings::mqtt_parameter_t_MQTT_PARAM_TLS_ENABLED,
+                        false,
+                    ),
+                    backend.set_tls_pkcs12(session, &empty, &empty),
+                    backend.set_alpn_protocols(session, &[]),
+                ]
+            }
+        };
+        if results.iter().any(|&result| result != 0) {
+            Err(Error::ConnectionError)
         } else {
-            Ok(handle)
+            Ok(())
         }
     }
 
-    pub fn unsubscribe(&self, handle: i64) -> Result<()> {
-        let result = unsafe { bindings::mqtt_unsubscribe(self.session, handle) };
-
+    fn set_int_parameter(&self, param: bindings::mqtt_parameter_t, value: i32) -> Result<()> {
+        let session = *self.session();
+        let result = self
+            .context
+            .backend
+            .set_int_parameter(session, param, value);
         if result != 0 {
-            Err(Error::SubscriptionError)
+            Err(Error::ConnectionError)
         } else {
             Ok(())
         }
     }
 
-    pub fn publish(&self, message: &Message) -> Result<i64> {
-        let topic = CString::new(&*message.topic)?;
-
-        let message_id = unsafe {
-            bindings::mqtt_publish(
-                self.session,
-                topic.as_ptr(),
-                message.payload.as_ptr(),
-                message.payload.len(),
-                message.qos.into(),
-                message.retained as i32,
-            )
-        };
+    pub fn subscribe<T>(&self, filter: T, qos: QoS) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.subscribe_filter(filter.try_into()?, qos)
+    }
 
-        if message_id < 0 {
-            Err(Error::PublicationError)
+    fn subscribe_filter(&self, filter: TopicFilter, qos: QoS) -> Result<i64> {
+        if let Some(acl) = &*self.acl.read().unwrap_or_else(PoisonError::into_inner) {
+            acl.check_subscribe(filter.as_str())?;
+        }
+        let topic = self.context.namespaced(filter.as_str()).into_owned();
+
+        #[cfg(feature = "tracing")]
+        let _span = self
+            .context
+            .instrumentation
+            .subscribe_span(&topic, qos)
+            .entered();
+
+        self.context.broker_limits().check_subscribe(&topic)?;
+        let filter = CString::new(topic.as_str())?;
+
+        let session = *self.session();
+        let bridge_handle = self.context.backend.subscribe(session, &filter, qos);
+        self.context
+            .observers
+            .each(|observer| observer.on_suback(&topic, qos, bridge_handle >= 0));
+
+        if bridge_handle < 0 {
+            #[cfg(feature = "metrics")]
+            self.context.metrics.error();
+            Err(Error::SubscriptionError(self.context.backend.last_error()))
         } else {
-            Ok(message_id)
+            let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+            self.context
+                .subscriptions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(
+                    handle,
+                    Subscription {
+                        filter: topic,
+                        qos,
+                        bridge_handle,
+                    },
+                );
+            Ok(handle)
         }
     }
 
-    pub fn state(&self) -> ConnectionState {
-        let state = unsafe { bindings::mqtt_session_get_state(self.session) };
-        state.into()
+    // Subscribes with a callback that only receives messages matching this
+    // filter. The client-wide message callback still sees them too.
+    pub fn subscribe_with<T, F>(&self, filter: T, qos: QoS, handler: F) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.subscribe_handler(filter.try_into()?, qos, Box::new(handler), false)
     }
 
-    unsafe extern "C" fn message_callback(
-        message: *const bindings::mqtt_message_data_t,
-        context: *mut std::ffi::c_void,
-    ) {
-        if message.is_null() || context.is_null() {
-            return;
+    // Like `subscribe_with`, but first replays the last values cached for
+    // matching topics (see `set_last_values`) to the handler, on the calling
+    // thread. The handler must not subscribe or unsubscribe during the
+    // replay.
+    pub fn subscribe_with_replay<T, F>(&self, filter: T, qos: QoS, handler: F) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.subscribe_handler(filter.try_into()?, qos, Box::new(handler), true)
+    }
+
+    fn subscribe_handler(
+        &self,
+        filter: TopicFilter,
+        qos: QoS,
+        callback: Box<MessageCallback>,
+        replay: bool,
+    ) -> Result<i64> {
+        // Registered under a placeholder handle before subscribing, so
+        // retained messages delivered during the subscribe are not missed.
+        // The lock can't be held across it: the network thread needs it to
+        // deliver them.
+        let placeholder = self.next_placeholder.fetch_sub(1, Ordering::Relaxed);
+        {
+            let mut handlers = self
+                .context
+                .handlers
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Holding the handlers lock keeps the cache from changing, so
+            // every message is either replayed or delivered live, not both.
+            if replay {
+                if let Some(values) = &*self
+                    .context
+                    .last_values
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                {
+                    for message in values.values() {
+                        if crate::topic::matches(filter.as_str(), &message.topic) {
+                            callback(&message.view());
+                        }
+                    }
+                }
+            }
+            handlers.push(SubscriptionHandler {
+                handle: placeholder,
+                filter: filter.clone(),
+                callback: Arc::from(callback),
+            });
         }
 
-        let context = &*(context as *const CallbackContext);
+        let result = self.subscribe_filter(filter, qos);
+        let mut handlers = self
+            .context
+            .handlers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(handle) => {
+                if let Some(h) = handlers.iter_mut().find(|h| h.handle == placeholder) {
+                    h.handle = handle;
+                }
+            }
+            Err(_) => handlers.retain(|h| h.handle != placeholder),
+        }
+        result
+    }
 
-        let payload = if (*message).payload.is_null() || (*message).payload_length == 0 {
-            &[]
-        } else if (*message).payload_length > isize::MAX as usize {
-            eprintln!("Payload too large");
-            return;
-        } else {
-            std::slice::from_raw_parts((*message).payload, (*message).payload_length)
-        };
+    // Subscribes with a handler receiving payloads decoded by `codec`, or the
+    // decoding error.
+    pub fn subscribe_decoded<T, C, M, F>(
+        &self,
+        filter: T,
+        qos: QoS,
+        codec: C,
+        handler: F,
+    ) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        C: Codec<M> + Send + Sync + 'static,
+        F: Fn(&MessageView, Result<M>) + Send + Sync + 'static,
+    {
+        self.subscribe_with(filter, qos, move |msg| {
+            handler(msg, codec.decode(msg.payload()))
+        })
+    }
 
-        let topic = match CStr::from_ptr((*message).topic).to_str() {
-            Ok(s) => s,
-            Err(_) => return,
-        };
+    #[cfg(feature = "prost")]
+    pub fn subscribe_proto<T, M, F>(&self, filter: T, qos: QoS, handler: F) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        M: prost::Message + Default,
+        F: Fn(&MessageView, Result<M>) + Send + Sync + 'static,
+    {
+        self.subscribe_decoded(filter, qos, ProstCodec, handler)
+    }
 
-        let qos = match (*message).qos {
-            0 => QoS::AtMostOnce,
-            1 => QoS::AtLeastOnce,
-            2 => QoS::ExactlyOnce,
-            _ => return,
-        };
+    pub fn unsubscribe(&self, handle: i64) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = self
+            .context
+            .instrumentation
+            .unsubscribe_span(handle)
+            .entered();
 
-        let msg = MessageView {
-            topic,
-            payload,
-            qos,
-            retained: (*message).retained != 0,
-        };
+        self.leases.remove(handle);
+        remove_subscription(&self.context, handle)
+    }
+
+    // Subscribes for `ttl`, after which the subscription is removed unless
+    // the returned lease was renewed with `keep_alive`.
+    pub fn subscribe_leased<T>(&self, filter: T, qos: QoS, ttl: Duration) -> Result<Lease>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        runtime::needs_threads("subscribe_leased")?;
+        let handle = self.subscribe(filter, qos)?;
+        Ok(self.lease(handle, ttl))
+    }
 
-        (context.message_callback)(&msg);
+    // Like `subscribe_with`, for a leased subscription.
+    pub fn subscribe_leased_with<T, F>(
+        &self,
+        filter: T,
+        qos: QoS,
+        ttl: Duration,
+        handler: F,
+    ) -> Result<Lease>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        runtime::needs_threads("subscribe_leased")?;
+        let handle = self.subscribe_with(filter, qos, handler)?;
+        Ok(self.lease(handle, ttl))
     }
 
-    unsafe extern "C" fn state_callback(
-        state: bindings::mqtt_session_state_t,
-        context: *mut std::ffi::c_void,
-    ) {
-        if context.is_null() {
-            return;
+    fn lease(&self, handle: i64, ttl: Duration) -> Lease {
+        let mut reaper = self.reaper.lock().unwrap_or_else(PoisonError::into_inner);
+        if reaper.is_none() {
+            let leases = self.leases.clone();
+            let context = ContextRef(&*self.context);
+            *reaper = Some(thread::spawn(move || {
+                while let Some(expired) = leases.wait_expired() {
+                    for handle in expired {
+                        let _ = remove_subscription(unsafe { context.get() }, handle);
+                    }
+                }
+            }));
         }
-
-        let context = &*(context as *const CallbackContext);
-        (context.state_callback)(state.into());
+        Lease::new(handle, ttl, self.leases.clone())
     }
 
-    unsafe extern "C" fn error_callback(
-        error_code: std::os::raw::c_int,
-        message: *const std::os::raw::c_char,
-        context: *mut std::ffi::c_void,
-    ) {
-        if message.is_null() || context.is_null() {
-            return;
+    // Must run before the session is stopped: the reaper uses it.
+    fn stop_reaper(&self) {
+        self.leases.stop();
+        let reaper = self
+            .reaper
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(reaper) = reaper {
+            let _ = reaper.join();
         }
+    }
 
-        let context = &*(context as *const CallbackContext);
-        let error_msg = CStr::from_ptr(message)
-            .to_str()
-            .unwrap_or("Invalid error message");
+    // Publishes `value` encoded by `codec`.
+    pub fn publish_encoded<T, C, M>(&self, topic: T, value: &M, qos: QoS, codec: &C) -> Result<i64>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+        C: Codec<M>,
+    {
+        let message = Message::new(topic, codec.encode(value)?)?.with_qos(qos);
+        self.publish(&message)
+    }
 
-        (context.error_callback)(error_code, error_msg);
+    // Publishes a CloudEvents event, in structured or binary mode.
+    #[cfg(feature = "cloudevents")]
+    pub fn publish_event<T>(
+        &self,
+        topic: T,
+        event: &Event,
+        qos: QoS,
+        mode: EventMode,
+    ) -> Result<i64>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+    {
+        let message = Message::new(topic, event.encode(mode)?)?.with_qos(qos);
+        self.publish(&message)
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        unsafe {
-            bindings::mqtt_session_stop(self.session);
-            bindings::mqtt_destroy_session(self.session);
-        }
+    #[cfg(feature = "prost")]
+    pub fn publish_proto<T, M>(&self, topic: T, value: &M, qos: QoS) -> Result<i64>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+        M: prost::Message + Default,
+    {
+        self.publish_encoded(topic, value, qos, &ProstCodec)
     }
-}
 
-unsafe impl Send for Client {}
-unsafe impl Sync for Client {}
+    // With a queueing rate limit, a message that has to wait is published
+    // later and its message id is 0.
+    pub fn publish(&self, message: &Message) -> Result<i64> {
+        self.send(message)
+            .inspect_err(|error| self.context.dead_letter(message, error))
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::mpsc;
-    use std::sync::{Arc, Mutex};
-    use std::thread;
-    use std::time::Duration;
+    fn send(&self, message: &Message) -> Result<i64> {
+        if message.is_expired() {
+            self.context.message_expired();
+            return Err(Error::Expired);
+        }
+        let chain = self.context.middleware();
+        let mut layered = None;
+        if !chain.is_empty() {
+            let mut owned = message.clone();
+            if let Action::Drop = middleware::run(&chain, &mut owned, true) {
+                return Err(Error::Dropped);
+            }
+            layered = Some(owned);
+        }
+        let message = layered.as_ref().unwrap_or(message);
+        if let Some(acl) = &*self.acl.read().unwrap_or_else(PoisonError::into_inner) {
+            acl.check_publish(&message.topic).map_err(|error| {
+                let topic = self.context.namespaced(&message.topic);
+                let bytes = message.payload.len();
+                self.context
+                    .publish_failed(&topic, message.qos, bytes, error)
+            })?;
+        }
+        // Compressed first, so stamping adds to the compressed envelope.
+        #[cfg(any(feature = "zstd", feature = "gzip"))]
+        let compressed = match &*self
+            .compression
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(compression) => compression.compress_message(message)?,
+            None => None,
+        };
+        #[cfg(any(feature = "zstd", feature = "gzip"))]
+        let message = compressed.as_ref().unwrap_or(message);
+        let limiter = self
+            .rate_limiter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let stamped = match &*self
+            .provenance
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            // An empty payload clears a retained message; stamped, it would
+            // replace it instead.
+            Some(provenance) if !message.payload.is_empty() => Some(Message {
+                payload: provenance.stamp(&message.payload)?,
+                ..message.clone()
+            }),
+            _ => None,
+        };
+        #[cfg(feature = "otel")]
+        let stamped = match self.trace_propagation.load(Ordering::Relaxed) {
+            true if !message.payload.is_empty() => {
+                let base = stamped.as_ref().unwrap_or(message);
+                match crate::otel::inject(&base.payload)? {
+                    Some(payload) => Some(Message {
+                        payload,
+                        ..base.clone()
+                    }),
+                    None => stamped,
+                }
+            }
+            _ => stamped,
+        };
+        #[cfg(feature = "uuid")]
+        let stamped = match self.idempotency_keys.load(Ordering::Relaxed) {
+            true if !message.payload.is_empty() => {
+                let base = stamped.as_ref().unwrap_or(message);
+                match base.idempotency_key() {
+                    Some(_) => stamped,
+                    None => Some(base.clone().with_unique_key()?),
+                }
+            }
+            _ => stamped,
+        };
+        let message = stamped.as_ref().unwrap_or(message);
+        #[cfg(feature = "encryption")]
+        let encrypted = match &*self
+            .context
+            .encryption
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(encryption) => encryption.encrypt_message(message)?,
+            None => None,
+        };
+        #[cfg(feature = "encryption")]
+        let message = encrypted.as_ref().unwrap_or(message);
+        if let Some(limiter) = limiter {
+            if let Admission::Queued = limiter.admit(message)? {
+                return Ok(0);
+            }
+        }
+        self.context.publish(message)
+    }
+
+    // Publishes an empty retained message, which makes the broker discard
+    // the message retained on `topic`.
+    pub fn clear_retained<T>(&self, topic: T) -> Result<()>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+    {
+        let clear = Message::new(topic, Vec::new())?
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain(true);
+        self.publish(&clear).map(|_| ())
+    }
+
+    // Returns the messages retained on topics matching `filter`: subscribes,
+    // collects the retained messages the broker sends in reply, then
+    // unsubscribes. MQTT 3.1.1 does not mark the end of those, so they are
+    // taken to be over at the first live message, after `RETAINED_QUIET`
+    // without one or after `timeout`, whichever comes first. Blocks, so it
+    // must not be called from a message callback.
+    pub fn fetch_retained<T>(&self, filter: T, timeout: Duration) -> Result<Vec<Message>>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        runtime::needs_threads("fetch_retained")?;
+        let (tx, rx) = mpsc::channel();
+        let handle = self.subscribe_with(filter, QoS::AtLeastOnce, move |msg| {
+            // Brokers set the retain flag only on messages sent because of
+            // the subscription.
+            let _ = tx.send(msg.is_retained().then(|| msg.to_owned()));
+        })?;
+
+        let deadline = Instant::now() + timeout;
+        let mut retained = Vec::new();
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(left.min(RETAINED_QUIET)) {
+                Ok(Some(message)) => retained.push(message),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        self.unsubscribe(handle)?;
+        Ok(retained)
+    }
+
+    // Publishes a payload made of several slices, such as a header and a
+    // body serialized separately. The bridge takes a contiguous payload, so
+    // the slices are copied into a per-thread buffer reused across calls
+    // rather than into a new `Vec`.
+    pub fn publish_vectored(
+        &self,
+        topic: &str,
+        slices: &[IoSlice<'_>],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<i64> {
+        gather(slices, |payload| {
+            self.publish_raw(topic, payload, qos, retain)
+        })
+    }
+
+    // Publishes without building a `Message`. Each topic is validated and
+    // converted for the bridge once, then kept, so hot loops publishing to
+    // fixed topics allocate nothing. With provenance or a rate limit set,
+    // which need an owned message, this is the same as `publish`.
+    pub fn publish_raw(&self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<i64> {
+        let owned = self
+            .rate_limiter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+            || !self.context.middleware().is_empty()
+            || self
+                .provenance
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_some();
+        #[cfg(feature = "otel")]
+        let owned = owned || self.trace_propagation.load(Ordering::Relaxed);
+        #[cfg(feature = "uuid")]
+        let owned = owned || self.idempotency_keys.load(Ordering::Relaxed);
+        #[cfg(any(feature = "zstd", feature = "gzip"))]
+        let owned = owned
+            || self
+                .compression
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_some();
+        #[cfg(feature = "encryption")]
+        let owned = owned
+            || self
+                .context
+                .encryption
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_some();
+        if owned {
+            let message = Message::new(topic, payload)?
+                .with_qos(qos)
+                .with_retain(retain);
+            return self.publish(&message);
+        }
+        self.publish_interned(topic, payload, qos, retain)
+            .inspect_err(|error| {
+                if let Ok(message) = Message::new(topic, payload) {
+                    let message = message.with_qos(qos).with_retain(retain);
+                    self.context.dead_letter(&message, error);
+                }
+            })
+    }
+
+    fn publish_interned(&self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<i64> {
+        if let Some(acl) = &*self.acl.read().unwrap_or_else(PoisonError::into_inner) {
+            acl.check_publish(topic).map_err(|error| {
+                let sent = self.context.namespaced(topic);
+                self.context
+                    .publish_failed(&sent, qos, payload.len(), error)
+            })?;
+        }
+
+        let topics = self
+            .interned_topics
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(c_topic) = topics.get(topic) {
+            return self.context.publish_raw(c_topic, payload, qos, retain);
+        }
+        drop(topics);
+
+        crate::topic::validate_topic_name(topic)?;
+        let c_topic = CString::new(&*self.context.namespaced(topic))?;
+        let result = self.context.publish_raw(&c_topic, payload, qos, retain);
+        let mut topics = self
+            .interned_topics
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if topics.len() < MAX_INTERNED_TOPICS {
+            topics.insert(topic.to_string(), c_topic);
+        }
+        result
+    }
+
+    // Stamps every payload published through `publish` with this client's
+    // id, host name and app name and version, wrapping it in an `Envelope`
+    // unless it already is one. Subscribers read it with
+    // `MessageView::provenance`; those unaware of envelopes see the framing.
+    pub fn set_provenance(&self, enabled: bool) {
+        let provenance = enabled.then(|| {
+            let (app_name, app_version) = runtime::app_identity();
+            Provenance {
+                client_id: self.client_id.id.clone(),
+                hostname: hostname(),
+                app_name,
+                app_version,
+            }
+        });
+        *self
+            .provenance
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = provenance;
+    }
+
+    // Adds the OpenTelemetry context of the span `publish` is called in to
+    // the payload, as a W3C `traceparent` envelope property (see
+    // `Envelope`). Receiving clients built with the `otel` feature link
+    // their dispatch span to it.
+    #[cfg(feature = "otel")]
+    pub fn set_trace_propagation(&self, enabled: bool) {
+        self.trace_propagation.store(enabled, Ordering::Relaxed);
+    }
+
+    // Gives every message published through `publish` a new UUID
+    // idempotency key, unless it has one (see `ExactlyOnce`). Paho resends
+    // a QoS 1 publish as is, key included, but a publish retried through
+    // `publish` gets a new key; stamp those with `Message::with_unique_key`
+    // instead. The key travels in an `Envelope` around the payload, which
+    // subscribers receive as is: `Envelope::decode` gives the payload back.
+    #[cfg(feature = "uuid")]
+    pub fn set_idempotency_keys(&self, enabled: bool) {
+        self.idempotency_keys.store(enabled, Ordering::Relaxed);
+    }
+
+    // Compresses payloads published through `publish` (see `Compression`);
+    // `None` turns it off.
+    #[cfg(any(feature = "zstd", feature = "gzip"))]
+    pub fn set_compression(&self, compression: Option<Compression>) {
+        *self
+            .compression
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = compression;
+    }
+
+    // Publishes `message` compressed with `compression`, whatever the
+    // client's own setting.
+    #[cfg(any(feature = "zstd", feature = "gzip"))]
+    pub fn publish_compressed(&self, message: &Message, compression: &Compression) -> Result<i64> {
+        match compression.compress_message(message)? {
+            Some(compressed) => self.publish(&compressed),
+            None => self.publish(message),
+        }
+    }
+
+    // Appends `layer` to the middleware chain (see `Middleware`). Layers
+    // cannot be removed; they are dropped with the client.
+    pub fn add_middleware<M: Middleware + 'static>(&self, layer: M) {
+        let mut chain = self
+            .context
+            .middleware
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut layers = chain.to_vec();
+        layers.push(Arc::new(layer));
+        *chain = layers.into();
+    }
+
+    // Registers `observer` for protocol events (see `Observer`), until the
+    // client is dropped. `Scope::observe` registers one for a scope only.
+    pub fn add_observer<O: Observer + 'static>(&self, observer: O) {
+        self.context.observers.add(Arc::new(observer));
+    }
+
+    pub(crate) fn add_shared_observer(&self, observer: Arc<dyn Observer>) {
+        self.context.observers.add(observer);
+    }
+
+    pub(crate) fn remove_observer(&self, observer: &Arc<dyn Observer>) {
+        self.context.observers.remove(observer);
+    }
+
+    // Records this client's operations to `log` (see `AuditLog`), until
+    // the client is dropped.
+    pub fn add_audit_log(&self, log: AuditLog) {
+        self.add_observer(log.for_client(self.client_id()));
+    }
+
+    // Hands publishes that fail for good to `dead_letters` (see
+    // `DeadLetters`); `None` turns it off.
+    pub fn set_dead_letters(&self, dead_letters: Option<DeadLetters>) {
+        *self
+            .context
+            .dead_letters
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = dead_letters.map(Arc::new);
+    }
+
+    // Encrypts what is published and decrypts what is received (see
+    // `Encryption`); `None` turns it off.
+    #[cfg(feature = "encryption")]
+    pub fn set_encryption(&self, encryption: Option<Encryption>) {
+        *self
+            .context
+            .encryption
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = encryption;
+    }
+
+    // Throttles `publish`; `None` removes the limit. Messages queued under
+    // the previous limit are dropped.
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) {
+        self.stop_rate_limit();
+        let Some(limit) = limit else {
+            return;
+        };
+        let limiter = Arc::new(RateLimiter::new(&limit));
+        if limiter.queues() {
+            let queue = limiter.clone();
+            let context = ContextRef(&*self.context);
+            *self
+                .rate_limit_thread
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(thread::spawn(move || {
+                while let Some(message) = queue.next_queued() {
+                    let context = unsafe { context.get() };
+                    if message.is_expired() {
+                        context.message_expired();
+                        context.dead_letter(&message, &Error::Expired);
+                        continue;
+                    }
+                    if let Err(error) = context.publish(&message) {
+                        context.dead_letter(&message, &error);
+                    }
+                }
+            }));
+        }
+        *self
+            .rate_limiter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(limiter);
+    }
+
+    // Returns how many queued messages were dropped.
+    fn stop_rate_limit(&self) -> usize {
+        let limiter = self
+            .rate_limiter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let dropped = limiter.map_or(0, |limiter| limiter.stop());
+        let thread = self
+            .rate_limit_thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+        dropped
+    }
+
+    // Runs `f` with a scope whose subscriptions, observers and heartbeats
+    // are removed when it returns, fails or panics. Heartbeat threads are joined before
+    // this returns.
+    pub fn scope<F, R>(&self, f: F) -> Result<R>
+    where
+        F: for<'scope, 'env> FnOnce(&Scope<'scope, 'env>) -> Result<R>,
+    {
+        let stop = Stop::default();
+        thread::scope(|threads| f(&Scope::new(self, threads, &stop)))
+    }
+
+    // Keeps the last message received on each topic, for `last_value` and
+    // `subscribe_with_replay`. Disabling drops what was cached. An empty
+    // retained message removes its topic.
+    pub fn set_last_values(&self, enabled: bool) {
+        let mut last_values = self
+            .context
+            .last_values
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match (enabled, last_values.is_some()) {
+            (true, false) => *last_values = Some(HashMap::new()),
+            (false, true) => *last_values = None,
+            _ => {}
+        }
+    }
+
+    pub fn last_value(&self, topic: &str) -> Option<Message> {
+        self.context
+            .last_values
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()?
+            .get(topic)
+            .cloned()
+    }
+
+    // Drops received messages already seen (see `InboundDedup`); `None`
+    // turns it off.
+    pub fn set_inbound_dedup(&self, dedup: Option<InboundDedup>) {
+        *self
+            .context
+            .inbound_dedup
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = dedup;
+    }
+
+    // Enables `publish_if_changed`, replacing any earlier dedup state.
+    pub fn set_publish_dedup(&self, dedup: PublishDedup) {
+        *self.dedup.lock().unwrap_or_else(PoisonError::into_inner) = Some(dedup);
+    }
+
+    // Publishes unless the payload equals the last one published to this
+    // topic through this method, per the client's `PublishDedup` (the
+    // default one if none was set). Returns None when skipped.
+    pub fn publish_if_changed(&self, message: &Message) -> Result<Option<i64>> {
+        let mut dedup = self.dedup.lock().unwrap_or_else(PoisonError::into_inner);
+        let dedup = dedup.get_or_insert_with(PublishDedup::default);
+        let Some(hash) = dedup.check(&message.topic, &message.payload, message.retained) else {
+            #[cfg(feature = "metrics")]
+            self.context.metrics.message_deduplicated();
+            return Ok(None);
+        };
+        let message_id = self.publish(message)?;
+        dedup.record(&message.topic, hash);
+        Ok(Some(message_id))
+    }
+
+    // Replaces the sampler applied to incoming messages before they reach
+    // the message callback.
+    pub fn set_sampler(&self, sampler: Sampler) {
+        *self
+            .context
+            .sampler
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = sampler;
+    }
+
+    // Counts every incoming message in `stats`, before sampling; `None`
+    // stops counting.
+    pub fn set_topic_stats(&self, stats: Option<Arc<TopicStats>>) {
+        *self
+            .context
+            .topic_stats
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = stats;
+    }
+
+    // Restricts the topics this client publishes to and the filters it
+    // subscribes to (see `TopicAcl`), which then fail with
+    // `Error::AccessDenied`; `None` lifts the restriction. Topics are
+    // checked before the namespace is added, and after middleware.
+    pub fn set_acl(&self, acl: Option<TopicAcl>) {
+        *self.acl.write().unwrap_or_else(PoisonError::into_inner) = acl;
+    }
+
+    // Caps the size of incoming payloads; `None` removes the cap. Checked
+    // before sampling and dispatch.
+    pub fn set_payload_limit(&self, limit: Option<PayloadLimit>) {
+        *self
+            .context
+            .payload_limit
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = limit;
+    }
+
+    // Hands incoming messages to dispatch threads through bounded queues,
+    // so that slow callbacks no longer hold up keep-alives and acks on the
+    // network thread; `None` goes back to dispatching inline. Messages still
+    // queued when the queue is replaced are dropped. The browser has no
+    // threads, so there messages are always dispatched inline.
+    pub fn set_inbound_queue(&self, queue: Option<InboundQueue>) {
+        self.stop_inbound();
+        let Some(queue) = queue.filter(|_| !cfg!(target_arch = "wasm32")) else {
+            return;
+        };
+        let inbound = Arc::new(Inbound::new(queue));
+        let threads = (0..inbound.workers()).map(|worker| {
+            let pending = inbound.clone();
+            let context = ContextRef(&*self.context);
+            thread::spawn(move || {
+                let context = unsafe { context.get() };
+                while let Some(message) = pending.pop(worker) {
+                    context.guard("message", || context.dispatch(&message.view()));
+                }
+            })
+        });
+        self.dispatch_threads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(threads);
+        *self
+            .context
+            .inbound
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(inbound);
+    }
+
+    // Replaces the error callback as the recipient of panics caught in
+    // callbacks and handlers, with the name of the callback ("message",
+    // "state", "error" or "delivery") and the panic message.
+    pub fn set_panic_hook<F>(&self, hook: F)
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        *self
+            .context
+            .panic_hook
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(hook));
+    }
+
+    // Panics caught in callbacks and handlers since the client was created.
+    pub fn callback_panics(&self) -> u64 {
+        self.context.callback_panics()
+    }
+
+    // Incoming messages dropped because the inbound queue was full or
+    // replaced, because they failed to decrypt or by middleware, since the
+    // client was created.
+    pub fn messages_dropped(&self) -> u64 {
+        self.context.messages_dropped.load(Ordering::Relaxed)
+    }
+
+    // Received messages dropped as duplicates by the inbound dedup, since
+    // the client was created.
+    pub fn duplicates_dropped(&self) -> u64 {
+        self.context.duplicates_dropped.load(Ordering::Relaxed)
+    }
+
+    // Messages not published because their expiry (`Message::with_expiry`)
+    // passed first, since the client was created.
+    pub fn messages_expired(&self) -> u64 {
+        self.context.messages_expired.load(Ordering::Relaxed)
+    }
+
+    fn stop_inbound(&self) {
+        let inbound = self
+            .context
+            .inbound
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(inbound) = inbound {
+            for _ in 0..inbound.stop() {
+                self.context.message_dropped();
+            }
+        }
+        let threads = std::mem::take(
+            &mut *self
+                .dispatch_threads
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+
+    // Disconnects, keeping the client for a later `connect`. Subscriptions
+    // are not restored on reconnect.
+    pub fn disconnect(&self) -> Result<()> {
+        self.stop_failover();
+        let _connecting = self.context.connecting();
+        if self.context.stop() != 0 {
+            Err(Error::ConnectionError)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Measures the round trip to the broker, for health checks or picking
+    // the closest broker. Blocks until the broker answers, so it must not be
+    // called from a callback, or for up to `timeout`, then failing with
+    // `Error::Timeout`. Paho cannot send a PINGREQ on demand, so the bridge
+    // times an UNSUBSCRIBE of `polar_mqtt/ping`, which the broker answers as
+    // promptly; one that timed out is still awaited, and pings fail with
+    // `Error::ConnectionError` until it is answered.
+    pub fn ping(&self, timeout: Duration) -> Result<Duration> {
+        runtime::needs_threads("ping")?;
+        if self.state() != ConnectionState::Connected {
+            return Err(Error::ConnectionError);
+        }
+        let session = *self.session();
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        let micros = match self.context.backend.ping(session, timeout_ms) {
+            backend::TIMEOUT => return Err(Error::Timeout),
+            micros => micros,
+        };
+        let rtt = u64::try_from(micros)
+            .map(Duration::from_micros)
+            .map_err(|_| Error::ConnectionError)?;
+        self.context
+            .observers
+            .each(|observer| observer.on_pingresp(rtt));
+        Ok(rtt)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        let session = *self.session();
+        self.context.backend.state(session)
+    }
+
+    // Waits up to `flush_timeout` for QoS 1/2 publishes to be acknowledged,
+    // removes every active subscription and stops the session. The browser
+    // cannot wait, so there the publishes still pending count as dropped.
+    pub fn shutdown(self, flush_timeout: Duration) -> ShutdownReport {
+        self.shutdown_with_cancel(flush_timeout, &CancellationToken::new())
+    }
+
+    // Like `shutdown`, but stops waiting for acknowledgements once `cancel`
+    // is cancelled; those still pending count as dropped.
+    pub fn shutdown_with_cancel(
+        self,
+        flush_timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> ShutdownReport {
+        let started = Instant::now();
+        // Messages still waiting for the rate limit count as dropped.
+        let queued_dropped = self.stop_rate_limit();
+
+        // An empty retained message removes the presence record.
+        if let Some(topic) = &self.client_id.presence_topic {
+            if let Ok(clear) = Message::new(topic, Vec::new()) {
+                let _ = self.publish(&clear.with_qos(QoS::AtLeastOnce).with_retain(true));
+            }
+        }
+
+        let (messages_flushed, messages_dropped) =
+            self.context.inflight.drain(flush_timeout, cancel);
+
+        self.stop_failover();
+        self.stop_reaper();
+        let handles: Vec<i64> = self
+            .context
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .map(|(_, subscription)| subscription.bridge_handle)
+            .collect();
+        let session = *self.session();
+        let subscriptions_removed = handles
+            .into_iter()
+            .filter(|&handle| self.context.backend.unsubscribe(session, handle) == 0)
+            .count();
+
+        self.context.backend.stop(session);
+
+        ShutdownReport {
+            messages_flushed,
+            messages_dropped: messages_dropped + queued_dropped,
+            subscriptions_removed,
+            elapsed: started.elapsed(),
+            final_state: self.state(),
+        }
+    }
+
+    pub(crate) unsafe extern "C" fn message_callback(
+        message: *const bindings::mqtt_message_data_t,
+        context: *mut std::ffi::c_void,
+    ) {
+        if message.is_null() || (*message).topic.is_null() || context.is_null() {
+            return;
+        }
+
+        let context = &*(context as *const CallbackContext);
+        context.guard("message", || {
+            context.touch();
+
+            let payload = if (*message).payload.is_null() || (*message).payload_length == 0 {
+                &[]
+            } else if (*message).payload_length > isize::MAX as usize {
+                #[cfg(feature = "metrics")]
+                context.metrics.message_oversized();
+                return;
+            } else {
+                std::slice::from_raw_parts((*message).payload, (*message).payload_length)
+            };
+
+            let topic = match CStr::from_ptr((*message).topic).to_str() {
+                Ok(s) => s,
+                Err(_) => return,
+            };
+            let topic = match &*context
+                .namespace
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                Some(namespace) => match topic.strip_prefix(namespace.as_str()) {
+                    Some(topic) => topic,
+                    None => {
+                        context.message_dropped();
+                        context.packet_dropped(topic, DropReason::OutsideNamespace);
+                        return;
+                    }
+                },
+                None => topic,
+            };
+
+            let qos = match (*message).qos {
+                0 => QoS::AtMostOnce,
+                1 => QoS::AtLeastOnce,
+                2 => QoS::ExactlyOnce,
+                _ => return,
+            };
+
+            let retained = (*message).retained != 0;
+
+            if context
+                .probe_topic
+                .get()
+                .is_some_and(|probe| probe == topic)
+            {
+                return;
+            }
+
+            if let Some(latency) = &*context
+                .latency
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                if latency.topic() == topic {
+                    latency.received(payload);
+                    return;
+                }
+            }
+
+            // Taken out of the lock, so that a handler may replace the limit.
+            let oversized = context
+                .payload_limit
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .as_ref()
+                .filter(|limit| payload.len() > limit.max_size)
+                .map(|limit| (limit.max_size, limit.policy.clone()));
+            let payload = match oversized {
+                Some((max_size, policy)) => {
+                    #[cfg(feature = "metrics")]
+                    context.metrics.message_oversized();
+                    match policy {
+                        OversizePolicy::Drop => {
+                            context.packet_dropped(topic, DropReason::Oversized);
+                            return;
+                        }
+                        OversizePolicy::Truncate => &payload[..max_size],
+                        OversizePolicy::Handler(handler) => {
+                            handler(&mut OversizedMessage::new(topic, payload, qos, retained));
+                            return;
+                        }
+                    }
+                }
+                None => payload,
+            };
+
+            #[cfg(feature = "encryption")]
+            let decrypted = match &*context
+                .encryption
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                Some(encryption) => match encryption.decrypt(topic, payload) {
+                    Ok(decrypted) => decrypted,
+                    Err(_) => {
+                        context.message_dropped();
+                        context.packet_dropped(topic, DropReason::Undecryptable);
+                        return;
+                    }
+                },
+                None => None,
+            };
+            #[cfg(feature = "encryption")]
+            let payload = decrypted.as_deref().unwrap_or(payload);
+
+            // Inflated no further than the payload limit; a payload that
+            // fails to decompress is delivered as received.
+            #[cfg(any(feature = "zstd", feature = "gzip"))]
+            let decompressed = compression::decompress(
+                payload,
+                context
+                    .payload_limit
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_ref()
+                    .map_or(usize::MAX, |limit| limit.max_size),
+            );
+            #[cfg(any(feature = "zstd", feature = "gzip"))]
+            let payload = match &decompressed {
+                Some(Ok(data)) => data.as_slice(),
+                _ => payload,
+            };
+
+            let chain = context.middleware();
+            let layered;
+            let (topic, payload, qos, retained) = if chain.is_empty() {
+                (topic, payload, qos, retained)
+            } else {
+                let mut owned = Message {
+                    topic: topic.to_string(),
+                    payload: payload.to_vec(),
+                    qos,
+                    retained,
+                    expires_at: None,
+                };
+                if let Action::Drop = middleware::run(&chain, &mut owned, false) {
+                    context.message_dropped();
+                    context.packet_dropped(topic, DropReason::Middleware);
+                    return;
+                }
+                layered = owned;
+                (
+                    layered.topic.as_str(),
+                    layered.payload.as_slice(),
+                    layered.qos,
+                    layered.retained,
+                )
+            };
+
+            let msg = MessageView {
+                topic,
+                payload,
+                qos,
+                retained,
+                shared: Default::default(),
+            };
+
+            #[cfg(feature = "chaos")]
+            if let Some(chaos) = context.chaos() {
+                chaos.intercept(&msg, |msg| context.accept(msg));
+                return;
+            }
+            context.accept(&msg);
+        });
+    }
+
+    unsafe extern "C" fn state_callback(
+        state: bindings::mqtt_session_state_t,
+        context: *mut std::ffi::c_void,
+    ) {
+        if context.is_null() {
+            return;
+        }
+
+        let context = &*(context as *const CallbackContext);
+        context.guard("state", || {
+            context.touch();
+            let state = state.into();
+
+            // Acks for publishes in flight when the session went down never arrive.
+            if state == ConnectionState::Disconnected {
+                context.inflight.clear();
+            }
+
+            // The bridge reports a lost connection as reconnecting, but only
+            // failover actually reconnects.
+            if state == ConnectionState::Reconnecting {
+                if let Some(failover) = &*context
+                    .failover
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                {
+                    failover.connection_lost();
+                }
+            }
+
+            #[cfg(feature = "metrics")]
+            context.metrics.state_changed(state);
+
+            #[cfg(feature = "tracing")]
+            context.instrumentation.state_changed(state);
+
+            context
+                .observers
+                .each(|observer| observer.on_state_change(state));
+            (context.state_callback)(state);
+        });
+    }
+
+    pub(crate) unsafe extern "C" fn error_callback(
+        error_code: std::os::raw::c_int,
+        message: *const std::os::raw::c_char,
+        context: *mut std::ffi::c_void,
+    ) {
+        if message.is_null() || context.is_null() {
+            return;
+        }
+
+        let context = &*(context as *const CallbackContext);
+        context.guard("error", || {
+            context.touch();
+            let error_msg = CStr::from_ptr(message)
+                .to_str()
+                .unwrap_or("Invalid error message");
+
+            context.last_error.store(error_code, Ordering::SeqCst);
+
+            #[cfg(feature = "metrics")]
+            context.metrics.error();
+
+            #[cfg(feature = "tracing")]
+            context.instrumentation.error(error_code, error_msg);
+
+            (context.error_callback)(error_code, error_msg);
+        });
+    }
+
+    unsafe extern "C" fn delivery_callback(message_id: i64, context: *mut std::ffi::c_void) {
+        if context.is_null() {
+            return;
+        }
+
+        let context = &*(context as *const CallbackContext);
+        context.guard("delivery", || {
+            context.touch();
+            let acked = context.inflight.complete(message_id);
+
+            #[cfg(feature = "metrics")]
+            if let Some(latency) = acked {
+                context.metrics.publish_acked(latency);
+            }
+            context
+                .observers
+                .each(|observer| observer.on_puback(message_id, acked));
+        });
+    }
+}
+
+impl CallbackContext {
+    // Detached from any session until `Client::new` creates one.
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "tracing")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn new(
+        client_id: &str,
+        backend: Arc<dyn Backend>,
+        presence: Option<Message>,
+        message_callback: Box<MessageCallback>,
+        state_callback: Box<StateCallback>,
+        error_callback: Box<ErrorCallback>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            backend,
+            session: RwLock::new(std::ptr::null_mut()),
+            connecting: Mutex::new(()),
+            broker: Mutex::new(None),
+            address: Mutex::new(None),
+            broker_limits: RwLock::new(BrokerLimits::default()),
+            max_packet_size: AtomicU32::new(0),
+            namespace: RwLock::new(None),
+            address_family: RwLock::new(None),
+            resolver: RwLock::new(None),
+            failover: RwLock::new(None),
+            presence,
+            message_callback,
+            handlers: RwLock::new(Vec::new()),
+            subscriptions: Mutex::new(HashMap::new()),
+            last_values: RwLock::new(None),
+            state_callback,
+            error_callback,
+            inflight: Arc::default(),
+            last_error: AtomicI32::new(0),
+            created: Instant::now(),
+            last_activity: AtomicU64::new(0),
+            probe_topic: OnceLock::new(),
+            latency: RwLock::new(None),
+            sampler: RwLock::new(Sampler::default()),
+            topic_stats: RwLock::new(None),
+            payload_limit: RwLock::new(None),
+            inbound: RwLock::new(None),
+            messages_dropped: AtomicU64::new(0),
+            messages_expired: AtomicU64::new(0),
+            inbound_dedup: RwLock::new(None),
+            duplicates_dropped: AtomicU64::new(0),
+            panic_hook: RwLock::new(None),
+            callback_panics: AtomicU64::new(0),
+            middleware: RwLock::new(Arc::new([])),
+            observers: Observers::default(),
+            dead_letters: RwLock::new(None),
+            #[cfg(feature = "encryption")]
+            encryption: RwLock::new(None),
+            #[cfg(feature = "chaos")]
+            chaos: RwLock::new(None),
+            #[cfg(feature = "metrics")]
+            metrics: ClientMetrics::new(client_id),
+            #[cfg(feature = "tracing")]
+            instrumentation: Instrumentation::new(client_id),
+        })
+    }
+
+    pub(crate) fn callback_panics(&self) -> u64 {
+        self.callback_panics.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "chaos")]
+    fn chaos(&self) -> Option<Arc<Chaos>> {
+        self.chaos
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn middleware(&self) -> Arc<[Arc<dyn Middleware>]> {
+        self.middleware
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn session(&self) -> RwLockReadGuard<'_, Session> {
+        self.session.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stop(&self) -> i32 {
+        let session = *self.session();
+        self.backend.stop(session)
+    }
+
+    fn connecting(&self) -> MutexGuard<'_, ()> {
+        self.connecting
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Connects the session, configured beforehand, to `host` and publishes
+    // the presence record.
+    fn start(&self, host: &str, port: u16) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = self.instrumentation.connect_span(host, port).entered();
+
+        let result = self.open(host, port);
+        self.observers
+            .each(|observer| observer.on_connect(host, port, result.as_ref().err()));
+        result?;
+
+        if let Some(presence) = &self.presence {
+            self.publish(presence)?;
+        }
+
+        Ok(())
+    }
+
+    fn open(&self, host: &str, port: u16) -> Result<()> {
+        let family = *self
+            .address_family
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let resolver = self
+            .resolver
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        // The backend connects to the chosen address, if any.
+        let (broker_host, address) = match (family, resolver) {
+            #[cfg(not(target_arch = "wasm32"))]
+            (family, resolver) if family.is_some() || resolver.is_some() => {
+                let address = crate::resolve::select_address(
+                    host,
+                    port,
+                    family.unwrap_or_default(),
+                    resolver.as_ref(),
+                )?;
+                (CString::new(address.ip().to_string())?, Some(address))
+            }
+            _ => (
+                CString::new(host)?,
+                host.trim_matches(['[', ']'])
+                    .parse::<IpAddr>()
+                    .ok()
+                    .map(|ip| SocketAddr::new(ip, port)),
+            ),
+        };
+
+        let session = *self.session();
+        let broker_port = address.map_or(port, |address| address.port());
+        let result = self.backend.set_broker(session, &broker_host, broker_port);
+
+        if result != 0 {
+            return Err(Error::InvalidBrokerUrl(format!("{}:{}", host, port)));
+        }
+
+        self.last_error.store(0, Ordering::SeqCst);
+        let result = self.backend.start(session);
+
+        if result != 0 {
+            return Err(connect_error(self.last_error.load(Ordering::SeqCst)));
+        }
+        *self.broker.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((host.to_string(), port));
+        *self.address.lock().unwrap_or_else(PoisonError::into_inner) = address;
+        Ok(())
+    }
+
+    // Moves a lost session over to `host`, keeping its subscriptions.
+    fn restart(&self, host: &str, port: u16) -> Result<()> {
+        let _connecting = self.connecting();
+        self.stop();
+        self.start(host, port)?;
+        self.resubscribe();
+        Ok(())
+    }
+
+    // Subscribes every filter again on the current session, under the same
+    // handles. Returns how many succeeded.
+    fn resubscribe(&self) -> usize {
+        let session = *self.session();
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut restored = 0;
+        for subscription in subscriptions.values_mut() {
+            let Ok(filter) = CString::new(subscription.filter.as_str()) else {
+                continue;
+            };
+            let bridge_handle = self.backend.subscribe(session, &filter, subscription.qos);
+            self.observers.each(|observer| {
+                observer.on_suback(&subscription.filter, subscription.qos, bridge_handle >= 0)
+            });
+            if bridge_handle >= 0 {
+                subscription.bridge_handle = bridge_handle;
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    pub fn publish(&self, message: &Message) -> Result<i64> {
+        let topic = CString::new(&*self.namespaced(&message.topic))?;
+        self.publish_raw(&topic, &message.payload, message.qos, message.retained)
+    }
+
+    // `c_topic` is the topic as sent, namespace included.
+    fn publish_raw(&self, c_topic: &CStr, payload: &[u8], qos: QoS, retained: bool) -> Result<i64> {
+        let topic = c_topic.to_str().unwrap_or_default();
+        #[cfg(feature = "tracing")]
+        let _span = self
+            .instrumentation
+            .publish_span(topic, qos, payload.len())
+            .entered();
+
+        let limit = match self.max_packet_size.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        };
+        let checked =
+            self.broker_limits()
+                .check_publish(topic, payload.len(), qos, retained, limit);
+        checked.map_err(|error| self.publish_failed(topic, qos, payload.len(), error))?;
+
+        let started = Instant::now();
+        let session = *self.session();
+        let message_id = self
+            .backend
+            .publish(session, c_topic, payload, qos, retained);
+
+        if message_id < 0 {
+            #[cfg(feature = "metrics")]
+            self.metrics.error();
+            let error = Error::PublicationError(self.backend.last_error());
+            Err(self.publish_failed(topic, qos, payload.len(), error))
+        } else {
+            #[cfg(feature = "metrics")]
+            self.metrics.message_published(payload.len());
+            self.observers
+                .each(|observer| observer.on_publish_sent(topic, message_id, qos, payload.len()));
+            if qos != QoS::AtMostOnce {
+                // Acknowledged before it could be registered.
+                if let Some(latency) = self.inflight.register(message_id, started) {
+                    #[cfg(feature = "metrics")]
+                    self.metrics.publish_acked(latency);
+                    self.observers
+                        .each(|observer| observer.on_puback(message_id, Some(latency)));
+                }
+            }
+            Ok(message_id)
+        }
+    }
+
+    fn dead_letter(&self, message: &Message, error: &Error) {
+        let Some(reason) = DeadLetterReason::of(error) else {
+            return;
+        };
+        let dead_letters = self
+            .dead_letters
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if let Some(dead_letters) = dead_letters {
+            dead_letters.send(message, reason, error);
+        }
+    }
+
+    // Tells the observers, and hands back `error`.
+    fn publish_failed(&self, topic: &str, qos: QoS, bytes: usize, error: Error) -> Error {
+        self.observers
+            .each(|observer| observer.on_publish_failed(topic, qos, bytes, &error));
+        error
+    }
+
+    // `topic`, or a filter, as the broker sees it.
+    fn namespaced<'a>(&self, topic: &'a str) -> Cow<'a, str> {
+        match &*self
+            .namespace
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(namespace) => Cow::Owned(format!("{}{}", namespace, topic)),
+            None => Cow::Borrowed(topic),
+        }
+    }
+
+    fn broker_limits(&self) -> RwLockReadGuard<'_, BrokerLimits> {
+        self.broker_limits
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn touch(&self) {
+        let now = self.created.elapsed().as_millis() as u64;
+        self.last_activity.store(now, Ordering::Relaxed);
+    }
+
+    // Runs a callback, containing panics: unwinding into the bridge is
+    // undefined behaviour. A panic is counted and reported to the panic hook,
+    // or to the error callback with `PANIC_ERROR_CODE`.
+    fn guard(&self, callback: &str, f: impl FnOnce()) {
+        let Err(panic) = panic::catch_unwind(AssertUnwindSafe(f)) else {
+            return;
+        };
+        self.callback_panics.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.callback_panicked();
+
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("non-string panic payload");
+        // Either may panic in turn; that one is dropped.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            match &*self
+                .panic_hook
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+            {
+                Some(hook) => hook(callback, message),
+                None => (self.error_callback)(
+                    PANIC_ERROR_CODE,
+                    &format!("{} callback panicked: {}", callback, message),
+                ),
+            }
+        }));
+    }
+
+    fn message_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.message_dropped();
+    }
+
+    fn packet_dropped(&self, topic: &str, reason: DropReason) {
+        self.observers
+            .each(|observer| observer.on_packet_dropped(topic, reason));
+    }
+
+    fn message_expired(&self) {
+        self.messages_expired.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.message_expired();
+    }
+
+    // Counts a received message and passes it on to the dispatch, unless it
+    // is a duplicate or sampled out.
+    fn accept(&self, msg: &MessageView) {
+        #[cfg(feature = "metrics")]
+        self.metrics.message_received(msg.payload.len());
+
+        if let Some(stats) = &*self
+            .topic_stats
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            stats.record(msg.topic, msg.payload.len());
+        }
+
+        if self
+            .inbound_dedup
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|dedup| dedup.is_duplicate(msg.topic, msg.payload))
+        {
+            self.duplicates_dropped.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            self.metrics.duplicate_dropped();
+            self.packet_dropped(msg.topic, DropReason::Duplicate);
+            return;
+        }
+
+        if !self
+            .sampler
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .admit(msg.topic)
+        {
+            #[cfg(feature = "metrics")]
+            self.metrics.message_sampled_out();
+            self.packet_dropped(msg.topic, DropReason::SampledOut);
+            return;
+        }
+
+        // Not held while pushing, which may block.
+        let inbound = self
+            .inbound
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        match inbound {
+            Some(inbound) => {
+                if inbound.push(msg.to_owned()) {
+                    self.message_dropped();
+                    self.packet_dropped(msg.topic, DropReason::QueueFull);
+                }
+            }
+            None => self.dispatch(msg),
+        }
+    }
+
+    // Runs the handlers and the message callback, on the network thread or
+    // on the dispatch thread of the inbound queue.
+    fn dispatch(&self, msg: &MessageView) {
+        #[cfg(feature = "tracing")]
+        let span = self
+            .instrumentation
+            .dispatch_span(msg.topic, msg.payload.len());
+        #[cfg(feature = "otel")]
+        crate::otel::link(&span, msg.payload);
+        #[cfg(feature = "tracing")]
+        let _span = span.entered();
+
+        let handlers = self.handlers.read().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(values) = &mut *self
+            .last_values
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            if msg.retained && msg.payload.is_empty() {
+                values.remove(msg.topic);
+            } else {
+                values.insert(msg.topic.to_string(), msg.to_owned());
+            }
+        }
+
+        // Called once the lock is released, so that a handler can subscribe
+        // and unsubscribe.
+        let matching: Vec<_> = handlers
+            .iter()
+            .filter(|h| crate::topic::matches(h.filter.as_str(), msg.topic))
+            .map(|h| Arc::clone(&h.callback))
+            .collect();
+        drop(handlers);
+        for callback in matching {
+            callback(msg);
+        }
+
+        (self.message_callback)(msg);
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.stop_rate_limit();
+        self.stop_inbound();
+        self.stop_failover();
+        self.stop_reaper();
+        let session = *self.session();
+        self.context.backend.stop(session);
+        self.context.backend.destroy_session(session);
+    }
+}
+
+unsafe impl Send for Client {}
+unsafe impl Sync for Client {}
+
+// Paho returns the CONNACK code when the broker refuses a connection.
+fn connect_error(code: i32) -> Error {
+    let reason = match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => return Error::ConnectionError,
+    };
+    Error::ConnectionRefused {
+        code,
+        reason: reason.to_string(),
+    }
+}
+
+// The result of setting `option` on the backend.
+fn option_result(result: i32, option: &str) -> Result<()> {
+    match result {
+        0 => Ok(()),
+        backend::UNSUPPORTED => Err(Error::Unsupported(option.to_string())),
+        _ => Err(Error::ConnectionError),
+    }
+}
+
+// Removes the subscription from the bridge and forgets its handlers.
+fn remove_subscription(context: &CallbackContext, handle: i64) -> Result<()> {
+    let mut subscriptions = context
+        .subscriptions
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let subscription = subscriptions
+        .get(&handle)
+        .ok_or_else(|| Error::SubscriptionError(format!("Unknown handle {}", handle)))?;
+    let bridge_handle = subscription.bridge_handle;
+    let session = *context
+        .session
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    if context.backend.unsubscribe(session, bridge_handle) != 0 {
+        return Err(Error::SubscriptionError(context.backend.last_error()));
+    }
+    if let Some(subscription) = subscriptions.remove(&handle) {
+        context
+            .observers
+            .each(|observer| observer.on_unsuback(&subscription.filter));
+    }
+    drop(subscriptions);
+    context
+        .handlers
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|h| h.handle != handle);
+    Ok(())
+}
+
+// The context as seen from the client's own threads: failover, the rate
+// limit queue, the inbound dispatch workers and the lease reaper. Each is
+// joined by its `stop_*` method, and `Drop for Client` calls them all
+// before the context is freed, so the pointer never dangles.
+struct ContextRef(*const CallbackContext);
+
+unsafe impl Send for ContextRef {}
+
+impl ContextRef {
+    unsafe fn get(&self) -> &CallbackContext {
+        &*self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::testing::EmbeddedBroker;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_runtime_reinitializes_after_last_client() {
+        for _ in 0..2 {
+            let client = Client::new(
+                format!("TestClient_{}", uuid::Uuid::new_v4()),
+                |_| {},
+                |_| {},
+                |_, _| {},
+            )
+            .unwrap();
+            assert!(crate::is_initialized());
+            drop(client);
+        }
+    }
+
+    #[test]
+    fn test_scope_stops_heartbeats() {
+        let client = Client::new(
+            format!("TestClient_{}", uuid::Uuid::new_v4()),
+            |_| {},
+            |_| {},
+            |_, _| {},
+        )
+        .unwrap();
+        let started = Instant::now();
+        let result = client.scope(|scope| {
+            let beat = Message::new("test/heartbeat", b"alive")?;
+            scope.heartbeat(beat, Duration::from_secs(60));
+            thread::sleep(Duration::from_millis(20));
+            Ok(42)
+        });
+        assert_eq!(result.unwrap(), 42);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_fake_backend_tracks_subscriptions() {
+        let fake = Arc::new(Fake::default());
+        let (tx, rx) = mpsc::channel();
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let states = states.clone();
+            Client::with_backend(
+                "fake",
+                fake.clone(),
+                move |msg| tx.send(msg.topic().to_string()).unwrap(),
+                move |state| states.lock().unwrap().push(state),
+                |_, _| {},
+            )
+            .unwrap()
+        };
+        assert!(matches!(
+            client.subscribe("a/#", QoS::AtLeastOnce),
+            Err(Error::SubscriptionError(reason)) if reason == "not connected"
+        ));
+
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("a/#", QoS::AtLeastOnce).unwrap();
+        let second = client.subscribe("b/+", QoS::AtMostOnce).unwrap();
+        assert_eq!(fake.filters(), ["a/#", "b/+"]);
+        client.publish(&Message::new("a/1", "x").unwrap()).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), "a/1");
+
+        // A lost session comes back with its subscriptions.
+        fake.drop_connections();
+        assert_eq!(client.reconnect().unwrap(), 2);
+        assert_eq!(fake.filters(), ["a/#", "b/+"]);
+        client.unsubscribe(second).unwrap();
+        fake.inject(Message::new("b/1", "y").unwrap());
+        fake.inject(Message::new("a/2", "y").unwrap());
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["a/2"]);
+
+        let report = client.shutdown(Duration::from_secs(1));
+        assert_eq!(report.subscriptions_removed, 1);
+        assert!(fake.filters().is_empty());
+        assert_eq!(fake.published().len(), 3);
+        use ConnectionState::*;
+        assert_eq!(
+            *states.lock().unwrap(),
+            [
+                Connected,
+                Reconnecting,
+                Disconnected,
+                Connected,
+                Disconnected
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ping_times_out_without_an_answer() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("ping", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        assert!(matches!(
+            client.ping(Duration::from_secs(1)),
+            Err(Error::ConnectionError)
+        ));
+
+        client.connect("broker", 1883).unwrap();
+        assert_eq!(
+            client.ping(Duration::from_secs(1)).unwrap(),
+            Duration::from_micros(1)
+        );
+        fake.cut_off();
+        assert!(matches!(
+            client.ping(Duration::from_millis(10)),
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_max_inflight_reaches_the_backend() {
+        let fake = Arc::new(Fake::default());
+        let client =
+            Client::with_backend("inflight", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        let max_inflight = bindings::mqtt_parameter_t_MQTT_PARAM_MAX_INFLIGHT;
+        client.connect("broker", 1883).unwrap();
+        assert_eq!(fake.int_parameter(max_inflight), None);
+
+        let options = ConnectOptions::new().with_max_inflight(32);
+        client.connect_with("broker", 1883, &options).unwrap();
+        assert_eq!(fake.int_parameter(max_inflight), Some(32));
+        let options = ConnectOptions::new().with_max_inflight(0);
+        client.connect_with("broker", 1883, &options).unwrap();
+        assert_eq!(fake.int_parameter(max_inflight), Some(1));
+    }
+
+    #[test]
+    fn test_reload_tls_reconnects_with_new_identity() {
+        let fake = Arc::new(Fake::default());
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let states = states.clone();
+            Client::with_backend(
+                "fake",
+                fake.clone(),
+                |_| {},
+                move |state| states.lock().unwrap().push(state),
+                |_, _| {},
+            )
+            .unwrap()
+        };
+        let rotated = TlsOptions::new().with_client_cert("device-2.crt", "device-2.key");
+        assert!(client.reload_tls(rotated.clone()).is_err());
+
+        let options = ConnectOptions::new().with_tls(TlsOptions::new().with_ca_file("ca.pem"));
+        client.connect_with("broker", 8883, &options).unwrap();
+        client.subscribe("a/#", QoS::AtLeastOnce).unwrap();
+        assert_eq!(client.reload_tls(rotated.clone()).unwrap(), 1);
+        assert_eq!(fake.filters(), ["a/#"]);
+        assert_eq!(client.state(), ConnectionState::Connected);
+        let stored = client.options.lock().unwrap().clone().unwrap();
+        assert_eq!(stored.tls(), Some(&rotated));
+
+        use ConnectionState::*;
+        assert_eq!(
+            *states.lock().unwrap(),
+            [Connected, Disconnected, Connected]
+        );
+    }
+
+    #[test]
+    fn test_address_selection_is_refused_with_tls() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("fake", fake, |_| {}, |_| {}, |_, _| {}).unwrap();
+        let options = ConnectOptions::new().with_address_family(AddressFamily::Ipv4);
+        assert!(matches!(
+            client.connect_uri_with("ssl://localhost:8883", &options),
+            Err(Error::InvalidOptions(_))
+        ));
+        let tls = options.clone().with_tls(TlsOptions::new());
+        assert!(matches!(
+            client.connect_with("localhost", 8883, &tls),
+            Err(Error::InvalidOptions(_))
+        ));
+        let resolver = ConnectOptions::new().with_resolver(|_| Vec::new());
+        assert!(matches!(
+            client.connect_uri_with("wss://localhost:443/mqtt", &resolver),
+            Err(Error::InvalidOptions(_))
+        ));
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn test_server_name_is_unsupported() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("sni", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        let tls = TlsOptions::new().with_server_name("broker.example.com");
+        let options = ConnectOptions::new().with_tls(tls.clone());
+        assert!(matches!(
+            client.connect_with("192.0.2.1", 8883, &options),
+            Err(Error::Unsupported(option)) if option == "TLS server name"
+        ));
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+
+        let tls = TlsOptions {
+            server_name: None,
+            ..tls
+        };
+        client
+            .connect_with("192.0.2.1", 8883, &ConnectOptions::new().with_tls(tls))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_namespace_prefixes_topics_and_strips_deliveries() {
+        let fake = Arc::new(Fake::default());
+        let (tx, rx) = mpsc::channel();
+        let client = Client::with_backend(
+            "tenant",
+            fake.clone(),
+            move |msg| tx.send(msg.topic().to_string()).unwrap(),
+            |_| {},
+            |_, _| {},
+        )
+        .unwrap();
+        let options = ConnectOptions::new()
+            .with_namespace("tenants/42")
+            .with_will(Message::new("status", "gone").unwrap());
+        client.connect_with("broker", 1883, &options).unwrap();
+        client.subscribe("a/#", QoS::AtLeastOnce).unwrap();
+        assert_eq!(fake.filters(), ["tenants/42/a/#"]);
+
+        client.publish(&Message::new("a/1", "x").unwrap()).unwrap();
+        client
+            .publish_raw("a/2", b"y", QoS::AtMostOnce, false)
+            .unwrap();
+        let sent: Vec<String> = fake.published().into_iter().map(|m| m.topic).collect();
+        assert_eq!(sent, ["tenants/42/a/1", "tenants/42/a/2"]);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["a/1", "a/2"]);
+
+        assert!(client
+            .connect_with("broker", 1883, &ConnectOptions::new().with_namespace("a/+"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_gather_reuses_staging_buffer() {
+        let header = [1u8, 2];
+        let body = vec![3u8; 100];
+        let slices = [IoSlice::new(&header), IoSlice::new(&body)];
+        let first = gather(&slices, |payload| {
+            assert_eq!(payload.len(), 102);
+            assert_eq!(&payload[..3], &[1, 2, 3]);
+            // Nested calls get a buffer of their own.
+            gather(&slices[..1], |inner| assert_eq!(inner, &[1, 2]));
+            payload.as_ptr()
+        });
+        assert_eq!(gather(&slices, |payload| payload.as_ptr()), first);
+    }
+
+    #[test]
+    fn test_publish_raw_interns_topics() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("raw", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        client.connect("broker", 1883).unwrap();
+
+        client
+            .publish_raw("a/b", b"1", QoS::AtMostOnce, false)
+            .unwrap();
+        client
+            .publish_raw("a/b", b"2", QoS::AtLeastOnce, true)
+            .unwrap();
+        assert!(matches!(
+            client.publish_raw("a/+", b"3", QoS::AtMostOnce, false),
+            Err(Error::InvalidTopic)
+        ));
+        let published = fake.published();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[1].topic(), "a/b");
+        assert_eq!(published[1].payload(), b"2");
+        assert!(published[1].is_retained());
+        let interned = client.interned_topics.read().unwrap();
+        assert_eq!(interned.keys().collect::<Vec<_>>(), ["a/b"]);
+    }
+
+    #[test]
+    fn test_expired_messages_are_not_published() {
+        let fake = Arc::new(Fake::default());
+        let client =
+            Client::with_backend("expiry", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        client.connect("broker", 1883).unwrap();
+
+        let message = Message::new("sensors/t", "21.5").unwrap();
+        let stale = message.clone().with_expiry(Duration::ZERO);
+        assert!(matches!(client.publish(&stale), Err(Error::Expired)));
+        assert_eq!(client.messages_expired(), 1);
+        assert!(fake.published().is_empty());
+
+        let fresh = message.with_expiry(Duration::from_secs(60));
+        client.publish(&fresh).unwrap();
+        assert_eq!(fake.published().len(), 1);
+        assert_eq!(client.messages_expired(), 1);
+    }
+
+    #[test]
+    fn test_fetch_retained_stops_at_the_first_live_message() {
+        let fake = Arc::new(Fake::default());
+        let client =
+            Client::with_backend("retained", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        client.connect("broker", 1883).unwrap();
+
+        client.clear_retained("config/a").unwrap();
+        let cleared = &fake.published()[0];
+        assert!(cleared.is_retained() && cleared.payload().is_empty());
+
+        // The fake retains nothing, so the broker's reply is injected once
+        // the subscription is in place.
+        let broker = {
+            let fake = fake.clone();
+            thread::spawn(move || {
+                while fake.filters().is_empty() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                for (topic, retained) in
+                    [("config/a", true), ("config/b", true), ("config/c", false)]
+                {
+                    let message = Message::new(topic, "v").unwrap().with_retain(retained);
+                    fake.inject(message);
+                }
+            })
+        };
+        let retained = client
+            .fetch_retained("config/#", Duration::from_secs(5))
+            .unwrap();
+        broker.join().unwrap();
+        let topics: Vec<_> = retained.iter().map(Message::topic).collect();
+        assert_eq!(topics, ["config/a", "config/b"]);
+        assert!(fake.filters().is_empty());
+    }
+
+    #[test]
+    fn test_resolver_picks_the_broker_address() {
+        let fake = Arc::new(Fake::default());
+        let client =
+            Client::with_backend("resolver", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = ConnectOptions::new().with_resolver(move |host| match host {
+            "broker.internal" => vec![address],
+            _ => Vec::new(),
+        });
+
+        assert!(matches!(
+            client.connect_with("elsewhere", 1883, &options),
+            Err(Error::InvalidBrokerUrl(_))
+        ));
+        client
+            .connect_with("broker.internal", 1883, &options)
+            .unwrap();
+        assert_eq!(client.peer_address(), Some(address));
+    }
+
+    #[test]
+    fn test_unreadable_pkcs12_bundle_fails_the_connect() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let errors = errors.clone();
+            Client::new(
+                format!("TestClient_{}", uuid::Uuid::new_v4()),
+                |_| {},
+                |_| {},
+                move |_, message| errors.lock().unwrap().push(message.to_string()),
+            )
+            .unwrap()
+        };
+        let dir = std::env::temp_dir().join(format!("polar-mqtt-p12-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let garbage = dir.join("garbage.p12");
+        std::fs::write(&garbage, b"not a bundle").unwrap();
+        // A self-signed identity exported with the password "secret".
+        let bundle = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/identity.p12");
+
+        for (file, password) in [(garbage.to_str().unwrap(), "secret"), (bundle, "wrong")] {
+            let tls = TlsOptions::new().with_pkcs12(file, password);
+            let options = ConnectOptions::new().with_tls(tls);
+            assert!(client.connect_with("localhost", 8883, &options).is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        let errors = errors.lock().unwrap();
+        let failures = errors
+            .iter()
+            .filter(|message| *message == "Failed to read PKCS#12 bundle");
+        assert_eq!(failures.count(), 2);
+    }
+
+    #[test]
+    fn test_errors_carry_bridge_reason() {
+        let client = Client::new(
+            format!("TestClient_{}", uuid::Uuid::new_v4()),
+            |_| {},
+            |_| {},
+            |_, _| {},
+        )
+        .unwrap();
+        let message = Message::new("test/unconnected", b"x").unwrap();
+        match client.publish(&message) {
+            Err(Error::PublicationError(reason)) => assert!(!reason.is_empty()),
+            other => panic!("{:?}", other),
+        }
+        match client.publish_raw("test/unconnected", b"x", QoS::AtLeastOnce, false) {
+            Err(Error::PublicationError(reason)) => assert!(!reason.is_empty()),
+            other => panic!("{:?}", other),
+        }
+        assert!(client
+            .interned_topics
+            .read()
+            .unwrap()
+            .contains_key("test/unconnected"));
+        assert!(matches!(
+            client.publish_raw("test/+", b"x", QoS::AtMostOnce, false),
+            Err(Error::InvalidTopic)
+        ));
+        match client.unsubscribe(42) {
+            Err(Error::SubscriptionError(reason)) => assert_eq!(reason, "Unknown handle 42"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_callback_panics_are_contained() {
+        let (tx, rx) = mpsc::channel();
+        let client = Client::new(
+            format!("TestClient_{}", uuid::Uuid::new_v4()),
+            |_| {},
+            |_| panic!("state boom"),
+            move |code, message| tx.send((code, message.to_string())).unwrap(),
+        )
+        .unwrap();
+        let context = &*client.context as *const CallbackContext as *mut std::ffi::c_void;
+
+        unsafe {
+            Client::state_callback(bindings::mqtt_session_state_t_MQTT_STATE_CONNECTED, context)
+        };
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            (
+                PANIC_ERROR_CODE,
+                "state callback panicked: state boom".to_string()
+            )
+        );
+
+        let (hook_tx, hook_rx) = mpsc::channel();
+        client.set_panic_hook(move |callback, message| {
+            hook_tx.send(format!("{}: {}", callback, message)).unwrap()
+        });
+        unsafe {
+            Client::state_callback(bindings::mqtt_session_state_t_MQTT_STATE_CONNECTED, context)
+        };
+        assert_eq!(hook_rx.try_recv().unwrap(), "state: state boom");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(client.callback_panics(), 2);
+    }
+
+    #[test]
+    fn test_recreate_session_needs_connect() {
+        let client = Client::new(
+            format!("TestClient_{}", uuid::Uuid::new_v4()),
+            |_| {},
+            |_| {},
+            |_, _| {},
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(client.idle_for() >= Duration::from_millis(20));
+        assert!(matches!(
+            client.recreate_session(),
+            Err(Error::ConnectionError)
+        ));
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
 
     #[test]
     fn test_integration() {
@@ -250,15 +3180,11 @@ mod tests {
         let test_topic = format!("test/topic/{}", uuid::Uuid::new_v4());
         let test_topic_clone = test_topic.clone();
 
-        let mut client = Client::new(
-            &format!("TestClient_{}", uuid::Uuid::new_v4()),
+        let client = Client::new(
+            format!("TestClient_{}", uuid::Uuid::new_v4()),
             move |msg| {
                 if msg.topic() == test_topic_clone {
-                    let _ = tx.lock().unwrap().send(
-                        Message::new(msg.topic(), msg.payload().to_vec())
-                            .with_qos(msg.qos())
-                            .with_retain(msg.is_retained()),
-                    );
+                    let _ = tx.lock().unwrap().send(msg.to_owned());
                 }
             },
             |state| eprintln!("State: {:?}", state),
@@ -274,7 +3200,8 @@ mod tests {
             }
         };
 
-        client.connect("broker.emqx.io", 1883).unwrap();
+        let broker = EmbeddedBroker::start().unwrap();
+        client.connect(&broker.host(), broker.port()).unwrap();
         check_errors();
 
         client.subscribe(&test_topic, QoS::AtLeastOnce).unwrap();
@@ -282,7 +3209,9 @@ mod tests {
         thread::sleep(Duration::from_secs(1));
         check_errors();
 
-        let message = Message::new(&test_topic, b"test").with_qos(QoS::AtLeastOnce);
+        let message = Message::new(&test_topic, b"test")
+            .unwrap()
+            .with_qos(QoS::AtLeastOnce);
         client.publish(&message).unwrap();
         check_errors();
 
@@ -0,0 +1,167 @@
+use crate::hierarchy::write_json_str;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientIdSuffix {
+    Hostname,
+    Pid,
+    #[cfg(feature = "uuid")]
+    Uuid,
+}
+
+// The client id a session is created with. Suffixes make the id unique per
+// process, so the same configuration deployed to many nodes does not make
+// them take over each other's sessions. Plain strings convert into an id
+// without suffixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientId {
+    base: String,
+    suffixes: Vec<ClientIdSuffix>,
+    presence_prefix: Option<String>,
+}
+
+impl ClientId {
+    pub fn new(base: impl Into<String>) -> Self {
+        Self {
+            base: base.into(),
+            suffixes: Vec::new(),
+            presence_prefix: None,
+        }
+    }
+
+    // Suffixes are appended in the order they are added, separated by `-`.
+    pub fn with_suffix(mut self, suffix: ClientIdSuffix) -> Self {
+        self.suffixes.push(suffix);
+        self
+    }
+
+    // After connecting, publish a retained presence record to
+    // `<prefix>/<client id>` naming the base id it was derived from. The
+    // record is cleared on shutdown.
+    pub fn with_presence_topic(mut self, prefix: impl Into<String>) -> Self {
+        self.presence_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    // The id of session `index` of a `ClientPool`: the base gets the index
+    // appended, before the suffixes.
+    pub(crate) fn numbered(&self, index: usize) -> ClientId {
+        Self {
+            base: format!("{}-{}", self.base, index),
+            ..self.clone()
+        }
+    }
+
+    // Suffixes are evaluated on every call; uuid suffixes differ each time.
+    pub(crate) fn resolve(&self) -> ResolvedClientId {
+        let mut id = self.base.clone();
+        for suffix in &self.suffixes {
+            id.push('-');
+            let value = match suffix {
+                ClientIdSuffix::Hostname => hostname(),
+                ClientIdSuffix::Pid => std::process::id().to_string(),
+                #[cfg(feature = "uuid")]
+                ClientIdSuffix::Uuid => uuid::Uuid::new_v4().simple().to_string(),
+            };
+            id.extend(value.chars().map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            }));
+        }
+
+        let presence_topic = self
+            .presence_prefix
+            .as_ref()
+            .map(|prefix| format!("{}/{}", prefix.trim_end_matches('/'), id));
+        ResolvedClientId {
+            id,
+            base: self.base.clone(),
+            presence_topic,
+        }
+    }
+}
+
+impl From<&str> for ClientId {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<String> for ClientId {
+    fn from(id: String) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<&String> for ClientId {
+    fn from(id: &String) -> Self {
+        Self::new(id.as_str())
+    }
+}
+
+impl From<&ClientId> for ClientId {
+    fn from(id: &ClientId) -> Self {
+        id.clone()
+    }
+}
+
+pub(crate) struct ResolvedClientId {
+    pub(crate) id: String,
+    pub(crate) base: String,
+    pub(crate) presence_topic: Option<String>,
+}
+
+impl ResolvedClientId {
+    pub(crate) fn presence_payload(&self) -> String {
+        let mut out = String::from("{\"client_id\":");
+        write_json_str(&mut out, &self.id);
+        out.push_str(",\"base_client_id\":");
+        write_json_str(&mut out, &self.base);
+        out.push_str(",\"hostname\":");
+        write_json_str(&mut out, &hostname());
+        out.push_str(&format!(",\"pid\":{}}}", std::process::id()));
+        out
+    }
+}
+
+pub(crate) fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let plain = ClientId::from("gateway").resolve();
+        assert_eq!(plain.id, "gateway");
+        assert!(plain.presence_topic.is_none());
+
+        let id = ClientId::new("gateway")
+            .with_suffix(ClientIdSuffix::Pid)
+            .with_presence_topic("presence/")
+            .resolve();
+        assert_eq!(id.id, format!("gateway-{}", std::process::id()));
+        assert_eq!(
+            id.presence_topic.as_deref(),
+            Some(format!("presence/{}", id.id).as_str())
+        );
+        assert!(id
+            .presence_payload()
+            .contains("\"base_client_id\":\"gateway\""));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::envelope::Envelope;
+use crate::error::{Error, Result};
+use crate::payload::FromPayload;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+pub const SPEC_VERSION: &str = "1.0";
+
+const ATTRIBUTES: [&str; 8] = [
+    "specversion",
+    "id",
+    "source",
+    "type",
+    "subject",
+    "time",
+    "datacontenttype",
+    "dataschema",
+];
+
+// How an event is carried in a payload, after the CloudEvents MQTT binding.
+// MQTT 3.1.1 has no user properties, so binary mode puts the attributes in
+// envelope properties (see `Envelope`) and the data in its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    // The whole event as a JSON object.
+    #[default]
+    Structured,
+    Binary,
+}
+
+// A CloudEvents 1.0 event. Extension attributes are kept as strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub id: String,
+    pub source: String,
+    pub event_type: String,
+    pub subject: Option<String>,
+    // RFC 3339.
+    pub time: Option<String>,
+    pub data_content_type: Option<String>,
+    pub data_schema: Option<String>,
+    pub extensions: Vec<(String, String)>,
+    pub data: Option<Vec<u8>>,
+}
+
+impl Event {
+    pub fn new(
+        id: impl Into<String>,
+        source: impl Into<String>,
+        event_type: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            source: source.into(),
+            event_type: event_type.into(),
+            subject: None,
+            time: None,
+            data_content_type: None,
+            data_schema: None,
+            extensions: Vec::new(),
+            data: None,
+        }
+    }
+
+    pub fn with_subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn with_time(mut self, time: SystemTime) -> Self {
+        self.time = Some(rfc3339(time));
+        self
+    }
+
+    pub fn with_data_schema(mut self, schema: impl Into<String>) -> Self {
+        self.data_schema = Some(schema.into());
+        self
+    }
+
+    pub fn with_data(mut self, content_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.data_content_type = Some(content_type.into());
+        self.data = Some(data.into());
+        self
+    }
+
+    pub fn with_json_data<T: Serialize>(self, data: &T) -> Result<Self> {
+        let data = serde_json::to_vec(data).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+        Ok(self.with_data("application/json", data))
+    }
+
+    // Sets extension attribute `name`, replacing an earlier value. Names
+    // are lowercase letters and digits; the standard attributes are taken.
+    pub fn with_extension(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self> {
+        let (name, value) = (name.into(), value.into());
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && !ATTRIBUTES.contains(&name.as_str())
+            && name != "data"
+            && name != "data_base64";
+        if !valid {
+            return Err(Error::InvalidPayload(format!(
+                "cloudevents: invalid extension name {:?}",
+                name
+            )));
+        }
+        match self.extensions.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.extensions.push((name, value)),
+        }
+        Ok(self)
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // The data decoded as JSON.
+    pub fn data_json<T: DeserializeOwned>(&self) -> Result<T> {
+        let data = self.data.as_deref().unwrap_or_default();
+        serde_json::from_slice(data).map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+
+    fn is_json(&self) -> bool {
+        self.data_content_type
+            .as_deref()
+            .is_none_or(|content_type| {
+                let essence = content_type.split(';').next().unwrap_or_default().trim();
+                essence == "application/json" || essence.ends_with("+json")
+            })
+    }
+
+    pub fn encode(&self, mode: Mode) -> Result<Vec<u8>> {
+        match mode {
+            Mode::Structured => self.encode_structured(),
+            Mode::Binary => self.encode_binary(),
+        }
+    }
+
+    fn optional_attributes(&self) -> [(&'static str, Option<&str>); 4] {
+        [
+            ("subject", self.subject.as_deref()),
+            ("time", self.time.as_deref()),
+            ("datacontenttype", self.data_content_type.as_deref()),
+            ("dataschema", self.data_schema.as_deref()),
+        ]
+    }
+
+    fn encode_structured(&self) -> Result<Vec<u8>> {
+        let mut object = Map::new();
+        object.insert("specversion".into(), SPEC_VERSION.into());
+        object.insert("id".into(), self.id.as_str().into());
+        object.insert("source".into(), self.source.as_str().into());
+        object.insert("type".into(), self.event_type.as_str().into());
+        for (name, value) in self.optional_attributes() {
+            if let Some(value) = value {
+                object.insert(name.into(), value.into());
+            }
+        }
+        for (name, value) in &self.extensions {
+            object.insert(name.clone(), value.as_str().into());
+        }
+        if let Some(data) = &self.data {
+            // JSON data is embedded as is, anything else as base64.
+            match serde_json::from_slice::<Value>(data) {
+                Ok(value) if self.is_json() => object.insert("data".into(), value),
+                _ => object.insert("data_base64".into(), BASE64.encode(data).into()),
+            };
+        }
+        serde_json::to_vec(&object).map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+
+    fn encode_binary(&self) -> Result<Vec<u8>> {
+        let mut envelope = Envelope::new(
+            self.data_content_type.as_deref().unwrap_or_default(),
+            self.data.clone().unwrap_or_default(),
+        )
+        .with_property("specversion", SPEC_VERSION)
+        .with_property("id", self.id.as_str())
+        .with_property("source", self.source.as_str())
+        .with_property("type", self.event_type.as_str());
+        for (name, value) in self.optional_attributes() {
+            // The content type has a field of its own.
+            if let (Some(value), false) = (value, name == "datacontenttype") {
+                envelope = envelope.with_property(name, value);
+            }
+        }
+        for (name, value) in &self.extensions {
+            envelope = envelope.with_property(name.as_str(), value.as_str());
+        }
+        envelope.encode()
+    }
+
+    // Reads an event in either mode.
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        match Envelope::decode(payload) {
+            Ok(envelope) if envelope.property("specversion").is_some() => {
+                Self::decode_binary(envelope)
+            }
+            _ => Self::decode_structured(payload),
+        }
+    }
+
+    fn decode_binary(envelope: Envelope) -> Result<Self> {
+        let attribute = |name: &str| envelope.property(name).map(str::to_string);
+        let required = |name: &str| attribute(name).ok_or_else(|| missing(name));
+        check_spec_version(envelope.property("specversion"))?;
+        Ok(Self {
+            id: required("id")?,
+            source: required("source")?,
+            event_type: required("type")?,
+            subject: attribute("subject"),
+            time: attribute("time"),
+            data_content_type: Some(envelope.content_type.clone()).filter(|ct| !ct.is_empty()),
+            data_schema: attribute("dataschema"),
+            extensions: envelope
+                .properties
+                .iter()
+                .filter(|(name, _)| !ATTRIBUTES.contains(&name.as_str()))
+                .cloned()
+                .collect(),
+            data: Some(envelope.payload.clone()).filter(|data| !data.is_empty()),
+        })
+    }
+
+    fn decode_structured(payload: &[u8]) -> Result<Self> {
+        let Value::Object(mut object) =
+            serde_json::from_slice(payload).map_err(|e| Error::InvalidPayload(e.to_string()))?
+        else {
+            return Err(Error::InvalidPayload(
+                "cloudevents: not an object".to_string(),
+            ));
+        };
+        let mut take = |name: &str| match object.remove(name) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(text)) => Some(text),
+            Some(other) => Some(other.to_string()),
+        };
+        check_spec_version(take("specversion").as_deref())?;
+        let mut event = Self {
+            id: take("id").ok_or_else(|| missing("id"))?,
+            source: take("source").ok_or_else(|| missing("source"))?,
+            event_type: take("type").ok_or_else(|| missing("type"))?,
+            subject: take("subject"),
+            time: take("time"),
+            data_content_type: take("datacontenttype"),
+            data_schema: take("dataschema"),
+            extensions: Vec::new(),
+            data: None,
+        };
+        event.data = match (object.remove("data"), object.remove("data_base64")) {
+            (_, Some(Value::String(encoded))) => Some(
+                BASE64
+                    .decode(encoded)
+                    .map_err(|e| Error::InvalidPayload(e.to_string()))?,
+            ),
+            // Text data of a non-JSON content type is the text itself.
+            (Some(Value::String(text)), _) if !event.is_json() => Some(text.into_bytes()),
+            (Some(Value::Null), _) | (None, _) => None,
+            (Some(value), _) => {
+                Some(serde_json::to_vec(&value).map_err(|e| Error::InvalidPayload(e.to_string()))?)
+            }
+        };
+        event.extensions = object
+            .into_iter()
+            .map(|(name, value)| match value {
+                Value::String(text) => (name, text),
+                other => (name, other.to_string()),
+            })
+            .collect();
+        Ok(event)
+    }
+}
+
+impl FromPayload for Event {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        Self::decode(payload)
+    }
+}
+
+fn missing(name: &str) -> Error {
+    Error::InvalidPayload(format!("cloudevents: missing {}", name))
+}
+
+fn check_spec_version(version: Option<&str>) -> Result<()> {
+    match version {
+        Some(SPEC_VERSION) => Ok(()),
+        Some(other) => Err(Error::InvalidPayload(format!(
+            "cloudevents: unsupported specversion {}",
+            other
+        ))),
+        None => Err(missing("specversion")),
+    }
+}
+
+// UTC, to the millisecond.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since the epoch, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_roundtrip_both_modes() {
+        let event = Event::new("42", "/sensors/a", "com.example.reading")
+            .with_subject("temp")
+            .with_time(UNIX_EPOCH + Duration::from_millis(1_700_000_000_250))
+            .with_json_data(&serde_json::json!({"celsius": 21.5}))
+            .unwrap()
+            .with_extension("site", "edge1")
+            .unwrap();
+        assert_eq!(event.time.as_deref(), Some("2023-11-14T22:13:20.250Z"));
+
+        let structured = event.encode(Mode::Structured).unwrap();
+        let object: Value = serde_json::from_slice(&structured).unwrap();
+        assert_eq!(object["data"]["celsius"], 21.5);
+        assert_eq!(object["site"], "edge1");
+        let decoded = Event::decode(&structured).unwrap();
+        assert_eq!(decoded.data_json::<Value>().unwrap()["celsius"], 21.5);
+        assert_eq!(decoded.extension("site"), Some("edge1"));
+        assert_eq!(decoded.subject.as_deref(), Some("temp"));
+
+        let binary = event.encode(Mode::Binary).unwrap();
+        assert_eq!(Event::decode(&binary).unwrap(), event);
+
+        let bytes =
+            Event::new("1", "/cam", "com.example.frame").with_data("image/png", vec![0, 159, 255]);
+        let structured = bytes.encode(Mode::Structured).unwrap();
+        assert!(String::from_utf8_lossy(&structured).contains("data_base64"));
+        assert_eq!(Event::decode(&structured).unwrap(), bytes);
+
+        assert!(
+            Event::decode(br#"{"specversion":"0.3","id":"1","source":"/","type":"t"}"#).is_err()
+        );
+        assert!(Event::decode(br#"{"specversion":"1.0","source":"/","type":"t"}"#).is_err());
+        assert!(event.clone().with_extension("Bad-Name", "x").is_err());
+        assert!(event.with_extension("id", "x").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::Result;
+
+// Encoding between values and payloads. MQTT 3.1.1 has no message
+// properties, so the content type is not sent; it is exposed for topic
+// conventions and framing.
+pub trait Codec<T> {
+    fn content_type(&self) -> &'static str;
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>>;
+
+    fn decode(&self, payload: &[u8]) -> Result<T>;
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T> Codec<T> for JsonCodec
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| crate::Error::InvalidPayload(e.to_string()))
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        serde_json::from_slice(payload).map_err(|e| crate::Error::InvalidPayload(e.to_string()))
+    }
+}
+
+#[cfg(feature = "prost")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProstCodec;
+
+#[cfg(feature = "prost")]
+impl<M: prost::Message + Default> Codec<M> for ProstCodec {
+    fn content_type(&self) -> &'static str {
+        "application/x-protobuf"
+    }
+
+    fn encode(&self, value: &M) -> Result<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<M> {
+        M::decode(payload).map_err(|e| crate::Error::InvalidPayload(e.to_string()))
+    }
+}
+
+#[cfg(all(test, feature = "prost"))]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Reading {
+        #[prost(string, tag = "1")]
+        sensor: String,
+        #[prost(double, tag = "2")]
+        value: f64,
+    }
+
+    #[test]
+    fn test_prost_roundtrip() {
+        let reading = Reading {
+            sensor: "t1".into(),
+            value: 21.5,
+        };
+        let payload = ProstCodec.encode(&reading).unwrap();
+        let decoded: Reading = ProstCodec.decode(&payload).unwrap();
+        assert_eq!(decoded, reading);
+        assert!(matches!(
+            Codec::<Reading>::decode(&ProstCodec, &[0xff]),
+            Err(crate::Error::InvalidPayload(_))
+        ));
+    }
+}
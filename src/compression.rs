@@ -0,0 +1,214 @@
+use crate::envelope::Envelope;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use std::io::{self, Read};
+
+// The envelope property naming the algorithm a payload is compressed with.
+pub const CONTENT_ENCODING: &str = "content-encoding";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => "zstd",
+            #[cfg(feature = "gzip")]
+            Self::Gzip => "gzip",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Self::Zstd),
+            #[cfg(feature = "gzip")]
+            "gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+
+    fn compress(self, data: &[u8], level: Option<i32>) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::stream::encode_all(data, level.unwrap_or(0)),
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use std::io::Write;
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    // Fails rather than inflate past `max` bytes.
+    fn decompress(self, data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+            #[cfg(feature = "gzip")]
+            Self::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+        };
+        let mut out = Vec::new();
+        reader.take(max as u64 + 1).read_to_end(&mut out)?;
+        if out.len() > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed payload too large",
+            ));
+        }
+        Ok(out)
+    }
+}
+
+// Compresses payloads of at least `threshold` bytes, for
+// `Client::set_compression` or `Client::publish_compressed`. A compressed
+// payload is an `Envelope` with the compressed flag set and the algorithm
+// in its `CONTENT_ENCODING` property; payloads that already are envelopes
+// keep their content type and properties. Clients built with the `zstd` or
+// `gzip` feature decompress such payloads before any callback sees them,
+// restoring the original payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    algorithm: Algorithm,
+    level: Option<i32>,
+    threshold: usize,
+}
+
+impl Compression {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            level: None,
+            threshold: 1024,
+        }
+    }
+
+    // zstd levels run from 1 to 22, gzip ones from 0 to 9; the algorithm's
+    // default otherwise.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn with_threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    // None if the payload is below the threshold, already compressed, or
+    // would not shrink.
+    pub(crate) fn compress(&self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        if payload.len() < self.threshold {
+            return Ok(None);
+        }
+        // Raw payloads are wrapped in an envelope without a content type.
+        let envelope = match Envelope::decode(payload) {
+            Ok(envelope) if envelope.compressed => return Ok(None),
+            Ok(envelope) => envelope,
+            Err(_) => Envelope::new("", payload.to_vec()),
+        };
+        let data = self.algorithm.compress(&envelope.payload, self.level)?;
+        let compressed = Envelope {
+            compressed: true,
+            payload: data,
+            ..envelope
+        }
+        .with_property(CONTENT_ENCODING, self.algorithm.name())
+        .encode()?;
+        Ok((compressed.len() < payload.len()).then_some(compressed))
+    }
+
+    pub(crate) fn compress_message(&self, message: &Message) -> Result<Option<Message>> {
+        Ok(self.compress(&message.payload)?.map(|payload| Message {
+            payload,
+            ..message.clone()
+        }))
+    }
+}
+
+// The original payload of a payload compressed by `Compression`, or None
+// for any other payload.
+pub(crate) fn decompress(payload: &[u8], max: usize) -> Option<Result<Vec<u8>>> {
+    let envelope = Envelope::decode(payload).ok()?;
+    let algorithm = envelope
+        .property(CONTENT_ENCODING)
+        .and_then(Algorithm::from_name)
+        .filter(|_| envelope.compressed)?;
+    let data = match algorithm.decompress(&envelope.payload, max) {
+        Ok(data) => data,
+        Err(e) => return Some(Err(Error::InvalidPayload(e.to_string()))),
+    };
+    let properties: Vec<(String, String)> = envelope
+        .properties
+        .into_iter()
+        .filter(|(key, _)| key != CONTENT_ENCODING)
+        .collect();
+    if envelope.content_type.is_empty() && properties.is_empty() {
+        return Some(Ok(data));
+    }
+    Some(
+        Envelope {
+            compressed: false,
+            properties,
+            payload: data,
+            ..envelope
+        }
+        .encode(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn algorithms() -> Vec<Algorithm> {
+        vec![
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd,
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip,
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip_above_threshold() {
+        let text = "temperature=21.5;".repeat(100).into_bytes();
+        for algorithm in algorithms() {
+            let compression = Compression::new(algorithm).with_threshold(64);
+            assert_eq!(compression.compress(b"short").unwrap(), None);
+
+            let compressed = compression.compress(&text).unwrap().unwrap();
+            assert!(compressed.len() < text.len());
+            let envelope = Envelope::decode(&compressed).unwrap();
+            assert!(envelope.compressed);
+            assert_eq!(envelope.property(CONTENT_ENCODING), Some(algorithm.name()));
+            // Not compressed twice.
+            assert_eq!(compression.compress(&compressed).unwrap(), None);
+            assert_eq!(decompress(&compressed, 1 << 20).unwrap().unwrap(), text);
+            assert!(decompress(&compressed, 100).unwrap().is_err());
+
+            // Envelopes keep their content type and properties.
+            let enveloped = Envelope::new("text/plain", text.clone())
+                .with_property("site", "edge1")
+                .encode()
+                .unwrap();
+            let compressed = compression.compress(&enveloped).unwrap().unwrap();
+            assert_eq!(
+                decompress(&compressed, 1 << 20).unwrap().unwrap(),
+                enveloped
+            );
+        }
+        assert!(decompress(b"plain", 1 << 20).is_none());
+    }
+}
@@ -0,0 +1,299 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::types::{QoS, TopicFilter};
+use polars::prelude::{Column, DataFrame, DataType, NamedFrom, Series, TimeUnit};
+use serde_json::Value;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+// Collects the messages received on its filters into one polars `DataFrame`
+// per window, for exploring broker traffic:
+//
+//   let frames = DataFrameCollector::new(Duration::from_secs(10))
+//       .with_filter("sensors/#")?
+//       .attach(&client)?;
+//   for frame in frames { println!("{}", frame?); }
+//
+// Frames have `topic`, `timestamp` (time of arrival), `payload`, `qos` and
+// `retained` columns, plus one per field named with `with_json_field`,
+// taken from payloads that decode as JSON objects. A field column is
+// Float64 if every value in the window is a number, Boolean if every one
+// is a boolean, and String otherwise; missing fields are null.
+pub struct DataFrameCollector {
+    window: Duration,
+    filters: Vec<TopicFilter>,
+    qos: QoS,
+    fields: Vec<String>,
+    tx: Option<Sender<(SystemTime, Message)>>,
+    rx: Receiver<(SystemTime, Message)>,
+    window_end: Instant,
+    client: Weak<Client>,
+    handles: Vec<i64>,
+}
+
+impl DataFrameCollector {
+    pub fn new(window: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            window: window.max(Duration::from_millis(1)),
+            filters: Vec::new(),
+            qos: QoS::AtLeastOnce,
+            fields: Vec::new(),
+            tx: Some(tx),
+            rx,
+            window_end: Instant::now(),
+            client: Weak::new(),
+            handles: Vec::new(),
+        }
+    }
+
+    pub fn with_filter<T>(mut self, filter: T) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.filters.push(filter.try_into()?);
+        Ok(self)
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    // Adds a column for `field` of JSON payloads: a top-level key, or a
+    // JSON pointer such as `/reading/temp` for a nested one.
+    pub fn with_json_field(mut self, field: impl Into<String>) -> Self {
+        self.fields.push(field.into());
+        self
+    }
+
+    // Subscribes to the filters; the first window starts now. The
+    // subscriptions are removed when the collector is dropped.
+    pub fn attach(mut self, client: &Arc<Client>) -> Result<Self> {
+        let Some(tx) = self.tx.take() else {
+            return Ok(self);
+        };
+        for filter in &self.filters {
+            let tx = tx.clone();
+            let handle = client.subscribe_with(filter, self.qos, move |msg| {
+                let _ = tx.send((SystemTime::now(), msg.to_owned()));
+            });
+            match handle {
+                Ok(handle) => self.handles.push(handle),
+                Err(e) => {
+                    for handle in self.handles.drain(..) {
+                        let _ = client.unsubscribe(handle);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.client = Arc::downgrade(client);
+        self.window_end = Instant::now() + self.window;
+        Ok(self)
+    }
+
+    // Blocks until the current window ends and returns its messages; None
+    // once the client is gone and the last window has been returned.
+    pub fn next_frame(&mut self) -> Option<Result<DataFrame>> {
+        if self.tx.is_some() {
+            return None;
+        }
+        let mut messages = Vec::new();
+        let mut disconnected = false;
+        loop {
+            let now = Instant::now();
+            if now >= self.window_end {
+                break;
+            }
+            match self.rx.recv_timeout(self.window_end - now) {
+                Ok(received) => messages.push(received),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+        // Windows follow each other without drift, however long the caller
+        // took with the previous frame.
+        self.window_end += self.window;
+        if disconnected && messages.is_empty() {
+            return None;
+        }
+        Some(frame(&messages, &self.fields))
+    }
+}
+
+impl Iterator for DataFrameCollector {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame()
+    }
+}
+
+impl Drop for DataFrameCollector {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            for &handle in &self.handles {
+                let _ = client.unsubscribe(handle);
+            }
+        }
+    }
+}
+
+fn frame(messages: &[(SystemTime, Message)], fields: &[String]) -> Result<DataFrame> {
+    let topics: Vec<&str> = messages.iter().map(|(_, m)| m.topic.as_str()).collect();
+    let timestamps: Vec<i64> = messages
+        .iter()
+        .map(|(timestamp, _)| {
+            timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as i64
+        })
+        .collect();
+    let payloads: Vec<&[u8]> = messages.iter().map(|(_, m)| m.payload.as_slice()).collect();
+    let qos: Vec<u32> = messages
+        .iter()
+        .map(|(_, m)| match m.qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        })
+        .collect();
+    let retained: Vec<bool> = messages.iter().map(|(_, m)| m.retained).collect();
+
+    let timestamps = Series::new("timestamp".into(), timestamps)
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+        .map_err(polars_error)?;
+    let mut columns = vec![
+        Column::new("topic".into(), topics),
+        timestamps.into(),
+        Column::new("payload".into(), payloads),
+        Column::new("qos".into(), qos),
+        Column::new("retained".into(), retained),
+    ];
+
+    if !fields.is_empty() {
+        let decoded: Vec<Option<Value>> = messages
+            .iter()
+            .map(|(_, m)| serde_json::from_slice(&m.payload).ok())
+            .collect();
+        for field in fields {
+            let values: Vec<Option<&Value>> = decoded
+                .iter()
+                .map(|value| value.as_ref().and_then(|value| lookup(value, field)))
+                .collect();
+            columns.push(field_column(field, &values));
+        }
+    }
+    DataFrame::new(columns).map_err(polars_error)
+}
+
+fn lookup<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    let value = if field.starts_with('/') {
+        value.pointer(field)
+    } else {
+        value.get(field)
+    };
+    value.filter(|value| !value.is_null())
+}
+
+fn field_column(name: &str, values: &[Option<&Value>]) -> Column {
+    let present = || values.iter().flatten();
+    if present().all(|value| value.is_number()) {
+        let values: Vec<Option<f64>> = values
+            .iter()
+            .map(|value| value.and_then(Value::as_f64))
+            .collect();
+        Column::new(name.into(), values)
+    } else if present().all(|value| value.is_boolean()) {
+        let values: Vec<Option<bool>> = values
+            .iter()
+            .map(|value| value.and_then(Value::as_bool))
+            .collect();
+        Column::new(name.into(), values)
+    } else {
+        let values: Vec<Option<String>> = values
+            .iter()
+            .map(|value| {
+                value.map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })
+            })
+            .collect();
+        Column::new(name.into(), values)
+    }
+}
+
+fn polars_error(e: polars::error::PolarsError) -> Error {
+    Error::InvalidPayload(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_decodes_json_fields() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let messages: Vec<(SystemTime, Message)> = [
+            (
+                "sensors/a",
+                r#"{"temp": 21.5, "ok": true, "reading": {"unit": "C"}}"#,
+            ),
+            (
+                "sensors/b",
+                r#"{"temp": 19, "ok": false, "reading": {"unit": 1}}"#,
+            ),
+            ("sensors/c", "not json"),
+        ]
+        .into_iter()
+        .map(|(topic, payload)| (at, Message::new(topic, payload).unwrap()))
+        .collect();
+        let fields = [
+            "temp".to_string(),
+            "ok".to_string(),
+            "/reading/unit".to_string(),
+        ];
+        let df = frame(&messages, &fields).unwrap();
+
+        assert_eq!(df.height(), 3);
+        assert_eq!(
+            df.get_column_names_str(),
+            [
+                "topic",
+                "timestamp",
+                "payload",
+                "qos",
+                "retained",
+                "temp",
+                "ok",
+                "/reading/unit"
+            ]
+        );
+        let temp = df.column("temp").unwrap().as_materialized_series();
+        assert_eq!(temp.dtype(), &DataType::Float64);
+        assert_eq!(temp.f64().unwrap().get(1), Some(19.0));
+        assert_eq!(temp.null_count(), 1);
+        assert_eq!(df.column("ok").unwrap().dtype(), &DataType::Boolean);
+        let unit = df.column("/reading/unit").unwrap().as_materialized_series();
+        assert_eq!(unit.str().unwrap().get(0), Some("C"));
+        assert_eq!(unit.str().unwrap().get(1), Some("1"));
+        assert_eq!(
+            df.column("timestamp").unwrap().dtype(),
+            &DataType::Datetime(TimeUnit::Microseconds, None)
+        );
+
+        let empty = frame(&[], &[]).unwrap();
+        assert_eq!(empty.height(), 0);
+        assert_eq!(empty.width(), 5);
+    }
+}
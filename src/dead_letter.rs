@@ -0,0 +1,195 @@
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::record::{self, write_header, write_record};
+use crate::time::{SystemTime, UNIX_EPOCH};
+use std::collections::hash_map::{Entry, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+// Why a publish was given up on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeadLetterReason {
+    // Refused by the bridge or the broker, or not connected.
+    Rejected,
+    // Expired before it could be sent, such as in the rate limit queue.
+    Expired,
+    // Over the broker's, or the client's, maximum packet size.
+    TooLarge,
+}
+
+impl DeadLetterReason {
+    // Errors that are the caller's own doing, such as an invalid topic or
+    // an ACL denial, are not dead letters.
+    pub(crate) fn of(error: &Error) -> Option<Self> {
+        match error {
+            Error::PublicationError(_) | Error::ConnectionError => Some(Self::Rejected),
+            Error::Expired => Some(Self::Expired),
+            Error::PacketTooLarge { .. } => Some(Self::TooLarge),
+            _ => None,
+        }
+    }
+
+    // The prefix of the spool files for this reason.
+    pub fn spool_prefix(self) -> &'static str {
+        match self {
+            Self::Rejected => "dead-rejected",
+            Self::Expired => "dead-expired",
+            Self::TooLarge => "dead-too-large",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub message: Message,
+    pub reason: DeadLetterReason,
+    pub error: String,
+}
+
+type DeadLetterCallback = Box<dyn Fn(&DeadLetter) + Send + Sync>;
+
+// Where publishes that failed for good go, instead of only failing the
+// call, set with `Client::set_dead_letters`. Each goes to the callback,
+// the spool, or both.
+//
+// The spool is a directory of recordings, one file per reason and run,
+// named `<reason prefix>-<unix millis>.pmqr`, which `RecordReader`
+// reads and `Replayer` publishes again; only the reason is kept, not the
+// error. Messages are spooled as the caller published them, except those
+// that failed after waiting in the rate limit queue, which are spooled as
+// sent, compressed or encrypted if that was set. A dead letter that cannot
+// be written is lost.
+#[derive(Default)]
+pub struct DeadLetters {
+    callback: Option<DeadLetterCallback>,
+    spool: Option<PathBuf>,
+    files: Mutex<HashMap<DeadLetterReason, BufWriter<File>>>,
+}
+
+impl DeadLetters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `callback` runs on the thread of the failed publish and must not
+    // block.
+    pub fn with_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&DeadLetter) + Send + Sync + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    // Spools to `dir`, created when the first dead letter arrives.
+    pub fn with_spool(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spool = Some(dir.into());
+        self
+    }
+
+    // The spool files in `dir` for `reason`, oldest first.
+    pub fn spooled(dir: impl AsRef<Path>, reason: DeadLetterReason) -> Result<Vec<PathBuf>> {
+        match dir.as_ref().exists() {
+            true => record::recordings(dir, reason.spool_prefix()),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    pub(crate) fn send(&self, message: &Message, reason: DeadLetterReason, error: &Error) {
+        let letter = DeadLetter {
+            message: message.clone(),
+            reason,
+            error: error.to_string(),
+        };
+        if let Some(dir) = &self.spool {
+            let _ = self.spool(dir, &letter);
+        }
+        if let Some(callback) = &self.callback {
+            callback(&letter);
+        }
+    }
+
+    fn spool(&self, dir: &Path, letter: &DeadLetter) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        let file = match files.entry(letter.reason) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                fs::create_dir_all(dir)?;
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let name = format!(
+                    "{}-{:013}.{}",
+                    letter.reason.spool_prefix(),
+                    millis,
+                    record::EXTENSION
+                );
+                let mut file = BufWriter::new(File::create(dir.join(name))?);
+                write_header(&mut file)?;
+                entry.insert(file)
+            }
+        };
+        write_record(file, SystemTime::now(), &letter.message)?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::client::Client;
+    use crate::record::RecordReader;
+    use crate::types::QoS;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_failed_publishes_reach_the_callback_and_spool() {
+        let dir = std::env::temp_dir().join(format!("polar-mqtt-dead-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("dead", fake, |_| {}, |_| {}, |_, _| {}).unwrap();
+        let letters = Arc::new(Mutex::new(Vec::new()));
+        let seen = letters.clone();
+        client.set_dead_letters(Some(
+            DeadLetters::new()
+                .with_callback(move |letter| seen.lock().unwrap().push(letter.clone()))
+                .with_spool(&dir),
+        ));
+
+        // Not connected, so the bridge refuses it.
+        let message = Message::new("orders/1", "order").unwrap();
+        assert!(client
+            .publish(&message.clone().with_qos(QoS::AtLeastOnce))
+            .is_err());
+        assert!(client
+            .publish_raw("orders/2", b"raw", QoS::AtMostOnce, false)
+            .is_err());
+        // Invalid topics are the caller's mistake.
+        assert!(client
+            .publish_raw("orders/+", b"bad", QoS::AtMostOnce, false)
+            .is_err());
+
+        let letters = letters.lock().unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].reason, DeadLetterReason::Rejected);
+        assert_eq!(letters[1].message.topic, "orders/2");
+
+        let spooled = DeadLetters::spooled(&dir, DeadLetterReason::Rejected).unwrap();
+        assert_eq!(spooled.len(), 1);
+        let messages: Vec<_> = RecordReader::open(&spooled[0])
+            .unwrap()
+            .map(|record| record.unwrap().message)
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].qos, QoS::AtLeastOnce);
+        assert_eq!(&*messages[1].payload, b"raw");
+        assert!(DeadLetters::spooled(&dir, DeadLetterReason::Expired)
+            .unwrap()
+            .is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
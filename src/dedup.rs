@@ -0,0 +1,240 @@
+use crate::envelope::Envelope;
+use crate::time::Instant;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+struct Entry {
+    hash: u64,
+    published: Instant,
+    last_used: u64,
+}
+
+// Remembers what was last published per topic, so unchanged values can be
+// skipped. Up to `window` topics are tracked; beyond that the least
+// recently published one is forgotten. With a force interval, an unchanged
+// value is republished once that long has passed since it last went out.
+pub struct PublishDedup {
+    window: usize,
+    force_interval: Option<Duration>,
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+impl Default for PublishDedup {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl PublishDedup {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            force_interval: None,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn with_force_interval(mut self, interval: Duration) -> Self {
+        self.force_interval = Some(interval);
+        self
+    }
+
+    // The hash to `record` once published, or None to skip the publish.
+    pub(crate) fn check(&self, topic: &str, payload: &[u8], retained: bool) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        retained.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        match self.entries.get(topic) {
+            Some(entry)
+                if entry.hash == hash
+                    && self
+                        .force_interval
+                        .is_none_or(|interval| entry.published.elapsed() < interval) =>
+            {
+                None
+            }
+            _ => Some(hash),
+        }
+    }
+
+    pub(crate) fn record(&mut self, topic: &str, hash: u64) {
+        self.clock += 1;
+        if !self.entries.contains_key(topic) && self.entries.len() >= self.window {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(topic, _)| topic.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            topic.to_string(),
+            Entry {
+                hash,
+                published: Instant::now(),
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+// What identifies a message to `InboundDedup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DedupKey {
+    // The topic and payload.
+    Payload,
+    // This envelope property (see `Envelope`), such as a CloudEvents `id`.
+    // Messages without it are keyed on their payload.
+    Property(String),
+}
+
+// Drops received messages already seen within `window`, before the sampler
+// and callbacks, such as QoS 1 messages redelivered after a reconnect. Up to
+// `capacity` keys are remembered; past that the oldest are forgotten early.
+// Set with `Client::set_inbound_dedup`.
+pub struct InboundDedup {
+    key: DedupKey,
+    window: Duration,
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    at: HashMap<u64, Instant>,
+    // In the order first seen.
+    order: VecDeque<(u64, Instant)>,
+}
+
+impl InboundDedup {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            key: DedupKey::Payload,
+            window,
+            capacity: 10_000,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn with_key(mut self, key: DedupKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    fn key(&self, topic: &str, payload: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        topic.hash(&mut hasher);
+        let id = match &self.key {
+            DedupKey::Property(name) => Envelope::decode(payload)
+                .ok()
+                .and_then(|envelope| envelope.property(name).map(str::to_string)),
+            DedupKey::Payload => None,
+        };
+        match id {
+            Some(id) => id.hash(&mut hasher),
+            None => payload.hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    pub(crate) fn is_duplicate(&self, topic: &str, payload: &[u8]) -> bool {
+        self.is_duplicate_at(topic, payload, Instant::now())
+    }
+
+    // The window runs from when a key was first seen; duplicates do not
+    // extend it.
+    fn is_duplicate_at(&self, topic: &str, payload: &[u8], now: Instant) -> bool {
+        let key = self.key(topic, payload);
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        let Seen { at, order } = &mut *seen;
+        while let Some(&(oldest, first)) = order.front() {
+            if now.duration_since(first) < self.window && order.len() < self.capacity {
+                break;
+            }
+            order.pop_front();
+            at.remove(&oldest);
+        }
+        if at.contains_key(&key) {
+            return true;
+        }
+        at.insert(key, now);
+        order.push_back((key, now));
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish(dedup: &mut PublishDedup, topic: &str, payload: &[u8]) -> bool {
+        match dedup.check(topic, payload, false) {
+            Some(hash) => {
+                dedup.record(topic, hash);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn test_window_and_force_interval() {
+        let mut dedup = PublishDedup::new(2);
+        assert!(publish(&mut dedup, "a", b"1"));
+        assert!(!publish(&mut dedup, "a", b"1"));
+        assert!(publish(&mut dedup, "a", b"2"));
+        assert!(dedup.check("a", b"2", true).is_some());
+
+        // "a" is evicted as the least recently published.
+        assert!(publish(&mut dedup, "b", b"1"));
+        assert!(publish(&mut dedup, "c", b"1"));
+        assert!(publish(&mut dedup, "a", b"2"));
+        assert!(!publish(&mut dedup, "c", b"1"));
+
+        let mut forced = PublishDedup::new(8).with_force_interval(Duration::ZERO);
+        assert!(publish(&mut forced, "a", b"1"));
+        assert!(publish(&mut forced, "a", b"1"));
+    }
+
+    #[test]
+    fn test_inbound_window_and_property_key() {
+        let dedup = InboundDedup::new(Duration::from_secs(10)).with_capacity(2);
+        let now = Instant::now();
+        assert!(!dedup.is_duplicate_at("a", b"1", now));
+        assert!(dedup.is_duplicate_at("a", b"1", now));
+        assert!(!dedup.is_duplicate_at("b", b"1", now));
+        // Past the window, or pushed out by newer keys, it is seen anew.
+        assert!(!dedup.is_duplicate_at("a", b"1", now + Duration::from_secs(11)));
+        assert!(!dedup.is_duplicate_at("c", b"1", now + Duration::from_secs(11)));
+        assert!(!dedup.is_duplicate_at("d", b"1", now + Duration::from_secs(11)));
+        assert!(!dedup.is_duplicate_at("a", b"1", now + Duration::from_secs(11)));
+
+        let dedup = InboundDedup::new(Duration::from_secs(10))
+            .with_key(DedupKey::Property("id".to_string()));
+        let message = |id: &str, value: &str| {
+            Envelope::new("text/plain", value.as_bytes().to_vec())
+                .with_property("id", id)
+                .encode()
+                .unwrap()
+        };
+        assert!(!dedup.is_duplicate_at("t", &message("1", "21"), now));
+        assert!(dedup.is_duplicate_at("t", &message("1", "22"), now));
+        assert!(!dedup.is_duplicate_at("t", &message("2", "21"), now));
+        assert!(!dedup.is_duplicate_at("t", b"raw", now));
+        assert!(dedup.is_duplicate_at("t", b"raw", now));
+    }
+}
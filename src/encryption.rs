@@ -0,0 +1,270 @@
+use crate::envelope::Envelope;
+use crate::error::{Error, Result};
+use crate::hex;
+use crate::message::Message;
+use crate::types::TopicFilter;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// The content type of encrypted envelopes, and their properties.
+pub const CONTENT_TYPE: &str = "application/vnd.polar-mqtt.encrypted";
+pub const KEY_ID: &str = "key-id";
+pub const NONCE: &str = "nonce";
+
+const NONCE_LEN: usize = 12;
+
+// An AES-256 key.
+pub type Key = [u8; 32];
+
+// Where `Encryption` gets its keys. Each encrypted payload names the key it
+// was encrypted with, so keys can be rotated: encrypt with the new one
+// while older ones can still decrypt.
+pub trait KeyProvider: Send + Sync {
+    // The key to encrypt a message on `topic` with, and its id; None
+    // publishes it unencrypted.
+    fn encryption_key(&self, topic: &str) -> Option<(String, Key)>;
+
+    fn decryption_key(&self, key_id: &str) -> Option<Key>;
+}
+
+// Keys held in memory: one to encrypt with, any number to decrypt with.
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: String,
+    keys: HashMap<String, Key>,
+}
+
+impl StaticKeys {
+    pub fn new(key_id: impl Into<String>, key: Key) -> Self {
+        let current = key_id.into();
+        Self {
+            keys: HashMap::from([(current.clone(), key)]),
+            current,
+        }
+    }
+
+    // Adds a key for decrypting only, such as one rotated out.
+    pub fn with_key(mut self, key_id: impl Into<String>, key: Key) -> Self {
+        self.keys.entry(key_id.into()).or_insert(key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn encryption_key(&self, _topic: &str) -> Option<(String, Key)> {
+        Some((self.current.clone(), self.keys[&self.current]))
+    }
+
+    fn decryption_key(&self, key_id: &str) -> Option<Key> {
+        self.keys.get(key_id).copied()
+    }
+}
+
+// Keys are not printed.
+impl fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current)
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+// End-to-end encryption of payloads with AES-256-GCM, for brokers that are
+// not trusted with them. Attached with `Client::set_encryption`, it
+// encrypts what is published to its filters (every topic without filters)
+// and decrypts what arrives, before any callback sees it.
+//
+// An encrypted payload is an `Envelope` of content type `CONTENT_TYPE`
+// whose `KEY_ID` and `NONCE` (hex) properties say how to decrypt it; the
+// key id and the topic, without any namespace, are authenticated along
+// with the payload, so it only decrypts on the topic it was published to. Encryption comes last
+// when publishing, so compression, provenance and trace context are all
+// encrypted too.
+//
+// Messages that fail to decrypt are dropped and counted in
+// `Client::messages_dropped`, as are unencrypted ones on the filters,
+// unless `with_plaintext_accepted` allows them.
+pub struct Encryption {
+    keys: Arc<dyn KeyProvider>,
+    filters: Vec<TopicFilter>,
+    plaintext_accepted: bool,
+}
+
+impl Encryption {
+    pub fn new<K: KeyProvider + 'static>(keys: K) -> Self {
+        Self {
+            keys: Arc::new(keys),
+            filters: Vec::new(),
+            plaintext_accepted: false,
+        }
+    }
+
+    pub fn with_filter<T>(mut self, filter: T) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.filters.push(filter.try_into()?);
+        Ok(self)
+    }
+
+    pub fn with_plaintext_accepted(mut self, accepted: bool) -> Self {
+        self.plaintext_accepted = accepted;
+        self
+    }
+
+    fn covers(&self, topic: &str) -> bool {
+        self.filters.is_empty()
+            || self
+                .filters
+                .iter()
+                .any(|filter| crate::topic::matches(filter.as_str(), topic))
+    }
+
+    // None for topics outside the filters, for empty payloads, which clear
+    // retained messages, and when the provider has no key for the topic.
+    pub(crate) fn encrypt(&self, topic: &str, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        if payload.is_empty() || !self.covers(topic) {
+            return Ok(None);
+        }
+        let Some((key_id, key)) = self.keys.encryption_key(topic) else {
+            return Ok(None);
+        };
+        let cipher = Aes256Gcm::new(&key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: payload,
+                    aad: &aad(&key_id, topic),
+                },
+            )
+            .map_err(|_| Error::InvalidPayload("encryption failed".to_string()))?;
+        Envelope::new(CONTENT_TYPE, ciphertext)
+            .with_property(KEY_ID, key_id)
+            .with_property(NONCE, hex::encode(&nonce))
+            .encode()
+            .map(Some)
+    }
+
+    pub(crate) fn encrypt_message(&self, message: &Message) -> Result<Option<Message>> {
+        Ok(self
+            .encrypt(&message.topic, &message.payload)?
+            .map(|payload| Message {
+                payload,
+                ..message.clone()
+            }))
+    }
+
+    // The plaintext of an encrypted payload, None for an unencrypted one
+    // that is let through, or an error if the message is to be dropped.
+    pub(crate) fn decrypt(&self, topic: &str, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        let envelope = match Envelope::decode(payload) {
+            Ok(envelope) if envelope.content_type == CONTENT_TYPE => envelope,
+            _ if payload.is_empty() || self.plaintext_accepted || !self.covers(topic) => {
+                return Ok(None)
+            }
+            _ => return Err(Error::InvalidPayload("unencrypted payload".to_string())),
+        };
+        let invalid = |reason: &str| Error::InvalidPayload(format!("encryption: {}", reason));
+        let key_id = envelope
+            .property(KEY_ID)
+            .ok_or_else(|| invalid("no key id"))?;
+        let key = self
+            .keys
+            .decryption_key(key_id)
+            .ok_or_else(|| invalid("unknown key"))?;
+        let nonce = envelope
+            .property(NONCE)
+            .and_then(hex::decode)
+            .filter(|nonce| nonce.len() == NONCE_LEN)
+            .ok_or_else(|| invalid("bad nonce"))?;
+        Aes256Gcm::new(&key.into())
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &envelope.payload,
+                    aad: &aad(key_id, topic),
+                },
+            )
+            .map(Some)
+            .map_err(|_| invalid("authentication failed"))
+    }
+}
+
+// The authenticated data: the key id and the topic, so a payload cannot be
+// replayed to another topic under the same key. Length-prefixed so that no
+// two pairs give the same bytes.
+fn aad(key_id: &str, topic: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + key_id.len() + topic.len());
+    aad.extend_from_slice(&(key_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(key_id.as_bytes());
+    aad.extend_from_slice(topic.as_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypts_filtered_topics_and_rotates() {
+        let encryption = Encryption::new(StaticKeys::new("k1", [1; 32]))
+            .with_filter("secure/#")
+            .unwrap();
+        assert_eq!(encryption.encrypt("public/temp", b"21").unwrap(), None);
+        assert_eq!(encryption.encrypt("secure/temp", b"").unwrap(), None);
+
+        let sealed = encryption.encrypt("secure/temp", b"21").unwrap().unwrap();
+        let envelope = Envelope::decode(&sealed).unwrap();
+        assert_eq!(envelope.property(KEY_ID), Some("k1"));
+        assert_ne!(envelope.payload, b"21");
+        assert_eq!(
+            encryption.decrypt("secure/temp", &sealed).unwrap(),
+            Some(b"21".to_vec())
+        );
+        // Fresh nonce each time.
+        assert_ne!(
+            encryption.encrypt("secure/temp", b"21").unwrap().unwrap(),
+            sealed
+        );
+
+        // Plaintext is refused on the filters only, unless accepted.
+        assert!(encryption.decrypt("secure/temp", b"21").is_err());
+        assert_eq!(encryption.decrypt("public/temp", b"21").unwrap(), None);
+
+        // After rotation the old key still decrypts; a tampered key id
+        // fails authentication.
+        let rotated = Encryption::new(StaticKeys::new("k2", [2; 32]).with_key("k1", [1; 32]))
+            .with_plaintext_accepted(true);
+        assert_eq!(
+            rotated.decrypt("secure/temp", &sealed).unwrap(),
+            Some(b"21".to_vec())
+        );
+        assert_eq!(rotated.decrypt("secure/temp", b"21").unwrap(), None);
+        let tampered = envelope
+            .clone()
+            .with_property(KEY_ID, "k2")
+            .encode()
+            .unwrap();
+        assert!(rotated.decrypt("secure/temp", &tampered).is_err());
+        let other = Encryption::new(StaticKeys::new("k1", [3; 32]));
+        assert!(other.decrypt("secure/temp", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_payloads_only_decrypt_on_their_topic() {
+        let encryption = Encryption::new(StaticKeys::new("k1", [1; 32]));
+        let sealed = encryption.encrypt("secure/a", b"open").unwrap().unwrap();
+        assert!(encryption.decrypt("secure/b", &sealed).is_err());
+        assert_eq!(
+            encryption.decrypt("secure/a", &sealed).unwrap(),
+            Some(b"open".to_vec())
+        );
+    }
+}
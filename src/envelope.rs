@@ -0,0 +1,302 @@
+use crate::codec::Codec;
+use crate::error::{Error, Result};
+use crate::payload::FromPayload;
+
+pub const MAGIC: [u8; 2] = *b"PM";
+pub const FORMAT_VERSION: u8 = 2;
+
+// The content type of envelopes wrapped around payloads to carry
+// properties.
+pub const CONTENT_TYPE: &str = "application/octet-stream";
+
+const FLAG_COMPRESSED: u8 = 0x01;
+const HEADER_LEN: usize = 7;
+
+// Framing for payloads that need to evolve:
+//
+//   magic (2) | format version (1) | flags (1) | schema version (u16 BE)
+//   | content type length (1) | content type (UTF-8)
+//   | [version 2: property count (1) | per property: key length (1) | key
+//      | value length (u16 BE) | value] | payload
+//
+// Properties stand in for MQTT 5 user properties, which the 3.1.1 protocol
+// spoken here lacks. Envelopes without properties are written as version 1,
+// readable by decoders that predate them.
+//
+// Decoders reject unknown format versions; schema versions are left to the
+// application. The compressed flag is only a marker, compressing the payload
+// is up to the producer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Envelope {
+    pub schema_version: u16,
+    pub content_type: String,
+    pub compressed: bool,
+    pub properties: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(content_type: impl Into<String>, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            content_type: content_type.into(),
+            payload: payload.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_schema_version(mut self, version: u16) -> Self {
+        self.schema_version = version;
+        self
+    }
+
+    pub fn with_compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    // Sets `key`, replacing an earlier value.
+    pub fn with_property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        match self.properties.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.properties.push((key, value)),
+        }
+        self
+    }
+
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // `payload` with `properties` set: an envelope gains them, anything else
+    // is wrapped in a new one.
+    pub(crate) fn stamp<K, V>(
+        payload: &[u8],
+        properties: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Vec<u8>>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let envelope =
+            Self::decode(payload).unwrap_or_else(|_| Self::new(CONTENT_TYPE, payload.to_vec()));
+        properties
+            .into_iter()
+            .fold(envelope, |envelope, (key, value)| {
+                envelope.with_property(key, value)
+            })
+            .encode()
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let content_type = self.content_type.as_bytes();
+        let content_type_len = u8::try_from(content_type.len())
+            .map_err(|_| Error::InvalidPayload("content type longer than 255 bytes".into()))?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + content_type.len() + self.payload.len());
+        out.extend_from_slice(&MAGIC);
+        out.push(if self.properties.is_empty() {
+            1
+        } else {
+            FORMAT_VERSION
+        });
+        out.push(if self.compressed { FLAG_COMPRESSED } else { 0 });
+        out.extend_from_slice(&self.schema_version.to_be_bytes());
+        out.push(content_type_len);
+        out.extend_from_slice(content_type);
+        if !self.properties.is_empty() {
+            let too_long = |what: &str| Error::InvalidPayload(format!("envelope: {}", what));
+            out.push(
+                u8::try_from(self.properties.len())
+                    .map_err(|_| too_long("more than 255 properties"))?,
+            );
+            for (key, value) in &self.properties {
+                out.push(
+                    u8::try_from(key.len())
+                        .map_err(|_| too_long("property key longer than 255 bytes"))?,
+                );
+                out.extend_from_slice(key.as_bytes());
+                out.extend_from_slice(
+                    &u16::try_from(value.len())
+                        .map_err(|_| too_long("property value longer than 65535 bytes"))?
+                        .to_be_bytes(),
+                );
+                out.extend_from_slice(value.as_bytes());
+            }
+        }
+        out.extend_from_slice(&self.payload);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidPayload(format!("envelope: {}", reason));
+
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid("truncated header"));
+        }
+        if bytes[..2] != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let version = bytes[2];
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+        let flags = bytes[3];
+        let schema_version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let content_type_end = HEADER_LEN + bytes[6] as usize;
+        let content_type = bytes
+            .get(HEADER_LEN..content_type_end)
+            .ok_or_else(|| invalid("truncated content type"))?;
+        let content_type =
+            std::str::from_utf8(content_type).map_err(|_| invalid("content type is not UTF-8"))?;
+
+        let mut rest = &bytes[content_type_end..];
+        let mut take = |n: usize| -> Result<&[u8]> {
+            let taken = rest
+                .get(..n)
+                .ok_or_else(|| invalid("truncated properties"))?;
+            rest = &rest[n..];
+            Ok(taken)
+        };
+        let mut properties = Vec::new();
+        if version >= 2 {
+            let count = take(1)?[0];
+            for _ in 0..count {
+                let key_len = take(1)?[0] as usize;
+                let key = take(key_len)?.to_vec();
+                let value_len = take(2)?;
+                let value_len = u16::from_be_bytes([value_len[0], value_len[1]]) as usize;
+                let value = take(value_len)?.to_vec();
+                let utf8 =
+                    |b: Vec<u8>| String::from_utf8(b).map_err(|_| invalid("property is not UTF-8"));
+                properties.push((utf8(key)?, utf8(value)?));
+            }
+        }
+
+        Ok(Self {
+            schema_version,
+            content_type: content_type.to_string(),
+            compressed: flags & FLAG_COMPRESSED != 0,
+            properties,
+            payload: rest.to_vec(),
+        })
+    }
+}
+
+impl FromPayload for Envelope {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        Self::decode(payload)
+    }
+}
+
+// Wraps a codec so its payloads travel in an `Envelope` tagged with the
+// codec's content type and the given schema version. Decoding rejects
+// envelopes with another content type or a newer schema version.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Enveloped<C> {
+    inner: C,
+    schema_version: u16,
+}
+
+impl<C> Enveloped<C> {
+    pub fn new(inner: C, schema_version: u16) -> Self {
+        Self {
+            inner,
+            schema_version,
+        }
+    }
+}
+
+impl<T, C: Codec<T>> Codec<T> for Enveloped<C> {
+    fn content_type(&self) -> &'static str {
+        self.inner.content_type()
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Envelope::new(self.inner.content_type(), self.inner.encode(value)?)
+            .with_schema_version(self.schema_version)
+            .encode()
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T> {
+        let envelope = Envelope::decode(payload)?;
+        if envelope.content_type != self.inner.content_type() {
+            return Err(Error::InvalidPayload(format!(
+                "envelope: expected {}, got {}",
+                self.inner.content_type(),
+                envelope.content_type
+            )));
+        }
+        if envelope.schema_version > self.schema_version {
+            return Err(Error::InvalidPayload(format!(
+                "envelope: schema version {} is newer than {}",
+                envelope.schema_version, self.schema_version
+            )));
+        }
+        if envelope.compressed {
+            return Err(Error::InvalidPayload(
+                "envelope: compressed payloads are not supported".into(),
+            ));
+        }
+        self.inner.decode(&envelope.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Utf8;
+
+    impl Codec<String> for Utf8 {
+        fn content_type(&self) -> &'static str {
+            "text/plain"
+        }
+
+        fn encode(&self, value: &String) -> Result<Vec<u8>> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn decode(&self, payload: &[u8]) -> Result<String> {
+            String::from_payload(payload)
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let envelope = Envelope::new("application/json", b"{}".to_vec())
+            .with_schema_version(3)
+            .with_compressed(true);
+        let bytes = envelope.encode().unwrap();
+        assert_eq!(&bytes[..4], b"PM\x01\x01");
+        assert_eq!(Envelope::decode(&bytes).unwrap(), envelope);
+
+        assert!(Envelope::decode(b"PM").is_err());
+        assert!(Envelope::decode(b"XX\x01\x00\x00\x00\x00").is_err());
+        assert!(Envelope::decode(b"PM\x01\x00\x00\x00\x05ab").is_err());
+
+        let envelope = Envelope::new("text/plain", b"hi".to_vec())
+            .with_property("origin", "a")
+            .with_property("origin", "b");
+        let bytes = envelope.encode().unwrap();
+        assert_eq!(bytes[2], 2);
+        let decoded = Envelope::decode(&bytes).unwrap();
+        assert_eq!(decoded.property("origin"), Some("b"));
+        assert_eq!(decoded, envelope);
+        assert!(Envelope::decode(&bytes[..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_enveloped_codec() {
+        let v1 = Enveloped::new(Utf8, 1);
+        let v2 = Enveloped::new(Utf8, 2);
+        let bytes = v1.encode(&"hello".to_string()).unwrap();
+        assert_eq!(v2.decode(&bytes).unwrap(), "hello");
+        assert!(v1
+            .decode(&v2.encode(&"hello".to_string()).unwrap())
+            .is_err());
+    }
+}
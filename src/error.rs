@@ -1,3 +1,4 @@
+use std::convert::Infallible;
 use std::ffi::NulError;
 use thiserror::Error;
 
@@ -5,20 +6,56 @@ use thiserror::Error;
 pub enum Error {
     #[error("MQTT initialization failed")]
     InitializationError,
-    #[error("Invalid broker URL")]
-    InvalidBrokerUrl,
+    #[error("Invalid broker URL: {0}")]
+    InvalidBrokerUrl(String),
     #[error("Invalid credentials")]
     InvalidCredentials,
     #[error("Connection failed")]
     ConnectionError,
-    #[error("Subscription failed")]
-    SubscriptionError,
-    #[error("Publication failed")]
-    PublicationError,
+    #[error("Connection refused: {reason}")]
+    ConnectionRefused { code: i32, reason: String },
+    #[error("Invalid client id: {0}")]
+    InvalidClientId(String),
+    #[error("Invalid TLS configuration: {0}")]
+    InvalidTls(String),
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+    #[error("Not supported by this backend: {0}")]
+    Unsupported(String),
+    #[error("Subscription failed: {0}")]
+    SubscriptionError(String),
+    #[error("Publication failed: {0}")]
+    PublicationError(String),
     #[error("Invalid topic")]
     InvalidTopic,
+    #[error("Invalid payload: {0}")]
+    InvalidPayload(String),
+    #[error("Packet of {size} bytes is over the maximum of {max}")]
+    PacketTooLarge { size: usize, max: usize },
+    #[error("Publish rate limit exceeded")]
+    RateLimited,
+    #[error("Timed out")]
+    Timeout,
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Message expired")]
+    Expired,
+    #[error("Message dropped by middleware")]
+    Dropped,
+    #[error("Access denied to {0}")]
+    AccessDenied(String),
+    #[error("RPC failed: {0}")]
+    Rpc(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
     #[error("String contains null byte: {0}")]
     NulError(#[from] NulError),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<Infallible> for Error {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
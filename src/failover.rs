@@ -0,0 +1,106 @@
+use crate::options::Backoff;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+// The brokers of a `connect_brokers` call and the signal between the state
+// callback, which reports lost connections, and the failover thread, which
+// reconnects.
+pub(crate) struct Failover {
+    brokers: Vec<(String, u16)>,
+    backoff: Backoff,
+    state: Mutex<FailoverState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct FailoverState {
+    next: usize,
+    lost: bool,
+    stopped: bool,
+}
+
+impl Failover {
+    pub(crate) fn new(brokers: Vec<(String, u16)>, backoff: Backoff) -> Self {
+        Self {
+            brokers,
+            backoff,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FailoverState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.brokers.len()
+    }
+
+    // The broker after the one tried last, round-robin.
+    pub(crate) fn next_broker(&self) -> (&str, u16) {
+        let mut state = self.lock();
+        let (host, port) = &self.brokers[state.next % self.brokers.len()];
+        state.next = (state.next + 1) % self.brokers.len();
+        (host, *port)
+    }
+
+    pub(crate) fn connection_lost(&self) {
+        self.lock().lost = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn stop(&self) {
+        self.lock().stopped = true;
+        self.changed.notify_all();
+    }
+
+    // Blocks until the connection is lost, returns false once stopped.
+    pub(crate) fn wait_lost(&self) -> bool {
+        let mut state = self
+            .changed
+            .wait_while(self.lock(), |state| !state.lost && !state.stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.lost = false;
+        !state.stopped
+    }
+
+    // Waits out the backoff after `failures` failed attempts, returns false
+    // if stopped meanwhile.
+    pub(crate) fn back_off(&self, failures: u32) -> bool {
+        let delay = self.backoff.delay(failures);
+        !self
+            .changed
+            .wait_timeout_while(self.lock(), delay, |state| !state.stopped)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+            .stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_robin_and_backoff() {
+        let failover = Failover::new(
+            vec![("a".to_string(), 1883), ("b".to_string(), 8883)],
+            Backoff::new(Duration::from_millis(100), Duration::from_millis(350)),
+        );
+        assert_eq!(failover.next_broker(), ("a", 1883));
+        assert_eq!(failover.next_broker(), ("b", 8883));
+        assert_eq!(failover.next_broker(), ("a", 1883));
+
+        let delays: Vec<u128> = (1..=4)
+            .map(|n| failover.backoff.delay(n).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 350, 350]);
+
+        failover.connection_lost();
+        assert!(failover.wait_lost());
+        failover.stop();
+        assert!(!failover.wait_lost());
+        assert!(!failover.back_off(1));
+    }
+}
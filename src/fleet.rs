@@ -0,0 +1,281 @@
+use crate::client::Client;
+use crate::error::Result;
+use crate::message::MessageView;
+use crate::types::QoS;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+
+type Hook<T> = dyn Fn(&T) -> std::result::Result<(), String> + Send + Sync;
+type Rejected = dyn Fn(&str, &str) + Send + Sync;
+
+// Distributes configuration to devices through retained JSON documents on
+// layered topics, typically one per fleet, group and device. Layers are
+// merged in the order they were added, later ones taking precedence, with
+// JSON merge patch rules (RFC 7396): objects merge key by key and `null`
+// removes a key. An empty retained message clears a layer.
+//
+// Each update is deserialized into `T`, checked by the validator and handed
+// to the apply hook. If any step fails the update is rolled back: the layer
+// keeps its previous document, the previous config is re-applied if the
+// apply hook had started, and the rejection is reported.
+pub struct FleetConfig<T> {
+    layers: Vec<String>,
+    validator: Option<Box<Hook<T>>>,
+    on_rejected: Option<Box<Rejected>>,
+}
+
+impl<T> Default for FleetConfig<T> {
+    fn default() -> Self {
+        Self {
+            layers: Vec::new(),
+            validator: None,
+            on_rejected: None,
+        }
+    }
+}
+
+struct FleetState<T> {
+    documents: Vec<Option<Value>>,
+    current: Option<Arc<T>>,
+}
+
+impl<T: DeserializeOwned + Send + Sync + 'static> FleetConfig<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a layer above the ones already added.
+    pub fn with_layer(mut self, topic: impl Into<String>) -> Self {
+        self.layers.push(topic.into());
+        self
+    }
+
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: Fn(&T) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    // Called with the topic and reason of every update that was rolled back.
+    pub fn with_on_rejected<F>(mut self, on_rejected: F) -> Self
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        self.on_rejected = Some(Box::new(on_rejected));
+        self
+    }
+
+    // Subscribes to every layer and applies the merged config whenever one
+    // changes. Retained layers arrive one at a time, so the first configs
+    // applied may lack the layers still on their way.
+    pub fn start<F>(self, client: &Arc<Client>, apply: F) -> Result<FleetConfigHandle<T>>
+    where
+        F: Fn(&T) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        let state = Arc::new(Mutex::new(FleetState {
+            documents: vec![None; self.layers.len()],
+            current: None,
+        }));
+        let layers = self.layers.clone();
+        let applier = Arc::new(Applier {
+            config: self,
+            apply: Box::new(apply),
+        });
+
+        let mut handles = Vec::with_capacity(layers.len());
+        for (layer, topic) in layers.iter().enumerate() {
+            let (applier, state) = (applier.clone(), state.clone());
+            let handle = client.subscribe_with(topic.as_str(), QoS::AtLeastOnce, move |msg| {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                applier.update(&mut state, layer, msg);
+            });
+            match handle {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    for handle in handles {
+                        let _ = client.unsubscribe(handle);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(FleetConfigHandle {
+            client: Arc::downgrade(client),
+            handles,
+            state,
+        })
+    }
+}
+
+struct Applier<T> {
+    config: FleetConfig<T>,
+    apply: Box<Hook<T>>,
+}
+
+impl<T: DeserializeOwned> Applier<T> {
+    fn update(&self, state: &mut FleetState<T>, layer: usize, msg: &MessageView) {
+        let document = match msg.payload() {
+            [] => None,
+            payload => match serde_json::from_slice(payload) {
+                Ok(document) => Some(document),
+                Err(e) => return self.reject(msg.topic(), &e.to_string()),
+            },
+        };
+        let previous = std::mem::replace(&mut state.documents[layer], document);
+
+        if let Err(reason) = self.try_apply(state) {
+            state.documents[layer] = previous;
+            self.reject(msg.topic(), &reason);
+        }
+    }
+
+    fn try_apply(&self, state: &mut FleetState<T>) -> std::result::Result<(), String> {
+        let merged = state.documents.iter().flatten().fold(
+            Value::Object(Map::new()),
+            |mut merged, document| {
+                merge_patch(&mut merged, document);
+                merged
+            },
+        );
+        let config: T = serde_json::from_value(merged).map_err(|e| e.to_string())?;
+        if let Some(validator) = &self.config.validator {
+            validator(&config)?;
+        }
+        if let Err(reason) = (self.apply)(&config) {
+            if let Some(current) = &state.current {
+                let _ = (self.apply)(current);
+            }
+            return Err(reason);
+        }
+        state.current = Some(Arc::new(config));
+        Ok(())
+    }
+
+    fn reject(&self, topic: &str, reason: &str) {
+        if let Some(on_rejected) = &self.config.on_rejected {
+            on_rejected(topic, reason);
+        }
+    }
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            value => merge_patch(target.entry(key).or_insert(Value::Null), value),
+        }
+    }
+}
+
+// Stops following the config layers when dropped.
+pub struct FleetConfigHandle<T> {
+    client: Weak<Client>,
+    handles: Vec<i64>,
+    state: Arc<Mutex<FleetState<T>>>,
+}
+
+impl<T> FleetConfigHandle<T> {
+    // The config last applied, if any.
+    pub fn current(&self) -> Option<Arc<T>> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .current
+            .clone()
+    }
+}
+
+impl<T> Drop for FleetConfigHandle<T> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            for &handle in &self.handles {
+                let _ = client.unsubscribe(handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_layers_merge_and_roll_back() {
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let applier = Applier {
+            config: FleetConfig::<HashMap<String, u32>>::new()
+                .with_validator(|config| match config.get("rate") {
+                    Some(&rate) if rate > 100 => Err("rate over 100".to_string()),
+                    _ => Ok(()),
+                })
+                .with_on_rejected({
+                    let rejected = rejected.clone();
+                    move |topic, reason| {
+                        rejected
+                            .lock()
+                            .unwrap()
+                            .push(format!("{}: {}", topic, reason))
+                    }
+                }),
+            apply: Box::new(|_| Ok(())),
+        };
+        let mut state = FleetState {
+            documents: vec![None, None],
+            current: None,
+        };
+        let mut update = |layer: usize, payload: &str| {
+            let msg = MessageView {
+                topic: ["group", "device"][layer],
+                payload: payload.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retained: true,
+                shared: Default::default(),
+            };
+            applier.update(&mut state, layer, &msg);
+            let mut current: Vec<_> = state
+                .current
+                .as_deref()
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            current.sort();
+            current
+        };
+
+        let pair = |k: &str, v| (k.to_string(), v);
+        assert_eq!(
+            update(0, r#"{"rate": 10, "level": 1}"#),
+            [pair("level", 1), pair("rate", 10)]
+        );
+        assert_eq!(
+            update(1, r#"{"rate": 20, "level": null}"#),
+            [pair("rate", 20)]
+        );
+        // Invalid: the device layer keeps its previous document.
+        assert_eq!(update(1, r#"{"rate": 500}"#), [pair("rate", 20)]);
+        assert_eq!(
+            update(0, r#"{"rate": 10, "depth": 3}"#),
+            [pair("depth", 3), pair("rate", 20)]
+        );
+        assert_eq!(update(1, ""), [pair("depth", 3), pair("rate", 10)]);
+        assert_eq!(*rejected.lock().unwrap(), ["device: rate over 100"]);
+    }
+}
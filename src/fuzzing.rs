@@ -0,0 +1,172 @@
+use crate::backend;
+use crate::bindings;
+use crate::client::{CallbackContext, Client};
+use std::ffi::c_void;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Drives the client's FFI callbacks as the bridge would, without a bridge,
+// for the fuzz targets in `fuzz/`. Inputs are raw fuzzer bytes, decoded
+// into the C structs the callbacks receive, so that null pointers, topics
+// that are not UTF-8, out-of-range QoS values and lengths too large for a
+// slice all reach the unsafe code.
+//
+// The callbacks catch panics, so a panic is counted rather than crashing
+// the fuzzer: callers should check `panics` after each input.
+pub struct CallbackShim {
+    context: Box<CallbackContext>,
+    delivered: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+}
+
+impl Default for CallbackShim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallbackShim {
+    pub fn new() -> Self {
+        let delivered = Arc::new(AtomicU64::new(0));
+        let errors = Arc::new(AtomicU64::new(0));
+        let context = {
+            let (delivered, errors) = (delivered.clone(), errors.clone());
+            // Never connected, so the backend is not called.
+            CallbackContext::new(
+                "fuzz",
+                backend::selected(),
+                None,
+                // Every byte is read, so sanitizers see a bad slice.
+                Box::new(move |msg| {
+                    black_box(msg.topic().bytes().fold(0u8, u8::wrapping_add));
+                    black_box(msg.payload().iter().fold(0u8, |a, b| a.wrapping_add(*b)));
+                    delivered.fetch_add(1, Ordering::Relaxed);
+                }),
+                Box::new(|_| {}),
+                Box::new(move |code, message| {
+                    black_box((code, message.len()));
+                    errors.fetch_add(1, Ordering::Relaxed);
+                }),
+            )
+        };
+        Self {
+            context,
+            delivered,
+            errors,
+        }
+    }
+
+    // `data` is a flags byte, a QoS byte, a retained byte and a split byte,
+    // then the topic and payload. Flags: 1 passes a null topic, 2 a null
+    // payload, 4 a payload length past `isize::MAX`; otherwise the length
+    // is the payload's, as the bridge never reports more than it copied.
+    pub fn message(&self, data: &[u8]) {
+        let header = |i: usize| data.get(i).copied().unwrap_or(0);
+        let (flags, qos, retained, split) = (header(0), header(1), header(2), header(3));
+        let rest = data.get(4..).unwrap_or_default();
+        let (topic, payload) = rest.split_at((split as usize).min(rest.len()));
+
+        let topic = nul_terminated(topic);
+        let message = bindings::mqtt_message_data_t {
+            topic: match flags & 1 {
+                0 => topic.as_ptr().cast(),
+                _ => std::ptr::null(),
+            },
+            payload: match flags & 2 {
+                0 => payload.as_ptr(),
+                _ => std::ptr::null(),
+            },
+            payload_length: match flags & 4 {
+                0 => payload.len(),
+                _ => (isize::MAX as usize).wrapping_add(1 + payload.len()),
+            },
+            // Sign-extended, so negative values are tried too.
+            qos: qos as i8 as i32,
+            retained: retained as i32,
+            message_id: 0,
+        };
+        unsafe {
+            Client::message_callback(&message, self.context_ptr());
+        }
+    }
+
+    // `data` is a flags byte and four bytes of error code, then the message.
+    // Flag 1 passes a null message.
+    pub fn error(&self, data: &[u8]) {
+        let flags = data.first().copied().unwrap_or(0);
+        let mut code = [0; 4];
+        let rest = data.get(1..).unwrap_or_default();
+        let n = rest.len().min(4);
+        code[..n].copy_from_slice(&rest[..n]);
+        let message = nul_terminated(&rest[n..]);
+        let message = match flags & 1 {
+            0 => message.as_ptr().cast(),
+            _ => std::ptr::null(),
+        };
+        unsafe {
+            Client::error_callback(i32::from_le_bytes(code), message, self.context_ptr());
+        }
+    }
+
+    // Messages that reached the message callback.
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    // Calls of the error callback, including those reporting a panic.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn panics(&self) -> u64 {
+        self.context.callback_panics()
+    }
+
+    fn context_ptr(&self) -> *mut c_void {
+        &*self.context as *const CallbackContext as *mut c_void
+    }
+}
+
+// Interior NULs are kept: C stops at the first, as it would.
+fn nul_terminated(bytes: &[u8]) -> Vec<u8> {
+    let mut owned = Vec::with_capacity(bytes.len() + 1);
+    owned.extend_from_slice(bytes);
+    owned.push(0);
+    owned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(flags: u8, qos: u8, topic: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![flags, qos, 0, topic.len() as u8];
+        data.extend_from_slice(topic);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        let shim = CallbackShim::new();
+        shim.message(&message(0, 1, b"a/b", b"hello"));
+        shim.message(&message(2, 0, b"a/b", b"hello"));
+        shim.message(&[]);
+        assert_eq!(shim.delivered(), 3);
+
+        shim.message(&message(1, 0, b"a/b", b"hello"));
+        shim.message(&message(4, 0, b"a/b", b"hello"));
+        shim.message(&message(0, 0, b"\xff\xfe", b"hello"));
+        shim.message(&message(0, 3, b"a/b", b"hello"));
+        shim.message(&message(0, 0xff, b"a/b", b"hello"));
+        assert_eq!(shim.delivered(), 3);
+
+        shim.error(&[0, 1, 0, 0, 0, b'x']);
+        shim.error(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+        shim.error(&[1, 1, 0, 0, 0]);
+        shim.error(&[]);
+        assert_eq!(shim.errors(), 3);
+        assert_eq!(shim.panics(), 0);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock, Weak};
+
+// A cheaply cloneable reference to a `Client`, for sharing one connection
+// between threads and tasks. Every `Client` method takes `&self`, the
+// connection lifecycle included, so a handle can do everything the client
+// can; it dereferences to it. The session is destroyed when the last
+// handle is dropped.
+//
+// Helpers that take `&Arc<Client>` (monitors, probes, recorders) accept
+// `handle.arc()`.
+#[derive(Clone)]
+pub struct ClientHandle {
+    client: Arc<Client>,
+}
+
+impl ClientHandle {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client: Arc::new(client),
+        }
+    }
+
+    pub fn arc(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    pub fn into_arc(self) -> Arc<Client> {
+        self.client
+    }
+
+    pub fn downgrade(&self) -> WeakClientHandle {
+        let weak = WeakClientHandle::new();
+        weak.bind(self);
+        weak
+    }
+
+    // Whether both handles refer to the same client.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.client, &other.client)
+    }
+}
+
+impl Deref for ClientHandle {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl AsRef<Client> for ClientHandle {
+    fn as_ref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl From<Client> for ClientHandle {
+    fn from(client: Client) -> Self {
+        Self::new(client)
+    }
+}
+
+impl From<Arc<Client>> for ClientHandle {
+    fn from(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+}
+
+impl From<ClientHandle> for Arc<Client> {
+    fn from(handle: ClientHandle) -> Self {
+        handle.client
+    }
+}
+
+// A reference to a client that does not keep it alive, for callbacks that
+// publish, such as replies from the message callback: a `ClientHandle`
+// captured there would be a cycle (client, callback, client) and the
+// client would never be dropped.
+//
+// The message callback is needed to create the client, so the handle can
+// be created unbound, captured, and bound to the client afterwards:
+//
+//     let replies = WeakClientHandle::new();
+//     let client = ClientHandle::new(Client::new(id, {
+//         let replies = replies.clone();
+//         move |msg| {
+//             let _ = replies.publish(&reply_to(msg));
+//         }
+//     }, on_state, on_error)?);
+//     replies.bind(&client);
+//
+// Clones share the binding.
+#[derive(Clone, Default)]
+pub struct WeakClientHandle {
+    client: Arc<OnceLock<Weak<Client>>>,
+}
+
+impl WeakClientHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Binds this handle and its clones to `client`. A handle is bound once;
+    // returns false if it already was.
+    pub fn bind(&self, client: &ClientHandle) -> bool {
+        self.client.set(Arc::downgrade(&client.client)).is_ok()
+    }
+
+    // None before `bind` and once the client is dropped.
+    pub fn upgrade(&self) -> Option<ClientHandle> {
+        self.client
+            .get()
+            .and_then(Weak::upgrade)
+            .map(ClientHandle::from)
+    }
+
+    // Publishes through the client, failing with `Error::ConnectionError`
+    // if there is none.
+    pub fn publish(&self, message: &Message) -> Result<i64> {
+        self.upgrade()
+            .ok_or(Error::ConnectionError)?
+            .publish(message)
+    }
+}
+
+impl fmt::Debug for WeakClientHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakClientHandle")
+            .field("bound", &self.client.get().is_some())
+            .field("alive", &self.upgrade().is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::types::{ConnectionState, QoS};
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn test_clones_share_the_client() {
+        let handle =
+            ClientHandle::new(Client::new("handle-test", |_| {}, |_| {}, |_, _| {}).unwrap());
+        let clone = handle.clone();
+        assert!(handle.ptr_eq(&clone));
+        assert_eq!(Arc::strong_count(handle.arc()), 2);
+
+        let id = thread::spawn(move || {
+            assert_eq!(clone.state(), ConnectionState::Disconnected);
+            clone.client_id().to_string()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(id, handle.client_id());
+        assert_eq!(Arc::strong_count(handle.arc()), 1);
+    }
+
+    #[test]
+    fn test_weak_handle_does_not_keep_the_client() {
+        let weak = WeakClientHandle::new();
+        let captured = weak.clone();
+        assert!(captured.upgrade().is_none());
+
+        let handle =
+            ClientHandle::new(Client::new("weak-test", |_| {}, |_| {}, |_, _| {}).unwrap());
+        assert!(weak.bind(&handle));
+        assert!(!weak.bind(&handle));
+        assert!(captured.upgrade().unwrap().ptr_eq(&handle));
+        assert_eq!(Arc::strong_count(handle.arc()), 1);
+
+        drop(handle);
+        assert!(captured.upgrade().is_none());
+        let message = Message::new("a/b", "x").unwrap();
+        assert!(matches!(
+            captured.publish(&message),
+            Err(Error::ConnectionError)
+        ));
+    }
+
+    #[test]
+    fn test_handler_can_unsubscribe_itself() {
+        let fake = Arc::new(Fake::default());
+        let handle = ClientHandle::new(
+            Client::with_backend("weak-fake", fake.clone(), |_| {}, |_| {}, |_, _| {}).unwrap(),
+        );
+        handle.connect("broker", 1883).unwrap();
+
+        let weak = handle.downgrade();
+        let own = Arc::new(AtomicI64::new(0));
+        let (tx, rx) = mpsc::channel();
+        let subscription = {
+            let own = own.clone();
+            handle
+                .subscribe_with("once/#", QoS::AtLeastOnce, move |msg| {
+                    let client = weak.upgrade().unwrap();
+                    client.unsubscribe(own.load(Ordering::SeqCst)).unwrap();
+                    client
+                        .subscribe_with("again/#", QoS::AtMostOnce, |_| {})
+                        .unwrap();
+                    tx.send(msg.topic().to_string()).unwrap();
+                })
+                .unwrap()
+        };
+        own.store(subscription, Ordering::SeqCst);
+
+        fake.inject(Message::new("once/1", "x").unwrap());
+        fake.inject(Message::new("once/2", "x").unwrap());
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["once/1"]);
+        assert_eq!(fake.filters(), ["again/#"]);
+    }
+}
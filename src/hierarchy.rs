@@ -0,0 +1,198 @@
+use crate::monitor::MonitorStats;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+// One topic level. Counts include every topic below the node; `topic` is set
+// when messages were also published to the node's own path.
+#[derive(Debug, Clone, Default)]
+pub struct HierarchyNode {
+    pub name: String,
+    pub path: String,
+    pub topic: bool,
+    pub messages: u64,
+    pub bytes: u64,
+    pub rate: f64,
+    pub children: BTreeMap<String, HierarchyNode>,
+}
+
+// The observed topic namespace as a tree, with message counts and rates
+// (messages per second over the stats window) aggregated at every level.
+#[derive(Debug, Clone, Default)]
+pub struct TopicHierarchy {
+    pub root: HierarchyNode,
+    pub window: Duration,
+}
+
+impl TopicHierarchy {
+    pub fn from_stats(stats: &MonitorStats) -> Self {
+        let mut root = HierarchyNode::default();
+        for summary in &stats.topics {
+            let mut node = &mut root;
+            node.messages += summary.messages;
+            node.bytes += summary.bytes;
+            let mut path = String::new();
+            for (i, level) in summary.topic.split('/').enumerate() {
+                if i > 0 {
+                    path.push('/');
+                }
+                path.push_str(level);
+                node = node
+                    .children
+                    .entry(level.to_string())
+                    .or_insert_with(|| HierarchyNode {
+                        name: level.to_string(),
+                        path: path.clone(),
+                        ..Default::default()
+                    });
+                node.messages += summary.messages;
+                node.bytes += summary.bytes;
+            }
+            node.topic = true;
+        }
+
+        let secs = stats.elapsed.as_secs_f64();
+        set_rates(&mut root, secs);
+        Self {
+            root,
+            window: stats.elapsed,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write_json(&mut out, &self.root);
+        out
+    }
+
+    // Graphviz source; render with e.g. `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph topics {\n    rankdir=LR;\n    node [shape=box];\n");
+        let mut next_id = 0;
+        write_dot(&mut out, &self.root, &mut next_id);
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn set_rates(node: &mut HierarchyNode, secs: f64) {
+    node.rate = if secs > 0.0 {
+        node.messages as f64 / secs
+    } else {
+        0.0
+    };
+    for child in node.children.values_mut() {
+        set_rates(child, secs);
+    }
+}
+
+fn write_json(out: &mut String, node: &HierarchyNode) {
+    out.push_str("{\"name\":");
+    write_json_str(out, &node.name);
+    out.push_str(",\"path\":");
+    write_json_str(out, &node.path);
+    let _ = write!(
+        out,
+        ",\"topic\":{},\"messages\":{},\"bytes\":{},\"rate\":{},\"children\":[",
+        node.topic, node.messages, node.bytes, node.rate
+    );
+    for (i, child) in node.children.values().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json(out, child);
+    }
+    out.push_str("]}");
+}
+
+pub(crate) fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Returns the id given to `node`.
+fn write_dot(out: &mut String, node: &HierarchyNode, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let name = if id == 0 { "(root)" } else { &node.name };
+    let _ = writeln!(
+        out,
+        "    n{} [label=\"{}\\n{} msgs, {:.2}/s\"{}];",
+        id,
+        dot_escape(name),
+        node.messages,
+        node.rate,
+        if node.topic { ", style=bold" } else { "" }
+    );
+    for child in node.children.values() {
+        let child_id = write_dot(out, child, next_id);
+        let _ = writeln!(out, "    n{} -> n{};", id, child_id);
+    }
+    id
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::TopicSummary;
+    use crate::time::Instant;
+
+    fn summary(topic: &str, messages: u64) -> TopicSummary {
+        TopicSummary {
+            topic: topic.to_string(),
+            messages,
+            bytes: messages * 10,
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_aggregates_and_exports() {
+        let stats = MonitorStats {
+            messages: 6,
+            bytes: 60,
+            topics: vec![
+                summary("sensors/a/temp", 3),
+                summary("sensors/b", 2),
+                summary("sensors", 1),
+            ],
+            evicted_topics: 0,
+            elapsed: Duration::from_secs(2),
+        };
+        let hierarchy = TopicHierarchy::from_stats(&stats);
+
+        let sensors = &hierarchy.root.children["sensors"];
+        assert_eq!(hierarchy.root.messages, 6);
+        assert_eq!(sensors.messages, 6);
+        assert!(sensors.topic);
+        assert_eq!(sensors.rate, 3.0);
+        let temp = &sensors.children["a"].children["temp"];
+        assert_eq!(
+            (temp.path.as_str(), temp.messages, temp.bytes),
+            ("sensors/a/temp", 3, 30)
+        );
+        assert!(!sensors.children["a"].topic);
+
+        let json = hierarchy.to_json();
+        assert!(json.starts_with("{\"name\":\"\",\"path\":\"\",\"topic\":false,\"messages\":6"));
+        assert!(json.contains("\"path\":\"sensors/a/temp\""));
+        assert!(hierarchy.to_dot().contains("n0 -> n1;"));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::envelope::{Envelope, MAGIC};
+use crate::error::{Error, Result};
+use crate::message::MessageView;
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+// Envelope property carrying the key of a message.
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+#[cfg(feature = "uuid")]
+pub(crate) fn new_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+pub(crate) fn key(payload: &[u8]) -> Option<String> {
+    if !payload.starts_with(&MAGIC) {
+        return None;
+    }
+    let envelope = Envelope::decode(payload).ok()?;
+    envelope.property(IDEMPOTENCY_KEY).map(str::to_string)
+}
+
+// Where `ExactlyOnce` keeps the keys of the messages it processed.
+// Implement it over a database to share them between consumers.
+pub trait KeyStore: Send + Sync {
+    fn contains(&self, key: &str) -> Result<bool>;
+
+    fn insert(&self, key: &str, processed: SystemTime) -> Result<()>;
+
+    // Forgets the keys processed before `cutoff`.
+    fn expire(&self, cutoff: SystemTime) -> Result<()>;
+}
+
+// Keys for the life of the process.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    keys: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn contains(&self, key: &str) -> Result<bool> {
+        let keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(keys.contains_key(key))
+    }
+
+    fn insert(&self, key: &str, processed: SystemTime) -> Result<()> {
+        let mut keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        keys.insert(key.to_string(), processed);
+        Ok(())
+    }
+
+    fn expire(&self, cutoff: SystemTime) -> Result<()> {
+        let mut keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        keys.retain(|_, processed| *processed >= cutoff);
+        Ok(())
+    }
+}
+
+// Keys kept in a file across restarts, one `<unix micros> <key>` line per
+// key, appended as keys are inserted and rewritten when some expire. The
+// keys are also held in memory. Appends are not synced, so a key inserted
+// just before the machine goes down can be lost, and its message processed
+// again.
+pub struct FileKeyStore {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+struct FileState {
+    keys: HashMap<String, SystemTime>,
+    file: File,
+}
+
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+impl FileKeyStore {
+    // Loads the keys in `path`, if it exists, skipping malformed lines.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut keys = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let Some((at, key)) = line.split_once(' ') else {
+                        continue;
+                    };
+                    if let Ok(at) = at.parse() {
+                        keys.insert(key.to_string(), UNIX_EPOCH + Duration::from_micros(at));
+                    }
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(FileState { keys, file }),
+        })
+    }
+
+    // Writes the keys to a new file, then moves it over the old one.
+    fn rewrite(&self, state: &mut FileState) -> Result<()> {
+        let temporary = self.path.with_extension("tmp");
+        let mut contents = String::new();
+        for (key, processed) in &state.keys {
+            contents.push_str(&format!("{} {}\n", micros(*processed), key));
+        }
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &self.path)?;
+        state.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn contains(&self, key: &str) -> Result<bool> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(state.keys.contains_key(key))
+    }
+
+    fn insert(&self, key: &str, processed: SystemTime) -> Result<()> {
+        if key.contains('\n') {
+            return Err(Error::InvalidPayload(
+                "idempotency key contains a newline".to_string(),
+            ));
+        }
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        writeln!(state.file, "{} {}", micros(processed), key)?;
+        state.keys.insert(key.to_string(), processed);
+        Ok(())
+    }
+
+    fn expire(&self, cutoff: SystemTime) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let before = state.keys.len();
+        state.keys.retain(|_, processed| *processed >= cutoff);
+        match state.keys.len() < before {
+            true => self.rewrite(&mut state),
+            false => Ok(()),
+        }
+    }
+}
+
+// Turns QoS 1 delivery, where a message can arrive more than once, into
+// effectively exactly-once processing, for messages published with an
+// idempotency key (see `Message::with_idempotency_key` and
+// `Client::set_idempotency_keys`). A message whose key was processed
+// within `retention` is skipped; messages without a key are always
+// processed. Keys are recorded once the handler returns, so a message whose
+// handler panicked, or did not finish before the process died, is
+// processed again when redelivered.
+pub struct ExactlyOnce {
+    store: Box<dyn KeyStore>,
+    retention: Duration,
+    // Keys being processed, so a duplicate arriving meanwhile on another
+    // thread is skipped too.
+    processing: Mutex<HashSet<String>>,
+    last_expired: Mutex<Instant>,
+    duplicates: AtomicU64,
+}
+
+impl ExactlyOnce {
+    // Keys are kept for a day.
+    pub fn new(store: impl KeyStore + 'static) -> Self {
+        Self {
+            store: Box::new(store),
+            retention: Duration::from_secs(24 * 60 * 60),
+            processing: Mutex::default(),
+            last_expired: Mutex::new(Instant::now()),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    // Must exceed the longest a message can take to be redelivered,
+    // including while the consumer is offline with a persistent session.
+    // Expired keys are forgotten every tenth of it.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    // Runs `handler` on `msg` unless it is a duplicate, returning whether
+    // it ran. Fails if the store does, without running the handler. The
+    // handler gets `msg` as received, its payload still in the envelope
+    // carrying the key.
+    pub fn handle<F>(&self, msg: &MessageView, handler: F) -> Result<bool>
+    where
+        F: FnOnce(&MessageView),
+    {
+        let Some(key) = key(msg.payload()) else {
+            handler(msg);
+            return Ok(true);
+        };
+        self.expire()?;
+        let Some(_claim) = Claim::new(&self.processing, &key) else {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        };
+        if self.store.contains(&key)? {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        handler(msg);
+        self.store.insert(&key, SystemTime::now())?;
+        Ok(true)
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    fn expire(&self) -> Result<()> {
+        let mut last_expired = self
+            .last_expired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last_expired.elapsed() < self.retention / 10 {
+            return Ok(());
+        }
+        *last_expired = Instant::now();
+        drop(last_expired);
+        match SystemTime::now().checked_sub(self.retention) {
+            Some(cutoff) => self.store.expire(cutoff),
+            None => Ok(()),
+        }
+    }
+}
+
+// A key marked as being processed until dropped.
+struct Claim<'a> {
+    processing: &'a Mutex<HashSet<String>>,
+    key: &'a str,
+}
+
+impl<'a> Claim<'a> {
+    fn new(processing: &'a Mutex<HashSet<String>>, key: &'a str) -> Option<Self> {
+        let mut keys = processing.lock().unwrap_or_else(PoisonError::into_inner);
+        // Not `then_some`, which would build, and drop, a claim for a key
+        // another thread holds.
+        match keys.insert(key.to_string()) {
+            true => Some(Self { processing, key }),
+            false => None,
+        }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.processing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+
+    #[test]
+    fn test_duplicates_are_skipped_across_restarts() {
+        let path = std::env::temp_dir().join(format!("polar-mqtt-keys-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let order = Message::new("orders/1", "order")
+            .unwrap()
+            .with_idempotency_key("k1")
+            .unwrap();
+        assert_eq!(order.idempotency_key().as_deref(), Some("k1"));
+        let plain = Message::new("orders/2", "order").unwrap();
+
+        let processed = Mutex::new(0);
+        let count = |_: &MessageView| *processed.lock().unwrap() += 1;
+        let once = ExactlyOnce::new(FileKeyStore::open(&path).unwrap());
+        assert!(once.handle(&order.view(), count).unwrap());
+        assert!(!once.handle(&order.view(), count).unwrap());
+        assert!(once.handle(&plain.view(), count).unwrap());
+        assert!(once.handle(&plain.view(), count).unwrap());
+        assert_eq!(once.duplicates(), 1);
+        drop(once);
+
+        // The key survives a restart, until it expires.
+        let store = FileKeyStore::open(&path).unwrap();
+        assert!(store.contains("k1").unwrap());
+        let once = ExactlyOnce::new(store).with_retention(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(once.handle(&order.view(), count).unwrap());
+        assert_eq!(*processed.lock().unwrap(), 4);
+
+        let store = MemoryKeyStore::new();
+        store.insert("old", UNIX_EPOCH).unwrap();
+        store.insert("new", SystemTime::now()).unwrap();
+        store.expire(UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        assert!(!store.contains("old").unwrap());
+        assert!(store.contains("new").unwrap());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_duplicate_is_skipped_while_the_first_is_processing() {
+        let order = Message::new("orders/1", "order")
+            .unwrap()
+            .with_idempotency_key("k1")
+            .unwrap();
+        let once = ExactlyOnce::new(MemoryKeyStore::new());
+        let (started, wait_started) = std::sync::mpsc::channel();
+        let (release, wait_release) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            let (once, order) = (&once, &order);
+            let first = scope.spawn(move || {
+                once.handle(&order.view(), |_| {
+                    started.send(()).unwrap();
+                    wait_release.recv().unwrap();
+                })
+            });
+            wait_started.recv().unwrap();
+            // Not yet in the store, but claimed by the first thread.
+            assert!(!once.handle(&order.view(), |_| panic!("ran twice")).unwrap());
+            release.send(()).unwrap();
+            assert!(first.join().unwrap().unwrap());
+        });
+        assert_eq!(once.duplicates(), 1);
+        assert!(!once.handle(&order.view(), |_| panic!("ran twice")).unwrap());
+    }
+}
@@ -0,0 +1,230 @@
+use crate::message::Message;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+// What the receive path does when the inbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    // Wait for room. This stalls the network thread, so the broker sees
+    // the backpressure, and PINGs wait too: keep callbacks well under the
+    // keepalive interval.
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+// Bounded queues between the network thread and the callbacks, which then
+// run on a pool of dispatch threads. Each topic goes to one worker, so
+// messages on a topic are still delivered in order. Set with
+// `Client::set_inbound_queue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundQueue {
+    capacity: usize,
+    drop_policy: DropPolicy,
+    workers: usize,
+}
+
+impl InboundQueue {
+    // One worker holding up to `capacity` messages, dropping the oldest when
+    // full.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            drop_policy: DropPolicy::DropOldest,
+            workers: 1,
+        }
+    }
+
+    pub fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    // Dispatch on `workers` threads, each with a queue of `capacity`.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Message>,
+    stopped: bool,
+}
+
+struct Shard {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+impl Shard {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct Inbound {
+    config: InboundQueue,
+    shards: Vec<Shard>,
+}
+
+impl Inbound {
+    pub(crate) fn new(config: InboundQueue) -> Self {
+        Self {
+            config,
+            shards: (0..config.workers)
+                .map(|_| Shard {
+                    state: Mutex::default(),
+                    changed: Condvar::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard(&self, topic: &str) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        topic.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    // Queues `message` for its topic's worker, returns whether a message was
+    // dropped for it.
+    pub(crate) fn push(&self, message: Message) -> bool {
+        let shard = self.shard(message.topic());
+        let mut state = shard.lock();
+        if state.messages.len() >= self.config.capacity {
+            match self.config.drop_policy {
+                DropPolicy::Block => {
+                    state = shard
+                        .changed
+                        .wait_while(state, |state| {
+                            state.messages.len() >= self.config.capacity && !state.stopped
+                        })
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                DropPolicy::DropOldest => {
+                    state.messages.pop_front();
+                    state.messages.push_back(message);
+                    return true;
+                }
+                DropPolicy::DropNewest => return true,
+            }
+        }
+        if state.stopped {
+            return true;
+        }
+        state.messages.push_back(message);
+        shard.changed.notify_all();
+        false
+    }
+
+    // Blocks until a message is queued for `worker`, returns None once
+    // stopped.
+    pub(crate) fn pop(&self, worker: usize) -> Option<Message> {
+        let shard = &self.shards[worker];
+        let mut state = shard
+            .changed
+            .wait_while(shard.lock(), |state| {
+                state.messages.is_empty() && !state.stopped
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.stopped {
+            return None;
+        }
+        let message = state.messages.pop_front();
+        shard.changed.notify_all();
+        message
+    }
+
+    // Wakes the workers and any blocked push. Returns how many queued
+    // messages were never dispatched.
+    pub(crate) fn stop(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut state = shard.lock();
+                state.stopped = true;
+                let dropped = state.messages.len();
+                state.messages.clear();
+                shard.changed.notify_all();
+                dropped
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payloads(inbound: &Inbound) -> Vec<Vec<u8>> {
+        inbound.shards[0]
+            .lock()
+            .messages
+            .iter()
+            .map(|m| m.payload().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let message = |n: u8| Message::new("test/in", vec![n]).unwrap();
+
+        let oldest = Inbound::new(InboundQueue::new(2));
+        assert!(!oldest.push(message(1)));
+        assert!(!oldest.push(message(2)));
+        assert!(oldest.push(message(3)));
+        assert_eq!(payloads(&oldest), [[2], [3]]);
+
+        let newest = Inbound::new(InboundQueue::new(2).with_drop_policy(DropPolicy::DropNewest));
+        for n in 1..=3 {
+            newest.push(message(n));
+        }
+        assert_eq!(payloads(&newest), [[1], [2]]);
+        assert_eq!(newest.pop(0).map(|m| m.payload().to_vec()), Some(vec![1]));
+
+        let blocking = Inbound::new(InboundQueue::new(1).with_drop_policy(DropPolicy::Block));
+        assert!(!blocking.push(message(1)));
+        std::thread::scope(|s| {
+            let pusher = s.spawn(|| blocking.push(message(2)));
+            assert_eq!(blocking.pop(0).map(|m| m.payload().to_vec()), Some(vec![1]));
+            assert!(!pusher.join().unwrap());
+        });
+        assert_eq!(blocking.stop(), 1);
+        assert!(blocking.pop(0).is_none());
+        assert!(blocking.push(message(3)));
+
+        // Each topic sticks to one worker.
+        let pool = Inbound::new(InboundQueue::new(8).with_workers(4));
+        for n in 0..4 {
+            pool.push(Message::new("test/a", vec![n]).unwrap());
+            pool.push(Message::new("test/b", vec![n]).unwrap());
+        }
+        let counts: Vec<usize> = pool
+            .shards
+            .iter()
+            .map(|shard| shard.lock().messages.len())
+            .filter(|&len| len > 0)
+            .collect();
+        assert!(counts == [8] || counts == [4, 4], "{:?}", counts);
+    }
+}
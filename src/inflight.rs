@@ -0,0 +1,160 @@
+use crate::cancel::CancellationToken;
+use crate::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// Publishes (QoS 1 and 2) that are waiting for the broker's acknowledgement,
+// keyed by the message id returned from the bridge.
+#[derive(Default)]
+pub(crate) struct Inflight {
+    state: Mutex<InflightState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct InflightState {
+    pending: HashMap<i64, Instant>,
+    // Acks delivered on the network thread before publish() returned the id.
+    early: HashSet<i64>,
+}
+
+impl Inflight {
+    fn lock(&self) -> MutexGuard<'_, InflightState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Returns the ack latency if the ack already arrived.
+    pub(crate) fn register(&self, message_id: i64, started: Instant) -> Option<Duration> {
+        let mut state = self.lock();
+        if state.early.remove(&message_id) {
+            Some(started.elapsed())
+        } else {
+            state.pending.insert(message_id, started);
+            None
+        }
+    }
+
+    // Returns the ack latency if the publish was registered.
+    pub(crate) fn complete(&self, message_id: i64) -> Option<Duration> {
+        let mut state = self.lock();
+        let latency = match state.pending.remove(&message_id) {
+            Some(started) => Some(started.elapsed()),
+            None => {
+                state.early.insert(message_id);
+                None
+            }
+        };
+        self.changed.notify_all();
+        latency
+    }
+
+    // Forgets everything in flight, returning how many publishes were lost.
+    pub(crate) fn clear(&self) -> usize {
+        let mut state = self.lock();
+        let lost = state.pending.len();
+        state.pending.clear();
+        state.early.clear();
+        self.changed.notify_all();
+        lost
+    }
+
+    // Blocks until every pending publish is acked, the timeout elapses or
+    // `cancel` is cancelled. Returns (acked, still pending).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn drain(
+        self: &Arc<Self>,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> (usize, usize) {
+        let _registration = cancel.on_cancel({
+            let inflight = self.clone();
+            move || {
+                let _state = inflight.lock();
+                inflight.changed.notify_all();
+            }
+        });
+        let mut state = self.lock();
+        let initial = state.pending.len();
+        let deadline = Instant::now() + timeout;
+        while !state.pending.is_empty() && !cancel.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        let remaining = state.pending.len();
+        (initial.saturating_sub(remaining), remaining)
+    }
+
+    // The browser cannot block, and acks arrive on the thread that would.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn drain(
+        self: &Arc<Self>,
+        _timeout: Duration,
+        _cancel: &CancellationToken,
+    ) -> (usize, usize) {
+        (0, self.lock().pending.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_register_then_complete() {
+        let inflight = Inflight::default();
+        assert!(inflight.register(1, Instant::now()).is_none());
+        assert!(inflight.complete(1).is_some());
+        assert_eq!(inflight.clear(), 0);
+    }
+
+    #[test]
+    fn test_ack_before_register() {
+        let inflight = Inflight::default();
+        assert!(inflight.complete(7).is_none());
+        assert!(inflight.register(7, Instant::now()).is_some());
+        assert_eq!(inflight.clear(), 0);
+    }
+
+    #[test]
+    fn test_drain_counts_flushed_and_dropped() {
+        let inflight = Arc::new(Inflight::default());
+        inflight.register(1, Instant::now());
+        inflight.register(2, Instant::now());
+
+        let acker = {
+            let inflight = inflight.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                inflight.complete(1);
+            })
+        };
+
+        let cancel = CancellationToken::new();
+        let (flushed, dropped) = inflight.drain(Duration::from_millis(200), &cancel);
+        acker.join().unwrap();
+        assert_eq!((flushed, dropped), (1, 1));
+
+        // Cancelling cuts the wait short.
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                cancel.cancel();
+            })
+        };
+        let started = Instant::now();
+        assert_eq!(inflight.drain(Duration::from_secs(10), &cancel), (0, 1));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+        assert_eq!(inflight.clear(), 1);
+    }
+}
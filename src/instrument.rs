@@ -0,0 +1,95 @@
+use crate::types::{ConnectionState, QoS};
+use std::sync::{Mutex, PoisonError};
+use tracing::{debug_span, info, info_span, warn, Span};
+
+// Per-client tracing fields. Spans for user-initiated operations are created
+// as children of the caller's current span so MQTT activity nests inside the
+// application's own instrumentation; callback spans have no parent since
+// they run on the bridge's network thread.
+pub(crate) struct Instrumentation {
+    client_id: String,
+    broker: Mutex<String>,
+}
+
+impl Instrumentation {
+    pub(crate) fn new(client_id: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            broker: Mutex::new(String::new()),
+        }
+    }
+
+    fn broker(&self) -> String {
+        self.broker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn connect_span(&self, host: &str, port: u16) -> Span {
+        *self.broker.lock().unwrap_or_else(PoisonError::into_inner) = format!("{}:{}", host, port);
+        info_span!("mqtt.connect", client_id = %self.client_id, broker = %self.broker())
+    }
+
+    pub(crate) fn subscribe_span(&self, topic: &str, qos: QoS) -> Span {
+        info_span!(
+            "mqtt.subscribe",
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            topic,
+            qos = ?qos
+        )
+    }
+
+    pub(crate) fn unsubscribe_span(&self, handle: i64) -> Span {
+        info_span!(
+            "mqtt.unsubscribe",
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            handle
+        )
+    }
+
+    pub(crate) fn publish_span(&self, topic: &str, qos: QoS, bytes: usize) -> Span {
+        debug_span!(
+            "mqtt.publish",
+            otel.kind = "producer",
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            topic,
+            qos = ?qos,
+            bytes
+        )
+    }
+
+    pub(crate) fn dispatch_span(&self, topic: &str, bytes: usize) -> Span {
+        debug_span!(
+            parent: None,
+            "mqtt.dispatch",
+            otel.kind = "consumer",
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            topic,
+            bytes
+        )
+    }
+
+    pub(crate) fn state_changed(&self, state: ConnectionState) {
+        info!(
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            state = ?state,
+            "mqtt state changed"
+        );
+    }
+
+    pub(crate) fn error(&self, code: i32, message: &str) {
+        warn!(
+            client_id = %self.client_id,
+            broker = %self.broker(),
+            code,
+            message,
+            "mqtt error"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::time::Instant;
+use crate::types::{QoS, TopicFilter};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+
+// The most recent message on each topic matching the cache's filters, for
+// dashboards and rule engines that need current values rather than a
+// stream. Unlike `Client::set_last_values`, which keeps every topic, it is
+// bounded: beyond `max_entries` the topic updated longest ago is evicted,
+// and entries older than `max_age` are neither returned nor counted. An
+// empty retained message removes its topic.
+pub struct LastValueCache {
+    filters: Vec<TopicFilter>,
+    qos: QoS,
+    entries: Arc<Mutex<Entries>>,
+    client: Weak<Client>,
+    handles: Vec<i64>,
+}
+
+struct Entry {
+    message: Message,
+    received: Instant,
+    seq: u64,
+}
+
+struct Entries {
+    max_entries: usize,
+    max_age: Option<Duration>,
+    by_topic: HashMap<String, Entry>,
+    // Topics by the sequence number of their last update, oldest first.
+    by_age: BTreeMap<u64, String>,
+    next_seq: u64,
+}
+
+impl Entries {
+    fn record(&mut self, msg: &MessageView, now: Instant) {
+        if let Some(entry) = self.by_topic.remove(msg.topic()) {
+            self.by_age.remove(&entry.seq);
+        }
+        if msg.is_retained() && msg.payload().is_empty() {
+            return;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.by_age.insert(seq, msg.topic().to_string());
+        self.by_topic.insert(
+            msg.topic().to_string(),
+            Entry {
+                message: msg.to_owned(),
+                received: now,
+                seq,
+            },
+        );
+        while self.by_topic.len() > self.max_entries {
+            let Some((_, topic)) = self.by_age.pop_first() else {
+                break;
+            };
+            self.by_topic.remove(&topic);
+        }
+    }
+
+    fn is_fresh(&self, entry: &Entry, now: Instant) -> bool {
+        self.max_age
+            .is_none_or(|max_age| now.duration_since(entry.received) <= max_age)
+    }
+
+    fn get(&self, topic: &str, now: Instant) -> Option<&Message> {
+        self.by_topic
+            .get(topic)
+            .filter(|entry| self.is_fresh(entry, now))
+            .map(|entry| &entry.message)
+    }
+
+    // Oldest update first.
+    fn fresh(&self, now: Instant) -> impl Iterator<Item = &Message> {
+        self.by_age
+            .values()
+            .filter_map(|topic| self.by_topic.get(topic))
+            .filter(move |entry| self.is_fresh(entry, now))
+            .map(|entry| &entry.message)
+    }
+}
+
+impl Default for LastValueCache {
+    fn default() -> Self {
+        Self {
+            filters: Vec::new(),
+            qos: QoS::AtLeastOnce,
+            entries: Arc::new(Mutex::new(Entries {
+                max_entries: 10_000,
+                max_age: None,
+                by_topic: HashMap::new(),
+                by_age: BTreeMap::new(),
+                next_seq: 0,
+            })),
+            client: Weak::new(),
+            handles: Vec::new(),
+        }
+    }
+}
+
+impl LastValueCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_filter<T>(mut self, filter: T) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.filters.push(filter.try_into()?);
+        Ok(self)
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        self.lock().max_entries = max_entries.max(1);
+        self
+    }
+
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        self.lock().max_age = Some(max_age);
+        self
+    }
+
+    // Subscribes to the filters and starts caching; the subscriptions are
+    // removed when the cache is dropped.
+    pub fn attach(mut self, client: &Arc<Client>) -> Result<Self> {
+        for filter in &self.filters {
+            let entries = self.entries.clone();
+            let handle = client.subscribe_with(filter, self.qos, move |msg| {
+                entries
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .record(msg, Instant::now());
+            });
+            match handle {
+                Ok(handle) => self.handles.push(handle),
+                Err(e) => {
+                    for handle in self.handles.drain(..) {
+                        let _ = client.unsubscribe(handle);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.client = Arc::downgrade(client);
+        Ok(self)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, topic: &str) -> Option<Message> {
+        self.lock().get(topic, Instant::now()).cloned()
+    }
+
+    // The cached messages, least recently updated first.
+    pub fn iter(&self) -> impl Iterator<Item = Message> {
+        let entries = self.lock();
+        let messages: Vec<Message> = entries.fresh(Instant::now()).cloned().collect();
+        messages.into_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().fresh(Instant::now()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for LastValueCache {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            for &handle in &self.handles {
+                let _ = client.unsubscribe(handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_by_entries_and_age() {
+        let cache = LastValueCache::new()
+            .with_max_entries(2)
+            .with_max_age(Duration::from_secs(60));
+        let start = Instant::now();
+        let record = |topic: &str, payload: &str, retained: bool, at: u64| {
+            let msg = MessageView {
+                topic,
+                payload: payload.as_bytes(),
+                qos: QoS::AtMostOnce,
+                retained,
+                shared: Default::default(),
+            };
+            cache.lock().record(&msg, start + Duration::from_secs(at));
+        };
+        let topics = |at: u64| -> Vec<String> {
+            let entries = cache.lock();
+            entries
+                .fresh(start + Duration::from_secs(at))
+                .map(|m| m.topic().to_string())
+                .collect()
+        };
+
+        record("room/1", "20", false, 0);
+        record("room/2", "21", false, 10);
+        record("room/1", "22", false, 20);
+        assert_eq!(topics(20), ["room/2", "room/1"]);
+
+        // Over two entries, the topic updated longest ago goes.
+        record("room/3", "23", false, 30);
+        assert_eq!(topics(30), ["room/1", "room/3"]);
+        let entries = cache.lock();
+        let latest = entries.get("room/1", start + Duration::from_secs(30));
+        assert_eq!(latest.map(|m| m.payload()), Some(&b"22"[..]));
+        // Stale after a minute.
+        assert!(entries
+            .get("room/1", start + Duration::from_secs(81))
+            .is_none());
+        drop(entries);
+        assert_eq!(topics(85), ["room/3"]);
+
+        record("room/3", "", true, 40);
+        assert_eq!(topics(40), ["room/1"]);
+    }
+}
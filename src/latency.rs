@@ -0,0 +1,254 @@
+use crate::client::Client;
+use crate::error::Result;
+use crate::message::Message;
+use crate::runtime;
+use crate::stats::LatencyStats;
+use crate::stop::Stop;
+use crate::time::Instant;
+use crate::types::{ConnectionState, QoS};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const PROBE_PREFIX: &str = "polar_mqtt/latency";
+
+// Measures the round trip through the broker: while connected, a probe
+// carrying a sequence number is published every `interval` to a topic only
+// this client subscribes to, and the time until it comes back is recorded.
+// Percentiles over the last `window` round trips are read with
+// `Client::latency_stats` or `LatencyProbeHandle::stats`. Probes are
+// consumed before the inbound pipeline and never reach callbacks.
+#[derive(Debug, Clone)]
+pub struct LatencyProbe {
+    interval: Duration,
+    window: usize,
+    qos: QoS,
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            window: 100,
+            qos: QoS::AtMostOnce,
+        }
+    }
+}
+
+impl LatencyProbe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_window(mut self, samples: usize) -> Self {
+        self.window = samples.max(1);
+        self
+    }
+
+    // QoS 1 and 2 probes include the broker's acknowledgement work.
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    // Starts probing `client` until the handle is dropped or the client is.
+    pub fn start(self, client: &Arc<Client>) -> Result<LatencyProbeHandle> {
+        runtime::needs_threads("the latency probe")?;
+        let topic = format!("{}/{}", PROBE_PREFIX, client.client_id());
+        let latency = Arc::new(Latency::new(topic.clone(), self.window));
+        client.set_latency(Some(latency.clone()));
+        let handle = match client.subscribe(topic.as_str(), self.qos) {
+            Ok(handle) => handle,
+            Err(e) => {
+                client.set_latency(None);
+                return Err(e);
+            }
+        };
+
+        let stop = Arc::new(Stop::default());
+        let thread = {
+            let (client, stop, latency) = (Arc::downgrade(client), stop.clone(), latency.clone());
+            thread::spawn(move || {
+                while !stop.wait(self.interval) {
+                    let Some(client) = client.upgrade() else {
+                        break;
+                    };
+                    if client.state() != ConnectionState::Connected {
+                        continue;
+                    }
+                    let sequence = latency.next_sequence.fetch_add(1, Ordering::Relaxed);
+                    let Ok(probe) = Message::new(topic.as_str(), sequence.to_be_bytes().to_vec())
+                    else {
+                        break;
+                    };
+                    // Recorded first, as the probe may be back before
+                    // `publish` returns.
+                    latency.sent(sequence, Instant::now());
+                    if client.publish(&probe.with_qos(self.qos)).is_err() {
+                        latency.unsent(sequence);
+                    }
+                }
+            })
+        };
+
+        Ok(LatencyProbeHandle {
+            client: Arc::downgrade(client),
+            latency,
+            handle,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+// Stops the probe when dropped.
+pub struct LatencyProbeHandle {
+    client: Weak<Client>,
+    latency: Arc<Latency>,
+    handle: i64,
+    stop: Arc<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LatencyProbeHandle {
+    pub fn stats(&self) -> LatencyStats {
+        self.latency.stats()
+    }
+}
+
+impl Drop for LatencyProbeHandle {
+    fn drop(&mut self) {
+        self.stop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(client) = self.client.upgrade() {
+            client.set_latency(None);
+            let _ = client.unsubscribe(self.handle);
+        }
+    }
+}
+
+// The probe's state, shared with the client's message callback.
+pub(crate) struct Latency {
+    topic: String,
+    window: usize,
+    next_sequence: AtomicU64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Probes sent and not yet back, oldest first; at most `window`.
+    pending: VecDeque<(u64, Instant)>,
+    samples: VecDeque<Duration>,
+    sent: u64,
+    received: u64,
+}
+
+impl Latency {
+    fn new(topic: String, window: usize) -> Self {
+        Self {
+            topic,
+            window,
+            next_sequence: AtomicU64::new(0),
+            state: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sent(&self, sequence: u64, at: Instant) {
+        let mut state = self.state();
+        state.sent += 1;
+        if state.pending.len() >= self.window {
+            state.pending.pop_front();
+        }
+        state.pending.push_back((sequence, at));
+    }
+
+    fn unsent(&self, sequence: u64) {
+        let mut state = self.state();
+        state.pending.retain(|&(s, _)| s != sequence);
+        state.sent -= 1;
+    }
+
+    pub(crate) fn received(&self, payload: &[u8]) {
+        self.received_at(payload, Instant::now());
+    }
+
+    fn received_at(&self, payload: &[u8], now: Instant) {
+        let Ok(sequence) = <[u8; 8]>::try_from(payload).map(u64::from_be_bytes) else {
+            return;
+        };
+        let mut state = self.state();
+        let Some(index) = state.pending.iter().position(|&(s, _)| s == sequence) else {
+            return;
+        };
+        let Some((_, sent_at)) = state.pending.remove(index) else {
+            return;
+        };
+        state.received += 1;
+        if state.samples.len() >= self.window {
+            state.samples.pop_front();
+        }
+        state
+            .samples
+            .push_back(now.saturating_duration_since(sent_at));
+    }
+
+    pub(crate) fn stats(&self) -> LatencyStats {
+        let state = self.state();
+        let mut samples: Vec<Duration> = state.samples.iter().copied().collect();
+        samples.sort_unstable();
+        LatencyStats::from_sorted(&samples, state.sent, state.received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_and_percentiles() {
+        let latency = Latency::new("probe".to_string(), 3);
+        let start = Instant::now();
+        for (sequence, millis) in [(0u64, 40u64), (1, 10), (2, 20), (3, 30)] {
+            latency.sent(sequence, start);
+            latency.received_at(
+                &sequence.to_be_bytes(),
+                start + Duration::from_millis(millis),
+            );
+        }
+        // Unknown and malformed probes are ignored, and so is a repeat.
+        latency.received_at(&9u64.to_be_bytes(), start);
+        latency.received_at(b"x", start);
+        latency.received_at(&3u64.to_be_bytes(), start);
+        latency.sent(4, start);
+        latency.sent(5, start);
+        latency.unsent(5);
+
+        // Only the last three round trips are kept.
+        let stats = latency.stats();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.sent, 5);
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.min, Duration::from_millis(10));
+        assert_eq!(stats.p50, Duration::from_millis(20));
+        assert_eq!(stats.p99, Duration::from_millis(30));
+        assert_eq!(stats.max, Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::time::Instant;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// Expiry deadlines of leased subscriptions, keyed by subscription handle.
+// The client's reaper thread waits on it and unsubscribes what expires.
+#[derive(Default)]
+pub(crate) struct Leases {
+    state: Mutex<LeaseState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct LeaseState {
+    deadlines: HashMap<i64, Instant>,
+    stopped: bool,
+}
+
+impl Leases {
+    fn lock(&self) -> MutexGuard<'_, LeaseState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn insert(&self, handle: i64, deadline: Instant) {
+        let mut state = self.lock();
+        if !state.stopped {
+            state.deadlines.insert(handle, deadline);
+            self.changed.notify_all();
+        }
+    }
+
+    // Returns false if the lease already expired or was removed.
+    pub(crate) fn renew(&self, handle: i64, deadline: Instant) -> bool {
+        match self.lock().deadlines.get_mut(&handle) {
+            Some(current) => {
+                *current = deadline;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn remove(&self, handle: i64) -> bool {
+        self.lock().deadlines.remove(&handle).is_some()
+    }
+
+    fn deadline(&self, handle: i64) -> Option<Instant> {
+        self.lock().deadlines.get(&handle).copied()
+    }
+
+    // Wakes the reaper and makes it exit. Outstanding leases are forgotten.
+    pub(crate) fn stop(&self) {
+        let mut state = self.lock();
+        state.stopped = true;
+        state.deadlines.clear();
+        self.changed.notify_all();
+    }
+
+    // Blocks until at least one lease expires and returns the expired
+    // handles, or None once stopped.
+    pub(crate) fn wait_expired(&self) -> Option<Vec<i64>> {
+        let mut state = self.lock();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            let now = Instant::now();
+            let expired: Vec<i64> = state
+                .deadlines
+                .iter()
+                .filter(|(_, &deadline)| deadline <= now)
+                .map(|(&handle, _)| handle)
+                .collect();
+            if !expired.is_empty() {
+                for handle in &expired {
+                    state.deadlines.remove(handle);
+                }
+                return Some(expired);
+            }
+            state = match state.deadlines.values().min() {
+                Some(&next) => {
+                    self.changed
+                        .wait_timeout(state, next - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+// A subscription that is removed once its TTL passes without a call to
+// `keep_alive`. Dropping the lease does not unsubscribe; it only stops the
+// renewals, so the subscription lapses at its current deadline.
+pub struct Lease {
+    handle: i64,
+    ttl: Duration,
+    leases: Arc<Leases>,
+}
+
+impl Lease {
+    pub(crate) fn new(handle: i64, ttl: Duration, leases: Arc<Leases>) -> Self {
+        leases.insert(handle, Instant::now() + ttl);
+        Self {
+            handle,
+            ttl,
+            leases,
+        }
+    }
+
+    pub fn handle(&self) -> i64 {
+        self.handle
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // Pushes the deadline back to a full TTL from now. Returns false if the
+    // subscription already expired or was unsubscribed.
+    pub fn keep_alive(&self) -> bool {
+        self.leases.renew(self.handle, Instant::now() + self.ttl)
+    }
+
+    // Time left before the subscription is removed, None once it is gone.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.leases
+            .deadline(self.handle)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.leases.deadline(self.handle).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_expiry_and_renewal() {
+        let leases = Arc::new(Leases::default());
+        let short = Lease::new(1, Duration::from_millis(20), leases.clone());
+        let long = Lease::new(2, Duration::from_millis(60), leases.clone());
+
+        let started = Instant::now();
+        assert_eq!(leases.wait_expired(), Some(vec![1]));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert!(short.is_expired());
+        assert!(!short.keep_alive());
+
+        thread::sleep(Duration::from_millis(30));
+        assert!(long.keep_alive());
+        assert!(long.remaining().unwrap() > Duration::from_millis(40));
+
+        let reaper = {
+            let leases = leases.clone();
+            thread::spawn(move || leases.wait_expired())
+        };
+        thread::sleep(Duration::from_millis(10));
+        leases.stop();
+        assert_eq!(reaper.join().unwrap(), None);
+        assert!(long.is_expired());
+    }
+}
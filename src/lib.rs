@@ -1,10 +1,183 @@
+// Lets `::polar_mqtt` paths in macro output resolve inside this crate too.
+extern crate self as polar_mqtt;
+
+#[cfg(all(feature = "backend-rust", feature = "raw"))]
+compile_error!("the `raw` feature exposes the C++ bridge, which `backend-rust` leaves out");
+#[cfg(all(target_arch = "wasm32", feature = "raw"))]
+compile_error!("the `raw` feature exposes the C++ bridge, which wasm32 builds leave out");
+#[cfg(all(target_arch = "wasm32", feature = "backend-rust"))]
+compile_error!("rumqttc needs sockets; wasm32 builds always use the WebSocket backend");
+
+mod acl;
+mod audit;
+pub mod aws;
+#[cfg(feature = "azure")]
+pub mod azure;
+mod backend;
 mod bindings;
+mod bridge;
+mod broker_limits;
+mod broker_stats;
+mod cancel;
+#[cfg(feature = "chaos")]
+mod chaos;
 mod client;
+mod client_id;
+#[cfg(feature = "cloudevents")]
+pub mod cloudevents;
+mod codec;
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+mod compression;
+#[cfg(feature = "polars")]
+mod dataframe;
+mod dead_letter;
+mod dedup;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+mod envelope;
 mod error;
+mod failover;
+#[cfg(feature = "json")]
+mod fleet;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod handle;
+#[cfg(any(feature = "encryption", feature = "management"))]
+mod hex;
+mod hierarchy;
+mod idempotency;
+mod inbound;
+mod inflight;
+#[cfg(feature = "tracing")]
+mod instrument;
+mod last_value;
+mod latency;
+mod lease;
+#[cfg(all(
+    any(feature = "log", feature = "tracing", target_os = "android"),
+    native_bridge
+))]
+mod logging;
+#[cfg(feature = "management")]
+pub mod management;
 mod message;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod middleware;
+mod monitor;
+mod observer;
+mod options;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod oversize;
+mod packet_log;
+mod payload;
+mod pool;
+mod profile;
+pub mod provenance;
+mod ratelimit;
+pub mod record;
+#[cfg(not(target_arch = "wasm32"))]
+mod resolve;
+// Watches from a thread of its own, which the browser cannot run.
+#[cfg(not(target_arch = "wasm32"))]
+mod resume;
+mod router;
+#[cfg(feature = "msgpack-rpc")]
+pub mod rpc;
+mod runtime;
+mod sampling;
+mod scope;
+#[cfg(feature = "tower")]
+mod service;
+mod shard;
+#[cfg(feature = "parquet")]
+pub mod sink;
+#[cfg(feature = "sparkplug")]
+pub mod sparkplug;
+pub mod stats;
+mod stop;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(feature = "raw")]
+pub mod sys;
+mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod time;
+pub mod topic;
 mod types;
+mod uri;
+mod watchdog;
 
-pub use client::Client;
+pub use acl::{Access, TopicAcl};
+pub use audit::AuditLog;
+pub use bridge::{Bridge, Direction, LoopProtection, Route};
+pub use broker_limits::{BrokerLimits, PROTOCOL_MAX_PACKET_SIZE};
+pub use broker_stats::{BrokerStats, BrokerStatsCallback, BrokerStatsMonitor};
+pub use cancel::CancellationToken;
+#[cfg(feature = "chaos")]
+pub use chaos::{FaultInjector, FaultInjectorHandle, FaultStats};
+pub use client::{Client, PANIC_ERROR_CODE};
+pub use client_id::{ClientId, ClientIdSuffix};
+pub use codec::Codec;
+#[cfg(feature = "json")]
+pub use codec::JsonCodec;
+#[cfg(feature = "prost")]
+pub use codec::ProstCodec;
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+pub use compression::{Algorithm, Compression};
+#[cfg(feature = "polars")]
+pub use dataframe::DataFrameCollector;
+pub use dead_letter::{DeadLetter, DeadLetterReason, DeadLetters};
+pub use dedup::{DedupKey, InboundDedup, PublishDedup};
+#[cfg(feature = "encryption")]
+pub use encryption::{Encryption, KeyProvider, StaticKeys};
+pub use envelope::{Envelope, Enveloped};
 pub use error::{Error, Result};
-pub use message::Message;
-pub use types::{ConnectionState, QoS};
+#[cfg(feature = "json")]
+pub use fleet::{FleetConfig, FleetConfigHandle};
+pub use handle::{ClientHandle, WeakClientHandle};
+pub use hierarchy::{HierarchyNode, TopicHierarchy};
+pub use idempotency::{ExactlyOnce, FileKeyStore, KeyStore, MemoryKeyStore, IDEMPOTENCY_KEY};
+pub use inbound::{DropPolicy, InboundQueue};
+pub use last_value::LastValueCache;
+pub use latency::{LatencyProbe, LatencyProbeHandle};
+pub use lease::Lease;
+pub use message::{Message, MessageView, SharedMessage};
+pub use middleware::{Action, Middleware};
+pub use monitor::{MonitorClient, MonitorOptions, MonitorStats, TopicSummary};
+pub use observer::{DropReason, Observer};
+pub use options::{AddressFamily, Backoff, ConnectOptions, TcpKeepalive, TlsOptions};
+pub use oversize::{OversizeHandler, OversizePolicy, OversizedMessage, PayloadLimit};
+pub use packet_log::{Packet, PacketDirection, PacketLog, PacketLogHandle};
+pub use payload::FromPayload;
+#[cfg(feature = "json")]
+pub use payload::Json;
+#[cfg(feature = "prost")]
+pub use payload::Proto;
+#[cfg(feature = "macros")]
+pub use polar_mqtt_macros::mqtt_handler;
+pub use pool::{ClientPool, Distribution, PoolStats};
+pub use profile::Profile;
+pub use provenance::Provenance;
+pub use ratelimit::{OverLimit, RateLimit};
+pub use record::{RecordReader, RecordedMessage, Recorder, RecorderHandle, Replayer};
+#[cfg(not(target_arch = "wasm32"))]
+pub use resume::{ResumeEvent, ResumeHandle, ResumeMonitor};
+pub use router::{Handler, Router};
+pub use runtime::{init, is_initialized, InitOptions};
+pub use sampling::{Sampler, Sampling};
+pub use scope::Scope;
+#[cfg(feature = "tower")]
+pub use service::{message_handler, PublishService};
+pub use shard::{HashRing, ShardGroup, ShardMember};
+#[cfg(feature = "parquet")]
+pub use sink::ParquetSink;
+pub use stats::{LatencyStats, Ranking, TopicRate, TopicStats};
+#[cfg(feature = "futures")]
+pub use stream::{MessageStream, Publisher};
+pub use template::{Params, TopicTemplate};
+pub use types::{ConnectionState, QoS, ShutdownReport, Topic, TopicFilter};
+pub use uri::{BrokerUri, Transport};
+pub use watchdog::{Watchdog, WatchdogEvent, WatchdogHandle};
@@ -0,0 +1,76 @@
+use crate::bindings;
+use std::ffi::{c_char, c_void, CStr};
+
+// Forwards lines logged by the native layer (including Paho's own trace when
+// debug is enabled) to `tracing` if that feature is on, otherwise to `log`,
+// or without either on Android to logcat, as Android discards stderr.
+pub(crate) fn install() {
+    unsafe {
+        bindings::mqtt_set_log_callback(Some(log_callback), std::ptr::null_mut());
+    }
+}
+
+pub(crate) fn uninstall() {
+    unsafe {
+        bindings::mqtt_set_log_callback(None, std::ptr::null_mut());
+    }
+}
+
+unsafe extern "C" fn log_callback(
+    level: bindings::mqtt_log_level_t,
+    message: *const c_char,
+    _context: *mut c_void,
+) {
+    if message.is_null() {
+        return;
+    }
+
+    let message = CStr::from_ptr(message).to_string_lossy();
+    emit(level, &message);
+}
+
+#[cfg(feature = "tracing")]
+fn emit(level: bindings::mqtt_log_level_t, message: &str) {
+    const TARGET: &str = "polar_mqtt::native";
+    match level {
+        bindings::mqtt_log_level_t_MQTT_LOG_ERROR => tracing::error!(target: TARGET, "{}", message),
+        bindings::mqtt_log_level_t_MQTT_LOG_WARN => tracing::warn!(target: TARGET, "{}", message),
+        bindings::mqtt_log_level_t_MQTT_LOG_INFO => tracing::info!(target: TARGET, "{}", message),
+        bindings::mqtt_log_level_t_MQTT_LOG_DEBUG => tracing::debug!(target: TARGET, "{}", message),
+        _ => tracing::trace!(target: TARGET, "{}", message),
+    }
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+fn emit(level: bindings::mqtt_log_level_t, message: &str) {
+    let level = match level {
+        bindings::mqtt_log_level_t_MQTT_LOG_ERROR => log::Level::Error,
+        bindings::mqtt_log_level_t_MQTT_LOG_WARN => log::Level::Warn,
+        bindings::mqtt_log_level_t_MQTT_LOG_INFO => log::Level::Info,
+        bindings::mqtt_log_level_t_MQTT_LOG_DEBUG => log::Level::Debug,
+        _ => log::Level::Trace,
+    };
+    log::log!(target: "polar_mqtt::native", level, "{}", message);
+}
+
+#[cfg(all(target_os = "android", not(any(feature = "log", feature = "tracing"))))]
+fn emit(level: bindings::mqtt_log_level_t, message: &str) {
+    // android/log.h
+    #[link(name = "log")]
+    extern "C" {
+        fn __android_log_write(priority: i32, tag: *const c_char, text: *const c_char) -> i32;
+    }
+    let priority = match level {
+        bindings::mqtt_log_level_t_MQTT_LOG_ERROR => 6,
+        bindings::mqtt_log_level_t_MQTT_LOG_WARN => 5,
+        bindings::mqtt_log_level_t_MQTT_LOG_INFO => 4,
+        bindings::mqtt_log_level_t_MQTT_LOG_DEBUG => 3,
+        _ => 2,
+    };
+    let Ok(text) = std::ffi::CString::new(message) else {
+        return;
+    };
+    unsafe {
+        __android_log_write(priority, b"polar_mqtt\0".as_ptr().cast(), text.as_ptr());
+    }
+}
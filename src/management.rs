@@ -0,0 +1,415 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::hex;
+use crate::message::Message;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::types::QoS;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const TOPIC_PREFIX: &str = "polar_mqtt/manage";
+
+// A command, as it appears on the second line of a command payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // Replies with the client's state and counters.
+    Stats,
+    // Sets the maximum level of the `log` facade: off, error, warn, info,
+    // debug or trace. Needs the `log` feature.
+    LogLevel(String),
+    // Subscribes every filter again, under the same handles.
+    Resubscribe,
+    // Reconnects to the current broker, keeping the subscriptions.
+    Reconnect,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Stats => f.write_str("stats"),
+            Command::LogLevel(level) => write!(f, "log-level {}", level),
+            Command::Resubscribe => f.write_str("resubscribe"),
+            Command::Reconnect => f.write_str("reconnect"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.split_once(' ') {
+            None if s == "stats" => Ok(Command::Stats),
+            None if s == "resubscribe" => Ok(Command::Resubscribe),
+            None if s == "reconnect" => Ok(Command::Reconnect),
+            Some(("log-level", level)) => Ok(Command::LogLevel(level.to_string())),
+            _ => Err(format!("unknown command {:?}", s)),
+        }
+    }
+}
+
+pub fn command_topic(prefix: &str, client_id: &str) -> String {
+    format!("{}/{}/command", prefix, client_id)
+}
+
+pub fn reply_topic(prefix: &str, client_id: &str) -> String {
+    format!("{}/{}/reply", prefix, client_id)
+}
+
+// The payload that makes the client `client_id` run `command`, signed with
+// `secret`:
+//
+//     <unix time in seconds>
+//     <command>
+//     <hex HMAC-SHA256 of "<client id>\n<time>\n<command>">
+//
+// Binding the client id into the signature keeps a command sent to one
+// device from being replayed to another.
+pub fn sign(secret: &[u8], client_id: &str, timestamp: u64, command: &Command) -> Vec<u8> {
+    let signature = mac(secret, client_id, timestamp, &command.to_string())
+        .finalize()
+        .into_bytes();
+    format!("{}\n{}\n{}", timestamp, command, hex::encode(&signature)).into_bytes()
+}
+
+fn mac(secret: &[u8], client_id: &str, timestamp: u64, command: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(format!("{}\n{}\n{}", client_id, timestamp, command).as_bytes());
+    mac
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+// Where `Management` keeps the timestamp of the last command it accepted,
+// which a command must be newer than. Kept only in memory, a command
+// captured within `max_skew` of being sent could be replayed once the
+// process restarts; `FileTimestampStore` keeps it across restarts.
+pub trait TimestampStore: Send + Sync {
+    // 0 if no command was accepted yet.
+    fn load(&self) -> Result<u64>;
+
+    fn store(&self, timestamp: u64) -> Result<()>;
+}
+
+// The timestamp for the life of the process.
+#[derive(Default)]
+pub struct MemoryTimestampStore {
+    last: AtomicU64,
+}
+
+impl MemoryTimestampStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TimestampStore for MemoryTimestampStore {
+    fn load(&self) -> Result<u64> {
+        Ok(self.last.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, timestamp: u64) -> Result<()> {
+        self.last.store(timestamp, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+// The timestamp in a file of its own, written to a temporary file and moved
+// over it on every accepted command.
+pub struct FileTimestampStore {
+    path: PathBuf,
+}
+
+impl FileTimestampStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl TimestampStore for FileTimestampStore {
+    fn load(&self) -> Result<u64> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => contents.trim().parse().map_err(|_| {
+                Error::InvalidPayload(format!("invalid timestamp in {}", self.path.display()))
+            }),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn store(&self, timestamp: u64) -> Result<()> {
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, timestamp.to_string())?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+// Lets operators control a client over MQTT. The client subscribes to
+// `<prefix>/<client id>/command`, runs commands signed with the shared
+// secret (see `sign`) and answers on `<prefix>/<client id>/reply` with
+// `ok <command>: <detail>` or `error <command>: <reason>`. Commands must be
+// timestamped within `max_skew` of the client's clock and newer than the
+// last one accepted, so a captured command cannot be replayed; see
+// `TimestampStore` for replays across restarts.
+#[derive(Clone)]
+pub struct Management {
+    secret: Vec<u8>,
+    prefix: String,
+    max_skew: Duration,
+    timestamps: Arc<dyn TimestampStore>,
+}
+
+impl Management {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            prefix: TOPIC_PREFIX.to_string(),
+            max_skew: Duration::from_secs(60),
+            timestamps: Arc::new(MemoryTimestampStore::new()),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    pub fn with_timestamp_store(mut self, store: impl TimestampStore + 'static) -> Self {
+        self.timestamps = Arc::new(store);
+        self
+    }
+
+    // Checks the signature and freshness of a command payload.
+    fn verify(
+        &self,
+        client_id: &str,
+        payload: &[u8],
+        last: u64,
+    ) -> std::result::Result<(u64, Command), String> {
+        let payload = std::str::from_utf8(payload).map_err(|_| "not UTF-8".to_string())?;
+        let mut lines = payload.splitn(3, '\n');
+        let (Some(timestamp), Some(command), Some(signature)) =
+            (lines.next(), lines.next(), lines.next())
+        else {
+            return Err("expected timestamp, command and signature lines".to_string());
+        };
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| format!("invalid timestamp {:?}", timestamp))?;
+        let signature = hex::decode(signature.trim()).ok_or("invalid signature")?;
+        mac(&self.secret, client_id, timestamp, command)
+            .verify_slice(&signature)
+            .map_err(|_| "bad signature")?;
+        if now_secs().abs_diff(timestamp) > self.max_skew.as_secs() {
+            return Err("timestamp too far from the client's clock".to_string());
+        }
+        if timestamp <= last {
+            return Err("timestamp not newer than the last command".to_string());
+        }
+        Ok((timestamp, command.parse()?))
+    }
+
+    // Starts accepting commands until the handle is dropped or the client is.
+    // Commands run on a thread of their own, not on the network thread. A
+    // command whose timestamp cannot be stored is refused without running.
+    pub fn start(self, client: &Arc<Client>) -> Result<ManagementHandle> {
+        let mut last = self.timestamps.load()?;
+        let client_id = client.client_id().to_string();
+        let reply_topic = reply_topic(&self.prefix, &client_id);
+
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let handle = client.subscribe_with(
+            command_topic(&self.prefix, &client_id).as_str(),
+            QoS::AtLeastOnce,
+            move |msg| {
+                let _ = tx.send(msg.payload().to_vec());
+            },
+        )?;
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (client, stopped) = (Arc::downgrade(client), stopped.clone());
+            thread::spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    let payload = match rx.recv_timeout(Duration::from_millis(250)) {
+                        Ok(payload) => payload,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    let Some(client) = client.upgrade() else {
+                        break;
+                    };
+                    let reply = match self.verify(&client_id, &payload, last) {
+                        Ok((timestamp, command)) => {
+                            let result = self.timestamps.store(timestamp).and_then(|()| {
+                                last = timestamp;
+                                run(&client, &command)
+                            });
+                            match result {
+                                Ok(detail) => format!("ok {}: {}", command, detail),
+                                Err(e) => format!("error {}: {}", command, e),
+                            }
+                        }
+                        Err(reason) => format!("error: {}", reason),
+                    };
+                    if let Ok(reply) = Message::new(reply_topic.as_str(), reply) {
+                        let _ = client.publish(&reply.with_qos(QoS::AtLeastOnce));
+                    }
+                }
+            })
+        };
+
+        Ok(ManagementHandle {
+            client: Arc::downgrade(client),
+            handle,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn run(client: &Client, command: &Command) -> Result<String> {
+    match command {
+        Command::Stats => Ok(format!(
+            "state={:?} broker={} subscriptions={} messages_dropped={}",
+            client.state(),
+            client
+                .current_broker()
+                .map_or("-".to_string(), |(host, port)| format!("{}:{}", host, port)),
+            client.subscription_count(),
+            client.messages_dropped(),
+        )),
+        Command::LogLevel(level) => set_log_level(level),
+        Command::Resubscribe => Ok(format!("{} restored", client.resubscribe())),
+        Command::Reconnect => client
+            .reconnect()
+            .map(|restored| format!("{} restored", restored)),
+    }
+}
+
+#[cfg(feature = "log")]
+fn set_log_level(level: &str) -> Result<String> {
+    let level: log::LevelFilter = level
+        .parse()
+        .map_err(|_| Error::InvalidPayload(format!("unknown log level {:?}", level)))?;
+    log::set_max_level(level);
+    Ok(level.to_string())
+}
+
+#[cfg(not(feature = "log"))]
+fn set_log_level(_level: &str) -> Result<String> {
+    Err(Error::InvalidPayload(
+        "log levels need the log feature".to_string(),
+    ))
+}
+
+// Stops accepting commands when dropped.
+pub struct ManagementHandle {
+    client: Weak<Client>,
+    handle: i64,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ManagementHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(client) = self.client.upgrade() {
+            let _ = client.unsubscribe(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+
+    #[test]
+    fn test_verify_signed_commands() {
+        let management = Management::new("secret");
+        let now = now_secs();
+        let payload = sign(
+            b"secret",
+            "dev-1",
+            now,
+            &Command::LogLevel("debug".to_string()),
+        );
+        assert_eq!(
+            management.verify("dev-1", &payload, 0),
+            Ok((now, Command::LogLevel("debug".to_string())))
+        );
+        // Replayed, sent to another client, signed with another secret, stale.
+        assert!(management.verify("dev-1", &payload, now).is_err());
+        assert!(management.verify("dev-2", &payload, 0).is_err());
+        let forged = sign(b"guess", "dev-1", now, &Command::Reconnect);
+        assert_eq!(
+            management.verify("dev-1", &forged, 0),
+            Err("bad signature".to_string())
+        );
+        let stale = sign(b"secret", "dev-1", now - 3600, &Command::Stats);
+        assert!(management.verify("dev-1", &stale, 0).is_err());
+    }
+
+    #[test]
+    fn test_accepted_timestamps_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("polar-mqtt-manage-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("dev-1", fake.clone(), |_| {}, |_| {}, |_, _| {});
+        let client = Arc::new(client.unwrap());
+        client.connect("broker", 1883).unwrap();
+        let (tx, replies) = mpsc::channel();
+        let reply_filter = reply_topic(TOPIC_PREFIX, "dev-1");
+        client
+            .subscribe_with(reply_filter.as_str(), QoS::AtLeastOnce, move |msg| {
+                let _ = tx.send(String::from_utf8_lossy(msg.payload()).into_owned());
+            })
+            .unwrap();
+        let command = Message::new(
+            command_topic(TOPIC_PREFIX, "dev-1").as_str(),
+            sign(b"secret", "dev-1", now_secs(), &Command::Stats),
+        )
+        .unwrap();
+        let start = || {
+            Management::new("secret")
+                .with_timestamp_store(FileTimestampStore::new(&path))
+                .start(&client)
+                .unwrap()
+        };
+        let reply = || replies.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let management = start();
+        fake.inject(command.clone());
+        assert!(reply().starts_with("ok stats: state=Connected"));
+        drop(management);
+
+        let _management = start();
+        fake.inject(command);
+        assert_eq!(reply(), "error: timestamp not newer than the last command");
+        let _ = fs::remove_file(&path);
+    }
+}
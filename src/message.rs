@@ -1,4 +1,12 @@
+use crate::envelope::Envelope;
+use crate::error::{Error, Result};
+use crate::idempotency;
+use crate::provenance::Provenance;
+use crate::time::Instant;
+use crate::types::Topic;
 use crate::QoS;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 // The owned version for publishing
 #[derive(Debug, Clone)]
@@ -7,6 +15,7 @@ pub struct Message {
     pub(crate) payload: Vec<u8>,
     pub(crate) qos: QoS,
     pub(crate) retained: bool,
+    pub(crate) expires_at: Option<Instant>,
 }
 
 // The borrowed version for callbacks
@@ -16,16 +25,35 @@ pub struct MessageView<'a> {
     pub(crate) payload: &'a [u8],
     pub(crate) qos: QoS,
     pub(crate) retained: bool,
+    // The payload copied out of the bridge's buffer, made on first request
+    // and shared by every handler the view is passed to.
+    pub(crate) shared: OnceLock<Arc<[u8]>>,
+}
+
+// A received message that can be handed to any number of queues or threads
+// without copying: clones share the topic and payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMessage {
+    topic: Arc<str>,
+    payload: Arc<[u8]>,
+    qos: QoS,
+    retained: bool,
 }
 
 impl Message {
-    pub fn new<T: Into<String>, P: Into<Vec<u8>>>(topic: T, payload: P) -> Self {
-        Self {
-            topic: topic.into(),
+    pub fn new<T, P>(topic: T, payload: P) -> Result<Self>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+        P: Into<Vec<u8>>,
+    {
+        Ok(Self {
+            topic: topic.try_into()?.into_string(),
             payload: payload.into(),
             qos: QoS::AtMostOnce,
             retained: false,
-        }
+            expires_at: None,
+        })
     }
 
     pub fn with_qos(mut self, qos: QoS) -> Self {
@@ -38,6 +66,31 @@ impl Message {
         self
     }
 
+    // Drops the message instead of publishing it once `ttl` has passed,
+    // counting from now, such as when it waited for the rate limit. MQTT
+    // 3.1.1 has no expiry property, so the broker is not told: a message
+    // it has already accepted, including a retained one, is delivered
+    // however late.
+    pub fn with_expiry(mut self, ttl: Duration) -> Self {
+        self.expires_at = Some(Instant::now() + ttl);
+        self
+    }
+
+    // Wraps the payload in an envelope carrying `key` (see `ExactlyOnce`),
+    // or sets the key of the envelope it already is. A publish retried
+    // after a failure must reuse the message, and so the key.
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Result<Self> {
+        self.payload =
+            Envelope::stamp(&self.payload, [(idempotency::IDEMPOTENCY_KEY, key.into())])?;
+        Ok(self)
+    }
+
+    // `with_idempotency_key` with a new random UUID.
+    #[cfg(feature = "uuid")]
+    pub fn with_unique_key(self) -> Result<Self> {
+        self.with_idempotency_key(idempotency::new_key())
+    }
+
     pub fn topic(&self) -> &str {
         &self.topic
     }
@@ -46,6 +99,20 @@ impl Message {
         &self.payload
     }
 
+    // For a `Middleware` rewriting the message.
+    pub fn payload_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.payload
+    }
+
+    pub fn set_topic<T>(&mut self, topic: T) -> Result<()>
+    where
+        T: TryInto<Topic>,
+        Error: From<T::Error>,
+    {
+        self.topic = topic.try_into()?.into_string();
+        Ok(())
+    }
+
     pub fn qos(&self) -> QoS {
         self.qos
     }
@@ -53,6 +120,35 @@ impl Message {
     pub fn is_retained(&self) -> bool {
         self.retained
     }
+
+    // Time left before the message expires, if it has an expiry.
+    pub fn expiry(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Instant::now())
+    }
+
+    // The producer, if it published with provenance enabled.
+    pub fn provenance(&self) -> Option<Provenance> {
+        Provenance::from_payload(&self.payload)
+    }
+
+    pub fn idempotency_key(&self) -> Option<String> {
+        idempotency::key(&self.payload)
+    }
+
+    pub(crate) fn view(&self) -> MessageView<'_> {
+        MessageView {
+            topic: &self.topic,
+            payload: &self.payload,
+            qos: self.qos,
+            retained: self.retained,
+            shared: OnceLock::new(),
+        }
+    }
 }
 
 impl MessageView<'_> {
@@ -62,6 +158,7 @@ impl MessageView<'_> {
             payload: self.payload.to_vec(),
             qos: self.qos,
             retained: self.retained,
+            expires_at: None,
         }
     }
 
@@ -73,6 +170,53 @@ impl MessageView<'_> {
         self.payload
     }
 
+    // The payload in a shared buffer. The bridge owns the received bytes
+    // only for the duration of the callback, so they are copied once, on
+    // the first call, and later calls on the same view return that copy.
+    pub fn payload_shared(&self) -> Arc<[u8]> {
+        self.shared.get_or_init(|| self.payload.into()).clone()
+    }
+
+    pub fn to_shared(&self) -> SharedMessage {
+        SharedMessage {
+            topic: self.topic.into(),
+            payload: self.payload_shared(),
+            qos: self.qos,
+            retained: self.retained,
+        }
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    pub fn is_retained(&self) -> bool {
+        self.retained
+    }
+
+    // The producer, if it published with provenance enabled.
+    pub fn provenance(&self) -> Option<Provenance> {
+        Provenance::from_payload(self.payload)
+    }
+
+    pub fn idempotency_key(&self) -> Option<String> {
+        idempotency::key(self.payload)
+    }
+}
+
+impl SharedMessage {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn payload_shared(&self) -> Arc<[u8]> {
+        self.payload.clone()
+    }
+
     pub fn qos(&self) -> QoS {
         self.qos
     }
@@ -80,6 +224,33 @@ impl MessageView<'_> {
     pub fn is_retained(&self) -> bool {
         self.retained
     }
+
+    // The producer, if it published with provenance enabled.
+    pub fn provenance(&self) -> Option<Provenance> {
+        Provenance::from_payload(&self.payload)
+    }
+
+    pub fn view(&self) -> MessageView<'_> {
+        MessageView {
+            topic: &self.topic,
+            payload: &self.payload,
+            qos: self.qos,
+            retained: self.retained,
+            shared: OnceLock::from(self.payload.clone()),
+        }
+    }
+}
+
+impl From<SharedMessage> for Message {
+    fn from(msg: SharedMessage) -> Self {
+        Message {
+            topic: msg.topic.to_string(),
+            payload: msg.payload.to_vec(),
+            qos: msg.qos,
+            retained: msg.retained,
+            expires_at: None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -93,8 +264,16 @@ mod tests {
             payload: vec![1, 2, 3],
             qos: QoS::AtLeastOnce,
             retained: false,
+            expires_at: None,
         };
         assert_eq!(msg.topic, "test/topic");
+        assert!(!msg.is_expired());
+        assert_eq!(msg.expiry(), None);
+
+        let msg = msg.with_expiry(Duration::from_secs(60));
+        assert!(!msg.is_expired());
+        assert!(msg.expiry().unwrap() > Duration::from_secs(59));
+        assert!(msg.with_expiry(Duration::ZERO).is_expired());
     }
 
     #[test]
@@ -109,6 +288,7 @@ mod tests {
             payload: &payload,
             qos,
             retained,
+            shared: OnceLock::new(),
         };
 
         assert_eq!(view.topic(), "test/topic");
@@ -129,6 +309,7 @@ mod tests {
             payload: &payload,
             qos,
             retained,
+            shared: OnceLock::new(),
         };
 
         let owned = view.to_owned();
@@ -143,4 +324,25 @@ mod tests {
         assert_eq!(owned.topic, String::from("test/topic"));
         assert_eq!(owned.payload, vec![1, 2, 3]);
     }
+
+    #[test]
+    fn test_payload_copied_once_and_shared() {
+        let payload = vec![1, 2, 3];
+        let view = MessageView {
+            topic: "test/topic",
+            payload: &payload,
+            qos: QoS::AtLeastOnce,
+            retained: false,
+            shared: OnceLock::new(),
+        };
+        let first = view.payload_shared();
+        assert!(Arc::ptr_eq(&first, &view.payload_shared()));
+
+        let shared = view.to_shared();
+        assert!(Arc::ptr_eq(&first, &shared.payload_shared()));
+        let copy = shared.clone();
+        assert!(Arc::ptr_eq(&first, &copy.view().payload_shared()));
+        assert_eq!(copy.topic(), "test/topic");
+        assert_eq!(Message::from(copy).payload(), &[1, 2, 3]);
+    }
 }
@@ -0,0 +1,242 @@
+use crate::types::ConnectionState;
+use metrics::{counter, describe_counter, describe_histogram, histogram, Counter, Histogram, Unit};
+use std::time::Duration;
+
+pub const MESSAGES_RECEIVED: &str = "polar_mqtt_messages_received_total";
+pub const BYTES_RECEIVED: &str = "polar_mqtt_bytes_received_total";
+pub const MESSAGES_SAMPLED_OUT: &str = "polar_mqtt_messages_sampled_out_total";
+pub const MESSAGES_OVERSIZED: &str = "polar_mqtt_messages_oversized_total";
+pub const MESSAGES_DROPPED: &str = "polar_mqtt_messages_dropped_total";
+pub const MESSAGES_EXPIRED: &str = "polar_mqtt_messages_expired_total";
+pub const MESSAGES_PUBLISHED: &str = "polar_mqtt_messages_published_total";
+pub const MESSAGES_DEDUPLICATED: &str = "polar_mqtt_messages_deduplicated_total";
+pub const DUPLICATES_DROPPED: &str = "polar_mqtt_duplicates_dropped_total";
+pub const BYTES_PUBLISHED: &str = "polar_mqtt_bytes_published_total";
+pub const ERRORS: &str = "polar_mqtt_errors_total";
+pub const CALLBACK_PANICS: &str = "polar_mqtt_callback_panics_total";
+pub const RECONNECTS: &str = "polar_mqtt_reconnects_total";
+pub const PUBLISH_ACK_LATENCY: &str = "polar_mqtt_publish_ack_latency_seconds";
+
+// Registers descriptions for every metric emitted by this crate with the
+// installed recorder. Optional, but makes exporters such as Prometheus emit
+// `HELP` lines and units.
+pub fn describe() {
+    describe_counter!(
+        MESSAGES_RECEIVED,
+        "Messages received and let through by the namespace, decryption and middleware, \
+         before deduplication, sampling and the inbound queue"
+    );
+    describe_counter!(BYTES_RECEIVED, Unit::Bytes, "Payload bytes received");
+    describe_counter!(
+        MESSAGES_SAMPLED_OUT,
+        "Messages received but dropped by sampling before dispatch"
+    );
+    describe_counter!(
+        MESSAGES_OVERSIZED,
+        "Messages received with a payload over the configured limit"
+    );
+    describe_counter!(
+        MESSAGES_DROPPED,
+        "Messages received but dropped: outside the namespace, undecryptable, dropped by \
+         middleware, or by a full or replaced inbound queue"
+    );
+    describe_counter!(
+        MESSAGES_EXPIRED,
+        "Messages not published because their expiry had passed"
+    );
+    describe_counter!(MESSAGES_PUBLISHED, "Messages accepted for publication");
+    describe_counter!(
+        MESSAGES_DEDUPLICATED,
+        "Publishes skipped because the payload was unchanged"
+    );
+    describe_counter!(
+        DUPLICATES_DROPPED,
+        "Messages received but dropped as duplicates"
+    );
+    describe_counter!(BYTES_PUBLISHED, Unit::Bytes, "Payload bytes published");
+    describe_counter!(
+        ERRORS,
+        "Errors reported by the session or returned by operations"
+    );
+    describe_counter!(
+        CALLBACK_PANICS,
+        "Panics caught in user callbacks and handlers"
+    );
+    describe_counter!(RECONNECTS, "Transitions into the reconnecting state");
+    describe_histogram!(
+        PUBLISH_ACK_LATENCY,
+        Unit::Seconds,
+        "Time between publish and broker acknowledgement (QoS 1 and 2 only)"
+    );
+}
+
+// Per-client handles, labeled with the client id so several clients in one
+// process stay distinguishable.
+pub(crate) struct ClientMetrics {
+    messages_received: Counter,
+    bytes_received: Counter,
+    messages_sampled_out: Counter,
+    messages_oversized: Counter,
+    messages_dropped: Counter,
+    messages_expired: Counter,
+    messages_published: Counter,
+    messages_deduplicated: Counter,
+    duplicates_dropped: Counter,
+    bytes_published: Counter,
+    errors: Counter,
+    callback_panics: Counter,
+    reconnects: Counter,
+    publish_ack_latency: Histogram,
+}
+
+impl ClientMetrics {
+    pub(crate) fn new(client_id: &str) -> Self {
+        let id = client_id.to_string();
+        Self {
+            messages_received: counter!(MESSAGES_RECEIVED, "client_id" => id.clone()),
+            bytes_received: counter!(BYTES_RECEIVED, "client_id" => id.clone()),
+            messages_sampled_out: counter!(MESSAGES_SAMPLED_OUT, "client_id" => id.clone()),
+            messages_oversized: counter!(MESSAGES_OVERSIZED, "client_id" => id.clone()),
+            messages_dropped: counter!(MESSAGES_DROPPED, "client_id" => id.clone()),
+            messages_expired: counter!(MESSAGES_EXPIRED, "client_id" => id.clone()),
+            messages_published: counter!(MESSAGES_PUBLISHED, "client_id" => id.clone()),
+            messages_deduplicated: counter!(MESSAGES_DEDUPLICATED, "client_id" => id.clone()),
+            duplicates_dropped: counter!(DUPLICATES_DROPPED, "client_id" => id.clone()),
+            bytes_published: counter!(BYTES_PUBLISHED, "client_id" => id.clone()),
+            errors: counter!(ERRORS, "client_id" => id.clone()),
+            callback_panics: counter!(CALLBACK_PANICS, "client_id" => id.clone()),
+            reconnects: counter!(RECONNECTS, "client_id" => id.clone()),
+            publish_ack_latency: histogram!(PUBLISH_ACK_LATENCY, "client_id" => id),
+        }
+    }
+
+    pub(crate) fn message_received(&self, payload_len: usize) {
+        self.messages_received.increment(1);
+        self.bytes_received.increment(payload_len as u64);
+    }
+
+    pub(crate) fn message_sampled_out(&self) {
+        self.messages_sampled_out.increment(1);
+    }
+
+    pub(crate) fn message_oversized(&self) {
+        self.messages_oversized.increment(1);
+    }
+
+    pub(crate) fn message_dropped(&self) {
+        self.messages_dropped.increment(1);
+    }
+
+    pub(crate) fn message_expired(&self) {
+        self.messages_expired.increment(1);
+    }
+
+    pub(crate) fn message_published(&self, payload_len: usize) {
+        self.messages_published.increment(1);
+        self.bytes_published.increment(payload_len as u64);
+    }
+
+    pub(crate) fn message_deduplicated(&self) {
+        self.messages_deduplicated.increment(1);
+    }
+
+    pub(crate) fn duplicate_dropped(&self) {
+        self.duplicates_dropped.increment(1);
+    }
+
+    pub(crate) fn publish_acked(&self, latency: Duration) {
+        self.publish_ack_latency.record(latency.as_secs_f64());
+    }
+
+    pub(crate) fn callback_panicked(&self) {
+        self.callback_panics.increment(1);
+    }
+
+    pub(crate) fn error(&self) {
+        self.errors.increment(1);
+    }
+
+    pub(crate) fn state_changed(&self, state: ConnectionState) {
+        if state == ConnectionState::Reconnecting {
+            self.reconnects.increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::client::Client;
+    use crate::message::Message;
+    use crate::types::QoS;
+    use metrics::{Gauge, Key, KeyName, Metadata, Recorder, SharedString};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Counters(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+    impl Counters {
+        fn get(&self, name: &str) -> u64 {
+            self.0
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0, |value| value.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for Counters {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let label = key.labels().next().unwrap();
+            assert_eq!((label.key(), label.value()), ("client_id", "metered"));
+            let mut counters = self.0.lock().unwrap();
+            Counter::from_arc(counters.entry(key.name().to_string()).or_default().clone())
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_counts_traffic_through_the_client() {
+        let fake = Arc::new(Fake::default());
+        let counters = Counters::default();
+        // The handles keep reporting to the recorder they were created with.
+        let client = metrics::with_local_recorder(&counters, || {
+            Client::with_backend("metered", fake.clone(), |_| {}, |_| {}, |_, _| {})
+        });
+        let client = client.unwrap();
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("meters/#", QoS::AtMostOnce).unwrap();
+
+        // Published, then delivered back through the subscription.
+        client
+            .publish(&Message::new("meters/7", "12.5").unwrap())
+            .unwrap();
+        assert_eq!(counters.get(MESSAGES_PUBLISHED), 1);
+        assert_eq!(counters.get(BYTES_PUBLISHED), 4);
+        assert_eq!(counters.get(MESSAGES_RECEIVED), 1);
+        assert_eq!(counters.get(BYTES_RECEIVED), 4);
+
+        assert!(client
+            .publish_raw("meters/+", b"1", QoS::AtMostOnce, false)
+            .is_err());
+        assert_eq!(counters.get(MESSAGES_PUBLISHED), 1);
+        fake.drop_connections();
+        assert_eq!(counters.get(RECONNECTS), 1);
+    }
+}
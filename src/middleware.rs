@@ -0,0 +1,139 @@
+use crate::message::Message;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Pass the message, possibly modified, to the next middleware.
+    Continue,
+    // Stop here: an outgoing message is not published and `publish` fails
+    // with `Error::Dropped`, an incoming one is dropped and counted in
+    // `Client::messages_dropped`.
+    Drop,
+}
+
+// A layer added with `Client::add_middleware`, which sees every message
+// published through `publish` before anything else does, and every message
+// received after the client's own decompression and decryption, before the
+// stats, sampler and callbacks. Outgoing messages go through the chain in
+// the order the layers were added, incoming ones in reverse, so a layer
+// that transforms payloads on the way out can undo it on the way in.
+//
+// Incoming messages are copied out of the receive buffer to be passed as
+// `&mut Message`, so an empty chain costs nothing and a non-empty one an
+// allocation per message. Layers run on the bridge's callback thread and
+// must not block.
+pub trait Middleware: Send + Sync {
+    fn on_outgoing(&self, _message: &mut Message) -> Action {
+        Action::Continue
+    }
+
+    fn on_incoming(&self, _message: &mut Message) -> Action {
+        Action::Continue
+    }
+}
+
+// Compresses outgoing payloads; incoming ones are decompressed by the
+// client whether or not the layer is there.
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+impl Middleware for crate::compression::Compression {
+    fn on_outgoing(&self, message: &mut Message) -> Action {
+        match self.compress(&message.payload) {
+            Ok(Some(payload)) => message.payload = payload,
+            Ok(None) => {}
+            Err(_) => return Action::Drop,
+        }
+        Action::Continue
+    }
+}
+
+// Encrypts outgoing payloads and decrypts incoming ones, dropping those
+// that do not decrypt.
+#[cfg(feature = "encryption")]
+impl Middleware for crate::encryption::Encryption {
+    fn on_outgoing(&self, message: &mut Message) -> Action {
+        match self.encrypt(&message.topic, &message.payload) {
+            Ok(Some(payload)) => message.payload = payload,
+            Ok(None) => {}
+            Err(_) => return Action::Drop,
+        }
+        Action::Continue
+    }
+
+    fn on_incoming(&self, message: &mut Message) -> Action {
+        match self.decrypt(&message.topic, &message.payload) {
+            Ok(Some(payload)) => message.payload = payload,
+            Ok(None) => {}
+            Err(_) => return Action::Drop,
+        }
+        Action::Continue
+    }
+}
+
+// Runs `message` through `chain`, first to last for outgoing messages and
+// last to first for incoming ones.
+pub(crate) fn run(chain: &[Arc<dyn Middleware>], message: &mut Message, outgoing: bool) -> Action {
+    let mut step = |layer: &Arc<dyn Middleware>| {
+        if outgoing {
+            layer.on_outgoing(message)
+        } else {
+            layer.on_incoming(message)
+        }
+    };
+    let dropped = if outgoing {
+        chain.iter().any(|layer| step(layer) == Action::Drop)
+    } else {
+        chain.iter().rev().any(|layer| step(layer) == Action::Drop)
+    };
+    if dropped {
+        Action::Drop
+    } else {
+        Action::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Tag(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Middleware for Tag {
+        fn on_outgoing(&self, message: &mut Message) -> Action {
+            self.1.lock().unwrap().push(self.0);
+            message.payload.extend_from_slice(self.0.as_bytes());
+            Action::Continue
+        }
+
+        fn on_incoming(&self, message: &mut Message) -> Action {
+            self.1.lock().unwrap().push(self.0);
+            if message.topic.starts_with("private/") {
+                return Action::Drop;
+            }
+            let len = message.payload.len() - self.0.len();
+            message.payload.truncate(len);
+            Action::Continue
+        }
+    }
+
+    #[test]
+    fn test_chain_order_and_drop() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let chain: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Tag("a", seen.clone())),
+            Arc::new(Tag("b", seen.clone())),
+        ];
+        let mut message = Message::new("site/temp", "21").unwrap();
+        assert_eq!(run(&chain, &mut message, true), Action::Continue);
+        assert_eq!(message.payload(), b"21ab");
+        assert_eq!(run(&chain, &mut message, false), Action::Continue);
+        assert_eq!(message.payload(), b"21");
+        assert_eq!(*seen.lock().unwrap(), ["a", "b", "b", "a"]);
+
+        // A dropped message goes no further down the chain.
+        seen.lock().unwrap().clear();
+        let mut message = Message::new("private/key", "x").unwrap();
+        assert_eq!(run(&chain, &mut message, false), Action::Drop);
+        assert_eq!(*seen.lock().unwrap(), ["b"]);
+    }
+}
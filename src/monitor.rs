@@ -0,0 +1,363 @@
+use crate::client::Client;
+use crate::client_id::ClientId;
+use crate::error::Result;
+use crate::hierarchy::TopicHierarchy;
+use crate::message::{Message, MessageView};
+use crate::options::ConnectOptions;
+use crate::sampling::Sampler;
+use crate::time::Instant;
+use crate::types::{ConnectionState, QoS, ShutdownReport};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    filters: Vec<String>,
+    qos: QoS,
+    stats: bool,
+    archive_capacity: usize,
+    max_topics: usize,
+    sampler: Sampler,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        Self {
+            filters: vec!["#".to_string()],
+            qos: QoS::AtMostOnce,
+            stats: true,
+            archive_capacity: 1000,
+            max_topics: 10_000,
+            sampler: Sampler::default(),
+        }
+    }
+}
+
+impl MonitorOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces the default `#` subscription.
+    pub fn with_filters<I, S>(mut self, filters: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.filters = filters.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_stats(mut self, enabled: bool) -> Self {
+        self.stats = enabled;
+        self
+    }
+
+    // Sampling applied before messages are counted or archived.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    // Number of most recent messages kept in memory; 0 disables the archive.
+    pub fn with_archive_capacity(mut self, capacity: usize) -> Self {
+        self.archive_capacity = capacity;
+        self
+    }
+
+    // Number of topics whose statistics are kept; past it, the topic seen
+    // least recently is forgotten to make room for a new one.
+    pub fn with_max_topics(mut self, max: usize) -> Self {
+        self.max_topics = max;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TopicSummary {
+    pub topic: String,
+    pub messages: u64,
+    pub bytes: u64,
+    pub last_seen: Instant,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MonitorStats {
+    pub messages: u64,
+    pub bytes: u64,
+    pub topics: Vec<TopicSummary>,
+    // Topics forgotten to stay within `MonitorOptions::with_max_topics`.
+    pub evicted_topics: u64,
+    // Time since the monitor was created or its stats were last reset.
+    pub elapsed: Duration,
+}
+
+impl MonitorStats {
+    pub fn hierarchy(&self) -> TopicHierarchy {
+        TopicHierarchy::from_stats(self)
+    }
+}
+
+struct MonitorState {
+    messages: u64,
+    bytes: u64,
+    // Each topic with the tick it was last seen at, its key in `recency`.
+    topics: HashMap<String, (u64, TopicSummary)>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    evicted_topics: u64,
+    archive: VecDeque<Message>,
+    since: Instant,
+}
+
+impl Default for MonitorState {
+    fn default() -> Self {
+        Self {
+            messages: 0,
+            bytes: 0,
+            topics: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            evicted_topics: 0,
+            archive: VecDeque::new(),
+            since: Instant::now(),
+        }
+    }
+}
+
+impl MonitorState {
+    fn count(&mut self, msg: &MessageView, max_topics: usize) {
+        let bytes = msg.payload().len() as u64;
+        self.messages += 1;
+        self.bytes += bytes;
+        if max_topics == 0 {
+            return;
+        }
+        let now = Instant::now();
+        self.tick += 1;
+        let tick = self.tick;
+        if !self.topics.contains_key(msg.topic()) && self.topics.len() == max_topics {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.topics.remove(&oldest);
+                self.evicted_topics += 1;
+            }
+        }
+        let (seen, summary) = self
+            .topics
+            .entry(msg.topic().to_string())
+            .or_insert_with(|| {
+                let summary = TopicSummary {
+                    topic: msg.topic().to_string(),
+                    messages: 0,
+                    bytes: 0,
+                    last_seen: now,
+                };
+                (tick, summary)
+            });
+        self.recency.remove(seen);
+        *seen = tick;
+        self.recency.insert(tick, msg.topic().to_string());
+        summary.messages += 1;
+        summary.bytes += bytes;
+        summary.last_seen = now;
+    }
+
+    fn archive(&mut self, msg: &MessageView, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        if self.archive.len() == capacity {
+            self.archive.pop_front();
+        }
+        self.archive.push_back(msg.to_owned());
+    }
+}
+
+// A read-only client for pointing at production brokers: it has no publish
+// methods, subscribes broadly on connect and keeps per-topic statistics and
+// an archive of recent messages.
+pub struct MonitorClient {
+    client: Client,
+    options: MonitorOptions,
+    state: Arc<Mutex<MonitorState>>,
+}
+
+impl MonitorClient {
+    pub fn new(client_id: impl Into<ClientId>, options: MonitorOptions) -> Result<Self> {
+        Self::build(options, |on_message| {
+            Client::new(client_id, on_message, |_| {}, |_, _| {})
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_backend(
+        client_id: impl Into<ClientId>,
+        options: MonitorOptions,
+        backend: Arc<dyn crate::backend::Backend>,
+    ) -> Result<Self> {
+        Self::build(options, |on_message| {
+            Client::with_backend(client_id, backend, on_message, |_| {}, |_, _| {})
+        })
+    }
+
+    fn build<F>(options: MonitorOptions, new_client: F) -> Result<Self>
+    where
+        F: FnOnce(Box<dyn Fn(&MessageView) + Send + Sync>) -> Result<Client>,
+    {
+        let state = Arc::new(Mutex::new(MonitorState::default()));
+        let (stats, max_topics) = (options.stats, options.max_topics);
+        let archive_capacity = options.archive_capacity;
+
+        let client = new_client({
+            let state = Arc::clone(&state);
+            Box::new(move |msg| {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                if stats {
+                    state.count(msg, max_topics);
+                }
+                state.archive(msg, archive_capacity);
+            })
+        })?;
+
+        client.set_sampler(options.sampler.clone());
+
+        Ok(Self {
+            client,
+            options,
+            state,
+        })
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> Result<()> {
+        self.connect_with(host, port, &ConnectOptions::default())
+    }
+
+    pub fn connect_with(&self, host: &str, port: u16, options: &ConnectOptions) -> Result<()> {
+        self.client.connect_with(host, port, options)?;
+        for filter in &self.options.filters {
+            self.client.subscribe(filter.as_str(), self.options.qos)?;
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.client.state()
+    }
+
+    // Topics are sorted by message count, busiest first.
+    pub fn stats(&self) -> MonitorStats {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut topics: Vec<TopicSummary> = state
+            .topics
+            .values()
+            .map(|(_, summary)| summary.clone())
+            .collect();
+        topics.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then_with(|| a.topic.cmp(&b.topic))
+        });
+        MonitorStats {
+            messages: state.messages,
+            bytes: state.bytes,
+            topics,
+            evicted_topics: state.evicted_topics,
+            elapsed: state.since.elapsed(),
+        }
+    }
+
+    // Up to `limit` archived messages, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<Message> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let skip = state.archive.len().saturating_sub(limit);
+        state.archive.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn reset_stats(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.messages = 0;
+        state.bytes = 0;
+        state.topics.clear();
+        state.recency.clear();
+        state.evicted_topics = 0;
+        state.since = Instant::now();
+    }
+
+    pub fn shutdown(self, flush_timeout: Duration) -> ShutdownReport {
+        self.client.shutdown(flush_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+
+    fn connected(fake: &Arc<Fake>, options: MonitorOptions) -> MonitorClient {
+        let monitor = MonitorClient::with_backend("monitor", options, fake.clone()).unwrap();
+        monitor.connect("broker", 1883).unwrap();
+        monitor
+    }
+
+    fn send(fake: &Fake, topic: &str, payload: &str) {
+        fake.inject(Message::new(topic, payload).unwrap());
+    }
+
+    #[test]
+    fn test_counts_topics_and_forgets_the_least_recently_seen() {
+        let fake = Arc::new(Fake::default());
+        let monitor = connected(&fake, MonitorOptions::new().with_max_topics(2));
+        assert_eq!(fake.filters(), ["#"]);
+        send(&fake, "a", "12");
+        send(&fake, "b", "1");
+        send(&fake, "a", "123");
+        // Over the limit: `b` was seen less recently than `a`.
+        send(&fake, "c", "1");
+
+        let stats = monitor.stats();
+        assert_eq!((stats.messages, stats.bytes), (4, 7));
+        let topics: Vec<_> = stats
+            .topics
+            .iter()
+            .map(|topic| (topic.topic.as_str(), topic.messages, topic.bytes))
+            .collect();
+        assert_eq!(topics, [("a", 2, 5), ("c", 1, 1)]);
+        assert_eq!(stats.evicted_topics, 1);
+
+        monitor.reset_stats();
+        let stats = monitor.stats();
+        assert_eq!((stats.messages, stats.topics.len()), (0, 0));
+        assert_eq!(stats.evicted_topics, 0);
+    }
+
+    #[test]
+    fn test_archive_keeps_the_most_recent_messages() {
+        let fake = Arc::new(Fake::default());
+        let monitor = connected(&fake, MonitorOptions::new().with_archive_capacity(2));
+        for payload in ["1", "2", "3"] {
+            send(&fake, "a", payload);
+        }
+        let payloads: Vec<_> = monitor
+            .recent(10)
+            .into_iter()
+            .map(|message| message.payload)
+            .collect();
+        assert_eq!(payloads, [b"2", b"3"]);
+        assert_eq!(monitor.recent(1)[0].payload, b"3");
+
+        let fake = Arc::new(Fake::default());
+        let options = MonitorOptions::new()
+            .with_archive_capacity(0)
+            .with_stats(false);
+        let monitor = connected(&fake, options);
+        send(&fake, "a", "1");
+        assert!(monitor.recent(10).is_empty());
+        assert_eq!(monitor.stats().messages, 0);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::error::Error;
+use crate::types::{ConnectionState, QoS};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+// Why an incoming message went no further, for `Observer::on_packet_dropped`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    // Over the payload limit, with `OversizePolicy::Drop`.
+    Oversized,
+    // Failed to decrypt, or arrived unencrypted on an encrypted filter.
+    Undecryptable,
+    // Dropped by a `Middleware` layer.
+    Middleware,
+    // Seen before, within the inbound dedup window.
+    Duplicate,
+    // Left out by the sampler.
+    SampledOut,
+    // The inbound queue was full; with `DropPolicy::DropOldest` the message
+    // dropped was an older one on the same topic.
+    QueueFull,
+    // On a topic outside the client's namespace.
+    OutsideNamespace,
+}
+
+// Protocol-level events, for diagnostics and metrics beyond what the
+// client counts itself. Registered with `Client::add_observer`; every
+// method does nothing unless overridden.
+//
+// The bridge runs Paho's synchronous client, which reports SUBACK and
+// UNSUBACK as the result of the call and handles keep-alive PINGREQs
+// itself, so `on_suback` and `on_unsuback` are called on the thread that
+// subscribed, and `on_pingresp` only for `Client::ping`. `on_puback` and
+// `on_packet_dropped` are called on the bridge's callback thread and must
+// not block.
+pub trait Observer: Send + Sync {
+    // A connect to `host`, including those made by failover, finished;
+    // `error` is why it failed.
+    fn on_connect(&self, _host: &str, _port: u16, _error: Option<&Error>) {}
+
+    fn on_state_change(&self, _state: ConnectionState) {}
+
+    // A PUBLISH was handed to the bridge; `message_id` is 0 at QoS 0.
+    fn on_publish_sent(&self, _topic: &str, _message_id: i64, _qos: QoS, _bytes: usize) {}
+
+    // A publish was refused, by the client or by the bridge, before it was
+    // sent.
+    fn on_publish_failed(&self, _topic: &str, _qos: QoS, _bytes: usize, _error: &Error) {}
+
+    // A QoS 1 or 2 publish was acknowledged (PUBACK or PUBCOMP), with the
+    // time since it was sent if it was tracked.
+    fn on_puback(&self, _message_id: i64, _latency: Option<Duration>) {}
+
+    // The broker answered a SUBSCRIBE, including those sent again after a
+    // reconnect; `accepted` is false if it refused the filter.
+    fn on_suback(&self, _filter: &str, _qos: QoS, _accepted: bool) {}
+
+    fn on_unsuback(&self, _filter: &str) {}
+
+    fn on_pingresp(&self, _round_trip: Duration) {}
+
+    fn on_packet_dropped(&self, _topic: &str, _reason: DropReason) {}
+}
+
+// The observers of a client. Replaced whole when one is added, so
+// notifying only clones an `Arc`.
+pub(crate) struct Observers {
+    observers: RwLock<Arc<[Arc<dyn Observer>]>>,
+}
+
+impl Default for Observers {
+    fn default() -> Self {
+        Self {
+            observers: RwLock::new(Arc::new([])),
+        }
+    }
+}
+
+impl Observers {
+    pub(crate) fn add(&self, observer: Arc<dyn Observer>) {
+        let mut observers = self
+            .observers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut all = observers.to_vec();
+        all.push(observer);
+        *observers = all.into();
+    }
+
+    pub(crate) fn remove(&self, observer: &Arc<dyn Observer>) {
+        let mut observers = self
+            .observers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let others: Vec<_> = observers
+            .iter()
+            .filter(|other| !std::ptr::addr_eq(Arc::as_ptr(other), Arc::as_ptr(observer)))
+            .cloned()
+            .collect();
+        *observers = others.into();
+    }
+
+    // Calls `notify` with each observer, in the order they were added.
+    pub(crate) fn each(&self, notify: impl Fn(&dyn Observer)) {
+        let observers = self
+            .observers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for observer in observers.iter() {
+            notify(&**observer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn on_suback(&self, filter: &str, _qos: QoS, accepted: bool) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("suback {} {}", filter, accepted));
+        }
+
+        fn on_packet_dropped(&self, topic: &str, reason: DropReason) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("dropped {} {:?}", topic, reason));
+        }
+    }
+
+    #[test]
+    fn test_notifies_each_observer() {
+        let observers = Observers::default();
+        observers.each(|observer| observer.on_unsuback("a/#"));
+
+        let first = Arc::new(Recorder::default());
+        let second = Arc::new(Recorder::default());
+        observers.add(first.clone());
+        observers.add(second.clone());
+        observers.each(|observer| observer.on_suback("a/#", QoS::AtLeastOnce, true));
+        observers.each(|observer| observer.on_packet_dropped("a/b", DropReason::Duplicate));
+        // Events an observer does not override are ignored.
+        observers.each(|observer| observer.on_pingresp(Duration::from_millis(3)));
+
+        for recorder in [first, second] {
+            assert_eq!(
+                *recorder.0.lock().unwrap(),
+                ["suback a/# true", "dropped a/b Duplicate"]
+            );
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::broker_limits::BrokerLimits;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// OS-level TCP keepalive probes, independent of the MQTT keep-alive. Useful
+// behind proxies and NATs that drop idle connections faster than the MQTT
+// keep-alive interval. The bridge applies them to its socket once connected,
+// on Linux and macOS only; elsewhere, with the rumqttc backend, and in a
+// browser, connecting with them fails with `Error::Unsupported`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    pub idle: Duration,
+    pub interval: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+impl TcpKeepalive {
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            interval: None,
+            retries: None,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+}
+
+// Which addresses of the broker host to connect to, see
+// `ConnectOptions::with_address_family`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    // IPv6 and IPv4, raced happy-eyeballs style.
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+type Resolve = dyn Fn(&str) -> Vec<SocketAddr> + Send + Sync;
+
+// A broker host resolver, see `ConnectOptions::with_resolver`. Unused in
+// the browser.
+#[derive(Clone)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub(crate) struct Resolver(Arc<Resolve>);
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+impl Resolver {
+    pub(crate) fn resolve(&self, host: &str) -> Vec<SocketAddr> {
+        (self.0)(host)
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver")
+    }
+}
+
+// TLS for the broker connection. Without a CA file the system trust store
+// is used; a client certificate and key enable mutual TLS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    // A .p12/.pfx bundle and its password, used instead of the certificate
+    // and key files. Only the bridge reads them.
+    pub pkcs12_file: Option<String>,
+    pub pkcs12_password: Option<String>,
+    pub alpn: Vec<String>,
+    // Sent as SNI instead of the broker host, for brokers reached by
+    // address or through an ingress that routes on it. Paho, rumqttc and
+    // browsers all send the broker host, so for now connecting with it fails
+    // with `Error::Unsupported` on every backend.
+    pub server_name: Option<String>,
+}
+
+impl TlsOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ca_file(mut self, ca_file: impl Into<String>) -> Self {
+        self.ca_file = Some(ca_file.into());
+        self
+    }
+
+    pub fn with_client_cert(
+        mut self,
+        cert_file: impl Into<String>,
+        key_file: impl Into<String>,
+    ) -> Self {
+        self.cert_file = Some(cert_file.into());
+        self.key_file = Some(key_file.into());
+        self
+    }
+
+    pub fn with_pkcs12(mut self, file: impl Into<String>, password: impl Into<String>) -> Self {
+        self.pkcs12_file = Some(file.into());
+        self.pkcs12_password = Some(password.into());
+        self
+    }
+
+    // Adds a protocol to offer during the handshake, in preference order.
+    pub fn with_alpn(mut self, protocol: impl Into<String>) -> Self {
+        self.alpn.push(protocol.into());
+        self
+    }
+
+    // Replaces the protocols to offer, in preference order.
+    pub fn with_alpn_protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.alpn = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    // The ALPN list in TLS wire format: each name prefixed by its length.
+    pub(crate) fn alpn_wire(&self) -> Result<Vec<u8>> {
+        let mut wire = Vec::new();
+        for protocol in &self.alpn {
+            let len = u8::try_from(protocol.len())
+                .ok()
+                .filter(|&len| len > 0)
+                .ok_or_else(|| Error::InvalidTls(format!("bad ALPN protocol {:?}", protocol)))?;
+            wire.push(len);
+            wire.extend_from_slice(protocol.as_bytes());
+        }
+        Ok(wire)
+    }
+}
+
+// Delay between reconnect attempts: starts at `initial` and doubles after
+// every failed attempt, up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max }
+    }
+
+    // The delay after `failures` consecutive failed attempts, from 1.
+    pub(crate) fn delay(&self, failures: u32) -> Duration {
+        self.initial
+            .saturating_mul(1 << failures.saturating_sub(1).min(16))
+            .min(self.max)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    pub(crate) keep_alive: Option<Duration>,
+    pub(crate) max_inflight: Option<u16>,
+    pub(crate) tcp_keepalive: Option<TcpKeepalive>,
+    pub(crate) tcp_nodelay: Option<bool>,
+    pub(crate) send_buffer_size: Option<u32>,
+    pub(crate) receive_buffer_size: Option<u32>,
+    pub(crate) address_family: Option<AddressFamily>,
+    pub(crate) resolver: Option<Resolver>,
+    pub(crate) will: Option<Message>,
+    pub(crate) tls: Option<TlsOptions>,
+    pub(crate) credentials: Option<(String, String)>,
+    pub(crate) backoff: Option<Backoff>,
+    pub(crate) websocket: Option<String>,
+    pub(crate) max_packet_size: Option<u32>,
+    pub(crate) broker_limits: Option<BrokerLimits>,
+    pub(crate) namespace: Option<String>,
+}
+
+impl ConnectOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // MQTT keep-alive (PINGREQ interval).
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    // How many QoS 1/2 publishes may await acknowledgement at once; further
+    // publishes block until one is acked. One keeps memory use lowest but
+    // limits throughput to one round trip per message. Unset, the bridge
+    // allows 10 and the rumqttc backend 100; the browser does not limit them.
+    // MQTT 3.1.1 has no Receive Maximum, so this does not limit the
+    // deliveries the broker sends; `InboundQueue` bounds those client-side.
+    pub fn with_max_inflight(mut self, max_inflight: u16) -> Self {
+        self.max_inflight = Some(max_inflight.max(1));
+        self
+    }
+
+    pub fn with_tcp_keepalive(mut self, tcp_keepalive: TcpKeepalive) -> Self {
+        self.tcp_keepalive = Some(tcp_keepalive);
+        self
+    }
+
+    // TCP_NODELAY on the broker connection: true sends small publishes at
+    // once, false lets Nagle's algorithm batch them. Unset keeps what the
+    // backend does by default. The bridge sets it on its socket once
+    // connected, on Linux and macOS only; elsewhere, with the rumqttc
+    // backend, and in a browser, connecting with it fails with
+    // `Error::Unsupported`.
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = Some(nodelay);
+        self
+    }
+
+    // Socket send and receive buffer sizes (SO_SNDBUF, SO_RCVBUF), for
+    // high-throughput links; the OS may round or cap them. The rumqttc
+    // backend sets them before the socket connects. The bridge can only set
+    // them once connected, on Linux and macOS, which is too late for a
+    // receive buffer to raise the TCP window scale; elsewhere, and in a
+    // browser, connecting with them fails with `Error::Unsupported`.
+    pub fn with_send_buffer_size(mut self, bytes: u32) -> Self {
+        self.send_buffer_size = Some(bytes);
+        self
+    }
+
+    pub fn with_receive_buffer_size(mut self, bytes: u32) -> Self {
+        self.receive_buffer_size = Some(bytes);
+        self
+    }
+
+    // Has the client resolve the broker host itself, to addresses of
+    // `family`, and connect to the first that accepts a TCP connection,
+    // trying IPv6 and IPv4 alternately with `AddressFamily::Any`.
+    // `Client::peer_address` reports the one chosen. The backend is then
+    // given the address rather than the name, so a WebSocket's Host header
+    // carries the address, and TLS, which needs the name to verify the
+    // broker, cannot be used: connecting fails with `Error::InvalidOptions`.
+    // Unset, the backend resolves the host, and Paho prefers an IPv4
+    // address when there is one. Ignored in the browser, which races the
+    // addresses itself.
+    pub fn with_address_family(mut self, family: AddressFamily) -> Self {
+        self.address_family = Some(family);
+        self
+    }
+
+    // Resolves broker hosts with `resolver` instead of the system resolver,
+    // for service discovery or static maps. The addresses it returns are
+    // raced as with `with_address_family`, which defaults to
+    // `AddressFamily::Any`, with their own ports: return the port given to
+    // `connect` to keep it. Hosts that are addresses are not resolved. As
+    // with `with_address_family`, TLS cannot be used with it. Ignored in the
+    // browser.
+    pub fn with_resolver(
+        mut self,
+        resolver: impl Fn(&str) -> Vec<SocketAddr> + Send + Sync + 'static,
+    ) -> Self {
+        self.resolver = Some(Resolver(Arc::new(resolver)));
+        self
+    }
+
+    // Last will, published by the broker if the connection drops without a
+    // clean disconnect.
+    pub fn with_will(mut self, will: Message) -> Self {
+        self.will = Some(will);
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    // Connects over WebSocket, requesting `path` (usually `/mqtt`).
+    pub fn with_websocket(mut self, path: impl Into<String>) -> Self {
+        self.websocket = Some(path.into());
+        self
+    }
+
+    // Largest PUBLISH packet, header and topic included, that `publish`
+    // sends; larger ones fail with `Error::PacketTooLarge`. The broker's
+    // own maximum, from `with_broker_limits`, applies as well, if lower. It
+    // is not read from the CONNACK: every backend connects with MQTT 3.1.1,
+    // which does not advertise one, so it must be configured.
+    pub fn with_max_packet_size(mut self, bytes: u32) -> Self {
+        self.max_packet_size = Some(bytes);
+        self
+    }
+
+    // What the broker accepts, from its configuration; requests beyond it
+    // fail without being sent. See `BrokerLimits`.
+    pub fn with_broker_limits(mut self, limits: BrokerLimits) -> Self {
+        self.broker_limits = Some(limits);
+        self
+    }
+
+    // Puts every topic the client publishes or subscribes to, the will's
+    // included, under `namespace` (`tenants/42` or `tenants/42/`), and takes
+    // it off the topics of the messages delivered. Messages on topics
+    // outside it are dropped. Which topics a client reaches is then decided
+    // in one place, rather than by every caller building topics right.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    // Pacing of failover reconnects, see `Client::connect_brokers`.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive
+    }
+
+    pub fn max_inflight(&self) -> Option<u16> {
+        self.max_inflight
+    }
+
+    pub fn max_packet_size(&self) -> Option<u32> {
+        self.max_packet_size
+    }
+
+    pub fn broker_limits(&self) -> Option<&BrokerLimits> {
+        self.broker_limits.as_ref()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub fn tcp_keepalive(&self) -> Option<TcpKeepalive> {
+        self.tcp_keepalive
+    }
+
+    pub fn tcp_nodelay(&self) -> Option<bool> {
+        self.tcp_nodelay
+    }
+
+    pub fn send_buffer_size(&self) -> Option<u32> {
+        self.send_buffer_size
+    }
+
+    pub fn receive_buffer_size(&self) -> Option<u32> {
+        self.receive_buffer_size
+    }
+
+    pub fn address_family(&self) -> Option<AddressFamily> {
+        self.address_family
+    }
+
+    pub fn will(&self) -> Option<&Message> {
+        self.will.as_ref()
+    }
+
+    pub fn tls(&self) -> Option<&TlsOptions> {
+        self.tls.as_ref()
+    }
+
+    pub fn websocket(&self) -> Option<&str> {
+        self.websocket.as_deref()
+    }
+
+    pub fn backoff(&self) -> Option<Backoff> {
+        self.backoff
+    }
+}
+
+// The bridge takes whole seconds as i32; round sub-second values up so a
+// non-zero duration never turns into "disabled".
+pub(crate) fn duration_secs(duration: Duration) -> i32 {
+    let secs = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    secs.min(i32::MAX as u64) as i32
+}
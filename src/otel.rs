@@ -0,0 +1,111 @@
+use crate::envelope::{Envelope, MAGIC};
+use crate::error::Result;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use std::str::FromStr;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// Envelope properties carrying the W3C trace context, named after its
+// headers.
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+// The payload with the current span's context added, or None outside a
+// span known to OpenTelemetry.
+pub(crate) fn inject(payload: &[u8]) -> Result<Option<Vec<u8>>> {
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return Ok(None);
+    }
+    let mut properties = vec![(TRACEPARENT, traceparent(span_context))];
+    let state = span_context.trace_state().header();
+    if !state.is_empty() {
+        properties.push((TRACESTATE, state));
+    }
+    Envelope::stamp(payload, properties).map(Some)
+}
+
+// Links `span` to the producer's span, if the payload carries its context.
+pub(crate) fn link(span: &Span, payload: &[u8]) {
+    if !payload.starts_with(&MAGIC) {
+        return;
+    }
+    let Ok(envelope) = Envelope::decode(payload) else {
+        return;
+    };
+    let remote = envelope
+        .property(TRACEPARENT)
+        .and_then(|value| parse_traceparent(value, envelope.property(TRACESTATE)));
+    if let Some(remote) = remote {
+        span.add_link(remote);
+    }
+}
+
+fn traceparent(span_context: &SpanContext) -> String {
+    format!(
+        "00-{}-{}-{:02x}",
+        span_context.trace_id(),
+        span_context.span_id(),
+        span_context.trace_flags().to_u8()
+    )
+}
+
+// Version 00 of the `traceparent` header; None if malformed or all zeros.
+pub fn parse_traceparent(value: &str, state: Option<&str>) -> Option<SpanContext> {
+    let mut parts = value.split('-');
+    let (version, trace_id, span_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if version != "00"
+        || parts.next().is_some()
+        || trace_id.len() != 32
+        || span_id.len() != 16
+        || flags.len() != 2
+    {
+        return None;
+    }
+    let span_context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        TraceFlags::new(u8::from_str_radix(flags, 16).ok()?),
+        true,
+        state
+            .and_then(|state| TraceState::from_str(state).ok())
+            .unwrap_or_default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_roundtrip() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let span_context = parse_traceparent(value, Some("vendor=abc")).unwrap();
+        assert!(span_context.is_remote());
+        assert!(span_context.is_sampled());
+        assert_eq!(span_context.trace_state().get("vendor"), Some("abc"));
+        assert_eq!(traceparent(&span_context), value);
+
+        assert!(parse_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            None
+        )
+        .is_none());
+        assert!(parse_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            None
+        )
+        .is_none());
+        assert!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7", None)
+                .is_none()
+        );
+
+        // Outside any span there is nothing to propagate.
+        assert_eq!(inject(b"21").unwrap(), None);
+    }
+}
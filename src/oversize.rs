@@ -0,0 +1,194 @@
+use crate::types::QoS;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+pub type OversizeHandler = dyn Fn(&mut OversizedMessage<'_>) + Send + Sync;
+
+// What to do with an incoming message whose payload is over the limit.
+// Whatever the policy, such messages are counted in the oversized metric.
+#[derive(Clone)]
+pub enum OversizePolicy {
+    Drop,
+    // Dispatch the first `max_size` bytes as if they were the whole payload.
+    Truncate,
+    // Pass it to this handler instead of the regular callbacks.
+    Handler(Arc<OversizeHandler>),
+}
+
+// Maximum size of incoming payloads, set with `Client::set_payload_limit`.
+pub struct PayloadLimit {
+    pub(crate) max_size: usize,
+    pub(crate) policy: OversizePolicy,
+}
+
+impl PayloadLimit {
+    // Drops payloads larger than `max_size` bytes.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            policy: OversizePolicy::Drop,
+        }
+    }
+
+    // Streams payloads over `threshold` bytes to `handler` rather than
+    // dispatching them as one slice. Neither the bridge nor the client
+    // copies the payload on the way, so a huge retained blob costs no
+    // allocation beyond Paho's own receive buffer.
+    pub fn streamed<F>(threshold: usize, handler: F) -> Self
+    where
+        F: Fn(&mut OversizedMessage<'_>) + Send + Sync + 'static,
+    {
+        Self::new(threshold).with_policy(OversizePolicy::Handler(Arc::new(handler)))
+    }
+
+    pub fn with_policy(mut self, policy: OversizePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+// A message over the payload limit, given to an `OversizePolicy::Handler`.
+// The payload is read incrementally, with `Read` or in chunks, straight
+// from the receive buffer, so that it never has to be copied whole.
+pub struct OversizedMessage<'a> {
+    topic: &'a str,
+    payload: &'a [u8],
+    qos: QoS,
+    retained: bool,
+    position: usize,
+}
+
+impl<'a> OversizedMessage<'a> {
+    pub(crate) fn new(topic: &'a str, payload: &'a [u8], qos: QoS, retained: bool) -> Self {
+        Self {
+            topic,
+            payload,
+            qos,
+            retained,
+            position: 0,
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        self.topic
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+
+    pub fn retained(&self) -> bool {
+        self.retained
+    }
+
+    // Full payload length, in bytes.
+    pub fn len(&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    // The payload in pieces of at most `size` bytes, from the start
+    // regardless of what was already read.
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = &'a [u8]> {
+        self.payload.chunks(size.max(1))
+    }
+}
+
+impl Read for OversizedMessage<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.payload[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use crate::client::Client;
+    use crate::message::{Message, MessageView};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_oversized_message_reads_incrementally() {
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut msg = OversizedMessage::new("big/blob", &payload, QoS::AtLeastOnce, false);
+        assert_eq!(
+            msg.chunks(100).map(<[u8]>::len).collect::<Vec<_>>(),
+            [100, 100, 56]
+        );
+
+        let mut head = [0u8; 10];
+        msg.read_exact(&mut head).unwrap();
+        assert_eq!(head[9], 9);
+        let mut rest = Vec::new();
+        msg.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest.len(), 246);
+        assert_eq!(rest[0], 10);
+    }
+
+    #[test]
+    fn test_large_payloads_are_streamed_to_the_handler() {
+        let fake = Arc::new(Fake::default());
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let client = {
+            let delivered = delivered.clone();
+            let on_message = move |msg: &MessageView| {
+                delivered.lock().unwrap().push(msg.payload().to_vec());
+            };
+            Client::with_backend("oversize", fake.clone(), on_message, |_| {}, |_, _| {})
+        };
+        let client = client.unwrap();
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("big/#", QoS::AtLeastOnce).unwrap();
+        let streamed = Arc::new(Mutex::new(Vec::new()));
+        let seen = streamed.clone();
+        client.set_payload_limit(Some(PayloadLimit::streamed(8, move |msg| {
+            assert_eq!((msg.topic(), msg.qos()), ("big/blob", QoS::AtLeastOnce));
+            let mut payload = Vec::new();
+            msg.read_to_end(&mut payload).unwrap();
+            seen.lock().unwrap().push(payload);
+        })));
+
+        let blob: Vec<u8> = (0..100).collect();
+        fake.inject(Message::new("big/small", "12345678").unwrap());
+        fake.inject(
+            Message::new("big/blob", blob.clone())
+                .unwrap()
+                .with_qos(QoS::AtLeastOnce),
+        );
+        assert_eq!(*delivered.lock().unwrap(), [b"12345678".to_vec()]);
+        assert_eq!(*streamed.lock().unwrap(), [blob]);
+    }
+
+    #[test]
+    fn test_handler_may_replace_the_limit() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("oversize", fake.clone(), |_| {}, |_| {}, |_, _| {});
+        let client = Arc::new(client.unwrap());
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("big/#", QoS::AtMostOnce).unwrap();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let (count, weak) = (seen.clone(), Arc::downgrade(&client));
+        client.set_payload_limit(Some(PayloadLimit::streamed(4, move |msg| {
+            count.fetch_add(msg.len(), Ordering::Relaxed);
+            // Deadlocked while the client held the limit during the call.
+            if let Some(client) = weak.upgrade() {
+                client.set_payload_limit(None);
+            }
+        })));
+
+        fake.inject(Message::new("big/1", "0123456789").unwrap());
+        fake.inject(Message::new("big/2", "0123456789").unwrap());
+        assert_eq!(seen.load(Ordering::Relaxed), 10);
+    }
+}
@@ -0,0 +1,312 @@
+#[cfg(native_bridge)]
+use crate::bindings;
+use crate::error::Result;
+use crate::types::QoS;
+#[cfg(native_bridge)]
+use std::ffi::{c_char, c_void, CStr};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+#[cfg(native_bridge)]
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+// One MQTT control packet, as described by Paho's protocol trace. Paho does
+// not trace whole packets: fields it leaves out are None, and the payload
+// is only seen through its length and first bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub direction: PacketDirection,
+    // CONNECT, PUBLISH, PUBACK, SUBSCRIBE, PINGREQ, ...
+    pub kind: String,
+    pub client_id: String,
+    pub message_id: Option<u16>,
+    pub qos: Option<QoS>,
+    pub retained: Option<bool>,
+    pub payload_len: Option<usize>,
+    // At most the first 20 bytes of the payload, as Paho wrote them.
+    pub preview: Vec<u8>,
+    // The trace line itself, for what the fields above leave out.
+    pub line: String,
+}
+
+impl Packet {
+    // Parses a protocol trace line such as
+    // `3 sensor-1 -> PUBLISH msgid: 7 qos: 1 retained: 0 rc 0 payload len(5): hello`.
+    // None if the line does not describe a packet.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (before, after, direction) = match (line.find(" -> "), line.find(" <- ")) {
+            (Some(sent), Some(received)) if received < sent => (
+                &line[..received],
+                &line[received + 4..],
+                PacketDirection::Received,
+            ),
+            (Some(sent), _) => (&line[..sent], &line[sent + 4..], PacketDirection::Sent),
+            (None, Some(received)) => (
+                &line[..received],
+                &line[received + 4..],
+                PacketDirection::Received,
+            ),
+            (None, None) => return None,
+        };
+        let client_id = before.split_whitespace().last().unwrap_or_default();
+        let (fields, preview) = match after.find("payload len(") {
+            Some(at) => (&after[..at], Some(&after[at + 12..])),
+            None => (after, None),
+        };
+        let mut tokens = fields.split_whitespace();
+        let kind = tokens.next()?;
+        if !kind.bytes().all(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+
+        let mut packet = Packet {
+            direction,
+            kind: kind.to_string(),
+            client_id: client_id.to_string(),
+            message_id: None,
+            qos: None,
+            retained: None,
+            payload_len: None,
+            preview: Vec::new(),
+            line: line.to_string(),
+        };
+        while let Some(token) = tokens.next() {
+            match token {
+                "msgid:" => packet.message_id = tokens.next().and_then(|v| v.parse().ok()),
+                "qos:" => {
+                    packet.qos = match tokens.next() {
+                        Some("0") => Some(QoS::AtMostOnce),
+                        Some("1") => Some(QoS::AtLeastOnce),
+                        Some("2") => Some(QoS::ExactlyOnce),
+                        _ => None,
+                    }
+                }
+                "retained:" => packet.retained = tokens.next().map(|v| v != "0"),
+                _ => {}
+            }
+        }
+        if let Some((len, rest)) = preview.and_then(|p| p.split_once(')')) {
+            packet.payload_len = len.parse().ok();
+            let bytes = rest.strip_prefix(": ").unwrap_or(rest).as_bytes();
+            let shown = packet.payload_len.unwrap_or(0).min(20).min(bytes.len());
+            packet.preview = bytes[..shown].to_vec();
+        }
+        Some(packet)
+    }
+}
+
+// `-> PUBLISH sensor-1 msgid=7 qos=1 retained=0 len=5 68656c6c6f`
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = match self.direction {
+            PacketDirection::Sent => "->",
+            PacketDirection::Received => "<-",
+        };
+        write!(f, "{} {} {}", arrow, self.kind, self.client_id)?;
+        if let Some(id) = self.message_id {
+            write!(f, " msgid={}", id)?;
+        }
+        if let Some(qos) = self.qos {
+            write!(f, " qos={}", qos as i32)?;
+        }
+        if let Some(retained) = self.retained {
+            write!(f, " retained={}", retained as u8)?;
+        }
+        if let Some(len) = self.payload_len {
+            write!(f, " len={} ", len)?;
+            for byte in &self.preview {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type Tap = Box<dyn Fn(&Packet) + Send + Sync>;
+
+// The active packet log, with the id of the handle that started it.
+static TAP: Mutex<Option<(u64, Arc<Tap>)>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// A debug dump of every MQTT control packet any client in the process sends
+// or receives, for diagnosing interop problems with odd brokers. Packets go
+// to a file, to `tracing` (target `polar_mqtt::packets`, at DEBUG) or to a
+// callback, whichever are configured, until the handle is dropped. Tracing
+// costs a formatted line per packet inside Paho, so this is not for
+// production use.
+//
+// The trace is process-wide: starting a second log replaces the first.
+// With `backend-rust`, and on wasm32, there is no Paho trace, so nothing is
+// logged.
+#[derive(Default)]
+pub struct PacketLog {
+    file: Option<PathBuf>,
+    #[cfg(feature = "tracing")]
+    tracing: bool,
+    callback: Option<Tap>,
+}
+
+impl PacketLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Appends one line per packet to `path`.
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    #[cfg(feature = "tracing")]
+    pub fn with_tracing(mut self) -> Self {
+        self.tracing = true;
+        self
+    }
+
+    // `callback` runs on Paho's threads and must not block.
+    pub fn with_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Packet) + Send + Sync + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn start(self) -> Result<PacketLogHandle> {
+        let file = match &self.file {
+            Some(path) => Some(Mutex::new(LineWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            None => None,
+        };
+        #[cfg(feature = "tracing")]
+        let tracing = self.tracing;
+        let callback = self.callback;
+        let tap: Tap = Box::new(move |packet: &Packet| {
+            if let Some(file) = &file {
+                let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+                let _ = writeln!(file, "{}", packet);
+            }
+            #[cfg(feature = "tracing")]
+            if tracing {
+                tracing::debug!(target: "polar_mqtt::packets", "{}", packet);
+            }
+            if let Some(callback) = &callback {
+                callback(packet);
+            }
+        });
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        *TAP.lock().unwrap_or_else(PoisonError::into_inner) = Some((id, Arc::new(tap)));
+        // Not under the lock: the bridge calls back holding its own.
+        set_packet_callback(true);
+        Ok(PacketLogHandle { id })
+    }
+}
+
+// Stops the packet log when dropped, unless another was started since.
+pub struct PacketLogHandle {
+    id: u64,
+}
+
+impl Drop for PacketLogHandle {
+    fn drop(&mut self) {
+        let stopped = {
+            let mut tap = TAP.lock().unwrap_or_else(PoisonError::into_inner);
+            match &*tap {
+                Some((id, _)) if *id == self.id => tap.take().is_some(),
+                _ => false,
+            }
+        };
+        if stopped {
+            set_packet_callback(false);
+        }
+    }
+}
+
+#[cfg(native_bridge)]
+fn set_packet_callback(on: bool) {
+    let callback: bindings::mqtt_packet_callback_t = match on {
+        true => Some(packet_callback),
+        false => None,
+    };
+    unsafe {
+        bindings::mqtt_set_packet_callback(callback, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(native_bridge))]
+fn set_packet_callback(_on: bool) {}
+
+#[cfg(native_bridge)]
+unsafe extern "C" fn packet_callback(line: *const c_char, _context: *mut c_void) {
+    if line.is_null() {
+        return;
+    }
+    let line = CStr::from_ptr(line).to_string_lossy();
+    let Some(packet) = Packet::parse(&line) else {
+        return;
+    };
+    let tap = TAP
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(|(_, tap)| tap.clone());
+    if let Some(tap) = tap {
+        // Unwinding into the bridge is undefined behaviour.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| tap(&packet)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_trace_lines() {
+        let publish = Packet::parse(
+            "20241016 101500.123 3 sensor-1 -> PUBLISH msgid: 7 qos: 1 retained: 0 rc 0 payload len(5): hello",
+        )
+        .unwrap();
+        assert_eq!(publish.direction, PacketDirection::Sent);
+        assert_eq!(publish.kind, "PUBLISH");
+        assert_eq!(publish.client_id, "sensor-1");
+        assert_eq!(publish.message_id, Some(7));
+        assert_eq!(publish.qos, Some(QoS::AtLeastOnce));
+        assert_eq!(publish.retained, Some(false));
+        assert_eq!(publish.payload_len, Some(5));
+        assert_eq!(publish.preview, b"hello");
+        assert_eq!(
+            publish.to_string(),
+            "-> PUBLISH sensor-1 msgid=7 qos=1 retained=0 len=5 68656c6c6f"
+        );
+
+        // Paho shows at most 20 bytes of a payload, which may contain " <- ".
+        let long = Packet::parse(
+            "3 sensor-1 <- PUBLISH msgid: 0 qos: 0 retained: 1 payload len(64): a <- b 0123456789abcdefghij",
+        )
+        .unwrap();
+        assert_eq!(long.direction, PacketDirection::Received);
+        assert_eq!(long.retained, Some(true));
+        assert_eq!(long.preview, b"a <- b 0123456789abc");
+
+        let ack = Packet::parse("3 sensor-1 <- PUBACK msgid: 7").unwrap();
+        assert_eq!(ack.kind, "PUBACK");
+        assert_eq!(ack.message_id, Some(7));
+        assert_eq!(ack.payload_len, None);
+        assert_eq!(ack.to_string(), "<- PUBACK sensor-1 msgid=7");
+
+        assert!(Packet::parse("3 sensor-1 -> PINGREQ (0)").is_some());
+        assert!(Packet::parse("Connecting to tcp://localhost:1883").is_none());
+        assert!(Packet::parse("x -> not a packet").is_none());
+    }
+}
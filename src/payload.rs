@@ -0,0 +1,47 @@
+use crate::error::{Error, Result};
+
+// Conversion from a raw payload, used by typed handlers to decode their
+// argument.
+pub trait FromPayload: Sized {
+    fn from_payload(payload: &[u8]) -> Result<Self>;
+}
+
+impl FromPayload for Vec<u8> {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        Ok(payload.to_vec())
+    }
+}
+
+impl FromPayload for String {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        String::from_utf8(payload.to_vec()).map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+}
+
+// A payload decoded from protobuf.
+#[cfg(feature = "prost")]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Proto<M>(pub M);
+
+#[cfg(feature = "prost")]
+impl<M: prost::Message + Default> FromPayload for Proto<M> {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        M::decode(payload)
+            .map(Proto)
+            .map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+}
+
+// A payload decoded from JSON.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: serde::de::DeserializeOwned> FromPayload for Json<T> {
+    fn from_payload(payload: &[u8]) -> Result<Self> {
+        serde_json::from_slice(payload)
+            .map(Json)
+            .map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(test)]
+use crate::backend::Backend;
+use crate::client::Client;
+use crate::client_id::ClientId;
+use crate::error::Result;
+use crate::message::{Message, MessageView};
+use crate::options::ConnectOptions;
+use crate::shard;
+use crate::time::Instant;
+use crate::types::{ConnectionState, ShutdownReport};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How a `ClientPool` picks the session for a publish.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Distribution {
+    // Each session in turn. Messages on one topic may overtake each other.
+    #[default]
+    RoundRobin,
+    // By hash of the topic, which keeps each topic on one session and so
+    // in order, at the cost of an uneven spread over few topics.
+    TopicHash,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub sessions: usize,
+    pub connected: usize,
+    // Publishes accepted by each session, in session order.
+    pub published: Vec<u64>,
+    pub publish_errors: u64,
+    // Summed over the sessions, see the `Client` methods of the same name.
+    pub messages_dropped: u64,
+    pub messages_expired: u64,
+    pub duplicates_dropped: u64,
+    pub callback_panics: u64,
+}
+
+impl PoolStats {
+    pub fn total_published(&self) -> u64 {
+        self.published.iter().sum()
+    }
+}
+
+// Several sessions to one broker, for publishers whose throughput is
+// bounded by a single session's inflight window. Session `i` connects as
+// `<client id>-<i>`, and publishes are spread over the sessions by the
+// pool's `Distribution`. Messages received by any session go to the one
+// message callback; subscribe through a single session, with `client`, as
+// each would receive its own copy.
+pub struct ClientPool {
+    clients: Vec<Client>,
+    distribution: Distribution,
+    next: AtomicUsize,
+    published: Vec<AtomicU64>,
+    publish_errors: AtomicU64,
+}
+
+type PoolStateCallback = dyn Fn(usize, ConnectionState) + Send + Sync;
+type PoolErrorCallback = dyn Fn(usize, i32, &str) + Send + Sync;
+
+impl ClientPool {
+    // `size` sessions, at least one. The state and error callbacks are
+    // given the index of the session.
+    pub fn new<F1, F2, F3>(
+        client_id: impl Into<ClientId>,
+        size: usize,
+        on_message: F1,
+        on_state_change: F2,
+        on_error: F3,
+    ) -> Result<Self>
+    where
+        F1: Fn(&MessageView) + Send + Sync + 'static,
+        F2: Fn(usize, ConnectionState) + Send + Sync + 'static,
+        F3: Fn(usize, i32, &str) + Send + Sync + 'static,
+    {
+        Self::build(
+            client_id.into(),
+            size,
+            Arc::new(on_message),
+            Arc::new(on_state_change),
+            Arc::new(on_error),
+            |id, on_message, on_state_change, on_error| {
+                Client::new(id, on_message, on_state_change, on_error)
+            },
+        )
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_backend<F1>(
+        client_id: impl Into<ClientId>,
+        size: usize,
+        backend: Arc<dyn Backend>,
+        on_message: F1,
+    ) -> Result<Self>
+    where
+        F1: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        Self::build(
+            client_id.into(),
+            size,
+            Arc::new(on_message),
+            Arc::new(|_, _| {}),
+            Arc::new(|_, _, _| {}),
+            |id, on_message, on_state_change, on_error| {
+                Client::with_backend(id, backend.clone(), on_message, on_state_change, on_error)
+            },
+        )
+    }
+
+    fn build<F>(
+        client_id: ClientId,
+        size: usize,
+        on_message: Arc<dyn Fn(&MessageView) + Send + Sync>,
+        on_state_change: Arc<PoolStateCallback>,
+        on_error: Arc<PoolErrorCallback>,
+        new_client: F,
+    ) -> Result<Self>
+    where
+        F: Fn(
+            ClientId,
+            Box<dyn Fn(&MessageView) + Send + Sync>,
+            Box<dyn Fn(ConnectionState) + Send + Sync>,
+            Box<dyn Fn(i32, &str) + Send + Sync>,
+        ) -> Result<Client>,
+    {
+        let clients = (0..size.max(1))
+            .map(|index| {
+                let on_message = on_message.clone();
+                let on_state_change = on_state_change.clone();
+                let on_error = on_error.clone();
+                new_client(
+                    client_id.numbered(index),
+                    Box::new(move |msg| on_message(msg)),
+                    Box::new(move |state| on_state_change(index, state)),
+                    Box::new(move |code, message| on_error(index, code, message)),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            published: clients.iter().map(|_| AtomicU64::new(0)).collect(),
+            clients,
+            distribution: Distribution::default(),
+            next: AtomicUsize::new(0),
+            publish_errors: AtomicU64::new(0),
+        })
+    }
+
+    pub fn with_distribution(mut self, distribution: Distribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn client(&self, index: usize) -> Option<&Client> {
+        self.clients.get(index)
+    }
+
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> Result<()> {
+        self.connect_with(host, port, &ConnectOptions::default())
+    }
+
+    // Connects every session, stopping at the first that fails; those
+    // connected before it stay connected.
+    pub fn connect_with(&self, host: &str, port: u16, options: &ConnectOptions) -> Result<()> {
+        self.clients
+            .iter()
+            .try_for_each(|client| client.connect_with(host, port, options))
+    }
+
+    // Publishes on the session the distribution picks, returning its index
+    // along with the message id, which is only unique per session.
+    pub fn publish(&self, message: &Message) -> Result<(usize, i64)> {
+        let index = self.session_for(message.topic());
+        match self.clients[index].publish(message) {
+            Ok(id) => {
+                self.published[index].fetch_add(1, Ordering::Relaxed);
+                Ok((index, id))
+            }
+            Err(error) => {
+                self.publish_errors.fetch_add(1, Ordering::Relaxed);
+                Err(error)
+            }
+        }
+    }
+
+    // The session a publish to `topic` would go to. With round-robin,
+    // each call moves on to the next session.
+    pub fn session_for(&self, topic: &str) -> usize {
+        match self.distribution {
+            Distribution::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            Distribution::TopicHash => shard::hash(topic.as_bytes()) as usize,
+        }
+        .wrapping_rem(self.clients.len())
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let sum = |stat: fn(&Client) -> u64| self.clients.iter().map(stat).sum();
+        PoolStats {
+            sessions: self.clients.len(),
+            connected: self
+                .clients
+                .iter()
+                .filter(|client| client.state() == ConnectionState::Connected)
+                .count(),
+            published: self
+                .published
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            publish_errors: self.publish_errors.load(Ordering::Relaxed),
+            messages_dropped: sum(Client::messages_dropped),
+            messages_expired: sum(Client::messages_expired),
+            duplicates_dropped: sum(Client::duplicates_dropped),
+            callback_panics: sum(Client::callback_panics),
+        }
+    }
+
+    // Disconnects every session, returning the first error.
+    pub fn disconnect(&self) -> Result<()> {
+        let mut result = Ok(());
+        for client in &self.clients {
+            let disconnected = client.disconnect();
+            result = result.and(disconnected);
+        }
+        result
+    }
+
+    // Shuts the sessions down in order, within `flush_timeout` overall.
+    pub fn shutdown(self, flush_timeout: Duration) -> Vec<ShutdownReport> {
+        let deadline = Instant::now() + flush_timeout;
+        self.clients
+            .into_iter()
+            .map(|client| client.shutdown(deadline.saturating_duration_since(Instant::now())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+
+    #[test]
+    fn test_publishes_are_spread_over_the_sessions() {
+        let fake = Arc::new(Fake::default());
+        let pool = ClientPool::with_backend("pool", 3, fake.clone(), |_| {}).unwrap();
+        assert_eq!(pool.clients()[2].client_id(), "pool-2");
+        pool.connect("broker", 1883).unwrap();
+
+        for _ in 0..6 {
+            pool.publish(&Message::new("a/1", "x").unwrap()).unwrap();
+        }
+        assert_eq!(pool.stats().published, [2, 2, 2]);
+
+        let pool = pool.with_distribution(Distribution::TopicHash);
+        let session = pool.session_for("b/1");
+        for _ in 0..3 {
+            assert_eq!(
+                pool.publish(&Message::new("b/1", "y").unwrap()).unwrap().0,
+                session
+            );
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.connected, 3);
+        assert_eq!(stats.published[session], 5);
+        assert_eq!(stats.total_published(), 9);
+        assert_eq!(fake.published().len(), 9);
+
+        let reports = pool.shutdown(Duration::from_secs(1));
+        assert!(reports
+            .iter()
+            .all(|report| report.final_state == ConnectionState::Disconnected));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::client::Client;
+use crate::client_id::{ClientId, ClientIdSuffix};
+use crate::error::Result;
+use crate::options::{Backoff, ConnectOptions};
+use crate::uri::BrokerUri;
+use std::time::Duration;
+
+// A broker environment: where to connect, how, and how to name clients.
+// Presets cover the public test brokers; define your own for staging or
+// production and keep connection details out of application code.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    name: String,
+    uri: BrokerUri,
+    options: ConnectOptions,
+    unique_ids: bool,
+}
+
+impl Profile {
+    pub fn new(name: impl Into<String>, uri: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.into(),
+            uri: uri.parse()?,
+            options: ConnectOptions::default(),
+            unique_ids: false,
+        })
+    }
+
+    pub fn with_options(mut self, options: ConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Suffix client ids with the host name and process id, as anonymous
+    // clients on a shared broker must not take over each other's sessions.
+    pub fn with_unique_ids(mut self, unique_ids: bool) -> Self {
+        self.unique_ids = unique_ids;
+        self
+    }
+
+    // test.mosquitto.org, plain TCP.
+    pub fn mosquitto_test() -> Self {
+        Self::public("mosquitto-test", "tcp://test.mosquitto.org:1883")
+    }
+
+    // broker.emqx.io, plain TCP.
+    pub fn emqx_public() -> Self {
+        Self::public("emqx-public", "tcp://broker.emqx.io:1883")
+    }
+
+    // broker.hivemq.com, plain TCP.
+    pub fn hivemq_public() -> Self {
+        Self::public("hivemq-public", "tcp://broker.hivemq.com:1883")
+    }
+
+    // Shared, rate-limited and sometimes slow to answer: a short keep-alive
+    // notices dead connections, a longer backoff goes easy on the broker.
+    fn public(name: &str, uri: &str) -> Self {
+        Self::new(name, uri)
+            .expect("preset URIs are valid")
+            .with_options(
+                ConnectOptions::new()
+                    .with_keep_alive(Duration::from_secs(30))
+                    .with_backoff(Backoff::new(
+                        Duration::from_secs(2),
+                        Duration::from_secs(60),
+                    )),
+            )
+            .with_unique_ids(true)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn uri(&self) -> &BrokerUri {
+        &self.uri
+    }
+
+    pub fn options(&self) -> &ConnectOptions {
+        &self.options
+    }
+
+    // The id to create a client with for this profile.
+    pub fn client_id(&self, base: impl Into<String>) -> ClientId {
+        let id = ClientId::new(base);
+        match self.unique_ids {
+            true => id
+                .with_suffix(ClientIdSuffix::Hostname)
+                .with_suffix(ClientIdSuffix::Pid),
+            false => id,
+        }
+    }
+
+    pub fn connect(&self, client: &Client) -> Result<()> {
+        client.connect_with(
+            &self.uri.host,
+            self.uri.port,
+            &self.uri.options(&self.options),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uri::Transport;
+
+    #[test]
+    fn test_presets_and_custom_profiles() {
+        let profile = Profile::hivemq_public();
+        assert_eq!(profile.uri().to_string(), "tcp://broker.hivemq.com:1883");
+        assert_eq!(
+            profile.options().keep_alive(),
+            Some(Duration::from_secs(30))
+        );
+        assert!(profile.client_id("demo").resolve().id.starts_with("demo-"));
+
+        let profile = Profile::new("staging", "wss://mqtt.staging.example.com").unwrap();
+        assert_eq!(profile.uri().transport, Transport::SecureWebSocket);
+        assert_eq!(profile.client_id("demo").resolve().id, "demo");
+        assert!(Profile::new("bad", "broker:1883").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::envelope::Envelope;
+use crate::error::Result;
+
+pub const CLIENT_ID: &str = "producer-client-id";
+pub const HOSTNAME: &str = "producer-hostname";
+pub const APP_NAME: &str = "producer-app-name";
+pub const APP_VERSION: &str = "producer-app-version";
+
+// Who produced a message: the publishing client, its host and the
+// application named in its `InitOptions`. Attached by clients with
+// `set_provenance` enabled, as envelope properties (see `Envelope`), and
+// read back with `MessageView::provenance`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub client_id: String,
+    pub hostname: String,
+    pub app_name: String,
+    pub app_version: String,
+}
+
+impl Provenance {
+    // Payloads that are already envelopes gain the properties; anything else
+    // is wrapped in a new one.
+    pub(crate) fn stamp(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        Envelope::stamp(
+            payload,
+            [
+                (CLIENT_ID, self.client_id.as_str()),
+                (HOSTNAME, self.hostname.as_str()),
+                (APP_NAME, self.app_name.as_str()),
+                (APP_VERSION, self.app_version.as_str()),
+            ],
+        )
+    }
+
+    // None unless the payload is an envelope naming at least the producing
+    // client.
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        let envelope = Envelope::decode(payload).ok()?;
+        let property = |key| envelope.property(key).unwrap_or_default().to_string();
+        Some(Self {
+            client_id: envelope.property(CLIENT_ID)?.to_string(),
+            hostname: property(HOSTNAME),
+            app_name: property(APP_NAME),
+            app_version: property(APP_VERSION),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamp_and_read_back() {
+        let provenance = Provenance {
+            client_id: "sensor-1".to_string(),
+            hostname: "edge-7".to_string(),
+            app_name: "probe".to_string(),
+            app_version: "2.1".to_string(),
+        };
+        let stamped = provenance.stamp(b"21.5").unwrap();
+        assert_eq!(Provenance::from_payload(&stamped), Some(provenance.clone()));
+        assert_eq!(Envelope::decode(&stamped).unwrap().payload, b"21.5");
+
+        // Existing envelopes keep their content type and payload.
+        let inner = Envelope::new("application/json", b"{}".to_vec())
+            .with_schema_version(4)
+            .encode()
+            .unwrap();
+        let envelope = Envelope::decode(&provenance.stamp(&inner).unwrap()).unwrap();
+        assert_eq!(
+            (envelope.content_type.as_str(), envelope.schema_version),
+            ("application/json", 4)
+        );
+        assert_eq!(envelope.property(HOSTNAME), Some("edge-7"));
+
+        assert_eq!(Provenance::from_payload(b"21.5"), None);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::cancel::CancellationToken;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::time::Instant;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// What `publish` does when the budget is used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverLimit {
+    // Wait for the budget.
+    Block,
+    // Fail with `Error::RateLimited`.
+    Error,
+    // Hold up to this many messages and publish them from a background
+    // thread as the budget allows. Publishing fails once the queue is full.
+    //
+    // In the browser, `Block` and `Queue` act as `Error`.
+    Queue(usize),
+}
+
+// Token buckets for outgoing messages and payload bytes, refilled at a
+// steady rate and holding at most one second's worth, so bursts are capped
+// at the per-second limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    messages_per_sec: Option<f64>,
+    bytes_per_sec: Option<f64>,
+    over_limit: OverLimit,
+    cancel: Option<CancellationToken>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: None,
+            bytes_per_sec: None,
+            over_limit: OverLimit::Block,
+            cancel: None,
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fails unless `rate` is finite and positive.
+    pub fn with_messages_per_sec(mut self, rate: f64) -> Result<Self> {
+        self.messages_per_sec = Some(validate_rate(rate)?);
+        Ok(self)
+    }
+
+    // Fails unless `rate` is finite and positive.
+    pub fn with_bytes_per_sec(mut self, rate: f64) -> Result<Self> {
+        self.bytes_per_sec = Some(validate_rate(rate)?);
+        Ok(self)
+    }
+
+    pub fn with_over_limit(mut self, over_limit: OverLimit) -> Self {
+        self.over_limit = over_limit;
+        self
+    }
+
+    // In `OverLimit::Block` mode, publishes waiting for the budget fail with
+    // `Error::Cancelled` once `cancel` is cancelled.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn over_limit(&self) -> OverLimit {
+        self.over_limit
+    }
+}
+
+fn validate_rate(rate: f64) -> Result<f64> {
+    match rate.is_finite() && rate > 0.0 {
+        true => Ok(rate),
+        false => Err(Error::InvalidOptions(format!("rate limit of {}", rate))),
+    }
+}
+
+// The longest a publisher sleeps before checking the budget again, however
+// small the rate.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        Self { rate, tokens: rate }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+    }
+
+    // Costs over the capacity are let through when the bucket is full,
+    // rather than never.
+    fn wait_for(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.rate) - self.tokens;
+        match missing > 0.0 {
+            true => Duration::try_from_secs_f64(missing / self.rate)
+                .map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT)),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+struct LimiterState {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    refilled: Instant,
+    queue: VecDeque<Message>,
+    stopped: bool,
+}
+
+impl LimiterState {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.refilled;
+        self.refilled = now;
+        for bucket in [&mut self.messages, &mut self.bytes].into_iter().flatten() {
+            bucket.refill(elapsed);
+        }
+    }
+
+    // Spends the budget for one message of `len` bytes, or returns how long
+    // until it is there.
+    fn take(&mut self, len: usize) -> std::result::Result<(), Duration> {
+        self.refill();
+        let wait = [(&self.messages, 1.0), (&self.bytes, len as f64)]
+            .into_iter()
+            .filter_map(|(bucket, cost)| bucket.as_ref().map(|b| b.wait_for(cost)))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if let Some(bucket) = &mut self.messages {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = &mut self.bytes {
+            bucket.tokens -= len as f64;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) enum Admission {
+    Now,
+    Queued,
+}
+
+pub(crate) struct RateLimiter {
+    over_limit: OverLimit,
+    cancel: Option<CancellationToken>,
+    state: Mutex<LimiterState>,
+    changed: Condvar,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: &RateLimit) -> Self {
+        Self {
+            // The browser can neither block nor run the queue's thread.
+            over_limit: match cfg!(target_arch = "wasm32") {
+                true => OverLimit::Error,
+                false => limit.over_limit,
+            },
+            cancel: limit.cancel.clone(),
+            state: Mutex::new(LimiterState {
+                messages: limit.messages_per_sec.map(Bucket::new),
+                bytes: limit.bytes_per_sec.map(Bucket::new),
+                refilled: Instant::now(),
+                queue: VecDeque::new(),
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn queues(&self) -> bool {
+        matches!(self.over_limit, OverLimit::Queue(_))
+    }
+
+    // Decides whether `message` may be published now. Blocks in
+    // `OverLimit::Block` mode; in queue mode, takes the message if it has
+    // to wait, including behind messages already queued.
+    pub(crate) fn admit(self: &Arc<Self>, message: &Message) -> Result<Admission> {
+        let _registration = match (&self.cancel, self.over_limit) {
+            (Some(cancel), OverLimit::Block) => {
+                let limiter = self.clone();
+                Some(cancel.on_cancel(move || {
+                    let _state = limiter.lock();
+                    limiter.changed.notify_all();
+                }))
+            }
+            _ => None,
+        };
+        let mut state = self.lock();
+        if let OverLimit::Queue(capacity) = self.over_limit {
+            if state.queue.is_empty() && state.take(message.payload.len()).is_ok() {
+                return Ok(Admission::Now);
+            }
+            if state.queue.len() >= capacity {
+                return Err(Error::RateLimited);
+            }
+            state.queue.push_back(message.clone());
+            self.changed.notify_all();
+            return Ok(Admission::Queued);
+        }
+        loop {
+            match state.take(message.payload.len()) {
+                Ok(()) => return Ok(Admission::Now),
+                Err(_) if self.over_limit == OverLimit::Error || state.stopped => {
+                    return Err(Error::RateLimited)
+                }
+                Err(_) if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) => {
+                    return Err(Error::Cancelled)
+                }
+                Err(wait) => {
+                    state = self
+                        .changed
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+            }
+        }
+    }
+
+    // Blocks until the oldest queued message fits the budget and returns
+    // it, or None once stopped.
+    pub(crate) fn next_queued(&self) -> Option<Message> {
+        let mut state = self.lock();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            let wait = match state.queue.front().map(|front| front.payload.len()) {
+                Some(len) => match state.take(len) {
+                    Ok(()) => return state.queue.pop_front(),
+                    Err(wait) => Some(wait),
+                },
+                None => None,
+            };
+            state = match wait {
+                Some(wait) => {
+                    self.changed
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+
+    // Wakes everything waiting on the limiter. Queued messages are dropped
+    // and blocked publishers fail.
+    pub(crate) fn stop(&self) -> usize {
+        let mut state = self.lock();
+        state.stopped = true;
+        let dropped = state.queue.len();
+        state.queue.clear();
+        self.changed.notify_all();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_budget_and_over_limit_modes() {
+        let message = Message::new("test/rate", vec![0u8; 40]).unwrap();
+
+        let limiter = Arc::new(RateLimiter::new(
+            &RateLimit::new()
+                .with_messages_per_sec(3.0)
+                .unwrap()
+                .with_bytes_per_sec(100.0)
+                .unwrap()
+                .with_over_limit(OverLimit::Error),
+        ));
+        // 100 bytes per second allows two 40-byte messages before the
+        // three-message budget runs out.
+        assert!(matches!(limiter.admit(&message), Ok(Admission::Now)));
+        assert!(matches!(limiter.admit(&message), Ok(Admission::Now)));
+        assert!(matches!(limiter.admit(&message), Err(Error::RateLimited)));
+
+        let limiter = Arc::new(RateLimiter::new(
+            &RateLimit::new()
+                .with_messages_per_sec(20.0)
+                .unwrap()
+                .with_over_limit(OverLimit::Queue(1)),
+        ));
+        for _ in 0..20 {
+            assert!(matches!(limiter.admit(&message), Ok(Admission::Now)));
+        }
+        assert!(matches!(limiter.admit(&message), Ok(Admission::Queued)));
+        assert!(matches!(limiter.admit(&message), Err(Error::RateLimited)));
+        let started = Instant::now();
+        assert!(limiter.next_queued().is_some());
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(limiter.stop(), 0);
+        assert!(limiter.next_queued().is_none());
+
+        let cancel = CancellationToken::new();
+        let limiter = Arc::new(RateLimiter::new(
+            &RateLimit::new()
+                .with_messages_per_sec(1.0)
+                .unwrap()
+                .with_cancellation(cancel.clone()),
+        ));
+        assert!(matches!(limiter.admit(&message), Ok(Admission::Now)));
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        assert!(matches!(limiter.admit(&message), Err(Error::Cancelled)));
+        canceller.join().unwrap();
+
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                RateLimit::new().with_messages_per_sec(rate),
+                Err(Error::InvalidOptions(_))
+            ));
+        }
+        // Overdrawn by a large message at a tiny rate.
+        let tiny = Bucket {
+            rate: 1e-300,
+            tokens: -40.0,
+        };
+        assert_eq!(tiny.wait_for(1.0), MAX_WAIT);
+    }
+}
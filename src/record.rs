@@ -0,0 +1,618 @@
+use crate::cancel::CancellationToken;
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::types::{QoS, TopicFilter};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Recording files start with `MAGIC` and a version byte, followed by one
+// record per message, integers little-endian:
+//
+//   u64 microseconds since the Unix epoch
+//   u8  QoS in bits 0-1, retain in bit 2
+//   u16 topic length, u32 payload length
+//   topic, payload
+pub const MAGIC: &[u8; 4] = b"PMQR";
+pub const EXTENSION: &str = "pmqr";
+const VERSION: u8 = 1;
+const RETAIN: u8 = 0b100;
+
+// The largest payload a reader accepts, that of the largest MQTT packet, so
+// that a corrupt length cannot make it allocate gigabytes.
+pub const MAX_PAYLOAD: usize = 268_435_455;
+
+// How many received messages may wait for the writer thread before more
+// are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct RecordedMessage {
+    pub timestamp: SystemTime,
+    pub message: Message,
+}
+
+pub(crate) fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])
+}
+
+pub(crate) fn write_record<W: Write>(
+    out: &mut W,
+    timestamp: SystemTime,
+    message: &Message,
+) -> io::Result<usize> {
+    let micros = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let qos = match message.qos {
+        QoS::AtMostOnce => 0,
+        QoS::AtLeastOnce => 1,
+        QoS::ExactlyOnce => 2,
+    };
+    let flags = qos | if message.retained { RETAIN } else { 0 };
+    let topic = message.topic.as_bytes();
+    let topic_len = u16::try_from(topic.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "topic too long"))?;
+    let payload_len = u32::try_from(message.payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload too large"))?;
+    out.write_all(&micros.to_le_bytes())?;
+    out.write_all(&[flags])?;
+    out.write_all(&topic_len.to_le_bytes())?;
+    out.write_all(&payload_len.to_le_bytes())?;
+    out.write_all(topic)?;
+    out.write_all(&message.payload)?;
+    Ok(15 + topic.len() + message.payload.len())
+}
+
+// Reads back a recording, one message at a time.
+pub struct RecordReader<R> {
+    input: R,
+}
+
+impl RecordReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut input: R) -> Result<Self> {
+        let mut header = [0u8; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(Error::InvalidPayload("not a recording".to_string()));
+        }
+        Ok(Self { input })
+    }
+
+    fn read_record(&mut self) -> Result<Option<RecordedMessage>> {
+        let mut fixed = [0u8; 15];
+        // A clean end of file falls between records.
+        match self.input.read(&mut fixed[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut fixed[1..])?,
+        }
+        let micros = u64::from_le_bytes(fixed[..8].try_into().unwrap_or_default());
+        let flags = fixed[8];
+        let topic_len = u16::from_le_bytes([fixed[9], fixed[10]]) as usize;
+        let payload_len = u32::from_le_bytes(fixed[11..15].try_into().unwrap_or_default()) as usize;
+
+        if payload_len > MAX_PAYLOAD {
+            return Err(Error::InvalidPayload(format!(
+                "recorded payload of {} bytes",
+                payload_len
+            )));
+        }
+
+        let mut topic = vec![0u8; topic_len];
+        self.input.read_exact(&mut topic)?;
+        // Grown as it is read, so a truncated file fails before the
+        // length it claims is allocated.
+        let mut payload = Vec::new();
+        (&mut self.input)
+            .take(payload_len as u64)
+            .read_to_end(&mut payload)?;
+        if payload.len() < payload_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let topic = String::from_utf8(topic).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+        let qos = match flags & 0b11 {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        };
+        Ok(Some(RecordedMessage {
+            timestamp: UNIX_EPOCH + Duration::from_micros(micros),
+            message: Message::new(topic, payload)?
+                .with_qos(qos)
+                .with_retain(flags & RETAIN != 0),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<RecordedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+// Records the messages received on a filter to files in a directory, for
+// replaying or inspecting later (see `RecordReader`). Files are named
+// `<prefix>-<unix millis>-<sequence>.pmqr`, so they sort in recording
+// order, and rotated once they reach `max_file_size` bytes or
+// `max_file_age`; beyond `max_files` the oldest are deleted. Messages that
+// arrive faster than the disk takes them are dropped once 10,000 are
+// waiting, and counted by `RecorderHandle::dropped`.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    dir: PathBuf,
+    prefix: String,
+    qos: QoS,
+    max_file_size: Option<u64>,
+    max_file_age: Option<Duration>,
+    max_files: Option<usize>,
+}
+
+impl Recorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: "recording".to_string(),
+            qos: QoS::AtLeastOnce,
+            max_file_size: None,
+            max_file_age: None,
+            max_files: None,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    pub fn with_max_file_age(mut self, age: Duration) -> Self {
+        self.max_file_age = Some(age);
+        self
+    }
+
+    pub fn with_max_files(mut self, files: usize) -> Self {
+        self.max_files = Some(files.max(1));
+        self
+    }
+
+    // Subscribes to `filter` and records until the handle is stopped or
+    // dropped. Messages are written on a thread of their own, so a slow
+    // disk does not hold up the network thread.
+    pub fn start<T>(self, client: &Arc<Client>, filter: T) -> Result<RecorderHandle>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        let qos = self.qos;
+        let mut files = Files::open(self)?;
+        record_with(client, filter, qos, move |rx| {
+            let mut written = 0;
+            // Stops at the first error; the subscription's sender then
+            // fails quietly until the handle is stopped.
+            for (timestamp, message) in rx {
+                files.write(timestamp, &message)?;
+                written += 1;
+            }
+            files.flush()?;
+            Ok(written)
+        })
+    }
+}
+
+// Subscribes to `filter` and hands what is received, stamped with the time
+// of arrival, to `write` on a thread of its own. `write` returns how many
+// messages it wrote once the channel closes. Messages arriving while
+// `QUEUE_CAPACITY` are waiting are dropped, and counted in the handle.
+pub(crate) fn record_with<T, F>(
+    client: &Arc<Client>,
+    filter: T,
+    qos: QoS,
+    write: F,
+) -> Result<RecorderHandle>
+where
+    T: TryInto<TopicFilter>,
+    Error: From<T::Error>,
+    F: FnOnce(mpsc::Receiver<(SystemTime, Message)>) -> io::Result<u64> + Send + 'static,
+{
+    let (tx, rx) = mpsc::sync_channel::<(SystemTime, Message)>(QUEUE_CAPACITY);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let handle = {
+        let dropped = dropped.clone();
+        client.subscribe_with(filter, qos, move |msg| {
+            if let Err(TrySendError::Full(_)) = tx.try_send((SystemTime::now(), msg.to_owned())) {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        })?
+    };
+    let thread = thread::spawn(move || write(rx));
+    Ok(RecorderHandle {
+        client: Arc::downgrade(client),
+        handle,
+        thread: Some(thread),
+        dropped,
+    })
+}
+
+// The files of a running recording.
+struct Files {
+    config: Recorder,
+    current: BufWriter<File>,
+    size: u64,
+    opened: Instant,
+    sequence: u64,
+}
+
+impl Files {
+    fn open(config: Recorder) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let files = Self {
+            current: Self::create(&config, 0)?,
+            config,
+            size: MAGIC.len() as u64 + 1,
+            opened: Instant::now(),
+            sequence: 0,
+        };
+        files.prune()?;
+        Ok(files)
+    }
+
+    fn create(config: &Recorder, sequence: u64) -> io::Result<BufWriter<File>> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name = format!(
+            "{}-{:013}-{:06}.{}",
+            config.prefix, millis, sequence, EXTENSION
+        );
+        let mut file = BufWriter::new(File::create(config.dir.join(name))?);
+        write_header(&mut file)?;
+        Ok(file)
+    }
+
+    fn write(&mut self, timestamp: SystemTime, message: &Message) -> io::Result<()> {
+        let full = self
+            .config
+            .max_file_size
+            .is_some_and(|max| self.size >= max)
+            || self
+                .config
+                .max_file_age
+                .is_some_and(|max| self.opened.elapsed() >= max);
+        if full {
+            self.rotate()?;
+        }
+        self.size += write_record(&mut self.current, timestamp, message)? as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.current.flush()?;
+        self.sequence += 1;
+        self.current = Self::create(&self.config, self.sequence)?;
+        self.size = MAGIC.len() as u64 + 1;
+        self.opened = Instant::now();
+        self.prune()
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let Some(max_files) = self.config.max_files else {
+            return Ok(());
+        };
+        let paths = list(&self.config.dir, &self.config.prefix)?;
+        for path in &paths[..paths.len().saturating_sub(max_files)] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.flush()
+    }
+}
+
+// The recording files in the directory, oldest first.
+fn list(dir: &Path, prefix: &str) -> io::Result<Vec<PathBuf>> {
+    let start = format!("{}-", prefix);
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == EXTENSION)
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&start))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+// The files a `Recorder` with `prefix` wrote to `dir`, in recording order.
+pub fn recordings(dir: impl AsRef<Path>, prefix: &str) -> Result<Vec<PathBuf>> {
+    Ok(list(dir.as_ref(), prefix)?)
+}
+
+// Stops recording when dropped; `stop` also reports how it went.
+pub struct RecorderHandle {
+    client: Weak<Client>,
+    handle: i64,
+    thread: Option<JoinHandle<io::Result<u64>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl RecorderHandle {
+    // Messages dropped because the writer fell too far behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // Unsubscribes, writes out what was received and returns how many
+    // messages were recorded, or the error that stopped the recording.
+    pub fn stop(mut self) -> Result<u64> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<u64> {
+        // Dropping the handler closes the channel, ending the writer.
+        if let Some(client) = self.client.upgrade() {
+            let _ = client.unsubscribe(self.handle);
+        }
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => Ok(result?),
+            Some(Err(_)) => Err(Error::Io(io::Error::other("recorder thread panicked"))),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for RecorderHandle {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// Publishes recorded messages again, back to back by default, or with the
+// recorded gaps between them scaled by `with_speed`. A topic prefix can be
+// swapped so a replay does not land on the live topics. Retained messages
+// are republished as retained.
+#[derive(Debug, Clone, Default)]
+pub struct Replayer {
+    speed: Option<f64>,
+    prefix: Option<(String, String)>,
+    cancel: Option<CancellationToken>,
+}
+
+impl Replayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keeps the recorded timing, `speed` times faster: 2.0 replays an hour
+    // in half an hour, 0.5 in two hours.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = (speed.is_finite() && speed > 0.0).then_some(speed);
+        self
+    }
+
+    // Publishes messages recorded under `from` under `to` instead; other
+    // topics are left as they are.
+    pub fn with_topic_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.prefix = Some((from.into(), to.into()));
+        self
+    }
+
+    // Stops the replay, failing with `Error::Cancelled`, once `cancel` is
+    // cancelled, including while waiting for the next message's turn.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Replays the files in order, as one recording, and returns how many
+    // messages were published.
+    pub fn replay_files<I>(&self, client: &Client, paths: I) -> Result<u64>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let mut recording = Vec::new();
+        for path in paths {
+            recording.push(RecordReader::open(path)?);
+        }
+        self.replay(client, recording.into_iter().flatten())
+    }
+
+    pub fn replay<I>(&self, client: &Client, recording: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = Result<RecordedMessage>>,
+    {
+        let started = Instant::now();
+        let mut first = None;
+        let mut published = 0;
+        for recorded in recording {
+            let recorded = recorded?;
+            if let Some(speed) = self.speed {
+                let first = *first.get_or_insert(recorded.timestamp);
+                let offset = recorded
+                    .timestamp
+                    .duration_since(first)
+                    .unwrap_or_default()
+                    .div_f64(speed);
+                self.sleep_until(started + offset)?;
+            } else if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                return Err(Error::Cancelled);
+            }
+            client.publish(&self.rewrite(recorded.message)?)?;
+            published += 1;
+        }
+        Ok(published)
+    }
+
+    fn rewrite(&self, message: Message) -> Result<Message> {
+        let Some((from, to)) = &self.prefix else {
+            return Ok(message);
+        };
+        let Some(rest) = message.topic.strip_prefix(from.as_str()) else {
+            return Ok(message);
+        };
+        Ok(Message::new(format!("{}{}", to, rest), message.payload)?
+            .with_qos(message.qos)
+            .with_retain(message.retained))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Result<()> {
+        let Some(cancel) = &self.cancel else {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            return Ok(());
+        };
+        let signal = Arc::new((Mutex::new(()), Condvar::new()));
+        let _registration = {
+            let signal = signal.clone();
+            cancel.on_cancel(move || {
+                let _guard = signal.0.lock().unwrap_or_else(PoisonError::into_inner);
+                signal.1.notify_all();
+            })
+        };
+        let mut guard = signal.0.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            guard = signal
+                .1
+                .wait_timeout(guard, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_and_reads_back() {
+        let dir = std::env::temp_dir().join(format!(
+            "polar_mqtt_record_{}_{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let config = Recorder::new(&dir).with_max_file_size(64).with_max_files(2);
+        let mut files = Files::open(config).unwrap();
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for i in 0..6u8 {
+            let message = Message::new(format!("room/{}", i), vec![i; 20])
+                .unwrap()
+                .with_qos(QoS::ExactlyOnce)
+                .with_retain(i % 2 == 0);
+            files
+                .write(started + Duration::from_secs(i.into()), &message)
+                .unwrap();
+        }
+        files.flush().unwrap();
+
+        // Each file holds two messages; the first of three was pruned.
+        let paths = recordings(&dir, "recording").unwrap();
+        assert_eq!(paths.len(), 2);
+        let recorded: Vec<RecordedMessage> = paths
+            .iter()
+            .flat_map(|path| RecordReader::open(path).unwrap())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(recorded.len(), 4);
+        let first = &recorded[0];
+        assert_eq!(first.message.topic(), "room/2");
+        assert_eq!(first.message.payload(), &[2; 20]);
+        assert_eq!(first.message.qos(), QoS::ExactlyOnce);
+        assert!(first.message.is_retained());
+        assert_eq!(first.timestamp, started + Duration::from_secs(2));
+        assert!(!recorded[1].message.is_retained());
+
+        assert!(RecordReader::new(&b"nope!"[..]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_lengths_are_refused() {
+        let mut huge = MAGIC.to_vec();
+        huge.push(VERSION);
+        huge.extend_from_slice(&[0; 9]);
+        huge.extend_from_slice(&1u16.to_le_bytes());
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge.push(b't');
+        let mut reader = RecordReader::new(&huge[..]).unwrap();
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidPayload(_)))));
+
+        // A length within bounds, but past the end of the file.
+        let mut truncated = huge.clone();
+        truncated[16..20].copy_from_slice(&1000u32.to_le_bytes());
+        truncated.extend_from_slice(b"short");
+        let mut reader = RecordReader::new(&truncated[..]).unwrap();
+        assert!(matches!(reader.next(), Some(Err(Error::Io(_)))));
+    }
+
+    #[test]
+    fn test_replayer_rewrites_prefix_and_cancels() {
+        let replayer = Replayer::new().with_topic_prefix("site/a/", "replay/a/");
+        let message = Message::new("site/a/temp", "21")
+            .unwrap()
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain(true);
+        let rewritten = replayer.rewrite(message).unwrap();
+        assert_eq!(rewritten.topic(), "replay/a/temp");
+        assert_eq!(rewritten.payload(), b"21");
+        assert_eq!(rewritten.qos(), QoS::AtLeastOnce);
+        assert!(rewritten.is_retained());
+        let other = Message::new("site/b/temp", "19").unwrap();
+        assert_eq!(replayer.rewrite(other).unwrap().topic(), "site/b/temp");
+
+        let cancel = CancellationToken::new();
+        let replayer = Replayer::new()
+            .with_speed(1.0)
+            .with_cancellation(cancel.clone());
+        let started = Instant::now();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        let result = replayer.sleep_until(started + Duration::from_secs(10));
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::{Error, Result};
+use crate::options::{AddressFamily, Resolver};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// RFC 8305's recommended wait before trying the next address.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The address of `host` to connect to. The backends hand the host name to
+// Paho or rumqttc, which try a single address of it (Paho an IPv4 one when
+// there is one), so the client resolves it itself and races TCP connects
+// to the addresses, happy-eyeballs style, for the backend to connect to
+// the winner. That costs a TCP handshake more per connect. `resolver`
+// replaces the system resolver.
+pub(crate) fn select_address(
+    host: &str,
+    port: u16,
+    family: AddressFamily,
+    resolver: Option<&Resolver>,
+) -> Result<SocketAddr> {
+    let addresses: Vec<SocketAddr> = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => match resolver {
+            Some(resolver) => resolver.resolve(host),
+            None => (host, port).to_socket_addrs()?.collect(),
+        },
+    };
+    let addresses = interleave(
+        addresses
+            .into_iter()
+            .filter(|address| match family {
+                AddressFamily::Any => true,
+                AddressFamily::Ipv4 => address.is_ipv4(),
+                AddressFamily::Ipv6 => address.is_ipv6(),
+            })
+            .collect(),
+    );
+    match addresses.as_slice() {
+        [] => Err(Error::InvalidBrokerUrl(format!(
+            "no {:?} address for {}:{}",
+            family, host, port
+        ))),
+        [address] => Ok(*address),
+        _ => race(&addresses).ok_or(Error::ConnectionError),
+    }
+}
+
+// Alternates between the families, starting with the one the resolver
+// ranked first.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return addresses;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while let Some(address) = preferred.pop_front() {
+        ordered.push(address);
+        ordered.extend(other.pop_front());
+    }
+    ordered.extend(other);
+    ordered
+}
+
+// Starts a connect to each address in turn, the next one after
+// `ATTEMPT_DELAY` or as soon as the last one fails, and returns the first
+// to connect. The probe connections are closed again.
+fn race(addresses: &[SocketAddr]) -> Option<SocketAddr> {
+    let (sender, receiver) = mpsc::channel();
+    let mut started = 0;
+    let mut pending = 0;
+    loop {
+        if let Some(&address) = addresses.get(started) {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let connected = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).is_ok();
+                let _ = sender.send(connected.then_some(address));
+            });
+            started += 1;
+            pending += 1;
+        }
+        let wait = match started < addresses.len() {
+            true => ATTEMPT_DELAY,
+            false => CONNECT_TIMEOUT,
+        };
+        match receiver.recv_timeout(wait) {
+            Ok(Some(address)) => return Some(address),
+            Ok(None) => {
+                pending -= 1;
+                if pending == 0 && started == addresses.len() {
+                    return None;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if started < addresses.len() => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::ConnectOptions;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_race_moves_on_from_a_refused_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(race(&[closed, open]), Some(open));
+
+        let options = ConnectOptions::new().with_resolver(move |host| match host {
+            "broker.internal" => vec![closed, open],
+            _ => Vec::new(),
+        });
+        let resolver = options.resolver.as_ref();
+        let selected = select_address("broker.internal", 1883, AddressFamily::Any, resolver);
+        assert_eq!(selected.ok(), Some(open));
+        assert!(select_address("elsewhere", 1883, AddressFamily::Any, resolver).is_err());
+
+        let v4: SocketAddr = "192.0.2.1:1883".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:1883".parse().unwrap();
+        assert_eq!(interleave(vec![v6, v6, v4]), vec![v6, v4, v6]);
+        assert_eq!(
+            select_address("[::1]", 1883, AddressFamily::Ipv4, None).ok(),
+            None
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::client::Client;
+use crate::error::Error;
+use crate::stop::Stop;
+use crate::time::{Instant, SystemTime};
+use crate::types::ConnectionState;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ResumeEvent {
+    // The system was suspended for about `slept_for`.
+    Resumed { slept_for: Duration },
+    // The connection was re-established and this many subscriptions restored.
+    Reconnected { subscriptions: usize },
+    ReconnectFailed(Error),
+}
+
+// Detects system suspend, during which the monotonic clock stands still
+// while the wall clock moves on. After a resume the socket is usually dead
+// without either side having noticed, and keep-alive takes up to 1.5 times
+// its interval to find out; instead, the connection is re-established right
+// away. Wall clock steps (NTP, manual changes) larger than `min_gap` look
+// the same and cause a needless but harmless reconnect.
+#[derive(Debug, Clone)]
+pub struct ResumeMonitor {
+    check_interval: Duration,
+    min_gap: Duration,
+    reconnect: bool,
+}
+
+impl Default for ResumeMonitor {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(1),
+            min_gap: Duration::from_secs(5),
+            reconnect: true,
+        }
+    }
+}
+
+impl ResumeMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    // Smallest unaccounted wall clock advance treated as a suspend.
+    pub fn with_min_gap(mut self, min_gap: Duration) -> Self {
+        self.min_gap = min_gap;
+        self
+    }
+
+    // Only report resumes, leaving the connection to the caller.
+    pub fn with_reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    // Starts watching until the handle is dropped or the client is.
+    pub fn start<F>(self, client: &Arc<Client>, on_event: F) -> ResumeHandle
+    where
+        F: Fn(ResumeEvent) + Send + 'static,
+    {
+        let stop = Arc::new(Stop::default());
+        let thread = {
+            let (client, stop) = (Arc::downgrade(client), stop.clone());
+            thread::spawn(move || {
+                let mut clocks = Clocks::now();
+                while !stop.wait(self.check_interval) {
+                    let slept_for = clocks.advance(Clocks::now());
+                    if slept_for < self.min_gap {
+                        continue;
+                    }
+                    let Some(client) = client.upgrade() else {
+                        break;
+                    };
+                    on_event(ResumeEvent::Resumed { slept_for });
+                    // A client never connected, or disconnected on purpose,
+                    // stays as it is.
+                    if self.reconnect && client.state() != ConnectionState::Disconnected {
+                        on_event(match client.reconnect() {
+                            Ok(subscriptions) => ResumeEvent::Reconnected { subscriptions },
+                            Err(e) => ResumeEvent::ReconnectFailed(e),
+                        });
+                    }
+                }
+            })
+        };
+        ResumeHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Clocks {
+    monotonic: Instant,
+    wall: SystemTime,
+}
+
+impl Clocks {
+    fn now() -> Self {
+        Self {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    // Moves to `now`, returning how much further the wall clock went than
+    // the monotonic one.
+    fn advance(&mut self, now: Clocks) -> Duration {
+        let monotonic = now.monotonic.duration_since(self.monotonic);
+        let wall = now.wall.duration_since(self.wall).unwrap_or_default();
+        *self = now;
+        wall.saturating_sub(monotonic)
+    }
+}
+
+// Stops the monitor when dropped.
+pub struct ResumeHandle {
+    stop: Arc<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ResumeHandle {
+    fn drop(&mut self) {
+        self.stop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wall_clock_gap() {
+        let start = Clocks::now();
+        let mut clocks = start;
+        let awake = Clocks {
+            monotonic: start.monotonic + Duration::from_secs(1),
+            wall: start.wall + Duration::from_secs(1),
+        };
+        assert_eq!(clocks.advance(awake), Duration::ZERO);
+
+        let resumed = Clocks {
+            monotonic: awake.monotonic + Duration::from_secs(1),
+            wall: awake.wall + Duration::from_secs(61),
+        };
+        assert_eq!(clocks.advance(resumed), Duration::from_secs(60));
+
+        // A wall clock stepped back is not a suspend.
+        let stepped_back = Clocks {
+            monotonic: resumed.monotonic + Duration::from_secs(1),
+            wall: resumed.wall - Duration::from_secs(30),
+        };
+        assert_eq!(clocks.advance(stepped_back), Duration::ZERO);
+    }
+}
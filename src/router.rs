@@ -0,0 +1,332 @@
+use crate::error::{Error, Result};
+use crate::message::MessageView;
+use crate::template::{Params, TopicTemplate};
+use crate::types::TopicFilter;
+use std::collections::HashMap;
+
+type RouteFn = Box<dyn Fn(&MessageView) -> Result<()> + Send + Sync>;
+type Callback = Box<dyn Fn(&MessageView) + Send + Sync>;
+type ErrorCallback = Box<dyn Fn(&MessageView, &Error) + Send + Sync>;
+
+// A handler that carries its own filter, as generated by `#[mqtt_handler]`.
+// The filter may be a template such as `devices/{id}/status`.
+pub trait Handler: Send + Sync + 'static {
+    fn filter(&self) -> &'static str;
+
+    fn call(&self, msg: &MessageView, params: &Params) -> Result<()>;
+}
+
+// One level of the filter trie. Literal levels, `+` and `#` are kept apart so
+// dispatch never has to scan sibling filters.
+#[derive(Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    single: Option<Box<Node>>,
+    // Handlers for filters ending with `#` at this level.
+    multi: Vec<usize>,
+    // Handlers for filters ending exactly at this level.
+    exact: Vec<usize>,
+}
+
+// Dispatches incoming messages to the handlers whose filter matches the
+// topic. A message matching several routes is passed to each of them, in
+// the order the routes were added.
+#[derive(Default)]
+pub struct Router {
+    root: Node,
+    filters: Vec<TopicFilter>,
+    handlers: Vec<RouteFn>,
+    fallback: Option<Callback>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Panics if `filter` is not a valid topic filter; see `try_route`.
+    pub fn route<T, F>(self, filter: T, handler: F) -> Self
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        match self.try_route(filter, handler) {
+            Ok(router) => router,
+            Err(e) => panic!("invalid route: {}", e),
+        }
+    }
+
+    pub fn try_route<T, F>(self, filter: T, handler: F) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.add(
+            filter.try_into()?,
+            Box::new(move |msg| {
+                handler(msg);
+                Ok(())
+            }),
+        )
+    }
+
+    fn add(mut self, filter: TopicFilter, handler: RouteFn) -> Result<Self> {
+        let index = self.handlers.len();
+
+        let mut node = &mut self.root;
+        for level in filter.as_str().split('/') {
+            match level {
+                "#" => {
+                    node.multi.push(index);
+                    break;
+                }
+                "+" => node = node.single.get_or_insert_with(Default::default),
+                _ => node = node.children.entry(level.to_string()).or_default(),
+            }
+        }
+        if !filter.as_str().ends_with('#') {
+            node.exact.push(index);
+        }
+
+        self.filters.push(filter);
+        self.handlers.push(handler);
+        Ok(self)
+    }
+
+    // Routes messages matching `template`, e.g. `devices/{device_id}/status`,
+    // to a handler that also receives the captured parameters. Panics if the
+    // template is invalid; see `try_template`.
+    pub fn template<F>(self, template: &str, handler: F) -> Self
+    where
+        F: Fn(&MessageView, &Params) + Send + Sync + 'static,
+    {
+        match self.try_template(template, handler) {
+            Ok(router) => router,
+            Err(e) => panic!("invalid route template: {}", e),
+        }
+    }
+
+    pub fn try_template<F>(self, template: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(&MessageView, &Params) + Send + Sync + 'static,
+    {
+        let template = TopicTemplate::new(template)?;
+        let filter = template.filter().clone();
+        self.try_route(filter, move |msg: &MessageView| {
+            if let Some(params) = template.captures(msg.topic()) {
+                handler(msg, &params);
+            }
+        })
+    }
+
+    // Registers a handler generated by `#[mqtt_handler]`. Panics if its
+    // filter is invalid; see `try_handler`.
+    pub fn handler<H: Handler>(self, handler: H) -> Self {
+        match self.try_handler(handler) {
+            Ok(router) => router,
+            Err(e) => panic!("invalid handler filter: {}", e),
+        }
+    }
+
+    pub fn try_handler<H: Handler>(self, handler: H) -> Result<Self> {
+        let template = TopicTemplate::new(handler.filter())?;
+        let filter = template.filter().clone();
+        self.add(
+            filter,
+            Box::new(move |msg| match template.captures(msg.topic()) {
+                Some(params) => handler.call(msg, &params),
+                None => Ok(()),
+            }),
+        )
+    }
+
+    // Called with the errors returned by handlers, e.g. payloads that failed
+    // to decode. Without it such errors are dropped.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&MessageView, &Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(callback));
+        self
+    }
+
+    // Called for messages no route matches.
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    // The filters of every route, in the order they were added; handy for
+    // subscribing to exactly what the router handles.
+    pub fn filters(&self) -> impl Iterator<Item = &TopicFilter> {
+        self.filters.iter()
+    }
+
+    // Returns the number of route handlers called.
+    pub fn dispatch(&self, msg: &MessageView) -> usize {
+        let mut matched = Vec::new();
+        let levels: Vec<&str> = msg.topic().split('/').collect();
+        // Wildcards in the first level never match `$` topics.
+        let wildcards = !msg.topic().starts_with('$');
+        Self::collect(&self.root, &levels, wildcards, &mut matched);
+
+        matched.sort_unstable();
+        matched.dedup();
+        for &index in &matched {
+            if let Err(e) = (self.handlers[index])(msg) {
+                if let Some(on_error) = &self.on_error {
+                    on_error(msg, &e);
+                }
+            }
+        }
+        if matched.is_empty() {
+            if let Some(fallback) = &self.fallback {
+                fallback(msg);
+            }
+        }
+        matched.len()
+    }
+
+    fn collect(node: &Node, levels: &[&str], wildcards: bool, matched: &mut Vec<usize>) {
+        if wildcards {
+            // `#` also matches the parent level: `a/#` matches `a`.
+            matched.extend_from_slice(&node.multi);
+        }
+        let Some((level, rest)) = levels.split_first() else {
+            matched.extend_from_slice(&node.exact);
+            return;
+        };
+        if let Some(child) = node.children.get(*level) {
+            Self::collect(child, rest, true, matched);
+        }
+        if wildcards {
+            if let Some(single) = &node.single {
+                Self::collect(single, rest, true, matched);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topic;
+    use crate::QoS;
+    use std::sync::{Arc, Mutex};
+
+    fn view(topic: &str) -> MessageView<'_> {
+        MessageView {
+            topic,
+            payload: &[],
+            qos: QoS::AtMostOnce,
+            retained: false,
+            shared: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_agrees_with_matches() {
+        let filters = [
+            "sensors/+/temp",
+            "sensors/#",
+            "#",
+            "+",
+            "+/+",
+            "a//c",
+            "a/+/c",
+            "$SYS/#",
+            "cmd",
+        ];
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new();
+        for filter in filters {
+            let hits = hits.clone();
+            router = router.route(filter, move |_| hits.lock().unwrap().push(filter));
+        }
+
+        for topic in [
+            "sensors/room1/temp",
+            "sensors",
+            "sensors/room1/a/temp",
+            "a//c",
+            "a/b",
+            "cmd",
+            "$SYS/broker/uptime",
+            "",
+        ] {
+            hits.lock().unwrap().clear();
+            router.dispatch(&view(topic));
+            let expected: Vec<_> = filters
+                .iter()
+                .copied()
+                .filter(|f| topic::matches(f, topic))
+                .collect();
+            assert_eq!(*hits.lock().unwrap(), expected, "{:?}", topic);
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let fell_back = Arc::new(Mutex::new(false));
+        let router = Router::new().route("cmd/#", |_| {}).fallback({
+            let fell_back = fell_back.clone();
+            move |_| *fell_back.lock().unwrap() = true
+        });
+
+        assert_eq!(router.dispatch(&view("cmd/reboot")), 1);
+        assert!(!*fell_back.lock().unwrap());
+        assert_eq!(router.dispatch(&view("sensors/x")), 0);
+        assert!(*fell_back.lock().unwrap());
+        assert!(Router::new().try_route("cmd/#/x", |_| {}).is_err());
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn test_mqtt_handler() {
+        use crate::mqtt_handler;
+
+        #[mqtt_handler("devices/{id}/name")]
+        fn rename(params: &Params, name: String) -> Result<()> {
+            assert_eq!((params.get("id"), name.as_str()), (Some("d1"), "pump"));
+            Ok(())
+        }
+
+        let errors = Arc::new(Mutex::new(0));
+        let router = Router::new().handler(rename).on_error({
+            let errors = errors.clone();
+            move |_, e| {
+                assert!(matches!(e, Error::InvalidPayload(_)));
+                *errors.lock().unwrap() += 1;
+            }
+        });
+
+        let mut msg = view("devices/d1/name");
+        msg.payload = b"pump";
+        assert_eq!(router.dispatch(&msg), 1);
+        msg.payload = &[0xff];
+        router.dispatch(&msg);
+        assert_eq!(*errors.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_template_params() {
+        let seen = Arc::new(Mutex::new(None));
+        let router = Router::new().template("devices/{device_id}/status", {
+            let seen = seen.clone();
+            move |_, params| *seen.lock().unwrap() = params.get("device_id").map(String::from)
+        });
+
+        assert_eq!(router.dispatch(&view("devices/abc/status")), 1);
+        assert_eq!(seen.lock().unwrap().as_deref(), Some("abc"));
+        assert_eq!(
+            router.filters().next().unwrap().as_str(),
+            "devices/+/status"
+        );
+    }
+}
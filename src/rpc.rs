@@ -0,0 +1,355 @@
+use crate::cancel::CancellationToken;
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::types::QoS;
+use rmpv::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// msgpack-rpc message types.
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+const NOTIFICATION: u64 = 2;
+
+pub const TOPIC_PREFIX: &str = "rpc";
+
+// Callers publish to `rpc/<service>/req/<caller id>` and get responses on
+// `rpc/<service>/res/<caller id>`.
+pub fn request_topic(service: &str, caller_id: &str) -> String {
+    format!("{}/{}/req/{}", TOPIC_PREFIX, service, caller_id)
+}
+
+pub fn response_topic(service: &str, caller_id: &str) -> String {
+    format!("{}/{}/res/{}", TOPIC_PREFIX, service, caller_id)
+}
+
+fn encode(value: &Value) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, value).map_err(|e| Error::InvalidPayload(e.to_string()))?;
+    Ok(out)
+}
+
+fn decode(payload: &[u8]) -> Result<Vec<Value>> {
+    match rmpv::decode::read_value(&mut &payload[..]) {
+        Ok(Value::Array(fields)) => Ok(fields),
+        Ok(_) => Err(Error::InvalidPayload("msgpack-rpc: not an array".into())),
+        Err(e) => Err(Error::InvalidPayload(e.to_string())),
+    }
+}
+
+type Method = dyn Fn(Value) -> std::result::Result<Value, String> + Send + Sync;
+
+// Responder side: methods registered by name, served over one client.
+#[derive(Default)]
+pub struct RpcServer {
+    service: String,
+    methods: HashMap<String, Box<Method>>,
+}
+
+impl RpcServer {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            methods: HashMap::new(),
+        }
+    }
+
+    // Registers `method`. Its params are decoded from the request's params
+    // array, so they take a tuple: `|(a, b): (i32, i32)|`.
+    pub fn method<P, R, E, F>(mut self, name: impl Into<String>, method: F) -> Self
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        E: Display,
+        F: Fn(P) -> std::result::Result<R, E> + Send + Sync + 'static,
+    {
+        self.methods.insert(
+            name.into(),
+            Box::new(move |params| {
+                // `()` travels as an empty array but decodes from nil.
+                let params = match params {
+                    Value::Array(ref fields) if fields.is_empty() => {
+                        rmpv::ext::from_value(Value::Nil).or_else(|_| rmpv::ext::from_value(params))
+                    }
+                    params => rmpv::ext::from_value(params),
+                }
+                .map_err(|e| format!("invalid params: {}", e))?;
+                let result = method(params).map_err(|e| e.to_string())?;
+                rmpv::ext::to_value(result).map_err(|e| e.to_string())
+            }),
+        );
+        self
+    }
+
+    // Subscribes to requests for this service and answers them on the
+    // network thread. Returns the subscription handle.
+    pub fn serve(self, client: &Arc<Client>) -> Result<i64> {
+        let filter = format!("{}/{}/req/#", TOPIC_PREFIX, self.service);
+        let responder = Arc::downgrade(client);
+        client.subscribe_with(filter.as_str(), QoS::AtLeastOnce, move |msg| {
+            if let Some(client) = responder.upgrade() {
+                self.handle(&client, msg);
+            }
+        })
+    }
+
+    fn handle(&self, client: &Client, msg: &MessageView) {
+        let Some(caller_id) = msg
+            .topic()
+            .strip_prefix(&format!("{}/{}/req/", TOPIC_PREFIX, self.service))
+        else {
+            return;
+        };
+        let Ok(mut fields) = decode(msg.payload()) else {
+            return;
+        };
+        let call = |name: &Value, params: Value| match name.as_str() {
+            Some(name) => match self.methods.get(name) {
+                Some(method) => method(params),
+                None => Err(format!("unknown method {}", name)),
+            },
+            None => Err("method name is not a string".to_string()),
+        };
+
+        match fields.first().and_then(Value::as_u64) {
+            Some(REQUEST) if fields.len() == 4 => {
+                let params = fields.pop().unwrap_or(Value::Nil);
+                let (error, result) = match call(&fields[2], params) {
+                    Ok(result) => (Value::Nil, result),
+                    Err(error) => (Value::from(error), Value::Nil),
+                };
+                let response = Value::Array(vec![
+                    Value::from(RESPONSE),
+                    fields[1].clone(),
+                    error,
+                    result,
+                ]);
+                if let Ok(payload) = encode(&response) {
+                    if let Ok(reply) =
+                        Message::new(response_topic(&self.service, caller_id), payload)
+                    {
+                        let _ = client.publish(&reply.with_qos(QoS::AtLeastOnce));
+                    }
+                }
+            }
+            Some(NOTIFICATION) if fields.len() == 3 => {
+                let params = fields.pop().unwrap_or(Value::Nil);
+                let _ = call(&fields[1], params);
+            }
+            _ => {}
+        }
+    }
+}
+
+type Pending = Mutex<HashMap<u32, Sender<std::result::Result<Value, String>>>>;
+
+// Caller side. Calls block until the response arrives or the timeout
+// passes, so they must not be made from a message callback.
+pub struct RpcClient {
+    client: Arc<Client>,
+    service: String,
+    timeout: Duration,
+    cancel: CancellationToken,
+    next_id: AtomicU32,
+    pending: Arc<Pending>,
+    handle: i64,
+}
+
+impl RpcClient {
+    pub fn new(client: Arc<Client>, service: impl Into<String>) -> Result<Self> {
+        let service = service.into();
+        let pending: Arc<Pending> = Arc::default();
+        let handle = {
+            let pending = pending.clone();
+            client.subscribe_with(
+                response_topic(&service, client.client_id()).as_str(),
+                QoS::AtLeastOnce,
+                move |msg| {
+                    let Ok(fields) = decode(msg.payload()) else {
+                        return;
+                    };
+                    let [kind, id, error, result] = &fields[..] else {
+                        return;
+                    };
+                    let (Some(RESPONSE), Some(id)) = (kind.as_u64(), id.as_u64()) else {
+                        return;
+                    };
+                    let waiter = pending
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .remove(&(id as u32));
+                    if let Some(waiter) = waiter {
+                        let _ = waiter.send(match error {
+                            Value::Nil => Ok(result.clone()),
+                            Value::String(s) => Err(s.as_str().unwrap_or_default().to_string()),
+                            other => Err(other.to_string()),
+                        });
+                    }
+                },
+            )?
+        };
+        Ok(Self {
+            client,
+            service,
+            timeout: Duration::from_secs(10),
+            cancel: CancellationToken::new(),
+            next_id: AtomicU32::new(0),
+            pending,
+            handle,
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Calls waiting for a response fail with `Error::Cancelled` once `cancel`
+    // is cancelled, and later calls fail right away.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    // Calls `method` with `params`, which must serialize to an array (a
+    // tuple such as `(a, b)` or `(a,)`).
+    pub fn call<P, R>(&self, method: &str, params: P) -> Result<R>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = Value::Array(vec![
+            Value::from(REQUEST),
+            Value::from(id),
+            Value::from(method),
+            to_params(params)?,
+        ]);
+
+        let (tx, rx) = mpsc::channel();
+        // Wakes the wait below; what it sends is never read.
+        let _registration = self.cancel.on_cancel({
+            let tx = tx.clone();
+            move || {
+                let _ = tx.send(Ok(Value::Nil));
+            }
+        });
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, tx);
+        let result = self.send(&request).and_then(|()| {
+            let response = rx.recv_timeout(self.timeout);
+            if self.cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            response.map_err(|_| Error::Timeout)?.map_err(Error::Rpc)
+        });
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+        rmpv::ext::from_value(result?).map_err(|e| Error::InvalidPayload(e.to_string()))
+    }
+
+    // Sends a notification: no id, no response.
+    pub fn notify<P: Serialize>(&self, method: &str, params: P) -> Result<()> {
+        self.send(&Value::Array(vec![
+            Value::from(NOTIFICATION),
+            Value::from(method),
+            to_params(params)?,
+        ]))
+    }
+
+    fn send(&self, value: &Value) -> Result<()> {
+        let message = Message::new(
+            request_topic(&self.service, self.client.client_id()),
+            encode(value)?,
+        )?;
+        self.client.publish(&message.with_qos(QoS::AtLeastOnce))?;
+        Ok(())
+    }
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        let _ = self.client.unsubscribe(self.handle);
+    }
+}
+
+fn to_params<P: Serialize>(params: P) -> Result<Value> {
+    match rmpv::ext::to_value(params) {
+        Ok(params @ Value::Array(_)) => Ok(params),
+        Ok(Value::Nil) => Ok(Value::Array(Vec::new())),
+        Ok(_) => Err(Error::InvalidPayload(
+            "msgpack-rpc: params must be an array".into(),
+        )),
+        Err(e) => Err(Error::InvalidPayload(e.to_string())),
+    }
+}
+
+// Declares a typed stub over `RpcClient`:
+//
+//     rpc_stub! {
+//         pub struct Calculator("calc") {
+//             fn add(a: i32, b: i32) -> i32;
+//         }
+//     }
+//
+// `Calculator::new(client)` connects the stub and `calc.add(1, 2)` returns
+// `Result<i32>`.
+#[macro_export]
+macro_rules! rpc_stub {
+    ($vis:vis struct $name:ident($service:literal) {
+        $(fn $method:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*
+    }) => {
+        $vis struct $name($crate::rpc::RpcClient);
+
+        impl $name {
+            $vis fn new(client: ::std::sync::Arc<$crate::Client>) -> $crate::Result<Self> {
+                $crate::rpc::RpcClient::new(client, $service).map(Self)
+            }
+
+            $vis fn rpc(&self) -> &$crate::rpc::RpcClient {
+                &self.0
+            }
+
+            $(
+                $vis fn $method(&self, $($arg: $ty),*) -> $crate::Result<$ret> {
+                    self.0.call(stringify!($method), ($($arg,)*))
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispatch() {
+        let server = RpcServer::new("calc")
+            .method("add", |(a, b): (i32, i32)| Ok::<_, String>(a + b))
+            .method("fail", |(): ()| Err::<(), _>("nope"));
+        let call = |method: &str, params: Value| server.methods[method](params);
+
+        let sum = call("add", to_params((2, 3)).unwrap()).unwrap();
+        assert_eq!(rmpv::ext::from_value::<i32>(sum).unwrap(), 5);
+        assert_eq!(call("fail", to_params(()).unwrap()).unwrap_err(), "nope");
+        assert!(call("add", to_params(("x",)).unwrap())
+            .unwrap_err()
+            .starts_with("invalid params"));
+        assert!(to_params(5).is_err());
+    }
+}
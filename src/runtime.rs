@@ -0,0 +1,230 @@
+#[cfg(native_bridge)]
+use crate::bindings;
+use crate::error::{Error, Result};
+#[cfg(all(
+    any(feature = "log", feature = "tracing", target_os = "android"),
+    native_bridge
+))]
+use crate::logging;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    pub app_name: String,
+    pub app_version: String,
+    // Enables DEBUG/TRACE output from the native layer, including Paho's
+    // protocol trace.
+    pub debug: bool,
+    // Native log lines are appended here in addition to being forwarded.
+    pub log_file: Option<PathBuf>,
+    // Forward native log lines to `tracing`/`log` (requires one of those
+    // features, or on Android to logcat); when off they go to `log_file` or
+    // stderr.
+    pub forward_logs: bool,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        Self {
+            app_name: "RustMQTTClient".to_string(),
+            app_version: "1.0".to_string(),
+            debug: false,
+            log_file: None,
+            forward_logs: true,
+        }
+    }
+}
+
+#[cfg_attr(not(native_bridge), allow(dead_code))]
+struct NativeOptions {
+    app_name: CString,
+    app_version: CString,
+    log_file: Option<CString>,
+    debug: bool,
+    #[cfg_attr(
+        not(any(feature = "log", feature = "tracing", target_os = "android")),
+        allow(dead_code)
+    )]
+    forward_logs: bool,
+}
+
+impl TryFrom<&InitOptions> for NativeOptions {
+    type Error = Error;
+
+    fn try_from(options: &InitOptions) -> Result<Self> {
+        Ok(Self {
+            app_name: CString::new(options.app_name.as_str())?,
+            app_version: CString::new(options.app_version.as_str())?,
+            log_file: match &options.log_file {
+                Some(path) => Some(CString::new(path.to_string_lossy().as_bytes())?),
+                None => None,
+            },
+            debug: options.debug,
+            forward_logs: options.forward_logs,
+        })
+    }
+}
+
+struct Runtime {
+    // Number of live clients. The native library is initialized when the
+    // first client is created and uninitialized when the last one is
+    // dropped, so hosts that load and unload this crate (plugins) don't
+    // leave global state behind.
+    clients: usize,
+    // Fixed by the first call to init(), or by the first client created
+    // without one. Reused on every re-initialization.
+    options: Option<NativeOptions>,
+}
+
+static RUNTIME: Mutex<Runtime> = Mutex::new(Runtime {
+    clients: 0,
+    options: None,
+});
+
+// Configures the native library. Optional: the first `Client::new` falls
+// back to `InitOptions::default()`. Only the first call has an effect; once
+// options are fixed (by an earlier init() or by a client already having been
+// created) further calls are no-ops and return `Ok(false)`.
+pub fn init(options: InitOptions) -> Result<bool> {
+    let options = NativeOptions::try_from(&options)?;
+    let mut runtime = RUNTIME.lock().unwrap_or_else(PoisonError::into_inner);
+    if runtime.options.is_some() {
+        return Ok(false);
+    }
+    runtime.options = Some(options);
+    Ok(true)
+}
+
+pub(crate) struct RuntimeGuard(());
+
+impl RuntimeGuard {
+    pub(crate) fn acquire() -> Result<Self> {
+        let mut runtime = RUNTIME.lock().unwrap_or_else(PoisonError::into_inner);
+        if runtime.clients == 0 {
+            if runtime.options.is_none() {
+                runtime.options = Some(NativeOptions::try_from(&InitOptions::default())?);
+            }
+            let options = runtime.options.as_ref().ok_or(Error::InitializationError)?;
+            initialize(options)?;
+        }
+        runtime.clients += 1;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for RuntimeGuard {
+    fn drop(&mut self) {
+        let mut runtime = RUNTIME.lock().unwrap_or_else(PoisonError::into_inner);
+        runtime.clients -= 1;
+        if runtime.clients == 0 {
+            uninitialize();
+        }
+    }
+}
+
+#[cfg(native_bridge)]
+fn initialize(options: &NativeOptions) -> Result<()> {
+    #[cfg(any(feature = "log", feature = "tracing", target_os = "android"))]
+    if options.forward_logs {
+        logging::install();
+    }
+
+    let result = unsafe {
+        bindings::mqtt_initialize(
+            options.app_name.as_ptr(),
+            options.app_version.as_ptr(),
+            options.debug as i32,
+            options
+                .log_file
+                .as_ref()
+                .map_or(std::ptr::null(), |f| f.as_ptr()),
+        )
+    };
+    if result != 0 {
+        #[cfg(any(feature = "log", feature = "tracing", target_os = "android"))]
+        logging::uninstall();
+        return Err(Error::InitializationError);
+    }
+    Ok(())
+}
+
+#[cfg(native_bridge)]
+fn uninitialize() {
+    unsafe {
+        bindings::mqtt_uninitialize();
+    }
+    #[cfg(any(feature = "log", feature = "tracing", target_os = "android"))]
+    logging::uninstall();
+}
+
+// The Rust backends have no native library to set up; `debug`, `log_file`
+// and `forward_logs` only concern the bridge.
+#[cfg(not(native_bridge))]
+fn initialize(_options: &NativeOptions) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(native_bridge))]
+fn uninitialize() {}
+
+// The app name and version the native library was (or will be)
+// initialized with.
+pub(crate) fn app_identity() -> (String, String) {
+    let runtime = RUNTIME.lock().unwrap_or_else(PoisonError::into_inner);
+    match &runtime.options {
+        Some(options) => (
+            options.app_name.to_string_lossy().into_owned(),
+            options.app_version.to_string_lossy().into_owned(),
+        ),
+        None => {
+            let defaults = InitOptions::default();
+            (defaults.app_name, defaults.app_version)
+        }
+    }
+}
+
+// Fails in the browser, where nothing may block or run on a thread of its
+// own, for `what`, which needs to.
+pub(crate) fn needs_threads(what: &str) -> Result<()> {
+    match cfg!(target_arch = "wasm32") {
+        true => Err(Error::Unsupported(format!("{} in the browser", what))),
+        false => Ok(()),
+    }
+}
+
+// Whether the native library is currently initialized, i.e. at least one
+// client is alive.
+pub fn is_initialized() -> bool {
+    RUNTIME
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clients
+        > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_double_init_is_noop() {
+        // The first call may already have been made by another test's client.
+        let _ = init(InitOptions::default()).unwrap();
+        assert!(!init(InitOptions {
+            app_name: "second".into(),
+            ..Default::default()
+        })
+        .unwrap());
+    }
+
+    #[test]
+    fn test_init_rejects_nul_bytes() {
+        let options = InitOptions {
+            app_name: "bad\0name".into(),
+            ..Default::default()
+        };
+        assert!(matches!(init(options), Err(Error::NulError(_))));
+    }
+}
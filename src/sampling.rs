@@ -0,0 +1,151 @@
+use crate::error::{Error, Result};
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::topic;
+use crate::types::TopicFilter;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    // Keep the first of every N messages; 0 and 1 keep everything.
+    OneIn(u64),
+    // Keep each message with the given probability, clamped to [0, 1].
+    Probability(f64),
+}
+
+#[derive(Debug)]
+struct Rule {
+    filter: TopicFilter,
+    sampling: Sampling,
+    seen: AtomicU64,
+}
+
+impl Clone for Rule {
+    fn clone(&self) -> Self {
+        Self {
+            filter: self.filter.clone(),
+            sampling: self.sampling,
+            seen: AtomicU64::new(self.seen.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// Per-filter sampling applied to incoming messages before they are
+// dispatched. The first rule whose filter matches the topic decides; topics
+// no rule matches are always kept.
+#[derive(Debug)]
+pub struct Sampler {
+    rules: Vec<Rule>,
+    rng: AtomicU64,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            rules: Vec::new(),
+            // xorshift must not start at zero.
+            rng: AtomicU64::new(seed | 1),
+        }
+    }
+}
+
+impl Clone for Sampler {
+    fn clone(&self) -> Self {
+        Self {
+            rules: self.rules.clone(),
+            rng: AtomicU64::new(self.rng.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Panics if `filter` is not a valid topic filter; see `try_rule`.
+    pub fn rule<T>(self, filter: T, sampling: Sampling) -> Self
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        match self.try_rule(filter, sampling) {
+            Ok(sampler) => sampler,
+            Err(e) => panic!("invalid sampling rule: {}", e),
+        }
+    }
+
+    pub fn try_rule<T>(mut self, filter: T, sampling: Sampling) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.rules.push(Rule {
+            filter: filter.try_into()?,
+            sampling,
+            seen: AtomicU64::new(0),
+        });
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Whether a message on `topic` should be kept.
+    pub fn admit(&self, topic: &str) -> bool {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| topic::matches(rule.filter.as_str(), topic))
+        else {
+            return true;
+        };
+
+        match rule.sampling {
+            Sampling::OneIn(n) if n <= 1 => true,
+            Sampling::OneIn(n) => rule.seen.fetch_add(1, Ordering::Relaxed) % n == 0,
+            Sampling::Probability(p) if p >= 1.0 => true,
+            Sampling::Probability(p) if p > 0.0 => self.next_unit() < p,
+            Sampling::Probability(_) => false,
+        }
+    }
+
+    // Uniform in [0, 1). Concurrent callers may occasionally draw the same
+    // value, which is harmless for sampling.
+    fn next_unit(&self) -> f64 {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.store(x, Ordering::Relaxed);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_in_n_and_first_match_wins() {
+        let sampler = Sampler::new()
+            .rule("sensors/debug/#", Sampling::Probability(0.0))
+            .rule("sensors/#", Sampling::OneIn(4));
+
+        let kept = (0..100).filter(|_| sampler.admit("sensors/a")).count();
+        assert_eq!(kept, 25);
+        assert!(!sampler.admit("sensors/debug/x"));
+        assert!(sampler.admit("other"));
+    }
+
+    #[test]
+    fn test_probability() {
+        let sampler = Sampler::new().rule("#", Sampling::Probability(0.25));
+        let kept = (0..10_000).filter(|_| sampler.admit("a/b")).count();
+        assert!((2_000..3_000).contains(&kept), "{}", kept);
+    }
+}
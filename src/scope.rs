@@ -0,0 +1,188 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::message::Message;
+use crate::message::MessageView;
+use crate::observer::Observer;
+use crate::stop::Stop;
+use crate::types::{QoS, TopicFilter};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+// Subscriptions, observers and heartbeats tied to a `Client::scope` call.
+// Everything registered here is removed when the scope ends, whether the
+// closure returns, fails or panics.
+pub struct Scope<'scope, 'env: 'scope> {
+    client: &'env Client,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    threads: &'scope thread::Scope<'scope, 'env>,
+    stop: &'env Stop,
+    handles: Mutex<Vec<i64>>,
+    observers: Mutex<Vec<Arc<dyn Observer>>>,
+    heartbeats: Mutex<Vec<thread::ScopedJoinHandle<'scope, ()>>>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub(crate) fn new(
+        client: &'env Client,
+        threads: &'scope thread::Scope<'scope, 'env>,
+        stop: &'env Stop,
+    ) -> Self {
+        Self {
+            client,
+            threads,
+            stop,
+            handles: Mutex::new(Vec::new()),
+            observers: Mutex::new(Vec::new()),
+            heartbeats: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn client(&self) -> &'env Client {
+        self.client
+    }
+
+    pub fn subscribe<T>(&self, filter: T, qos: QoS) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        let handle = self.client.subscribe(filter, qos)?;
+        self.track(handle);
+        Ok(handle)
+    }
+
+    pub fn subscribe_with<T, F>(&self, filter: T, qos: QoS, handler: F) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        let handle = self.client.subscribe_with(filter, qos, handler)?;
+        self.track(handle);
+        Ok(handle)
+    }
+
+    // Removes a subscription before the scope ends.
+    pub fn unsubscribe(&self, handle: i64) -> Result<()> {
+        self.handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|&h| h != handle);
+        self.client.unsubscribe(handle)
+    }
+
+    // Watches the client with `observer` (see `Observer`) until the scope
+    // ends.
+    pub fn observe<O: Observer + 'static>(&self, observer: O) {
+        let observer: Arc<dyn Observer> = Arc::new(observer);
+        self.client.add_shared_observer(observer.clone());
+        self.observers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(observer);
+    }
+
+    // Publishes `message` every `interval` until the scope ends, starting
+    // now. Failed publishes are skipped; the next one is still attempted.
+    // Not in the browser, which has no threads.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn heartbeat(&self, message: Message, interval: Duration) {
+        let (client, stop) = (self.client, self.stop);
+        let heartbeat = self.threads.spawn(move || loop {
+            let _ = client.publish(&message);
+            if stop.wait(interval) {
+                break;
+            }
+        });
+        self.heartbeats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(heartbeat);
+    }
+
+    fn track(&self, handle: i64) {
+        self.handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(handle);
+    }
+}
+
+impl Drop for Scope<'_, '_> {
+    fn drop(&mut self) {
+        // Heartbeats end first, so none is sent once the rest is removed.
+        self.stop.stop();
+        let heartbeats = self
+            .heartbeats
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for heartbeat in heartbeats.drain(..) {
+            let _ = heartbeat.join();
+        }
+        let handles = std::mem::take(
+            self.handles
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for handle in handles {
+            let _ = self.client.unsubscribe(handle);
+        }
+        let observers = self
+            .observers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for observer in observers.drain(..) {
+            self.client.remove_observer(&observer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct Sent(Arc<AtomicUsize>);
+
+    impl Observer for Sent {
+        fn on_publish_sent(&self, _topic: &str, _message_id: i64, _qos: QoS, _bytes: usize) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_everything_registered_is_removed_when_the_scope_fails() {
+        let fake = Arc::new(Fake::default());
+        let client = Client::with_backend("scoped", fake.clone(), |_| {}, |_| {}, |_, _| {});
+        let client = client.unwrap();
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("kept", QoS::AtMostOnce).unwrap();
+        let sent = Sent::default();
+
+        let result: Result<()> = client.scope(|scope| {
+            scope.subscribe("a/#", QoS::AtMostOnce)?;
+            scope.subscribe_with("b/#", QoS::AtLeastOnce, |_| {})?;
+            let early = scope.subscribe("c", QoS::AtMostOnce)?;
+            scope.unsubscribe(early)?;
+            scope.observe(sent.clone());
+            // Publishes at once, then waits out the interval or the scope.
+            scope.heartbeat(Message::new("beat", "1")?, Duration::from_secs(60));
+            assert_eq!(fake.filters(), ["a/#", "b/#", "kept"]);
+            Err(Error::Expired)
+        });
+        assert!(matches!(result, Err(Error::Expired)));
+
+        assert_eq!(fake.filters(), ["kept"]);
+        assert_eq!(fake.published().len(), 1);
+        assert_eq!(sent.0.load(Ordering::Relaxed), 1);
+        client
+            .publish(&Message::new("after", "1").unwrap())
+            .unwrap();
+        assert_eq!(sent.0.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use std::future::{self, Future};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use tower_service::Service;
+
+// Publishing as a `tower::Service`, so middleware such as rate limits,
+// retries or load shedding can wrap it. Responds with the message id.
+#[derive(Clone)]
+pub struct PublishService {
+    client: Arc<Client>,
+}
+
+impl PublishService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+}
+
+impl Service<Message> for PublishService {
+    type Response = i64;
+    type Error = Error;
+    type Future = future::Ready<Result<i64>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, message: Message) -> Self::Future {
+        future::ready(self.client.publish(&message))
+    }
+}
+
+// Turns a service stack into a message callback, for `Client::new` or
+// `Client::subscribe_with`. Each message is passed owned and the service is
+// driven to completion on the network thread before the next one, so
+// middleware that needs an async runtime (timers, spawning) does not work
+// here. Service errors go to `on_error`.
+pub fn message_handler<S, F>(service: S, on_error: F) -> impl Fn(&MessageView) + Send + Sync
+where
+    S: Service<Message> + Send + 'static,
+    F: Fn(S::Error) + Send + Sync + 'static,
+{
+    let service = Mutex::new(service);
+    move |msg: &MessageView| {
+        let mut service = service.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = block_on(future::poll_fn(|cx| service.poll_ready(cx)))
+            .and_then(|()| block_on(service.call(msg.to_owned())));
+        if let Err(e) = result {
+            on_error(e);
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::QoS;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Rejects every other message, readying itself on a second poll.
+    struct Flaky {
+        calls: usize,
+        pending: bool,
+    }
+
+    impl Service<Message> for Flaky {
+        type Response = ();
+        type Error = String;
+        type Future = future::Ready<std::result::Result<(), String>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), String>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        fn call(&mut self, message: Message) -> Self::Future {
+            self.calls += 1;
+            future::ready(if self.calls.is_multiple_of(2) {
+                Err(message.topic().to_string())
+            } else {
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn test_message_handler() {
+        let errors = Arc::new(AtomicUsize::new(0));
+        let handler = {
+            let errors = errors.clone();
+            message_handler(
+                Flaky {
+                    calls: 0,
+                    pending: false,
+                },
+                move |topic| {
+                    assert_eq!(topic, "a/b");
+                    errors.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        };
+        let msg = MessageView {
+            topic: "a/b",
+            payload: b"x",
+            qos: QoS::AtMostOnce,
+            retained: false,
+            shared: Default::default(),
+        };
+        for _ in 0..4 {
+            handler(&msg);
+        }
+        assert_eq!(errors.load(Ordering::SeqCst), 2);
+    }
+}
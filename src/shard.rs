@@ -0,0 +1,304 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::types::{QoS, TopicFilter};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, PoisonError, RwLock, Weak};
+
+pub const TOPIC_PREFIX: &str = "polar_mqtt/shards";
+
+type RebalanceCallback = dyn Fn(&[String]) + Send + Sync;
+
+// FNV-1a, rather than std's hasher, so every consumer, whatever its Rust
+// version, agrees on who owns a topic. The murmur3 finalizer spreads the
+// short, similar keys placed on the ring.
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+// Consistent hashing of topics onto members. Each member is placed on the
+// ring at `virtual_nodes` points; a topic belongs to the member at the first
+// point at or after its hash. When a member joins or leaves, only the topics
+// next to its points move.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    points: BTreeMap<u64, String>,
+    members: BTreeSet<String>,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            points: BTreeMap::new(),
+            members: BTreeSet::new(),
+        }
+    }
+
+    fn point(member: &str, replica: usize) -> u64 {
+        hash(format!("{}#{}", member, replica).as_bytes())
+    }
+
+    // Returns false if `member` was already on the ring.
+    pub fn add(&mut self, member: &str) -> bool {
+        if !self.members.insert(member.to_string()) {
+            return false;
+        }
+        for replica in 0..self.virtual_nodes {
+            self.points
+                .entry(Self::point(member, replica))
+                .or_insert_with(|| member.to_string());
+        }
+        true
+    }
+
+    // Returns false if `member` was not on the ring.
+    pub fn remove(&mut self, member: &str) -> bool {
+        if !self.members.remove(member) {
+            return false;
+        }
+        self.points.retain(|_, owner| owner != member);
+        true
+    }
+
+    pub fn owner(&self, topic: &str) -> Option<&str> {
+        let hash = hash(topic.as_bytes());
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, member)| member.as_str())
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(String::as_str)
+    }
+}
+
+// Splits the messages on a filter among the members of a group, each member
+// being a client that joined it. Members announce themselves with a
+// retained message on `<prefix>/<group>/members/<client id>` and follow
+// that topic to keep a shared `HashRing`; each receives the whole filter
+// and dispatches only the topics it owns. A member that leaves clears its
+// announcement; set `leave_will` as the will so that one that crashes does
+// too.
+pub struct ShardGroup {
+    group: String,
+    prefix: String,
+    virtual_nodes: usize,
+    on_rebalance: Option<Box<RebalanceCallback>>,
+}
+
+impl ShardGroup {
+    pub fn new(group: impl Into<String>) -> Self {
+        Self {
+            group: group.into(),
+            prefix: TOPIC_PREFIX.to_string(),
+            virtual_nodes: 64,
+            on_rebalance: None,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes;
+        self
+    }
+
+    // Called with the members, sorted, whenever one joins or leaves.
+    pub fn with_on_rebalance<F>(mut self, on_rebalance: F) -> Self
+    where
+        F: Fn(&[String]) + Send + Sync + 'static,
+    {
+        self.on_rebalance = Some(Box::new(on_rebalance));
+        self
+    }
+
+    pub fn member_topic(&self, member: &str) -> String {
+        format!("{}/{}/members/{}", self.prefix, self.group, member)
+    }
+
+    // The will that removes `member` from the group if it disconnects
+    // uncleanly, for `ConnectOptions::with_will`.
+    pub fn leave_will(&self, member: &str) -> Result<Message> {
+        Ok(Message::new(self.member_topic(member), Vec::new())?
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain(true))
+    }
+
+    // Joins the group and subscribes to `filter`, calling `handler` for the
+    // messages on topics this member owns.
+    pub fn join<T, F>(
+        self,
+        client: &Arc<Client>,
+        filter: T,
+        qos: QoS,
+        handler: F,
+    ) -> Result<ShardMember>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.join_filter(client, filter.try_into()?, qos, Box::new(handler))
+    }
+
+    fn join_filter(
+        self,
+        client: &Arc<Client>,
+        filter: TopicFilter,
+        qos: QoS,
+        handler: Box<dyn Fn(&MessageView) + Send + Sync>,
+    ) -> Result<ShardMember> {
+        let member = client.client_id().to_string();
+        let mut ring = HashRing::new(self.virtual_nodes);
+        ring.add(&member);
+        let ring = Arc::new(RwLock::new(ring));
+        let announcement = self.leave_will(&member)?;
+
+        let members_filter = format!("{}/{}/members/+", self.prefix, self.group);
+        let prefix_len = members_filter.len() - 1;
+        let on_rebalance = self.on_rebalance;
+        let members = {
+            let ring = ring.clone();
+            client.subscribe_with(members_filter.as_str(), QoS::AtLeastOnce, move |msg| {
+                let Some(id) = msg.topic().get(prefix_len..) else {
+                    return;
+                };
+                let mut ring = ring.write().unwrap_or_else(PoisonError::into_inner);
+                let changed = match msg.payload().is_empty() {
+                    true => ring.remove(id),
+                    false => ring.add(id),
+                };
+                if changed {
+                    let members: Vec<String> = ring.members().map(str::to_string).collect();
+                    drop(ring);
+                    if let Some(on_rebalance) = &on_rebalance {
+                        on_rebalance(&members);
+                    }
+                }
+            })?
+        };
+
+        let data = {
+            let (ring, member) = (ring.clone(), member.clone());
+            client.subscribe_with(filter, qos, move |msg| {
+                let owned = ring
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .owner(msg.topic())
+                    == Some(member.as_str());
+                if owned {
+                    handler(msg);
+                }
+            })
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                let _ = client.unsubscribe(members);
+                return Err(e);
+            }
+        };
+
+        let join = Message::new(announcement.topic(), "1")?
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain(true);
+        if let Err(e) = client.publish(&join) {
+            let _ = client.unsubscribe(data);
+            let _ = client.unsubscribe(members);
+            return Err(e);
+        }
+
+        Ok(ShardMember {
+            client: Arc::downgrade(client),
+            ring,
+            handles: [data, members],
+            leave: announcement,
+        })
+    }
+}
+
+// Membership in a shard group; leaves the group when dropped.
+pub struct ShardMember {
+    client: Weak<Client>,
+    ring: Arc<RwLock<HashRing>>,
+    handles: [i64; 2],
+    leave: Message,
+}
+
+impl ShardMember {
+    pub fn members(&self) -> Vec<String> {
+        self.ring
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .members()
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn owner(&self, topic: &str) -> Option<String> {
+        self.ring
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .owner(topic)
+            .map(str::to_string)
+    }
+}
+
+impl Drop for ShardMember {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            for handle in self.handles {
+                let _ = client.unsubscribe(handle);
+            }
+            let _ = client.publish(&self.leave);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_balances_and_moves_little() {
+        let topics: Vec<String> = (0..3000).map(|i| format!("sensors/{}/temp", i)).collect();
+        let mut ring = HashRing::new(64);
+        assert_eq!(ring.owner("sensors/1/temp"), None);
+        for member in ["a", "b", "c"] {
+            ring.add(member);
+        }
+        let before: Vec<String> = topics
+            .iter()
+            .map(|t| ring.owner(t).unwrap().to_string())
+            .collect();
+        for member in ["a", "b", "c"] {
+            let share = before.iter().filter(|owner| *owner == member).count();
+            assert!((600..=1400).contains(&share), "{}: {}", member, share);
+        }
+
+        // Only topics taken over by the new member move.
+        assert!(ring.add("d"));
+        assert!(!ring.add("d"));
+        for (topic, owner) in topics.iter().zip(&before) {
+            let now = ring.owner(topic).unwrap();
+            assert!(now == owner || now == "d");
+        }
+        assert!(ring.remove("d"));
+        let after: Vec<&str> = topics.iter().map(|t| ring.owner(t).unwrap()).collect();
+        assert_eq!(after, before);
+    }
+}
@@ -0,0 +1,349 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::record::{record_with, RecorderHandle};
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::types::{QoS, TopicFilter};
+use arrow_array::{
+    ArrayRef, BinaryArray, BooleanArray, RecordBatch, StringArray, TimestampMicrosecondArray,
+    UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const EXTENSION: &str = "parquet";
+
+// The columns of every file a `ParquetSink` writes.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("topic", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("payload", DataType::Binary, false),
+        Field::new("qos", DataType::UInt8, false),
+        Field::new("retained", DataType::Boolean, false),
+    ]))
+}
+
+// Writes the messages received on a filter to Parquet files, for loading
+// into analytics tools. Messages are gathered into Arrow record batches of
+// `batch_size` rows; files are named `<prefix>-<unix millis>-<sequence>.parquet`
+// and rolled over once they hold `max_file_rows` rows or are `max_file_age`
+// old. A file can only be read once it is rolled over or the sink stopped,
+// as Parquet writes its footer last.
+#[derive(Debug, Clone)]
+pub struct ParquetSink {
+    dir: PathBuf,
+    prefix: String,
+    qos: QoS,
+    batch_size: usize,
+    max_file_rows: Option<u64>,
+    max_file_age: Option<Duration>,
+}
+
+impl ParquetSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: "messages".to_string(),
+            qos: QoS::AtLeastOnce,
+            batch_size: 1024,
+            max_file_rows: None,
+            max_file_age: None,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    pub fn with_max_file_rows(mut self, rows: u64) -> Self {
+        self.max_file_rows = Some(rows.max(1));
+        self
+    }
+
+    pub fn with_max_file_age(mut self, age: Duration) -> Self {
+        self.max_file_age = Some(age);
+        self
+    }
+
+    // Subscribes to `filter` and writes until the handle is stopped or
+    // dropped, on a thread of its own. Stopping writes out the last batch
+    // and closes the current file.
+    pub fn start<T>(self, client: &Arc<Client>, filter: T) -> Result<RecorderHandle>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        let qos = self.qos;
+        let max_file_age = self.max_file_age;
+        let mut files = Files::open(self)?;
+        record_with(client, filter, qos, move |rx| {
+            let mut written = 0;
+            loop {
+                // Wakes up for an idle file that is due to be rolled over.
+                let received = match max_file_age {
+                    Some(age) => rx.recv_timeout(age.saturating_sub(files.opened.elapsed())),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((timestamp, message)) => {
+                        files.push(timestamp, &message)?;
+                        written += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => files.roll_over_if_due()?,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            files.close()?;
+            Ok(written)
+        })
+    }
+}
+
+// The rows not yet written out as a record batch.
+#[derive(Default)]
+struct Rows {
+    topics: Vec<String>,
+    timestamps: Vec<i64>,
+    payloads: Vec<Vec<u8>>,
+    qos: Vec<u8>,
+    retained: Vec<bool>,
+}
+
+impl Rows {
+    fn push(&mut self, timestamp: SystemTime, message: &Message) {
+        let micros = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        self.topics.push(message.topic.clone());
+        self.timestamps.push(micros);
+        self.payloads.push(message.payload.clone());
+        self.qos.push(match message.qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => 1,
+            QoS::ExactlyOnce => 2,
+        });
+        self.retained.push(message.retained);
+    }
+
+    fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    fn take_batch(&mut self, schema: SchemaRef) -> io::Result<RecordBatch> {
+        let rows = std::mem::take(self);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.topics)),
+            Arc::new(TimestampMicrosecondArray::from(rows.timestamps).with_timezone("UTC")),
+            Arc::new(BinaryArray::from_iter_values(rows.payloads)),
+            Arc::new(UInt8Array::from(rows.qos)),
+            Arc::new(BooleanArray::from(rows.retained)),
+        ];
+        RecordBatch::try_new(schema, columns).map_err(io::Error::other)
+    }
+}
+
+// The files of a running sink.
+struct Files {
+    config: ParquetSink,
+    schema: SchemaRef,
+    current: Option<ArrowWriter<File>>,
+    rows: Rows,
+    file_rows: u64,
+    opened: Instant,
+    sequence: u64,
+}
+
+impl Files {
+    fn open(config: ParquetSink) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let schema = schema();
+        Ok(Self {
+            current: Some(Self::create(&config, &schema, 0)?),
+            config,
+            schema,
+            rows: Rows::default(),
+            file_rows: 0,
+            opened: Instant::now(),
+            sequence: 0,
+        })
+    }
+
+    fn create(
+        config: &ParquetSink,
+        schema: &SchemaRef,
+        sequence: u64,
+    ) -> io::Result<ArrowWriter<File>> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name = format!(
+            "{}-{:013}-{:06}.{}",
+            config.prefix, millis, sequence, EXTENSION
+        );
+        let file = File::create(config.dir.join(name))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(io::Error::other)
+    }
+
+    fn push(&mut self, timestamp: SystemTime, message: &Message) -> io::Result<()> {
+        self.roll_over_if_due()?;
+        self.rows.push(timestamp, message);
+        self.file_rows += 1;
+        if self.rows.len() >= self.config.batch_size {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let batch = self.rows.take_batch(self.schema.clone())?;
+        if let Some(writer) = &mut self.current {
+            writer.write(&batch).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn roll_over_if_due(&mut self) -> io::Result<()> {
+        let aged = self
+            .config
+            .max_file_age
+            .is_some_and(|max| self.opened.elapsed() >= max);
+        let full = self
+            .config
+            .max_file_rows
+            .is_some_and(|max| self.file_rows >= max);
+        // An empty file is kept rather than rolled over for nothing.
+        if aged && self.file_rows == 0 {
+            self.opened = Instant::now();
+            return Ok(());
+        }
+        if !aged && !full {
+            return Ok(());
+        }
+        self.close()?;
+        self.sequence += 1;
+        self.current = Some(Self::create(&self.config, &self.schema, self.sequence)?);
+        self.file_rows = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.current.take() {
+            writer.close().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_batches_and_rolls_over() {
+        let dir = std::env::temp_dir().join(format!(
+            "polar_mqtt_sink_{}_{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let config = ParquetSink::new(&dir)
+            .with_batch_size(2)
+            .with_max_file_rows(3);
+        let mut files = Files::open(config).unwrap();
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for i in 0..5u8 {
+            let message = Message::new(format!("room/{}", i), vec![i; 4])
+                .unwrap()
+                .with_qos(QoS::AtLeastOnce)
+                .with_retain(i == 0);
+            files
+                .push(started + Duration::from_secs(i.into()), &message)
+                .unwrap();
+        }
+        files.close().unwrap();
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert_eq!(paths.len(), 2);
+        let batches: Vec<RecordBatch> = paths
+            .iter()
+            .flat_map(|path| {
+                ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap()
+            })
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        assert_eq!(rows, 5);
+
+        let first = &batches[0];
+        assert_eq!(first.schema().fields(), schema().fields());
+        let topics = first
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(topics.value(0), "room/0");
+        let timestamps = first
+            .column(1)
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(timestamps.value(0), 1_700_000_000_000_000);
+        let retained = first
+            .column(4)
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        assert!(retained.value(0));
+        assert!(!retained.is_null(1) && !retained.value(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+mod proto;
+
+pub use proto::{data_type, Metric, Payload, Value};
+
+use crate::client::Client;
+use crate::codec::{Codec, ProstCodec};
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::options::ConnectOptions;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::types::{QoS, ShutdownReport};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const NAMESPACE: &str = "spBv1.0";
+
+const BD_SEQ: &str = "bdSeq";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    NBirth,
+    NDeath,
+    DBirth,
+    DDeath,
+    NData,
+    DData,
+    NCmd,
+    DCmd,
+    State,
+}
+
+impl MessageType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageType::NBirth => "NBIRTH",
+            MessageType::NDeath => "NDEATH",
+            MessageType::DBirth => "DBIRTH",
+            MessageType::DDeath => "DDEATH",
+            MessageType::NData => "NDATA",
+            MessageType::DData => "DDATA",
+            MessageType::NCmd => "NCMD",
+            MessageType::DCmd => "DCMD",
+            MessageType::State => "STATE",
+        }
+    }
+
+    fn is_device(self) -> bool {
+        matches!(
+            self,
+            MessageType::DBirth | MessageType::DDeath | MessageType::DData | MessageType::DCmd
+        )
+    }
+}
+
+impl FromStr for MessageType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "NBIRTH" => MessageType::NBirth,
+            "NDEATH" => MessageType::NDeath,
+            "DBIRTH" => MessageType::DBirth,
+            "DDEATH" => MessageType::DDeath,
+            "NDATA" => MessageType::NData,
+            "DDATA" => MessageType::DData,
+            "NCMD" => MessageType::NCmd,
+            "DCMD" => MessageType::DCmd,
+            "STATE" => MessageType::State,
+            _ => return Err(Error::InvalidTopic),
+        })
+    }
+}
+
+// A topic in the Sparkplug B namespace:
+// `spBv1.0/<group>/<type>/<edge node>[/<device>]`. STATE messages are not
+// covered; they use a different layout.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SparkplugTopic {
+    pub group_id: String,
+    pub message_type: MessageType,
+    pub edge_node_id: String,
+    pub device_id: Option<String>,
+}
+
+impl SparkplugTopic {
+    pub fn node(group_id: &str, message_type: MessageType, edge_node_id: &str) -> Self {
+        Self {
+            group_id: group_id.to_string(),
+            message_type,
+            edge_node_id: edge_node_id.to_string(),
+            device_id: None,
+        }
+    }
+
+    pub fn device(
+        group_id: &str,
+        message_type: MessageType,
+        edge_node_id: &str,
+        device_id: &str,
+    ) -> Self {
+        Self {
+            device_id: Some(device_id.to_string()),
+            ..Self::node(group_id, message_type, edge_node_id)
+        }
+    }
+}
+
+impl fmt::Display for SparkplugTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}",
+            NAMESPACE,
+            self.group_id,
+            self.message_type.as_str(),
+            self.edge_node_id
+        )?;
+        if let Some(device_id) = &self.device_id {
+            write!(f, "/{}", device_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SparkplugTopic {
+    type Err = Error;
+
+    fn from_str(topic: &str) -> Result<Self> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let (group_id, message_type, edge_node_id, device_id) = match levels[..] {
+            [NAMESPACE, group, kind, node] => (group, kind.parse::<MessageType>()?, node, None),
+            [NAMESPACE, group, kind, node, device] => {
+                (group, kind.parse::<MessageType>()?, node, Some(device))
+            }
+            _ => return Err(Error::InvalidTopic),
+        };
+        if message_type == MessageType::State || message_type.is_device() != device_id.is_some() {
+            return Err(Error::InvalidTopic);
+        }
+        for id in [Some(group_id), Some(edge_node_id), device_id]
+            .into_iter()
+            .flatten()
+        {
+            validate_id(id)?;
+        }
+        Ok(Self {
+            group_id: group_id.to_string(),
+            message_type,
+            edge_node_id: edge_node_id.to_string(),
+            device_id: device_id.map(String::from),
+        })
+    }
+}
+
+fn validate_id(id: &str) -> Result<()> {
+    if id.is_empty() || id.contains(['/', '+', '#']) {
+        return Err(Error::InvalidTopic);
+    }
+    Ok(())
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub fn decode_payload(payload: &[u8]) -> Result<Payload> {
+    ProstCodec.decode(payload)
+}
+
+type CommandHandler = dyn Fn(&SparkplugTopic, Payload) + Send + Sync;
+
+// A Sparkplug B edge node over an existing `Client`. It owns the sequence
+// numbers: `seq` restarts at 0 with every NBIRTH and wraps at 255, `bdSeq`
+// goes up by one on every connect and ties each NBIRTH to the NDEATH
+// registered as the connection's will.
+pub struct EdgeNode {
+    client: Client,
+    group_id: String,
+    edge_node_id: String,
+    seq: AtomicU8,
+    bd_seq: u8,
+    command_handler: Option<Arc<CommandHandler>>,
+    command_handles: Vec<i64>,
+}
+
+impl EdgeNode {
+    pub fn new(client: Client, group_id: &str, edge_node_id: &str) -> Result<Self> {
+        validate_id(group_id)?;
+        validate_id(edge_node_id)?;
+        Ok(Self {
+            client,
+            group_id: group_id.to_string(),
+            edge_node_id: edge_node_id.to_string(),
+            seq: AtomicU8::new(0),
+            bd_seq: 0,
+            command_handler: None,
+            command_handles: Vec::new(),
+        })
+    }
+
+    // Receives NCMD and DCMD messages addressed to this node. Payloads that
+    // fail to decode are dropped.
+    pub fn with_command_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&SparkplugTopic, Payload) + Send + Sync + 'static,
+    {
+        self.command_handler = Some(Arc::new(handler));
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // Connects with an NDEATH will, subscribes to commands and publishes
+    // NBIRTH with `metrics` plus the `bdSeq` metric.
+    pub fn connect(
+        &mut self,
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+        metrics: Vec<Metric>,
+    ) -> Result<()> {
+        let bd_seq = self.bd_seq;
+        self.bd_seq = self.bd_seq.wrapping_add(1);
+
+        let will = Message::new(
+            self.node_topic(MessageType::NDeath),
+            self.death_payload(bd_seq)?,
+        )?
+        .with_qos(QoS::AtLeastOnce);
+        self.client
+            .connect_with(host, port, &options.clone().with_will(will))?;
+
+        for handle in self.command_handles.drain(..) {
+            let _ = self.client.unsubscribe(handle);
+        }
+        if let Some(handler) = &self.command_handler {
+            for (kind, suffix) in [(MessageType::NCmd, ""), (MessageType::DCmd, "/+")] {
+                let handler = handler.clone();
+                let filter = format!("{}{}", self.node_topic(kind), suffix);
+                let handle = self.client.subscribe_with(
+                    filter.as_str(),
+                    QoS::AtLeastOnce,
+                    move |msg: &MessageView| {
+                        if let (Ok(topic), Ok(payload)) =
+                            (msg.topic().parse(), decode_payload(msg.payload()))
+                        {
+                            handler(&topic, payload);
+                        }
+                    },
+                )?;
+                self.command_handles.push(handle);
+            }
+        }
+
+        let mut metrics = metrics;
+        metrics.push(Metric::uint64(BD_SEQ, bd_seq.into()));
+        self.seq.store(0, Ordering::SeqCst);
+        self.publish(self.node_topic(MessageType::NBirth), metrics)
+    }
+
+    pub fn publish_node_data(&self, metrics: Vec<Metric>) -> Result<()> {
+        self.publish(self.node_topic(MessageType::NData), metrics)
+    }
+
+    pub fn publish_device_birth(&self, device_id: &str, metrics: Vec<Metric>) -> Result<()> {
+        self.publish(self.device_topic(MessageType::DBirth, device_id)?, metrics)
+    }
+
+    pub fn publish_device_data(&self, device_id: &str, metrics: Vec<Metric>) -> Result<()> {
+        self.publish(self.device_topic(MessageType::DData, device_id)?, metrics)
+    }
+
+    pub fn publish_device_death(&self, device_id: &str) -> Result<()> {
+        self.publish(
+            self.device_topic(MessageType::DDeath, device_id)?,
+            Vec::new(),
+        )
+    }
+
+    // Publishes NDEATH before disconnecting, since a clean disconnect
+    // discards the will.
+    pub fn shutdown(self, flush_timeout: Duration) -> ShutdownReport {
+        let bd_seq = self.bd_seq.wrapping_sub(1);
+        if let Ok(payload) = self.death_payload(bd_seq) {
+            if let Ok(death) = Message::new(self.node_topic(MessageType::NDeath), payload) {
+                let _ = self.client.publish(&death.with_qos(QoS::AtLeastOnce));
+            }
+        }
+        self.client.shutdown(flush_timeout)
+    }
+
+    fn node_topic(&self, kind: MessageType) -> String {
+        SparkplugTopic::node(&self.group_id, kind, &self.edge_node_id).to_string()
+    }
+
+    fn device_topic(&self, kind: MessageType, device_id: &str) -> Result<String> {
+        validate_id(device_id)?;
+        Ok(SparkplugTopic::device(&self.group_id, kind, &self.edge_node_id, device_id).to_string())
+    }
+
+    fn death_payload(&self, bd_seq: u8) -> Result<Vec<u8>> {
+        let payload = Payload {
+            timestamp: Some(now_millis()),
+            metrics: vec![Metric::uint64(BD_SEQ, bd_seq.into())],
+            ..Default::default()
+        };
+        ProstCodec.encode(&payload)
+    }
+
+    fn publish(&self, topic: String, metrics: Vec<Metric>) -> Result<()> {
+        let payload = Payload {
+            timestamp: Some(now_millis()),
+            metrics,
+            seq: Some(self.seq.fetch_add(1, Ordering::SeqCst).into()),
+            ..Default::default()
+        };
+        self.client
+            .publish_encoded(topic, &payload, QoS::AtMostOnce, &ProstCodec)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        let topic: SparkplugTopic = "spBv1.0/plant1/DDATA/gateway/pump3".parse().unwrap();
+        assert_eq!(
+            topic,
+            SparkplugTopic::device("plant1", MessageType::DData, "gateway", "pump3")
+        );
+        assert_eq!(topic.to_string(), "spBv1.0/plant1/DDATA/gateway/pump3");
+
+        for bad in [
+            "spBv1.0/plant1/NDATA/gateway/pump3",
+            "spBv1.0/plant1/DDATA/gateway",
+            "spBv1.0/plant1/XDATA/gateway",
+            "spAv1.0/plant1/NDATA/gateway",
+        ] {
+            assert!(bad.parse::<SparkplugTopic>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_payload_roundtrip() {
+        let payload = Payload {
+            timestamp: Some(1),
+            metrics: vec![Metric::int32("temp", -5), Metric::string("state", "ok")],
+            seq: Some(255),
+            ..Default::default()
+        };
+        let decoded = decode_payload(&ProstCodec.encode(&payload).unwrap()).unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(
+            decoded.metrics[0].value,
+            Some(Value::IntValue(-5i32 as u32))
+        );
+    }
+}
@@ -0,0 +1,129 @@
+// The subset of the Sparkplug B payload schema (sparkplug_b.proto) used by
+// this module: scalar metrics only, no datasets, templates or properties.
+// Field tags follow the upstream definition so payloads interoperate.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Payload {
+    #[prost(uint64, optional, tag = "1")]
+    pub timestamp: Option<u64>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+    #[prost(uint64, optional, tag = "3")]
+    pub seq: Option<u64>,
+    #[prost(string, optional, tag = "4")]
+    pub uuid: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metric {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(uint64, optional, tag = "2")]
+    pub alias: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+    #[prost(uint32, optional, tag = "4")]
+    pub datatype: Option<u32>,
+    #[prost(bool, optional, tag = "5")]
+    pub is_historical: Option<bool>,
+    #[prost(bool, optional, tag = "6")]
+    pub is_transient: Option<bool>,
+    #[prost(bool, optional, tag = "7")]
+    pub is_null: Option<bool>,
+    #[prost(oneof = "Value", tags = "10, 11, 12, 13, 14, 15, 16")]
+    pub value: Option<Value>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Value {
+    #[prost(uint32, tag = "10")]
+    IntValue(u32),
+    #[prost(uint64, tag = "11")]
+    LongValue(u64),
+    #[prost(float, tag = "12")]
+    FloatValue(f32),
+    #[prost(double, tag = "13")]
+    DoubleValue(f64),
+    #[prost(bool, tag = "14")]
+    BooleanValue(bool),
+    #[prost(string, tag = "15")]
+    StringValue(String),
+    #[prost(bytes = "vec", tag = "16")]
+    BytesValue(Vec<u8>),
+}
+
+// Sparkplug B data type codes.
+pub mod data_type {
+    pub const INT8: u32 = 1;
+    pub const INT16: u32 = 2;
+    pub const INT32: u32 = 3;
+    pub const INT64: u32 = 4;
+    pub const UINT8: u32 = 5;
+    pub const UINT16: u32 = 6;
+    pub const UINT32: u32 = 7;
+    pub const UINT64: u32 = 8;
+    pub const FLOAT: u32 = 9;
+    pub const DOUBLE: u32 = 10;
+    pub const BOOLEAN: u32 = 11;
+    pub const STRING: u32 = 12;
+    pub const DATETIME: u32 = 13;
+    pub const TEXT: u32 = 14;
+    pub const BYTES: u32 = 17;
+}
+
+impl Metric {
+    // Signed integers are stored two's-complement in the unsigned fields, as
+    // the specification requires.
+    pub fn int32(name: impl Into<String>, value: i32) -> Self {
+        Self::with_value(name, data_type::INT32, Value::IntValue(value as u32))
+    }
+
+    pub fn int64(name: impl Into<String>, value: i64) -> Self {
+        Self::with_value(name, data_type::INT64, Value::LongValue(value as u64))
+    }
+
+    pub fn uint64(name: impl Into<String>, value: u64) -> Self {
+        Self::with_value(name, data_type::UINT64, Value::LongValue(value))
+    }
+
+    pub fn float(name: impl Into<String>, value: f32) -> Self {
+        Self::with_value(name, data_type::FLOAT, Value::FloatValue(value))
+    }
+
+    pub fn double(name: impl Into<String>, value: f64) -> Self {
+        Self::with_value(name, data_type::DOUBLE, Value::DoubleValue(value))
+    }
+
+    pub fn boolean(name: impl Into<String>, value: bool) -> Self {
+        Self::with_value(name, data_type::BOOLEAN, Value::BooleanValue(value))
+    }
+
+    pub fn string(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::with_value(name, data_type::STRING, Value::StringValue(value.into()))
+    }
+
+    pub fn bytes(name: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        Self::with_value(name, data_type::BYTES, Value::BytesValue(value.into()))
+    }
+
+    pub fn with_value(name: impl Into<String>, datatype: u32, value: Value) -> Self {
+        Self {
+            name: Some(name.into()),
+            datatype: Some(datatype),
+            value: Some(value),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_alias(mut self, alias: u64) -> Self {
+        self.alias = Some(alias);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
@@ -0,0 +1,252 @@
+use crate::time::Instant;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+
+// What `TopicStats::top_n` ranks topics by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ranking {
+    MessageRate,
+    ByteRate,
+    Messages,
+    Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRate {
+    pub topic: String,
+    // Totals since the topic was first seen or the stats were reset.
+    pub messages: u64,
+    pub bytes: u64,
+    // Averages over the sliding window.
+    pub messages_per_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+// Per-topic message and byte counts, with rates over a sliding window of
+// whole seconds. Attach to a client with `Client::set_topic_stats` to count
+// everything it receives, before sampling, or feed it with `record`.
+//
+// Recording takes a shared lock on the topic table, and an exclusive one
+// only for a topic seen for the first time; totals are atomic and the
+// window of each topic has a lock of its own.
+pub struct TopicStats {
+    started: Instant,
+    window_secs: u64,
+    topics: RwLock<HashMap<String, Arc<Counters>>>,
+}
+
+struct Counters {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    window: Mutex<Vec<Bucket>>,
+}
+
+// Counts for one second, identified by its offset from `started`.
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    second: u64,
+    messages: u64,
+    bytes: u64,
+}
+
+impl TopicStats {
+    // Rates are averaged over `window`, rounded up to whole seconds.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            window_secs: (window.as_secs() + u64::from(window.subsec_nanos() > 0)).max(1),
+            topics: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, topic: &str, bytes: usize) {
+        self.record_at(topic, bytes, Instant::now());
+    }
+
+    fn record_at(&self, topic: &str, bytes: usize, now: Instant) {
+        let counters = self
+            .topics
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(topic)
+            .cloned();
+        let counters = match counters {
+            Some(counters) => counters,
+            None => self
+                .topics
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(topic.to_string())
+                .or_insert_with(|| {
+                    Arc::new(Counters {
+                        messages: AtomicU64::new(0),
+                        bytes: AtomicU64::new(0),
+                        window: Mutex::new(vec![Bucket::default(); self.window_secs as usize]),
+                    })
+                })
+                .clone(),
+        };
+
+        counters.messages.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let second = now.duration_since(self.started).as_secs();
+        let mut window = counters
+            .window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let bucket = &mut window[(second % self.window_secs) as usize];
+        if bucket.second != second {
+            *bucket = Bucket {
+                second,
+                ..Bucket::default()
+            };
+        }
+        bucket.messages += 1;
+        bucket.bytes += bytes as u64;
+    }
+
+    fn rate(&self, topic: &str, counters: &Counters, now: Instant) -> TopicRate {
+        let elapsed = now.duration_since(self.started);
+        let second = elapsed.as_secs();
+        let oldest = (second + 1).saturating_sub(self.window_secs);
+        let (messages, bytes) = counters
+            .window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|bucket| (oldest..=second).contains(&bucket.second))
+            .fold((0, 0), |(m, b), bucket| {
+                (m + bucket.messages, b + bucket.bytes)
+            });
+        // The window covers the current, partial second; early on it is
+        // only as long as the stats have existed.
+        let span = (elapsed.as_secs_f64() - oldest as f64).max(1.0);
+        TopicRate {
+            topic: topic.to_string(),
+            messages: counters.messages.load(Ordering::Relaxed),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+            messages_per_sec: messages as f64 / span,
+            bytes_per_sec: bytes as f64 / span,
+        }
+    }
+
+    pub fn get(&self, topic: &str) -> Option<TopicRate> {
+        let topics = self.topics.read().unwrap_or_else(PoisonError::into_inner);
+        Some(self.rate(topic, topics.get(topic)?, Instant::now()))
+    }
+
+    // Every topic seen, in no particular order.
+    pub fn snapshot(&self) -> Vec<TopicRate> {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> Vec<TopicRate> {
+        self.topics
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(topic, counters)| self.rate(topic, counters, now))
+            .collect()
+    }
+
+    // The `n` topics ranking highest, ties broken by topic name.
+    pub fn top_n(&self, n: usize, ranking: Ranking) -> Vec<TopicRate> {
+        let mut rates = self.snapshot();
+        rank(&mut rates, ranking);
+        rates.truncate(n);
+        rates
+    }
+
+    pub fn reset(&self) {
+        self.topics
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+fn rank(rates: &mut [TopicRate], ranking: Ranking) {
+    let key = |rate: &TopicRate| match ranking {
+        Ranking::MessageRate => rate.messages_per_sec,
+        Ranking::ByteRate => rate.bytes_per_sec,
+        Ranking::Messages => rate.messages as f64,
+        Ranking::Bytes => rate.bytes as f64,
+    };
+    rates.sort_by(|a, b| {
+        key(b)
+            .partial_cmp(&key(a))
+            .unwrap_or(CmpOrdering::Equal)
+            .then_with(|| a.topic.cmp(&b.topic))
+    });
+}
+
+// Round-trip times measured by a `LatencyProbe`, over its window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub samples: usize,
+    // Probes published and come back since the probe started; the
+    // difference is probes lost or still on their way.
+    pub sent: u64,
+    pub received: u64,
+    pub min: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    // Nearest-rank percentiles of `samples`, sorted in ascending order.
+    pub(crate) fn from_sorted(samples: &[Duration], sent: u64, received: u64) -> Self {
+        let rank = |p: f64| {
+            let index = ((samples.len() as f64 * p).ceil() as usize).saturating_sub(1);
+            samples.get(index).copied().unwrap_or_default()
+        };
+        Self {
+            samples: samples.len(),
+            sent,
+            received,
+            min: samples.first().copied().unwrap_or_default(),
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sliding_window_rates() {
+        let stats = TopicStats::new(Duration::from_secs(2));
+        let at = |secs: u64| stats.started + Duration::from_secs(secs);
+        for _ in 0..4 {
+            stats.record_at("a", 10, at(0));
+        }
+        stats.record_at("b", 100, at(0));
+        stats.record_at("b", 100, at(1));
+
+        let mut rates = stats.snapshot_at(at(1) + Duration::from_millis(500));
+        rank(&mut rates, Ranking::MessageRate);
+        assert_eq!(rates[0].topic, "a");
+        assert_eq!((rates[0].messages, rates[0].bytes), (4, 40));
+        assert_eq!(rates[0].messages_per_sec, 4.0 / 1.5);
+        rank(&mut rates, Ranking::ByteRate);
+        assert_eq!(rates[0].topic, "b");
+
+        // Second 0 has left the window; totals are kept.
+        let mut rates = stats.snapshot_at(at(3));
+        rank(&mut rates, Ranking::Messages);
+        assert_eq!((rates[0].messages, rates[0].messages_per_sec), (4, 0.0));
+        assert_eq!(rates[1].bytes, 200);
+
+        stats.reset();
+        assert!(stats.top_n(10, Ranking::Bytes).is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+// Set to end a background thread, which waits on it between rounds: the
+// heartbeats of a `Scope`, the watchdog, the latency probe and the resume
+// monitor.
+#[derive(Default)]
+pub(crate) struct Stop {
+    stopped: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Stop {
+    pub(crate) fn stop(&self) {
+        *self.stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.changed.notify_all();
+    }
+
+    // Returns true once stopped, otherwise after `timeout`.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        *self
+            .changed
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+    }
+}
@@ -0,0 +1,189 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::types::{QoS, TopicFilter};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+
+// The messages received on a filter as a `futures::Stream`, for use with
+// stream combinators (`forward`, `buffer_unordered`, `select`, ...).
+// Messages are buffered until polled, without bound, so a stream that is
+// not polled holds on to everything that arrives. The stream never ends;
+// dropping it removes the subscription.
+pub struct MessageStream {
+    shared: Arc<Shared>,
+    client: Weak<Client>,
+    handle: i64,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Message>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, message: Message) {
+        let waker = {
+            let mut state = self.lock();
+            state.queue.push_back(message);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl MessageStream {
+    pub fn subscribe<T>(client: &Arc<Client>, filter: T, qos: QoS) -> Result<Self>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        let shared = Arc::new(Shared::default());
+        let handle = {
+            let shared = shared.clone();
+            client.subscribe_with(filter, qos, move |msg: &MessageView| {
+                shared.push(msg.to_owned())
+            })?
+        };
+        Ok(Self {
+            shared,
+            client: Arc::downgrade(client),
+            handle,
+        })
+    }
+
+    // Messages received and not yet polled.
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        let mut state = self.shared.lock();
+        match state.queue.pop_front() {
+            Some(message) => Poll::Ready(Some(message)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), None)
+    }
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.upgrade() {
+            let _ = client.unsubscribe(self.handle);
+        }
+    }
+}
+
+// Publishing as a `futures::Sink`, so a stream of messages can be
+// `forward`ed to the broker. Each message is published as it is sent, with
+// `Client::publish`; there is nothing to flush, and closing the sink leaves
+// the client connected.
+#[derive(Clone)]
+pub struct Publisher {
+    client: Arc<Client>,
+}
+
+impl Publisher {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+}
+
+impl Sink<Message> for Publisher {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<()> {
+        self.client.publish(&message).map(|_| ())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_stream_buffers_and_wakes() {
+        let mut stream = MessageStream {
+            shared: Arc::new(Shared::default()),
+            client: Weak::new(),
+            handle: 0,
+        };
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        stream.shared.push(Message::new("a/b", "1").unwrap());
+        stream.shared.push(Message::new("a/b", "2").unwrap());
+        // Woken once, by the first message after the pending poll.
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+        assert_eq!(stream.size_hint(), (2, None));
+
+        for expected in [b"1", b"2"] {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(message)) => assert_eq!(message.payload(), expected),
+                _ => panic!("expected a message"),
+            }
+        }
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+        assert!(stream.is_empty());
+    }
+}
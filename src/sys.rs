@@ -0,0 +1,20 @@
+//! Raw bindings to the C bridge (`cpp/bridge/include/mqtt_c.hpp`), for calling
+//! bridge functions the safe API does not wrap yet. Nothing here is covered by
+//! semver; expect it to change with the bridge.
+//!
+//! Sessions obtained from `Client::raw_session` belong to the client:
+//!
+//! - Do not call `mqtt_destroy_session`, `mqtt_create_session`,
+//!   `mqtt_initialize` or `mqtt_uninitialize`; the client and the runtime
+//!   own those.
+//! - Do not replace the message, state, delivery or log callbacks. Their
+//!   context pointers refer to the client's internal state.
+//! - Subscriptions and publishes made here bypass the client's bookkeeping,
+//!   so `Client::shutdown` will neither remove nor flush them.
+//!
+//! Parameters (`mqtt_set_int_parameter`, `mqtt_set_bool_parameter`) and
+//! broker settings (`mqtt_set_broker`, `mqtt_set_credentials`,
+//! `mqtt_set_tls_certificates`) take effect on the next `mqtt_session_start`,
+//! i.e. the next `Client::connect`.
+
+pub use crate::bindings::*;
@@ -0,0 +1,178 @@
+use crate::error::{Error, Result};
+use crate::types::TopicFilter;
+use std::collections::HashSet;
+
+// Values captured from a concrete topic by a `TopicTemplate`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal,
+    // `{name}`: exactly one level.
+    Param(String),
+    // `{name..}`: the remaining levels, last segment only.
+    Rest(String),
+}
+
+// A topic filter with named levels, e.g. `devices/{device_id}/status` or
+// `logs/{app}/{path..}`. `{name}` captures one level like `+`, `{name..}`
+// captures the rest of the topic like `#`. Plain `+` and `#` are still
+// allowed and are not captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicTemplate {
+    template: String,
+    filter: TopicFilter,
+    segments: Vec<Segment>,
+}
+
+impl TopicTemplate {
+    pub fn new(template: &str) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut levels = Vec::new();
+        let mut segments = Vec::new();
+
+        for level in template.split('/') {
+            let segment = match level.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+                Some(inner) => match inner.strip_suffix("..") {
+                    Some(name) => Segment::Rest(name.to_string()),
+                    None => Segment::Param(inner.to_string()),
+                },
+                None if level.contains(['{', '}']) => return Err(Error::InvalidTopic),
+                None => Segment::Literal,
+            };
+            match &segment {
+                Segment::Param(name) | Segment::Rest(name) => {
+                    if name.is_empty()
+                        || name.contains(['{', '}', '+', '#'])
+                        || !names.insert(name.clone())
+                    {
+                        return Err(Error::InvalidTopic);
+                    }
+                }
+                Segment::Literal => {}
+            }
+            levels.push(match segment {
+                Segment::Param(_) => "+",
+                Segment::Rest(_) => "#",
+                Segment::Literal => level,
+            });
+            segments.push(segment);
+        }
+
+        // The filter check also rejects `{name..}` anywhere but last.
+        let filter = TopicFilter::new(levels.join("/"))?;
+        Ok(Self {
+            template: template.to_string(),
+            filter,
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    pub fn filter(&self) -> &TopicFilter {
+        &self.filter
+    }
+
+    // Extracts the parameters from `topic`, or `None` if it does not match.
+    pub fn captures(&self, topic: &str) -> Option<Params> {
+        if !crate::topic::matches(self.filter.as_str(), topic) {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        let mut rest = Some(topic);
+        for segment in &self.segments {
+            let (level, remaining) = match rest {
+                Some(r) => match r.split_once('/') {
+                    Some((level, remaining)) => (Some(level), Some(remaining)),
+                    None => (Some(r), None),
+                },
+                None => (None, None),
+            };
+            match segment {
+                Segment::Literal => {}
+                // Matched, so a level is present.
+                Segment::Param(name) => values.push((name.clone(), level?.to_string())),
+                // `a/{rest..}` matches `a` too, capturing nothing.
+                Segment::Rest(name) => {
+                    values.push((name.clone(), rest.unwrap_or_default().to_string()));
+                    break;
+                }
+            }
+            rest = remaining;
+        }
+        Some(Params { values })
+    }
+}
+
+impl TryFrom<&str> for TopicTemplate {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captures() {
+        let template = TopicTemplate::new("devices/{device_id}/status").unwrap();
+        assert_eq!(template.filter().as_str(), "devices/+/status");
+        let params = template.captures("devices/abc/status").unwrap();
+        assert_eq!(params.get("device_id"), Some("abc"));
+        assert!(template.captures("devices/abc/config").is_none());
+
+        let template = TopicTemplate::new("logs/{app}/+/{path..}").unwrap();
+        let params = template.captures("logs/web/eu/a/b/c").unwrap();
+        assert_eq!(
+            params.iter().collect::<Vec<_>>(),
+            [("app", "web"), ("path", "a/b/c")]
+        );
+        let params = template.captures("logs/web/eu").unwrap();
+        assert_eq!(params.get("path"), Some(""));
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for bad in [
+            "a/{}/b",
+            "a/{x}/{x}",
+            "a/x{y}",
+            "a/{rest..}/b",
+            "a/{x",
+            "a/{x+}",
+        ] {
+            assert!(TopicTemplate::new(bad).is_err(), "{}", bad);
+        }
+    }
+}
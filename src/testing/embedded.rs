@@ -0,0 +1,409 @@
+use super::mock::{MockBroker, MockClient};
+use crate::error::Result;
+use crate::message::{Message, MessageView};
+use crate::types::QoS;
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+// A broker speaking MQTT 3.1.1 over TCP on 127.0.0.1, on a port picked by
+// the system, for integration tests that need a real connection without
+// depending on a public broker. Routing is `MockBroker`'s, reachable with
+// `broker` to inspect what was published or to publish from the test.
+//
+// Sessions are not persisted and keep-alive is not enforced; wills are
+// published when a connection drops without DISCONNECT. QoS 2 publishes are
+// acknowledged with the full PUBREC/PUBREL/PUBCOMP exchange but delivered
+// at QoS 1. Everything stops when the broker is dropped.
+pub struct EmbeddedBroker {
+    addr: SocketAddr,
+    broker: MockBroker,
+    stop: Arc<AtomicBool>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+// A clone of the connection's stream, to shut it down, and its thread.
+type Connection = (TcpStream, JoinHandle<()>);
+
+impl EmbeddedBroker {
+    pub fn start() -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let broker = MockBroker::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let acceptor = {
+            let (broker, stop, connections) = (broker.clone(), stop.clone(), connections.clone());
+            let next_id = Arc::new(AtomicU64::new(1));
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let Ok(control) = stream.try_clone() else {
+                        continue;
+                    };
+                    let (broker, next_id) = (broker.clone(), next_id.clone());
+                    let thread = thread::spawn(move || {
+                        let _ = serve(stream, &broker, &next_id);
+                    });
+                    connections
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((control, thread));
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            broker,
+            stop,
+            connections,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn host(&self) -> String {
+        self.addr.ip().to_string()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    // `tcp://127.0.0.1:<port>`, for `Client::connect_uri`.
+    pub fn uri(&self) -> String {
+        format!("tcp://{}", self.addr)
+    }
+
+    pub fn broker(&self) -> &MockBroker {
+        &self.broker
+    }
+}
+
+impl Drop for EmbeddedBroker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wakes the acceptor up.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        let connections = std::mem::take(
+            &mut *self
+                .connections
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for (stream, thread) in connections {
+            let _ = stream.shutdown(Shutdown::Both);
+            let _ = thread.join();
+        }
+    }
+}
+
+type Writer = Arc<Mutex<TcpStream>>;
+
+fn send(writer: &Writer, packet_type: u8, flags: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![packet_type << 4 | flags];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    writer
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .write_all(&packet)
+}
+
+fn read_packet(reader: &mut impl Read) -> io::Result<(u8, u8, Vec<u8>)> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut len = 0usize;
+    for shift in 0..4 {
+        reader.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body)?;
+            return Ok((header >> 4, header & 0x0f, body));
+        }
+    }
+    Err(invalid("remaining length over four bytes"))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// Reads the fields of a packet body in order.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("packet too short"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> io::Result<&'a str> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.0)
+    }
+}
+
+fn qos_of(bits: u8) -> io::Result<QoS> {
+    match bits {
+        0 => Ok(QoS::AtMostOnce),
+        1 => Ok(QoS::AtLeastOnce),
+        2 => Ok(QoS::ExactlyOnce),
+        _ => Err(invalid("bad QoS")),
+    }
+}
+
+fn message(topic: &str, payload: &[u8], qos: QoS, retain: bool) -> io::Result<Message> {
+    Message::new(topic, payload.to_vec())
+        .map(|message| message.with_qos(qos).with_retain(retain))
+        .map_err(|_| invalid("bad topic"))
+}
+
+// Runs one connection until it closes or breaks the protocol.
+fn serve(stream: TcpStream, broker: &MockBroker, next_id: &AtomicU64) -> io::Result<()> {
+    let writer: Writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+
+    let (packet_type, _, body) = read_packet(&mut reader)?;
+    if packet_type != CONNECT {
+        return Err(invalid("expected CONNECT"));
+    }
+    let mut fields = Fields(&body);
+    let protocol = fields.string()?;
+    let level = fields.u8()?;
+    if !matches!((protocol, level), ("MQTT", 4) | ("MQIsdp", 3)) {
+        // Unacceptable protocol version.
+        return send(&writer, CONNACK, 0, &[0, 1]);
+    }
+    let flags = fields.u8()?;
+    let _keep_alive = fields.u16()?;
+    let mut client_id = fields.string()?.to_string();
+    if client_id.is_empty() {
+        client_id = format!("embedded-{}", next_id.fetch_add(1, Ordering::Relaxed));
+    }
+    let will = match flags & 0x04 != 0 {
+        true => {
+            let topic = fields.string()?;
+            let payload = fields.bytes()?;
+            Some(message(
+                topic,
+                payload,
+                qos_of(flags >> 3 & 0x03)?,
+                flags & 0x20 != 0,
+            )?)
+        }
+        false => None,
+    };
+
+    let client = {
+        let writer = writer.clone();
+        let packet_ids = AtomicU16::new(0);
+        broker.client(client_id, move |msg: &MessageView| {
+            let mut body = Vec::with_capacity(msg.topic().len() + msg.payload().len() + 4);
+            body.extend_from_slice(&(msg.topic().len() as u16).to_be_bytes());
+            body.extend_from_slice(msg.topic().as_bytes());
+            let qos = match msg.qos() {
+                QoS::AtMostOnce => 0,
+                _ => {
+                    // Packet ids are never 0.
+                    let id = packet_ids.fetch_add(1, Ordering::Relaxed) % u16::MAX + 1;
+                    body.extend_from_slice(&id.to_be_bytes());
+                    1
+                }
+            };
+            body.extend_from_slice(msg.payload());
+            let _ = send(&writer, PUBLISH, qos << 1 | msg.is_retained() as u8, &body);
+        })
+    };
+    send(&writer, CONNACK, 0, &[0, 0])?;
+
+    let result = session(&mut reader, &writer, &client);
+    drop(client);
+    if !matches!(result, Ok(true)) {
+        if let Some(will) = will {
+            broker.publish(&will);
+        }
+    }
+    result.map(|_| ())
+}
+
+// Handles packets after CONNECT; Ok(true) on DISCONNECT.
+fn session(reader: &mut impl Read, writer: &Writer, client: &MockClient) -> io::Result<bool> {
+    let mut subscriptions: HashMap<String, i64> = HashMap::new();
+    loop {
+        let (packet_type, flags, body) = match read_packet(reader) {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut fields = Fields(&body);
+        match packet_type {
+            PUBLISH => {
+                let qos = qos_of(flags >> 1 & 0x03)?;
+                let topic = fields.string()?;
+                let id = match qos {
+                    QoS::AtMostOnce => None,
+                    _ => Some(fields.u16()?),
+                };
+                let message = message(topic, fields.rest(), qos, flags & 0x01 != 0)?;
+                let _ = client.publish(&message);
+                match (qos, id) {
+                    (QoS::AtLeastOnce, Some(id)) => send(writer, PUBACK, 0, &id.to_be_bytes())?,
+                    (QoS::ExactlyOnce, Some(id)) => send(writer, PUBREC, 0, &id.to_be_bytes())?,
+                    _ => {}
+                }
+            }
+            PUBREL => send(writer, PUBCOMP, 0, &fields.u16()?.to_be_bytes())?,
+            // Acknowledgements of what was delivered at QoS 1.
+            PUBACK | PUBREC | PUBCOMP => {}
+            SUBSCRIBE => {
+                let id = fields.u16()?;
+                let mut codes = id.to_be_bytes().to_vec();
+                while !fields.0.is_empty() {
+                    let filter = fields.string()?;
+                    let qos = qos_of(fields.u8()? & 0x03)?;
+                    // A second subscription to a filter replaces the first.
+                    if let Some(handle) = subscriptions.remove(filter) {
+                        let _ = client.unsubscribe(handle);
+                    }
+                    codes.push(match client.subscribe(filter, qos) {
+                        Ok(handle) => {
+                            subscriptions.insert(filter.to_string(), handle);
+                            (qos != QoS::AtMostOnce) as u8
+                        }
+                        Err(_) => 0x80,
+                    });
+                }
+                send(writer, SUBACK, 0, &codes)?;
+            }
+            UNSUBSCRIBE => {
+                let id = fields.u16()?;
+                while !fields.0.is_empty() {
+                    if let Some(handle) = subscriptions.remove(fields.string()?) {
+                        let _ = client.unsubscribe(handle);
+                    }
+                }
+                send(writer, UNSUBACK, 0, &id.to_be_bytes())?;
+            }
+            PINGREQ => send(writer, PINGRESP, 0, &[])?,
+            DISCONNECT => return Ok(true),
+            _ => return Err(invalid("unexpected packet")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn packet(packet_type: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![packet_type << 4 | flags, body.len() as u8];
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_speaks_mqtt_over_tcp() {
+        let broker = EmbeddedBroker::start().unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", broker.port())).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut connect = string("MQTT");
+        connect.extend_from_slice(&[4, 0x02, 0, 60]);
+        connect.extend(string("raw"));
+        stream.write_all(&packet(CONNECT, 0, &connect)).unwrap();
+        assert_eq!(read_packet(&mut stream).unwrap(), (CONNACK, 0, vec![0, 0]));
+
+        let mut subscribe = vec![0, 1];
+        subscribe.extend(string("a/#"));
+        subscribe.push(1);
+        stream.write_all(&packet(SUBSCRIBE, 2, &subscribe)).unwrap();
+        assert_eq!(
+            read_packet(&mut stream).unwrap(),
+            (SUBACK, 0, vec![0, 1, 1])
+        );
+
+        let mut publish = string("a/b");
+        publish.extend_from_slice(&[0, 7]);
+        publish.extend_from_slice(b"hi");
+        stream.write_all(&packet(PUBLISH, 2, &publish)).unwrap();
+        // Delivered back before the PUBACK, as the publish is routed first.
+        let (packet_type, flags, body) = read_packet(&mut stream).unwrap();
+        assert_eq!((packet_type, flags), (PUBLISH, 2));
+        assert!(body.starts_with(&string("a/b")) && body.ends_with(b"hi"));
+        assert_eq!(read_packet(&mut stream).unwrap(), (PUBACK, 0, vec![0, 7]));
+
+        stream.write_all(&packet(PINGREQ, 0, &[])).unwrap();
+        assert_eq!(read_packet(&mut stream).unwrap(), (PINGRESP, 0, vec![]));
+        assert_eq!(broker.broker().clients(), ["raw"]);
+        assert_eq!(broker.broker().published()[0].payload(), b"hi");
+    }
+}
@@ -0,0 +1,425 @@
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageView};
+use crate::topic;
+use crate::types::{QoS, TopicFilter};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+type Callback = Arc<dyn Fn(&MessageView) + Send + Sync>;
+
+// An in-process broker for unit tests: subscriptions (shared ones
+// included), retained messages and QoS 0 and 1, with no network. Code under
+// test talks to it through `MockClient`s, which mirror the `Client` calls
+// for publishing and subscribing.
+//
+// Delivery is synchronous: a publish runs the callbacks of every matching
+// subscriber before it returns, so a test can assert right after it. QoS 2
+// is delivered as QoS 1. Every publish is also recorded, for `published`.
+#[derive(Clone, Default)]
+pub struct MockBroker {
+    inner: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: BTreeMap<u64, Session>,
+    next_session: u64,
+    retained: BTreeMap<String, Message>,
+    published: Vec<Message>,
+    // Next member of each shared subscription group to deliver to.
+    share_turns: HashMap<String, usize>,
+}
+
+struct Session {
+    client_id: String,
+    on_message: Callback,
+    subscriptions: BTreeMap<i64, Subscription>,
+    next_handle: i64,
+}
+
+struct Subscription {
+    // Without any `$share/<group>/` prefix.
+    filter: String,
+    group: Option<String>,
+    qos: QoS,
+    handler: Option<Callback>,
+}
+
+fn min_qos(a: QoS, b: QoS) -> QoS {
+    match (a, b) {
+        (QoS::AtMostOnce, _) | (_, QoS::AtMostOnce) => QoS::AtMostOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+impl MockBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Connects a client. `on_message` sees every message delivered to it,
+    // like the message callback of `Client::new`.
+    pub fn client<F>(&self, client_id: impl Into<String>, on_message: F) -> MockClient
+    where
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        let mut state = self.lock();
+        let session = state.next_session;
+        state.next_session += 1;
+        state.sessions.insert(
+            session,
+            Session {
+                client_id: client_id.into(),
+                on_message: Arc::new(on_message),
+                subscriptions: BTreeMap::new(),
+                next_handle: 1,
+            },
+        );
+        MockClient {
+            broker: self.clone(),
+            session,
+            next_message_id: AtomicI64::new(1),
+        }
+    }
+
+    // Publishes as if from a client that is not under test.
+    pub fn publish(&self, message: &Message) {
+        self.route(message);
+    }
+
+    // Every message published so far, in order.
+    pub fn published(&self) -> Vec<Message> {
+        self.lock().published.clone()
+    }
+
+    pub fn retained(&self, topic: &str) -> Option<Message> {
+        self.lock().retained.get(topic).cloned()
+    }
+
+    pub fn clients(&self) -> Vec<String> {
+        self.lock()
+            .sessions
+            .values()
+            .map(|session| session.client_id.clone())
+            .collect()
+    }
+
+    fn route(&self, message: &Message) {
+        let mut deliveries = Vec::new();
+        {
+            let mut state = self.lock();
+            state.published.push(message.clone());
+            if message.retained {
+                match message.payload.is_empty() {
+                    true => state.retained.remove(&message.topic),
+                    false => state
+                        .retained
+                        .insert(message.topic.clone(), message.clone()),
+                };
+            }
+
+            let mut groups: BTreeMap<String, Vec<(u64, i64)>> = BTreeMap::new();
+            for (&id, session) in &state.sessions {
+                // One delivery per session, at the highest QoS granted by
+                // its matching subscriptions; each subscription's handler
+                // still runs.
+                let mut qos = None;
+                let mut handlers = Vec::new();
+                for (&handle, subscription) in &session.subscriptions {
+                    if !topic::matches(&subscription.filter, &message.topic) {
+                        continue;
+                    }
+                    match &subscription.group {
+                        Some(group) => groups.entry(group.clone()).or_default().push((id, handle)),
+                        None => {
+                            let granted = min_qos(message.qos, subscription.qos);
+                            qos = Some(qos.map_or(granted, |q| match q {
+                                QoS::AtMostOnce => granted,
+                                _ => q,
+                            }));
+                            handlers.extend(subscription.handler.clone());
+                        }
+                    }
+                }
+                if let Some(qos) = qos {
+                    deliveries.push((qos, false, handlers, session.on_message.clone()));
+                }
+            }
+
+            for (group, members) in groups {
+                let turn = state.share_turns.entry(group).or_default();
+                let (id, handle) = members[*turn % members.len()];
+                *turn += 1;
+                let session = &state.sessions[&id];
+                let subscription = &session.subscriptions[&handle];
+                deliveries.push((
+                    min_qos(message.qos, subscription.qos),
+                    false,
+                    subscription.handler.clone().into_iter().collect(),
+                    session.on_message.clone(),
+                ));
+            }
+        }
+
+        // Outside the lock, so callbacks can publish and subscribe.
+        for (qos, retained, handlers, on_message) in deliveries {
+            deliver(message, qos, retained, &handlers, &on_message);
+        }
+    }
+
+    fn subscribe(
+        &self,
+        session: u64,
+        filter: TopicFilter,
+        qos: QoS,
+        handler: Option<Callback>,
+    ) -> Result<i64> {
+        let filter = filter.into_string();
+        let (group, filter) = match filter.strip_prefix("$share/") {
+            Some(rest) => match rest.split_once('/') {
+                Some((group, filter)) if !group.is_empty() && !filter.is_empty() => {
+                    (Some(format!("{}/{}", group, filter)), filter.to_string())
+                }
+                _ => return Err(Error::SubscriptionError(format!("Bad filter {}", filter))),
+            },
+            None => (None, filter),
+        };
+
+        let (handle, retained, on_message) = {
+            let mut state = self.lock();
+            // Shared subscriptions get no retained messages.
+            let retained: Vec<Message> = match group {
+                Some(_) => Vec::new(),
+                None => state
+                    .retained
+                    .values()
+                    .filter(|message| topic::matches(&filter, &message.topic))
+                    .cloned()
+                    .collect(),
+            };
+            let session = state
+                .sessions
+                .get_mut(&session)
+                .ok_or(Error::ConnectionError)?;
+            let handle = session.next_handle;
+            session.next_handle += 1;
+            session.subscriptions.insert(
+                handle,
+                Subscription {
+                    filter,
+                    group,
+                    qos,
+                    handler: handler.clone(),
+                },
+            );
+            (handle, retained, session.on_message.clone())
+        };
+
+        let handlers: Vec<Callback> = handler.into_iter().collect();
+        for message in &retained {
+            deliver(
+                message,
+                min_qos(message.qos, qos),
+                true,
+                &handlers,
+                &on_message,
+            );
+        }
+        Ok(handle)
+    }
+}
+
+fn deliver(
+    message: &Message,
+    qos: QoS,
+    retained: bool,
+    handlers: &[Callback],
+    on_message: &Callback,
+) {
+    let view = MessageView {
+        topic: &message.topic,
+        payload: &message.payload,
+        qos,
+        retained,
+        shared: Default::default(),
+    };
+    for handler in handlers {
+        handler(&view);
+    }
+    on_message(&view);
+}
+
+// A client of a `MockBroker`, disconnected when dropped.
+pub struct MockClient {
+    broker: MockBroker,
+    session: u64,
+    next_message_id: AtomicI64,
+}
+
+impl MockClient {
+    pub fn client_id(&self) -> String {
+        self.broker
+            .lock()
+            .sessions
+            .get(&self.session)
+            .map(|session| session.client_id.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn broker(&self) -> &MockBroker {
+        &self.broker
+    }
+
+    // Returns the message id, 0 at QoS 0. Subscribers have received the
+    // message, and a QoS 1 publish is acknowledged, once this returns.
+    pub fn publish(&self, message: &Message) -> Result<i64> {
+        if message.is_expired() {
+            return Err(Error::Expired);
+        }
+        self.broker.route(message);
+        Ok(match message.qos {
+            QoS::AtMostOnce => 0,
+            _ => self.next_message_id.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    pub fn subscribe<T>(&self, filter: T, qos: QoS) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+    {
+        self.broker
+            .subscribe(self.session, filter.try_into()?, qos, None)
+    }
+
+    pub fn subscribe_with<T, F>(&self, filter: T, qos: QoS, handler: F) -> Result<i64>
+    where
+        T: TryInto<TopicFilter>,
+        Error: From<T::Error>,
+        F: Fn(&MessageView) + Send + Sync + 'static,
+    {
+        self.broker.subscribe(
+            self.session,
+            filter.try_into()?,
+            qos,
+            Some(Arc::new(handler)),
+        )
+    }
+
+    pub fn unsubscribe(&self, handle: i64) -> Result<()> {
+        self.broker
+            .lock()
+            .sessions
+            .get_mut(&self.session)
+            .and_then(|session| session.subscriptions.remove(&handle))
+            .map(|_| ())
+            .ok_or_else(|| Error::SubscriptionError(format!("Unknown handle {}", handle)))
+    }
+}
+
+impl Drop for MockClient {
+    fn drop(&mut self) {
+        self.broker.lock().sessions.remove(&self.session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder() -> (Arc<Mutex<Vec<String>>>, impl Fn(&MessageView) + Send + Sync) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        (seen, move |msg: &MessageView| {
+            sink.lock().unwrap().push(format!(
+                "{} {} {:?}{}",
+                msg.topic(),
+                String::from_utf8_lossy(msg.payload()),
+                msg.qos(),
+                if msg.is_retained() { " retained" } else { "" }
+            ))
+        })
+    }
+
+    #[test]
+    fn test_routes_retains_and_shares() {
+        let broker = MockBroker::new();
+        let (seen, on_message) = recorder();
+        let client = broker.client("under-test", on_message);
+
+        let retained = Message::new("site/a/temp", "20")
+            .unwrap()
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain(true);
+        broker.publish(&retained);
+        client.subscribe("site/+/temp", QoS::AtLeastOnce).unwrap();
+        let handle = client.subscribe("site/#", QoS::AtMostOnce).unwrap();
+        assert_eq!(broker.retained("site/a/temp").unwrap().payload(), b"20");
+
+        // Delivered once, at the highest QoS granted.
+        let reply = broker.client("peer", |_| {});
+        reply
+            .publish(
+                &Message::new("site/b/temp", "21")
+                    .unwrap()
+                    .with_qos(QoS::AtLeastOnce),
+            )
+            .unwrap();
+        client.unsubscribe(handle).unwrap();
+        assert!(client.unsubscribe(handle).is_err());
+        reply
+            .publish(&Message::new("site/b/hum", "50").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "site/a/temp 20 AtLeastOnce retained",
+                "site/a/temp 20 AtMostOnce retained",
+                "site/b/temp 21 AtLeastOnce",
+            ]
+        );
+
+        // Shared subscriptions take turns; dropped clients get nothing.
+        let (first, on_first) = recorder();
+        let (second, on_second) = recorder();
+        let a = broker.client("a", on_first);
+        let b = broker.client("b", on_second);
+        a.subscribe("$share/g/jobs/#", QoS::AtMostOnce).unwrap();
+        b.subscribe("$share/g/jobs/#", QoS::AtMostOnce).unwrap();
+        for job in ["1", "2", "3"] {
+            broker.publish(&Message::new("jobs/x", job).unwrap());
+        }
+        assert_eq!(first.lock().unwrap().len(), 2);
+        assert_eq!(second.lock().unwrap().len(), 1);
+        drop(a);
+        assert_eq!(broker.clients(), ["under-test", "peer", "b"]);
+        assert_eq!(broker.published().len(), 6);
+    }
+
+    #[test]
+    fn test_callbacks_can_reply() {
+        let broker = MockBroker::new();
+        let (seen, on_message) = recorder();
+        let requester = broker.client("requester", on_message);
+        requester.subscribe("replies", QoS::AtMostOnce).unwrap();
+
+        let server = broker.client("server", |_| {});
+        let replies = broker.clone();
+        server
+            .subscribe_with("requests", QoS::AtMostOnce, move |msg| {
+                let reply = Message::new("replies", msg.payload().to_vec()).unwrap();
+                replies.publish(&reply);
+            })
+            .unwrap();
+        requester
+            .publish(&Message::new("requests", "ping").unwrap())
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["replies ping AtMostOnce"]);
+    }
+}
@@ -0,0 +1,7 @@
+// Test doubles for code built on this crate, behind the `testing` feature.
+
+mod embedded;
+mod mock;
+
+pub use embedded::EmbeddedBroker;
+pub use mock::{MockBroker, MockClient};
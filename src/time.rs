@@ -0,0 +1,6 @@
+// The clocks used throughout the crate. std's panic on
+// wasm32-unknown-unknown, where `web-time` reads the browser's instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime, UNIX_EPOCH};
@@ -0,0 +1,107 @@
+use crate::error::{Error, Result};
+
+// MQTT caps topic names and filters at 65535 bytes of UTF-8.
+pub const MAX_TOPIC_LEN: usize = 65535;
+
+fn validate_common(s: &str) -> Result<()> {
+    if s.is_empty() || s.len() > MAX_TOPIC_LEN || s.contains('\0') {
+        return Err(Error::InvalidTopic);
+    }
+    Ok(())
+}
+
+// A topic name is what messages are published to: no wildcards allowed.
+pub fn validate_topic_name(topic: &str) -> Result<()> {
+    validate_common(topic)?;
+    if topic.contains(['+', '#']) {
+        return Err(Error::InvalidTopic);
+    }
+    Ok(())
+}
+
+// A filter is what subscriptions use: `+` must occupy a whole level and `#`
+// must occupy the whole last level.
+pub fn validate_filter(filter: &str) -> Result<()> {
+    validate_common(filter)?;
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        match level {
+            "+" => {}
+            "#" if levels.peek().is_none() => {}
+            _ if level.contains(['+', '#']) => return Err(Error::InvalidTopic),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Whether `topic` is matched by `filter`. Topics starting with `$` (e.g.
+// `$SYS/...`) are not matched by filters whose first level is a wildcard.
+// Neither argument is validated; use the functions above for that.
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            // `#` also matches the parent level: `a/#` matches `a`.
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("sensors/+/temp", "sensors/room1/temp"));
+        assert!(!matches("sensors/+/temp", "sensors/room1/humidity"));
+        assert!(!matches("sensors/+/temp", "sensors/room1/a/temp"));
+        assert!(matches("sensors/#", "sensors"));
+        assert!(matches("sensors/#", "sensors/room1/temp"));
+        assert!(matches("#", "a/b/c"));
+        assert!(matches("+/+", "a/b"));
+        assert!(matches("+", ""));
+        assert!(matches("a//c", "a//c"));
+        assert!(matches("a/+/c", "a//c"));
+        assert!(!matches("a/b", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+    }
+
+    #[test]
+    fn test_dollar_topics() {
+        assert!(!matches("#", "$SYS/broker/uptime"));
+        assert!(!matches("+/broker/uptime", "$SYS/broker/uptime"));
+        assert!(matches("$SYS/#", "$SYS/broker/uptime"));
+        assert!(matches("$SYS/+/uptime", "$SYS/broker/uptime"));
+    }
+
+    #[test]
+    fn test_validate_filter() {
+        for ok in ["#", "+", "a/+/b", "a/#", "+/+/#", "/", "a//b"] {
+            assert!(validate_filter(ok).is_ok(), "{}", ok);
+        }
+        for bad in ["", "a/#/b", "a#", "a/b+", "+a/b", "##", "a\0b"] {
+            assert!(validate_filter(bad).is_err(), "{:?}", bad);
+        }
+        assert!(validate_filter(&"a".repeat(MAX_TOPIC_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_validate_topic_name() {
+        assert!(validate_topic_name("a/b/c").is_ok());
+        assert!(validate_topic_name("$SYS/x").is_ok());
+        assert!(validate_topic_name("a/+/c").is_err());
+        assert!(validate_topic_name("a/#").is_err());
+        assert!(validate_topic_name("").is_err());
+    }
+}
@@ -1,4 +1,9 @@
 use crate::bindings;
+use crate::error::{Error, Result};
+use crate::topic;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QoS {
@@ -36,3 +41,127 @@ impl From<bindings::mqtt_session_state_t> for ConnectionState {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub messages_flushed: usize,
+    pub messages_dropped: usize,
+    pub subscriptions_removed: usize,
+    pub elapsed: Duration,
+    pub final_state: ConnectionState,
+}
+
+// A topic name, validated at construction: non-empty, at most
+// `MAX_TOPIC_LEN` bytes, no null bytes and no wildcards.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Topic(String);
+
+// A subscription filter, validated at construction: same limits as `Topic`,
+// with `+` occupying a whole level and `#` only as the whole last level.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TopicFilter(String);
+
+macro_rules! topic_newtype {
+    ($name:ident, $validate:path) => {
+        impl $name {
+            pub fn new(s: impl Into<String>) -> Result<Self> {
+                let s = s.into();
+                $validate(&s)?;
+                Ok(Self(s))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = Error;
+
+            fn try_from(s: String) -> Result<Self> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = Error;
+
+            fn try_from(s: &str) -> Result<Self> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&String> for $name {
+            type Error = Error;
+
+            fn try_from(s: &String) -> Result<Self> {
+                Self::new(s.as_str())
+            }
+        }
+
+        impl TryFrom<&$name> for $name {
+            type Error = Error;
+
+            fn try_from(t: &$name) -> Result<Self> {
+                Ok(t.clone())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self> {
+                Self::new(s)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+topic_newtype!(Topic, topic::validate_topic_name);
+topic_newtype!(TopicFilter, topic::validate_filter);
+
+impl TopicFilter {
+    pub fn matches(&self, topic: &Topic) -> bool {
+        topic::matches(&self.0, &topic.0)
+    }
+}
+
+// Every valid topic name is also a valid (wildcard-free) filter.
+impl From<Topic> for TopicFilter {
+    fn from(topic: Topic) -> Self {
+        Self(topic.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_validation() {
+        assert!(Topic::new("a/b").is_ok());
+        assert!(matches!(Topic::new("a/+"), Err(Error::InvalidTopic)));
+        assert!(matches!(Topic::try_from(""), Err(Error::InvalidTopic)));
+        assert!(TopicFilter::new("a/+/#").is_ok());
+        assert!(matches!(
+            "a/#/b".parse::<TopicFilter>(),
+            Err(Error::InvalidTopic)
+        ));
+    }
+}
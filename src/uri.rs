@@ -0,0 +1,197 @@
+use crate::error::{Error, Result};
+use crate::options::ConnectOptions;
+use std::fmt;
+use std::str::FromStr;
+
+const DEFAULT_WS_PATH: &str = "/mqtt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Tls,
+    WebSocket,
+    SecureWebSocket,
+}
+
+impl Transport {
+    pub fn scheme(self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Tls => "ssl",
+            Transport::WebSocket => "ws",
+            Transport::SecureWebSocket => "wss",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Transport::Tcp => 1883,
+            Transport::Tls => 8883,
+            Transport::WebSocket => 80,
+            Transport::SecureWebSocket => 443,
+        }
+    }
+
+    pub fn is_tls(self) -> bool {
+        matches!(self, Transport::Tls | Transport::SecureWebSocket)
+    }
+
+    pub fn is_websocket(self) -> bool {
+        matches!(self, Transport::WebSocket | Transport::SecureWebSocket)
+    }
+}
+
+// A broker address such as `ssl://broker.example.com:8883` or
+// `ws://broker/mqtt`. `mqtt://`, `mqtts://` and `tls://` are accepted as
+// aliases; a missing port means the transport's default, a missing
+// WebSocket path means `/mqtt`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrokerUri {
+    pub transport: Transport,
+    pub host: String,
+    pub port: u16,
+    // Request path, WebSocket only.
+    pub path: Option<String>,
+}
+
+impl BrokerUri {
+    // `base` with the transport of this URI: TLS is switched on (keeping any
+    // TLS settings in `base`) or off, and likewise WebSocket.
+    pub(crate) fn options(&self, base: &ConnectOptions) -> ConnectOptions {
+        let mut options = base.clone();
+        options.tls = match self.transport.is_tls() {
+            true => Some(base.tls.clone().unwrap_or_default()),
+            false => None,
+        };
+        options.websocket = self.path.clone();
+        options
+    }
+}
+
+impl FromStr for BrokerUri {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let invalid = |what: String| Error::InvalidBrokerUrl(format!("{}: {}", uri, what));
+
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| invalid("missing scheme, such as tcp://".to_string()))?;
+        let transport = match scheme.to_ascii_lowercase().as_str() {
+            "tcp" | "mqtt" => Transport::Tcp,
+            "ssl" | "tls" | "mqtts" => Transport::Tls,
+            "ws" => Transport::WebSocket,
+            "wss" => Transport::SecureWebSocket,
+            _ => return Err(invalid(format!("unsupported scheme {:?}", scheme))),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if authority.contains('@') {
+            return Err(invalid(
+                "credentials in the URI are not supported, use ConnectOptions".to_string(),
+            ));
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unterminated IPv6 address".to_string()))?;
+                match after {
+                    "" => (host, None),
+                    _ => match after.strip_prefix(':') {
+                        Some(port) => (host, Some(port)),
+                        None => return Err(invalid(format!("unexpected {:?} after host", after))),
+                    },
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host".to_string()));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .ok()
+                .filter(|&port| port != 0)
+                .ok_or_else(|| invalid(format!("invalid port {:?}", port)))?,
+            None => transport.default_port(),
+        };
+
+        let path = if transport.is_websocket() {
+            Some(match path {
+                "" => DEFAULT_WS_PATH.to_string(),
+                path => path.to_string(),
+            })
+        } else if path.is_empty() || path == "/" {
+            None
+        } else {
+            return Err(invalid(format!(
+                "path {:?} needs a ws:// or wss:// scheme",
+                path
+            )));
+        };
+
+        Ok(Self {
+            transport,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+impl fmt::Display for BrokerUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://", self.transport.scheme())?;
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            f.write_str(&self.host)?;
+        }
+        write!(f, ":{}{}", self.port, self.path.as_deref().unwrap_or(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let uri: BrokerUri = "ssl://broker.example.com:8884".parse().unwrap();
+        assert_eq!(uri.transport, Transport::Tls);
+        assert_eq!((uri.host.as_str(), uri.port), ("broker.example.com", 8884));
+
+        let uri: BrokerUri = "wss://broker.example.com".parse().unwrap();
+        assert_eq!(uri.port, 443);
+        assert_eq!(uri.to_string(), "wss://broker.example.com:443/mqtt");
+
+        let uri: BrokerUri = "mqtt://[::1]".parse().unwrap();
+        assert_eq!((uri.host.as_str(), uri.port), ("::1", 1883));
+        assert_eq!(uri.to_string(), "tcp://[::1]:1883");
+
+        for bad in [
+            "broker:1883",
+            "http://broker",
+            "tcp://:1883",
+            "tcp://broker:0",
+            "tcp://broker:port",
+            "tcp://broker/mqtt",
+            "tcp://user:pw@broker",
+            "tcp://[::1",
+        ] {
+            match bad.parse::<BrokerUri>() {
+                Err(Error::InvalidBrokerUrl(why)) => assert!(why.starts_with(bad), "{}", why),
+                other => panic!("{}: {:?}", bad, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::runtime;
+use crate::stop::Stop;
+use crate::time::Instant;
+use crate::types::{ConnectionState, QoS};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const PROBE_PREFIX: &str = "polar_mqtt/watchdog";
+
+#[derive(Debug)]
+pub enum WatchdogEvent {
+    // No callback from the bridge for `silent_for` while connected.
+    Stalled { silent_for: Duration },
+    // The session was re-created and this many subscriptions restored.
+    Recreated { subscriptions: usize },
+    RecreateFailed(Error),
+    // Connected, but nothing received from the broker for `silent_for`,
+    // probes included, as with a half-open TCP connection.
+    Stale { silent_for: Duration },
+    // The stale connection was replaced and this many subscriptions kept.
+    Reconnected { subscriptions: usize },
+    ReconnectFailed(Error),
+}
+
+// Detects a native layer that stopped calling back altogether, such as a
+// bridge whose worker thread died. While connected, a QoS 0 probe is
+// published to a topic only this client subscribes to whenever nothing was
+// heard for `probe_interval`; if still nothing arrives within `timeout`, the
+// layer is considered wedged and the session is re-created.
+//
+// With `with_stale_after`, it also watches the connection itself: the
+// bridge tracks when the broker last sent anything, and once that is half
+// the limit ago probes go out at QoS 1, so that at least the PUBACK comes
+// back. If nothing at all has arrived for the whole limit, the connection
+// is taken for half-open and forcibly reconnected.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    probe_interval: Duration,
+    timeout: Duration,
+    recreate: bool,
+    stale_after: Option<Duration>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            recreate: true,
+            stale_after: None,
+        }
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Only report stalls, leaving recovery to the caller.
+    pub fn with_recreate(mut self, recreate: bool) -> Self {
+        self.recreate = recreate;
+        self
+    }
+
+    pub fn with_stale_after(mut self, limit: Duration) -> Self {
+        self.stale_after = Some(limit);
+        self
+    }
+
+    // Starts watching `client` until the handle is dropped or the client is.
+    pub fn start<F>(self, client: &Arc<Client>, on_event: F) -> Result<WatchdogHandle>
+    where
+        F: Fn(WatchdogEvent) + Send + 'static,
+    {
+        runtime::needs_threads("the watchdog")?;
+        let topic = format!("{}/{}", PROBE_PREFIX, client.client_id());
+        let probe = Message::new(topic.as_str(), Vec::new())?;
+        client.set_probe_topic(topic.clone());
+        let handle = client.subscribe(topic.as_str(), QoS::AtMostOnce)?;
+
+        let stop = Arc::new(Stop::default());
+        let thread = {
+            let (client, stop) = (Arc::downgrade(client), stop.clone());
+            let tick = self
+                .stale_after
+                .map_or(self.timeout, |limit| self.timeout.min(limit))
+                .min(self.probe_interval)
+                / 2;
+            let acked_probe = probe.clone().with_qos(QoS::AtLeastOnce);
+            thread::spawn(move || {
+                let mut reported = false;
+                let mut acted_at: Option<Instant> = None;
+                let mut reconnected_at: Option<Instant> = None;
+                while !stop.wait(tick) {
+                    let Some(client) = client.upgrade() else {
+                        break;
+                    };
+                    if client.state() != ConnectionState::Connected {
+                        continue;
+                    }
+                    if let (Some(limit), Some(silent_for)) =
+                        (self.stale_after, client.inbound_idle_for())
+                    {
+                        // A fresh connection gets a full limit too.
+                        let settling = reconnected_at.is_some_and(|at| at.elapsed() < limit);
+                        if silent_for >= limit && !settling {
+                            on_event(WatchdogEvent::Stale { silent_for });
+                            reconnected_at = Some(Instant::now());
+                            on_event(match client.reconnect() {
+                                Ok(subscriptions) => WatchdogEvent::Reconnected { subscriptions },
+                                Err(e) => WatchdogEvent::ReconnectFailed(e),
+                            });
+                            continue;
+                        }
+                        if silent_for >= limit / 2 {
+                            let _ = client.publish(&acked_probe);
+                        }
+                    }
+                    let idle = client.idle_for();
+                    if idle < self.timeout {
+                        reported = false;
+                        if idle >= self.probe_interval {
+                            let _ = client.publish(&probe);
+                        }
+                        continue;
+                    }
+                    // Give a re-created session a full timeout to show life.
+                    if acted_at.is_some_and(|at| at.elapsed() < self.timeout) {
+                        continue;
+                    }
+                    if !reported {
+                        on_event(WatchdogEvent::Stalled { silent_for: idle });
+                        reported = true;
+                    }
+                    if self.recreate {
+                        acted_at = Some(Instant::now());
+                        on_event(match client.recreate_session() {
+                            Ok(subscriptions) => WatchdogEvent::Recreated { subscriptions },
+                            Err(e) => WatchdogEvent::RecreateFailed(e),
+                        });
+                    }
+                }
+            })
+        };
+
+        Ok(WatchdogHandle {
+            client: Arc::downgrade(client),
+            handle,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+// Stops the watchdog when dropped.
+pub struct WatchdogHandle {
+    client: Weak<Client>,
+    handle: i64,
+    stop: Arc<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.stop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(client) = self.client.upgrade() {
+            let _ = client.unsubscribe(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::Fake;
+    use std::sync::mpsc;
+
+    fn connected(fake: &Arc<Fake>) -> Arc<Client> {
+        let client = Client::with_backend("watched", fake.clone(), |_| {}, |_| {}, |_, _| {});
+        let client = Arc::new(client.unwrap());
+        client.connect("broker", 1883).unwrap();
+        client.subscribe("orders/#", QoS::AtLeastOnce).unwrap();
+        client
+    }
+
+    fn next(events: &mpsc::Receiver<WatchdogEvent>) -> WatchdogEvent {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_stalled_callbacks_recreate_the_session() {
+        let fake = Arc::new(Fake::default());
+        let client = connected(&fake);
+        let (sender, events) = mpsc::channel();
+        let _watchdog = Watchdog::new()
+            .with_probe_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(100))
+            .start(&client, move |event| {
+                let _ = sender.send(event);
+            })
+            .unwrap();
+        // Answered probes keep it quiet.
+        assert!(events.recv_timeout(Duration::from_millis(300)).is_err());
+
+        fake.cut_off();
+        assert!(
+            matches!(next(&events), WatchdogEvent::Stalled { silent_for }
+            if silent_for >= Duration::from_millis(100))
+        );
+        // The probe subscription is restored along with the caller's.
+        assert!(matches!(
+            next(&events),
+            WatchdogEvent::Recreated { subscriptions: 2 }
+        ));
+        assert_eq!(fake.filters().len(), 2);
+        assert_eq!(client.state(), ConnectionState::Connected);
+        assert!(events.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+
+    #[test]
+    fn test_stale_connection_is_reconnected() {
+        let fake = Arc::new(Fake::default());
+        let client = connected(&fake);
+        let (sender, events) = mpsc::channel();
+        let _watchdog = Watchdog::new()
+            .with_timeout(Duration::from_secs(60))
+            .with_stale_after(Duration::from_millis(100))
+            .start(&client, move |event| {
+                let _ = sender.send(event);
+            })
+            .unwrap();
+        // Acknowledged probes count as hearing from the broker.
+        assert!(events.recv_timeout(Duration::from_millis(300)).is_err());
+
+        fake.cut_off();
+        assert!(matches!(next(&events), WatchdogEvent::Stale { silent_for }
+            if silent_for >= Duration::from_millis(100)));
+        assert!(matches!(
+            next(&events),
+            WatchdogEvent::Reconnected { subscriptions: 2 }
+        ));
+        assert_eq!(fake.filters().len(), 2);
+        assert!(events.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+}